@@ -0,0 +1,103 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::io::{Cursor, Read};
+
+use test::Bencher;
+
+use lightws::frame::{FrameHead, Fin, OpCode, Mask, PayloadLen};
+use lightws::frame::{new_mask_key, apply_mask4};
+use lightws::role::{Client, FixedMaskClient, Server, RoleHelper};
+use lightws::stream::Stream;
+
+// payload bytes per iteration
+const TOTAL_LEN: usize = 256 * 1024;
+
+// frames with the same payload length, masked if sent by a client
+fn make_frames(len: usize, is_masked: bool) -> Vec<u8> {
+    let mut frames = Vec::new();
+    for i in 0..TOTAL_LEN / len {
+        let key = new_mask_key();
+        let mask = if is_masked {
+            Mask::Key(key)
+        } else {
+            Mask::None
+        };
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Binary,
+            mask,
+            PayloadLen::from_num(len as u64),
+        );
+        let mut buf = [0u8; 14];
+        let n = head.encode(&mut buf).unwrap();
+        frames.extend_from_slice(&buf[..n]);
+
+        let mut payload = vec![i as u8; len];
+        if is_masked {
+            apply_mask4(key, &mut payload);
+        }
+        frames.extend_from_slice(&payload);
+    }
+    frames
+}
+
+fn read_frames<R: RoleHelper>(b: &mut Bencher, len: usize, is_masked: bool) {
+    let frames = make_frames(len, is_masked);
+    let total = TOTAL_LEN / len * len;
+    let mut buf = vec![0u8; 4096];
+    b.bytes = total as u64;
+    b.iter(|| {
+        let mut ws = Stream::new(Cursor::new(frames.as_slice()), R::new());
+        let mut n = 0;
+        loop {
+            let read_n = ws.read(&mut buf).unwrap();
+            if read_n == 0 && ws.is_read_end() {
+                break;
+            }
+            n += read_n;
+        }
+        assert_eq!(n, total);
+    });
+}
+
+// text frames, as sent by a chat server or client
+fn write_frames<R: RoleHelper>(b: &mut Bencher, len: usize) {
+    let payload = vec![b'a'; len];
+    let count = TOTAL_LEN / len;
+    let mut ws = Stream::new(Vec::with_capacity(count * (len + 14)), R::new());
+    b.bytes = (count * len) as u64;
+    b.iter(|| {
+        ws.as_mut().clear();
+        for _ in 0..count {
+            ws.write_frame(Fin::Y, OpCode::Text, test::black_box(&payload))
+                .unwrap();
+        }
+    });
+}
+
+macro_rules! bench_frames {
+    ($($len: expr => $read_client: ident, $read_server: ident,
+        $write_client: ident, $write_server: ident;)+) => {
+        $(
+            #[bench]
+            fn $read_client(b: &mut Bencher) { read_frames::<Client>(b, $len, false) }
+
+            #[bench]
+            fn $read_server(b: &mut Bencher) { read_frames::<Server>(b, $len, true) }
+
+            #[bench]
+            fn $write_client(b: &mut Bencher) { write_frames::<FixedMaskClient>(b, $len) }
+
+            #[bench]
+            fn $write_server(b: &mut Bencher) { write_frames::<Server>(b, $len) }
+        )+
+    };
+}
+
+bench_frames! {
+    10 => client_read_10, server_read_10, client_write_10, server_write_10;
+    100 => client_read_100, server_read_100, client_write_100, server_write_100;
+    1000 => client_read_1000, server_read_1000, client_write_1000, server_write_1000;
+}
@@ -0,0 +1,84 @@
+#![feature(test)]
+
+extern crate test;
+
+use std::io::{Cursor, Read};
+
+use test::Bencher;
+
+use lightws::frame::{FrameHead, Fin, OpCode, Mask, PayloadLen};
+use lightws::frame::{new_mask_key, apply_mask4};
+use lightws::role::{Client, Server, RoleHelper};
+use lightws::stream::Stream;
+use lightws::error::FrameError;
+
+const FRAMES: usize = 4096;
+const PAYLOAD_LEN: usize = 16;
+
+// many small masked frames, as sent by a client
+fn make_frames() -> Vec<u8> {
+    let mut frames = Vec::new();
+    for i in 0..FRAMES {
+        let key = new_mask_key();
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Binary,
+            Mask::Key(key),
+            PayloadLen::from_num(PAYLOAD_LEN as u64),
+        );
+        let mut buf = [0u8; 14];
+        let n = head.encode(&mut buf).unwrap();
+        frames.extend_from_slice(&buf[..n]);
+
+        let mut payload = [i as u8; PAYLOAD_LEN];
+        apply_mask4(key, &mut payload);
+        frames.extend_from_slice(&payload);
+    }
+    frames
+}
+
+fn read_frames<R: RoleHelper>(b: &mut Bencher) {
+    let frames = make_frames();
+    let mut buf = vec![0u8; 4096];
+    b.bytes = frames.len() as u64;
+    b.iter(|| {
+        let mut ws = Stream::new(Cursor::new(frames.as_slice()), R::new());
+        let mut total = 0;
+        loop {
+            let n = ws.read(&mut buf).unwrap();
+            if n == 0 && ws.is_read_end() {
+                break;
+            }
+            total += n;
+        }
+        assert_eq!(total, FRAMES * PAYLOAD_LEN);
+    });
+}
+
+type Decode = fn(&[u8]) -> Result<(FrameHead, usize), FrameError>;
+
+fn decode_heads(b: &mut Bencher, decode: Decode) {
+    let frames = make_frames();
+    b.bytes = frames.len() as u64;
+    b.iter(|| {
+        let mut beg = 0;
+        while beg < frames.len() {
+            let (head, n) = decode(test::black_box(&frames[beg..])).unwrap();
+            beg += n + head.length.to_num() as usize;
+        }
+    });
+}
+
+// specialized for server
+#[bench]
+fn server_read(b: &mut Bencher) { read_frames::<Server>(b) }
+
+// generic path, which also unmasks the payload
+#[bench]
+fn generic_read(b: &mut Bencher) { read_frames::<Client>(b) }
+
+#[bench]
+fn decode_masked_head(b: &mut Bencher) { decode_heads(b, FrameHead::decode_masked) }
+
+#[bench]
+fn decode_head(b: &mut Bencher) { decode_heads(b, FrameHead::decode) }
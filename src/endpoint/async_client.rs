@@ -9,7 +9,8 @@ use super::Endpoint;
 
 use crate::role::ClientRole;
 use crate::handshake::{HttpHeader, Request, Response};
-use crate::handshake::{new_sec_key, derive_accept_key};
+use crate::handshake::{new_sec_key, derive_accept_key, AcceptKeyScheme};
+use crate::handshake::{Extensions, Transcript};
 use crate::error::HandshakeError;
 use crate::stream::Stream;
 
@@ -46,33 +47,87 @@ impl<IO: AsyncRead + AsyncWrite + Unpin, Role: ClientRole> Endpoint<IO, Role> {
             })
         })
         .await
+        .map(|(_, n)| n)
     }
 
     /// Async version of [`connect`](Self::connect).
     pub async fn connect_async(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+    ) -> Result<Stream<IO, Role>> {
+        Self::connect_async_inner(io, buf, host, path, None, derive_accept_key).await
+    }
+
+    /// Async version of [`connect_with_transcript`](Self::connect_with_transcript).
+    pub async fn connect_with_transcript_async(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        transcript: &mut Transcript,
+    ) -> Result<Stream<IO, Role>> {
+        Self::connect_async_inner(io, buf, host, path, Some(transcript), derive_accept_key).await
+    }
+
+    /// Async version of [`connect_with_key_scheme`](Self::connect_with_key_scheme).
+    pub async fn connect_with_key_scheme_async(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        scheme: AcceptKeyScheme,
+    ) -> Result<Stream<IO, Role>> {
+        Self::connect_async_inner(io, buf, host, path, None, scheme).await
+    }
+
+    async fn connect_async_inner(
         mut io: IO,
         buf: &mut [u8],
         host: &str,
         path: &str,
+        mut transcript: Option<&mut Transcript>,
+        scheme: AcceptKeyScheme,
     ) -> Result<Stream<IO, Role>> {
         let sec_key = new_sec_key();
-        let sec_accept = derive_accept_key(&sec_key);
+        let sec_accept = scheme(&sec_key);
 
         // send
         let request = Request::new(path.as_bytes(), host.as_bytes(), &sec_key);
-        let _ = Self::send_request_async(&mut io, buf, &request).await?;
+        let n = Self::send_request_async(&mut io, buf, &request).await?;
+        if let Some(transcript) = transcript.as_mut() {
+            transcript.record_request(&buf[..n]);
+        }
 
         // recv
         let mut other_headers = HttpHeader::new_storage();
         let mut response = Response::new_storage(&mut other_headers);
         // this is safe since we do not modify response.
-        let _ = unsafe { Self::recv_response_async(&mut io, buf, &mut response) }.await?;
+        let (n, read_n) = poll_fn(|cx| unsafe {
+            detail::recv_response(&mut io, buf, &mut response, |io, buf| {
+                let mut buf = ReadBuf::new(buf);
+                Pin::new(io)
+                    .poll_read(cx, &mut buf)
+                    .map_ok(|_| buf.filled().len())
+            })
+        })
+        .await?;
+        if let Some(transcript) = transcript {
+            transcript.record_response(&buf[..n]);
+        }
 
         // check
         if response.sec_accept != sec_accept {
             return Err(HandshakeError::SecWebSocketAccept.into());
         }
 
-        Ok(Stream::new(io, Role::new()))
+        // no extension is offered
+        response.extensions()?.check_offered(&Extensions::new())?;
+        Ok(Stream::from_handshake_with_remainder(
+            io,
+            Role::new(),
+            &buf[n..read_n],
+        ))
     }
 }
@@ -5,11 +5,13 @@ use std::future::poll_fn;
 use tokio::io::{ReadBuf, AsyncRead, AsyncWrite};
 
 use super::detail;
-use super::Endpoint;
+use super::{Endpoint, ProtocolPolicy};
 
 use crate::role::ServerRole;
 use crate::handshake::{HttpHeader, Request, Response};
-use crate::handshake::derive_accept_key;
+use crate::handshake::{derive_accept_key, AcceptKeyScheme, Transcript};
+use crate::handshake::encode_protocol_rejection;
+use crate::handshake::static_headers::HEADER_SEC_WEBSOCKET_PROTOCOL_NAME;
 use crate::error::HandshakeError;
 use crate::stream::Stream;
 
@@ -52,16 +54,124 @@ impl<IO: AsyncRead + AsyncWrite + Unpin, Role: ServerRole> Endpoint<IO, Role> {
 
     /// Async version of [`accept`](Self::accept).
     pub async fn accept_async(
-        mut io: IO,
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+    ) -> Result<Stream<IO, Role>> {
+        Self::accept_async_inner(
+            io,
+            buf,
+            host,
+            path,
+            &[],
+            ProtocolPolicy::Optional,
+            None,
+            derive_accept_key,
+        )
+        .await
+        .map(|x| x.0)
+    }
+
+    /// Async version of [`accept_with_protocols`](Self::accept_with_protocols).
+    pub async fn accept_with_protocols_async<'p>(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+    ) -> Result<(Stream<IO, Role>, Option<&'p str>)> {
+        Self::accept_async_inner(
+            io,
+            buf,
+            host,
+            path,
+            protocols,
+            policy,
+            None,
+            derive_accept_key,
+        )
+        .await
+    }
+
+    /// Async version of [`accept_with_transcript`](Self::accept_with_transcript).
+    pub async fn accept_with_transcript_async(
+        io: IO,
         buf: &mut [u8],
         host: &str,
         path: &str,
+        transcript: &mut Transcript,
     ) -> Result<Stream<IO, Role>> {
+        Self::accept_async_inner(
+            io,
+            buf,
+            host,
+            path,
+            &[],
+            ProtocolPolicy::Optional,
+            Some(transcript),
+            derive_accept_key,
+        )
+        .await
+        .map(|x| x.0)
+    }
+
+    /// Async version of [`accept_with_key_scheme`](Self::accept_with_key_scheme).
+    pub async fn accept_with_key_scheme_async(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        scheme: AcceptKeyScheme,
+    ) -> Result<Stream<IO, Role>> {
+        Self::accept_async_inner(
+            io,
+            buf,
+            host,
+            path,
+            &[],
+            ProtocolPolicy::Optional,
+            None,
+            scheme,
+        )
+        .await
+        .map(|x| x.0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn accept_async_inner<'p>(
+        mut io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+        mut transcript: Option<&mut Transcript>,
+        scheme: AcceptKeyScheme,
+    ) -> Result<(Stream<IO, Role>, Option<&'p str>)> {
         // recv
         let mut other_headers = HttpHeader::new_storage();
         let mut request = Request::new_storage(&mut other_headers);
         // this is safe since we do not modify request.
-        let _ = unsafe { Self::recv_request_async(&mut io, buf, &mut request) }.await?;
+        match unsafe { Self::recv_request_async(&mut io, buf, &mut request) }.await {
+            Ok(n) => {
+                if let Some(transcript) = transcript.as_mut() {
+                    transcript.record_request(&buf[..n]);
+                }
+            }
+            Err(e) => {
+                if let Some(encode) = detail::rejection_of(&e) {
+                    let _ = poll_fn(|cx| {
+                        detail::send_rejection(&mut io, encode, |io, buf| {
+                            Pin::new(io).poll_write(cx, buf)
+                        })
+                    })
+                    .await;
+                }
+                return Err(e);
+            }
+        }
 
         // check
         if request.host != host.as_bytes() {
@@ -73,10 +183,70 @@ impl<IO: AsyncRead + AsyncWrite + Unpin, Role: ServerRole> Endpoint<IO, Role> {
         }
 
         // send
-        let sec_accept = derive_accept_key(request.sec_key);
-        let response = Response::new(&sec_accept);
-        let _ = Self::send_response_async(&mut io, buf, &response).await?;
+        let (protocol, n) =
+            Self::respond_async(&mut io, buf, &request, protocols, policy, scheme).await?;
+        if let Some(transcript) = transcript {
+            transcript.record_response(&buf[..n]);
+        }
+
+        Ok((Stream::new(io, Role::new()), protocol))
+    }
+
+    /// Async version of [`upgrade_from_request`](Self::upgrade_from_request).
+    pub async fn upgrade_from_request_async<'p, const N: usize>(
+        mut io: IO,
+        buf: &mut [u8],
+        request: &Request<'_, '_, N>,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+    ) -> Result<(Stream<IO, Role>, Option<&'p str>)> {
+        if let Err(e) = detail::check_request(request) {
+            let e = e.into();
+            if let Some(encode) = detail::rejection_of(&e) {
+                let _ = poll_fn(|cx| {
+                    detail::send_rejection(&mut io, encode, |io, buf| {
+                        Pin::new(io).poll_write(cx, buf)
+                    })
+                })
+                .await;
+            }
+            return Err(e);
+        }
+
+        let (protocol, _) =
+            Self::respond_async(&mut io, buf, request, protocols, policy, derive_accept_key)
+                .await?;
+        Ok((Stream::new(io, Role::new()), protocol))
+    }
+
+    /// Async version of `Endpoint::respond`.
+    async fn respond_async<'p, const N: usize>(
+        io: &mut IO,
+        buf: &mut [u8],
+        request: &Request<'_, '_, N>,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+        scheme: AcceptKeyScheme,
+    ) -> Result<(Option<&'p str>, usize)> {
+        let protocol = detail::select_protocol(request, protocols);
+        if protocol.is_none() && policy == ProtocolPolicy::Required {
+            let _ = poll_fn(|cx| {
+                detail::send_rejection(io, encode_protocol_rejection, |io, buf| {
+                    Pin::new(io).poll_write(cx, buf)
+                })
+            })
+            .await;
+            return Err(HandshakeError::SecWebSocketProtocol.into());
+        }
 
-        Ok(Stream::new(io, Role::new()))
+        let sec_accept = scheme(request.sec_key);
+        let mut headers = [HttpHeader::new(
+            HEADER_SEC_WEBSOCKET_PROTOCOL_NAME,
+            protocol.unwrap_or_default().as_bytes(),
+        )];
+        let headers_len = if protocol.is_some() { 1 } else { 0 };
+        let response = Response::new_with_headers(&sec_accept, &mut headers[..headers_len]);
+        let n = Self::send_response_async(io, buf, &response).await?;
+        Ok((protocol, n))
     }
 }
@@ -6,7 +6,8 @@ use super::Endpoint;
 
 use crate::role::ClientRole;
 use crate::handshake::{HttpHeader, Request, Response};
-use crate::handshake::{new_sec_key, derive_accept_key};
+use crate::handshake::{new_sec_key, derive_accept_key, AcceptKeyScheme};
+use crate::handshake::{Extensions, Transcript};
 use crate::error::HandshakeError;
 use crate::stream::Stream;
 
@@ -36,6 +37,11 @@ impl<IO: Read + Write, Role: ClientRole> Endpoint<IO, Role> {
     /// This function will block on reading data, until there is enough
     /// data to parse a response or an error occurs.
     ///
+    /// The size of the header is limited by the length of the buffer.
+    /// If the buffer is filled before the end of header, e.g. the peer keeps
+    /// sending headers, a [`HandshakeError::HeaderTooLarge`] error is returned.
+    /// A buffer of 16 KiB or less is recommended, e.g. `&mut buf[..16384]`.
+    ///
     /// # Safety
     ///
     /// Caller must not modify the buffer while `response` is in use,
@@ -46,7 +52,7 @@ impl<IO: Read + Write, Role: ClientRole> Endpoint<IO, Role> {
         response: &mut Response<'h, 'b, N>,
     ) -> Result<usize> {
         match detail::recv_response(io, buf, response, |io, buf| io.read(buf).into()) {
-            Poll::Ready(x) => x,
+            Poll::Ready(x) => x.map(|(_, n)| n),
             Poll::Pending => unreachable!(),
         }
     }
@@ -56,26 +62,83 @@ impl<IO: Read + Write, Role: ClientRole> Endpoint<IO, Role> {
     /// This function is a combination of [`send_request`](Self::send_request)
     /// and [`recv_response`](Self::recv_response), without accessing [`Response`].
     /// It will block until the handshake completes, or an error occurs.
-    pub fn connect(mut io: IO, buf: &mut [u8], host: &str, path: &str) -> Result<Stream<IO, Role>> {
+    ///
+    /// Frames which arrive with the response are kept in the stream,
+    /// see [`Stream::from_handshake_with_remainder`].
+    pub fn connect(io: IO, buf: &mut [u8], host: &str, path: &str) -> Result<Stream<IO, Role>> {
+        Self::connect_inner(io, buf, host, path, None, derive_accept_key)
+    }
+
+    /// Same as [`connect`](Self::connect), and retain the raw bytes
+    /// of request and response in `transcript`, for debugging.
+    pub fn connect_with_transcript(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        transcript: &mut Transcript,
+    ) -> Result<Stream<IO, Role>> {
+        Self::connect_inner(io, buf, host, path, Some(transcript), derive_accept_key)
+    }
+
+    /// Same as [`connect`](Self::connect), and check `sec-websocket-accept`
+    /// with `scheme` instead of the one of RFC-6455.
+    ///
+    /// This is for interop with legacy peers only, see [`AcceptKeyScheme`].
+    pub fn connect_with_key_scheme(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        scheme: AcceptKeyScheme,
+    ) -> Result<Stream<IO, Role>> {
+        Self::connect_inner(io, buf, host, path, None, scheme)
+    }
+
+    fn connect_inner(
+        mut io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        mut transcript: Option<&mut Transcript>,
+        scheme: AcceptKeyScheme,
+    ) -> Result<Stream<IO, Role>> {
         let sec_key = new_sec_key();
-        let sec_accept = derive_accept_key(&sec_key);
+        let sec_accept = scheme(&sec_key);
 
         // send
         let request = Request::new(path.as_bytes(), host.as_bytes(), &sec_key);
-        let _ = Self::send_request(&mut io, buf, &request)?;
+        let n = Self::send_request(&mut io, buf, &request)?;
+        if let Some(transcript) = transcript.as_mut() {
+            transcript.record_request(&buf[..n]);
+        }
 
         // recv
         let mut other_headers = HttpHeader::new_storage();
         let mut response = Response::new_storage(&mut other_headers);
         // this is safe since we do not modify response.
-        let _ = unsafe { Self::recv_response(&mut io, buf, &mut response) }?;
+        let (n, read_n) = match unsafe {
+            detail::recv_response(&mut io, buf, &mut response, |io, buf| io.read(buf).into())
+        } {
+            Poll::Ready(x) => x?,
+            Poll::Pending => unreachable!(),
+        };
+        if let Some(transcript) = transcript {
+            transcript.record_response(&buf[..n]);
+        }
 
         // check
         if response.sec_accept != sec_accept {
             return Err(HandshakeError::SecWebSocketAccept.into());
         }
 
-        Ok(Stream::new(io, Role::new()))
+        // no extension is offered
+        response.extensions()?.check_offered(&Extensions::new())?;
+        Ok(Stream::from_handshake_with_remainder(
+            io,
+            Role::new(),
+            &buf[n..read_n],
+        ))
     }
 }
 
@@ -85,7 +148,7 @@ mod test {
     use super::*;
     use super::super::test::*;
     use crate::error::HandshakeError;
-    use crate::role::Client;
+    use crate::role::{Client, RoleHelper};
 
     #[test]
     fn send_upgrade_request() {
@@ -143,6 +206,65 @@ mod test {
         }
     }
 
+    #[test]
+    fn recv_upgrade_response_with_extensions() {
+        let mut rw = LimitReadWriter {
+            rbuf: Vec::from(RESPONSE_WITH_EXTENSIONS),
+            wbuf: Vec::new(),
+            rlimit: 32,
+            wlimit: 0,
+            cursor: 0,
+        };
+
+        let mut buf = vec![0u8; 1024];
+        let mut headers = HttpHeader::new_storage();
+        let mut response = Response::new_storage(&mut headers);
+
+        let recv_n =
+            unsafe { Endpoint::<_, Client>::recv_response(&mut rw, &mut buf, &mut response) }
+                .unwrap();
+        assert_eq!(recv_n, RESPONSE_WITH_EXTENSIONS.len());
+
+        let extensions = response.extensions().unwrap();
+        let deflate = extensions.get("permessage-deflate").unwrap();
+        assert_eq!(deflate.param("server_max_window_bits"), Some(Some("12")));
+
+        // not offered
+        let e = extensions.check_offered(&Extensions::new()).unwrap_err();
+        assert_eq!(e, HandshakeError::SecWebSocketExtensions);
+
+        let mut offered = Extensions::new();
+        offered.parse(b"permessage-deflate").unwrap();
+        extensions.check_offered(&offered).unwrap();
+
+        let mut stream = Stream::new(rw, Client::new());
+        stream.set_extensions(extensions.clone());
+        assert_eq!(stream.extensions(), &extensions);
+    }
+
+    #[test]
+    fn recv_upgrade_response_too_large() {
+        let mut rw = LimitReadWriter {
+            rbuf: Vec::from(RESPONSE),
+            wbuf: Vec::new(),
+            rlimit: 7,
+            wlimit: 0,
+            cursor: 0,
+        };
+
+        // not enough to hold the header
+        let mut buf = vec![0u8; RESPONSE.len() - 1];
+        let mut headers = HttpHeader::new_storage();
+        let mut response = Response::new_storage(&mut headers);
+
+        let e = unsafe { Endpoint::<_, Client>::recv_response(&mut rw, &mut buf, &mut response) }
+            .unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::HeaderTooLarge)
+        );
+    }
+
     #[test]
     fn client_connect() {
         // use std::error::Error;
@@ -165,4 +287,132 @@ mod test {
             assert_eq!(*e, HandshakeError::SecWebSocketAccept);
         }
     }
+
+    #[test]
+    fn client_connect_with_key_scheme() {
+        // a dummy scheme, which ignores the key
+        fn scheme(_: &[u8]) -> [u8; 28] { *b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=" }
+
+        let mut rw = LimitReadWriter {
+            rbuf: Vec::from(RESPONSE),
+            wbuf: Vec::new(),
+            rlimit: 1,
+            wlimit: 1,
+            cursor: 0,
+        };
+
+        let mut buf = vec![0u8; 1024];
+
+        let stream = Endpoint::<_, Client>::connect_with_key_scheme(
+            &mut rw,
+            &mut buf,
+            "example.com",
+            "/",
+            scheme,
+        );
+        assert!(stream.is_ok());
+    }
+
+    #[test]
+    fn client_connect_with_transcript() {
+        let mut rw = LimitReadWriter {
+            rbuf: Vec::from(RESPONSE),
+            wbuf: Vec::new(),
+            rlimit: 1,
+            wlimit: 1,
+            cursor: 0,
+        };
+
+        let mut buf = vec![0u8; 1024];
+        let mut transcript = Transcript::new();
+
+        // sec-websocket-accept mismatch,
+        // bytes are retained anyway
+        let _ = Endpoint::<_, Client>::connect_with_transcript(
+            &mut rw,
+            &mut buf,
+            "example.com",
+            "/",
+            &mut transcript,
+        );
+        assert_eq!(transcript.request_bytes(), rw.wbuf);
+        assert_eq!(transcript.response_bytes(), RESPONSE);
+        assert!(!transcript.is_truncated());
+    }
+
+    // accept the request, then reply the response
+    // along with the frames in one read
+    struct Peer {
+        wbuf: Vec<u8>,
+        frame: &'static [u8],
+        is_replied: bool,
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.wbuf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    impl Read for Peer {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            if self.is_replied {
+                return Ok(0);
+            }
+            self.is_replied = true;
+
+            let mut headers = HttpHeader::new_storage();
+            let mut request = Request::new_storage(&mut headers);
+            request.decode(&self.wbuf).unwrap();
+            let sec_accept = derive_accept_key(request.sec_key);
+            let response = Response::new(&sec_accept);
+
+            let n = response.encode(buf).unwrap();
+            buf[n..n + self.frame.len()].copy_from_slice(self.frame);
+            Ok(n + self.frame.len())
+        }
+    }
+
+    #[test]
+    fn client_connect_with_remainder() {
+        let peer = Peer {
+            wbuf: Vec::new(),
+            frame: b"\x82\x05hello",
+            is_replied: false,
+        };
+        let mut buf = vec![0u8; 1024];
+        let mut stream =
+            Endpoint::<_, Client>::connect(peer, &mut buf, "example.com", "/").unwrap();
+        assert_eq!(stream.remainder_len(), 7);
+
+        let mut buf = vec![0u8; 16];
+        assert_eq!(stream.read(&mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(stream.remainder_len(), 0);
+    }
+
+    #[test]
+    fn client_connect_with_remainder_into_messages() {
+        use crate::stream::MessageStream;
+
+        // a fragmented text message, then a binary one
+        let peer = Peer {
+            wbuf: Vec::new(),
+            frame: b"\x01\x02he\x80\x03llo\x82\x05world",
+            is_replied: false,
+        };
+        let mut buf = vec![0u8; 1024];
+        let stream = Endpoint::<_, Client>::connect(peer, &mut buf, "example.com", "/").unwrap();
+        assert_eq!(stream.remainder_len(), 16);
+
+        let mut stream = MessageStream::from(stream);
+        assert_eq!(stream.read_text().unwrap(), "hello");
+        assert_eq!(stream.read_binary().unwrap(), b"world");
+        let e = stream.read_binary().unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(stream.is_read_eof());
+    }
 }
@@ -1,10 +1,115 @@
-use std::io::Result;
+use std::io::{Error, Result};
 use std::task::{Poll, ready};
 
 use crate::handshake::Request;
 use crate::handshake::Response;
+use crate::handshake::{encode_version_rejection, encode_header_rejection, encode_method_rejection};
+use crate::handshake::static_headers::*;
 use crate::error::HandshakeError;
 
+/// Encoder of a response to reject a request.
+type EncodeRejection = fn(&mut [u8]) -> std::result::Result<usize, HandshakeError>;
+
+/// Size of the buffer to encode a rejection.
+const REJECTION_BUF_SIZE: usize = 64;
+
+/// Get the encoder of a response to reply, if a request is rejected due to
+/// an unsupported websocket version, a header larger than the buffer,
+/// or a method other than `GET`.
+pub fn rejection_of(e: &Error) -> Option<EncodeRejection> {
+    let e = e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<crate::error::Error>());
+    match e {
+        Some(crate::error::Error::Handshake(e)) => match e {
+            HandshakeError::SecWebSocketVersion => Some(encode_version_rejection),
+            HandshakeError::HeaderTooLarge => Some(encode_header_rejection),
+            HandshakeError::HttpMethod => Some(encode_method_rejection),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Check a request which is not decoded by [`Request::decode`],
+/// e.g. parsed by another HTTP server.
+///
+/// `host` and a well-formed `sec-websocket-key` are required.
+/// `upgrade`, `connection` and `sec-websocket-version` are checked
+/// if they are kept in `other_headers`.
+pub fn check_request<const N: usize>(
+    request: &Request<'_, '_, N>,
+) -> std::result::Result<(), HandshakeError> {
+    // any of comma separated tokens matches (case insensitive)
+    let has_token = |name: &[u8], token: &[u8]| {
+        let mut values = request.headers(name).peekable();
+        values.peek().is_none()
+            || values.any(|x| {
+                x.split(|c| *c == b',')
+                    .any(|x| x.trim_ascii().eq_ignore_ascii_case(token))
+            })
+    };
+
+    if request.host.is_empty() {
+        return Err(HandshakeError::HttpHost);
+    }
+    // base64 of 16 bytes
+    let key = request.sec_key;
+    if key.len() != 24
+        || !key.ends_with(b"==")
+        || !key[..22]
+            .iter()
+            .all(|c| c.is_ascii_alphanumeric() || *c == b'+' || *c == b'/')
+    {
+        return Err(HandshakeError::SecWebSocketKey);
+    }
+    if !has_token(HEADER_UPGRADE_NAME, HEADER_UPGRADE_VALUE) {
+        return Err(HandshakeError::Upgrade);
+    }
+    if !has_token(HEADER_CONNECTION_NAME, HEADER_CONNECTION_VALUE) {
+        return Err(HandshakeError::Connection);
+    }
+    if !request
+        .headers(HEADER_SEC_WEBSOCKET_VERSION_NAME)
+        .all(|x| x == HEADER_SEC_WEBSOCKET_VERSION_VALUE)
+    {
+        return Err(HandshakeError::SecWebSocketVersion);
+    }
+    Ok(())
+}
+
+/// Select the first supported subprotocol which is offered by the client.
+pub fn select_protocol<'p, const N: usize>(
+    request: &Request<'_, '_, N>,
+    protocols: &[&'p str],
+) -> Option<&'p str> {
+    protocols
+        .iter()
+        .copied()
+        .find(|p| request.protocols().any(|x| x == p.as_bytes()))
+}
+
+/// Send a small response to reject a request, which is encoded by `encode`,
+/// e.g. [`encode_version_rejection`](crate::handshake::encode_version_rejection).
+pub fn send_rejection<E, F, IO>(io: &mut IO, encode: E, mut write: F) -> Poll<Result<usize>>
+where
+    E: Fn(&mut [u8]) -> std::result::Result<usize, HandshakeError>,
+    F: FnMut(&mut IO, &[u8]) -> Poll<Result<usize>>,
+{
+    let mut buf = [0u8; REJECTION_BUF_SIZE];
+    let total = encode(&mut buf).unwrap();
+
+    let mut offset = 0;
+
+    while offset < total {
+        let n = ready!(write(io, &buf[offset..total]))?;
+
+        offset += n;
+    }
+
+    Poll::Ready(Ok(total))
+}
+
 pub fn send_response<'h, 'b: 'h, F, IO, const N: usize>(
     io: &mut IO,
     buf: &mut [u8],
@@ -63,6 +168,7 @@ where
         }
     }
 
-    // provided buffer is filled, however it could not accommodate the response.
-    Poll::Ready(Err(HandshakeError::NotEnoughCapacity.into()))
+    // provided buffer is filled, however it could not accommodate the header,
+    // which also stops a peer from sending headers forever.
+    Poll::Ready(Err(HandshakeError::HeaderTooLarge.into()))
 }
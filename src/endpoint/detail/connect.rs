@@ -30,12 +30,14 @@ where
     Poll::Ready(Ok(total))
 }
 
+/// Returns the length of the response,
+/// and the count of read bytes, which may include the following frames.
 pub unsafe fn recv_response<'h, 'b: 'h, F, IO, const N: usize>(
     io: &mut IO,
     buf: &mut [u8],
     response: &mut Response<'h, 'b, N>,
     mut read: F,
-) -> Poll<Result<usize>>
+) -> Poll<Result<(usize, usize)>>
 where
     F: FnMut(&mut IO, &mut [u8]) -> Poll<Result<usize>>,
 {
@@ -57,12 +59,13 @@ where
         offset += n;
 
         match response.decode(&buf_const[..offset]) {
-            Ok(_) => return Poll::Ready(Ok(offset)),
+            Ok(n) => return Poll::Ready(Ok((n, offset))),
             Err(ref e) if *e == HandshakeError::NotEnoughData => continue,
             Err(e) => return Poll::Ready(Err(e.into())),
         }
     }
 
-    // provided buffer is filled, however it could not accommodate the response.
-    Poll::Ready(Err(HandshakeError::NotEnoughCapacity.into()))
+    // provided buffer is filled, however it could not accommodate the header,
+    // which also stops a peer from sending headers forever.
+    Poll::Ready(Err(HandshakeError::HeaderTooLarge.into()))
 }
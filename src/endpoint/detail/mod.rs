@@ -2,4 +2,5 @@ mod accept;
 mod connect;
 
 pub(super) use accept::{recv_request, send_response};
+pub(super) use accept::{rejection_of, send_rejection, select_protocol, check_request};
 pub(super) use connect::{recv_response, send_request};
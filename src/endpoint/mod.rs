@@ -4,7 +4,8 @@
 //! both sync and async IO.
 //!
 //! To open or accept a connection directly, use [`Endpoint::connect`],
-//! [`Endpoint::accept`], or their async version.
+//! [`Endpoint::accept`], or their async version. To open a connection
+//! to a `ws://` url over tcp, use [`connect`] or [`connect_async`].
 //!
 //! To have detailed control over a handshake, use [`Endpoint::send_request`],
 //! [`Endpoint::recv_response`], [`Endpoint::recv_request`], [`Endpoint::send_response`],
@@ -13,16 +14,31 @@
 mod detail;
 mod client;
 mod server;
+mod tcp;
+
+pub use tcp::connect;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "tokio")] {
         mod async_client;
         mod async_server;
+        pub use tcp::connect_async;
     }
 }
 
 use std::marker::PhantomData;
 
+/// Policy on subprotocols when none of the offered ones is supported,
+/// see [`Endpoint::accept_with_protocols`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProtocolPolicy {
+    /// complete the handshake without a subprotocol, which is the default
+    #[default]
+    Optional,
+    /// reject the handshake with a status code of 400
+    Required,
+}
+
 /// Handshake endpoint.
 pub struct Endpoint<IO, Role> {
     _marker: PhantomData<IO>,
@@ -47,6 +63,13 @@ mod test {
         connection: upgrade\r\n\
         sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
 
+    pub const RESPONSE_WITH_EXTENSIONS: &[u8] = b"\
+        HTTP/1.1 101 Switching Protocols\r\n\
+        upgrade: websocket\r\n\
+        connection: upgrade\r\n\
+        sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+        sec-websocket-extensions: permessage-deflate; server_max_window_bits=12\r\n\r\n";
+
     pub struct LimitReadWriter {
         pub rbuf: Vec<u8>,
         pub wbuf: Vec<u8>,
@@ -2,11 +2,13 @@ use std::io::{Read, Write, Result};
 use std::task::Poll;
 
 use super::detail;
-use super::Endpoint;
+use super::{Endpoint, ProtocolPolicy};
 
 use crate::role::ServerRole;
 use crate::handshake::{HttpHeader, Request, Response};
-use crate::handshake::derive_accept_key;
+use crate::handshake::{derive_accept_key, AcceptKeyScheme, Transcript};
+use crate::handshake::encode_protocol_rejection;
+use crate::handshake::static_headers::HEADER_SEC_WEBSOCKET_PROTOCOL_NAME;
 use crate::error::HandshakeError;
 use crate::stream::Stream;
 
@@ -36,6 +38,11 @@ impl<IO: Read + Write, Role: ServerRole> Endpoint<IO, Role> {
     /// This function will block on reading data, until there is enough
     /// data to parse a request or an error occurs.
     ///
+    /// The size of the header is limited by the length of the buffer.
+    /// If the buffer is filled before the end of header, e.g. the peer keeps
+    /// sending headers, a [`HandshakeError::HeaderTooLarge`] error is returned.
+    /// A buffer of 16 KiB or less is recommended, e.g. `&mut buf[..16384]`.
+    ///
     /// # Safety
     ///
     /// Caller must not modify the buffer while `request` is in use,
@@ -55,13 +62,137 @@ impl<IO: Read + Write, Role: ServerRole> Endpoint<IO, Role> {
     ///
     /// This function is a combination of [`recv_request`](Self::recv_request)
     /// and [`send_response`](Self::send_response), without accessing [`Request`].
-    /// It will block until the handshake completes, or an error occurs.    
-    pub fn accept(mut io: IO, buf: &mut [u8], host: &str, path: &str) -> Result<Stream<IO, Role>> {
+    /// It will block until the handshake completes, or an error occurs.
+    ///
+    /// If the client offers an unsupported websocket version, a response
+    /// with a status code of 426 is sent, see
+    /// [`encode_version_rejection`](crate::handshake::encode_version_rejection),
+    /// then a [`HandshakeError::SecWebSocketVersion`] error is returned.
+    /// If the request does not fit in `buf`, a response with a status code
+    /// of 431 is sent, see
+    /// [`encode_header_rejection`](crate::handshake::encode_header_rejection),
+    /// then a [`HandshakeError::HeaderTooLarge`] error is returned.
+    /// If the method is not `GET`, a response with a status code of 405 is
+    /// sent, see [`encode_method_rejection`](crate::handshake::encode_method_rejection),
+    /// then a [`HandshakeError::HttpMethod`] error is returned.
+    pub fn accept(io: IO, buf: &mut [u8], host: &str, path: &str) -> Result<Stream<IO, Role>> {
+        Self::accept_inner(
+            io,
+            buf,
+            host,
+            path,
+            &[],
+            ProtocolPolicy::Optional,
+            None,
+            derive_accept_key,
+        )
+        .map(|x| x.0)
+    }
+
+    /// Same as [`accept`](Self::accept), and select a subprotocol which is
+    /// offered by the client, from `protocols` in the order of preference.
+    /// Returns the new stream and the selected subprotocol.
+    ///
+    /// If none of the supported subprotocols is offered, the handshake
+    /// completes without a subprotocol under [`ProtocolPolicy::Optional`].
+    /// Under [`ProtocolPolicy::Required`], a response with a status code
+    /// of 400 is sent, see
+    /// [`encode_protocol_rejection`](crate::handshake::encode_protocol_rejection),
+    /// then a [`HandshakeError::SecWebSocketProtocol`] error is returned.
+    pub fn accept_with_protocols<'p>(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+    ) -> Result<(Stream<IO, Role>, Option<&'p str>)> {
+        Self::accept_inner(
+            io,
+            buf,
+            host,
+            path,
+            protocols,
+            policy,
+            None,
+            derive_accept_key,
+        )
+    }
+
+    /// Same as [`accept`](Self::accept), and retain the raw bytes
+    /// of request and response in `transcript`, for debugging.
+    pub fn accept_with_transcript(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        transcript: &mut Transcript,
+    ) -> Result<Stream<IO, Role>> {
+        Self::accept_inner(
+            io,
+            buf,
+            host,
+            path,
+            &[],
+            ProtocolPolicy::Optional,
+            Some(transcript),
+            derive_accept_key,
+        )
+        .map(|x| x.0)
+    }
+
+    /// Same as [`accept`](Self::accept), and derive `sec-websocket-accept`
+    /// with `scheme` instead of the one of RFC-6455.
+    ///
+    /// This is for interop with legacy peers only, see [`AcceptKeyScheme`].
+    pub fn accept_with_key_scheme(
+        io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        scheme: AcceptKeyScheme,
+    ) -> Result<Stream<IO, Role>> {
+        Self::accept_inner(
+            io,
+            buf,
+            host,
+            path,
+            &[],
+            ProtocolPolicy::Optional,
+            None,
+            scheme,
+        )
+        .map(|x| x.0)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn accept_inner<'p>(
+        mut io: IO,
+        buf: &mut [u8],
+        host: &str,
+        path: &str,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+        mut transcript: Option<&mut Transcript>,
+        scheme: AcceptKeyScheme,
+    ) -> Result<(Stream<IO, Role>, Option<&'p str>)> {
         // recv
         let mut other_headers = HttpHeader::new_storage();
         let mut request = Request::new_storage(&mut other_headers);
         // this is safe since we do not modify request.
-        let _ = unsafe { Self::recv_request(&mut io, buf, &mut request) }?;
+        match unsafe { Self::recv_request(&mut io, buf, &mut request) } {
+            Ok(n) => {
+                if let Some(transcript) = transcript.as_mut() {
+                    transcript.record_request(&buf[..n]);
+                }
+            }
+            Err(e) => {
+                if let Some(encode) = detail::rejection_of(&e) {
+                    let _ = detail::send_rejection(&mut io, encode, |io, buf| io.write(buf).into());
+                }
+                return Err(e);
+            }
+        }
 
         // check
         if request.host != host.as_bytes() {
@@ -73,11 +204,78 @@ impl<IO: Read + Write, Role: ServerRole> Endpoint<IO, Role> {
         }
 
         // send
-        let sec_accept = derive_accept_key(request.sec_key);
-        let response = Response::new(&sec_accept);
-        let _ = Self::send_response(&mut io, buf, &response)?;
+        let (protocol, n) = Self::respond(&mut io, buf, &request, protocols, policy, scheme)?;
+        if let Some(transcript) = transcript {
+            transcript.record_response(&buf[..n]);
+        }
+
+        Ok((Stream::new(io, Role::new()), protocol))
+    }
+
+    /// Complete the handshake of a request which has been parsed elsewhere,
+    /// return a new websocket stream and the selected subprotocol.
+    ///
+    /// This is the integration point under another HTTP server, which parses
+    /// the request then hands over the connection. The request is not parsed
+    /// again, instead it is built with [`Request::new_with_headers`]. Then
+    /// the websocket headers are checked, and the response is encoded
+    /// to `buf` and sent. Subprotocols are selected like
+    /// [`accept_with_protocols`](Self::accept_with_protocols).
+    ///
+    /// `host` and a well-formed `sec-websocket-key` are required.
+    /// `upgrade`, `connection` and `sec-websocket-version` are checked
+    /// if they are present in `other_headers`, since the HTTP server may have
+    /// checked and dropped them. An unsupported websocket version is rejected
+    /// with a status code of 426, see
+    /// [`encode_version_rejection`](crate::handshake::encode_version_rejection);
+    /// other errors are returned without a response.
+    pub fn upgrade_from_request<'p, const N: usize>(
+        mut io: IO,
+        buf: &mut [u8],
+        request: &Request<'_, '_, N>,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+    ) -> Result<(Stream<IO, Role>, Option<&'p str>)> {
+        if let Err(e) = detail::check_request(request) {
+            let e = e.into();
+            if let Some(encode) = detail::rejection_of(&e) {
+                let _ = detail::send_rejection(&mut io, encode, |io, buf| io.write(buf).into());
+            }
+            return Err(e);
+        }
 
-        Ok(Stream::new(io, Role::new()))
+        let (protocol, _) =
+            Self::respond(&mut io, buf, request, protocols, policy, derive_accept_key)?;
+        Ok((Stream::new(io, Role::new()), protocol))
+    }
+
+    /// Select a subprotocol and send the response,
+    /// return the subprotocol and the number of bytes transmitted.
+    fn respond<'p, const N: usize>(
+        io: &mut IO,
+        buf: &mut [u8],
+        request: &Request<'_, '_, N>,
+        protocols: &[&'p str],
+        policy: ProtocolPolicy,
+        scheme: AcceptKeyScheme,
+    ) -> Result<(Option<&'p str>, usize)> {
+        let protocol = detail::select_protocol(request, protocols);
+        if protocol.is_none() && policy == ProtocolPolicy::Required {
+            let _ = detail::send_rejection(io, encode_protocol_rejection, |io, buf| {
+                io.write(buf).into()
+            });
+            return Err(HandshakeError::SecWebSocketProtocol.into());
+        }
+
+        let sec_accept = scheme(request.sec_key);
+        let mut headers = [HttpHeader::new(
+            HEADER_SEC_WEBSOCKET_PROTOCOL_NAME,
+            protocol.unwrap_or_default().as_bytes(),
+        )];
+        let headers_len = if protocol.is_some() { 1 } else { 0 };
+        let response = Response::new_with_headers(&sec_accept, &mut headers[..headers_len]);
+        let n = Self::send_response(io, buf, &response)?;
+        Ok((protocol, n))
     }
 }
 
@@ -161,4 +359,327 @@ mod test {
 
         let _ = Endpoint::<_, Server>::accept(&mut rw, &mut buf, "www.example.com", "/ws");
     }
+
+    #[test]
+    fn server_accept_with_transcript() {
+        for (limit, truncated) in [(1024, false), (16, true)] {
+            let mut rw = LimitReadWriter {
+                rbuf: Vec::from(REQUEST),
+                wbuf: Vec::new(),
+                rlimit: 1,
+                wlimit: 1,
+                cursor: 0,
+            };
+
+            let mut buf = vec![0u8; 1024];
+            let mut transcript = Transcript::with_limit(limit);
+
+            Endpoint::<_, Server>::accept_with_transcript(
+                &mut rw,
+                &mut buf,
+                "www.example.com",
+                "/ws",
+                &mut transcript,
+            )
+            .unwrap();
+
+            let n = std::cmp::min(limit, REQUEST.len());
+            assert_eq!(transcript.request_bytes(), &REQUEST[..n]);
+            let n = std::cmp::min(limit, RESPONSE.len());
+            assert_eq!(transcript.response_bytes(), &RESPONSE[..n]);
+            assert_eq!(transcript.response_bytes(), &rw.wbuf[..n]);
+            assert_eq!(transcript.is_truncated(), truncated);
+        }
+    }
+
+    #[test]
+    fn server_reject_version() {
+        use std::error::Error;
+
+        let request = std::str::from_utf8(REQUEST)
+            .unwrap()
+            .replace("sec-websocket-version: 13", "sec-websocket-version: 8");
+        let mut rw = LimitReadWriter {
+            rbuf: request.into_bytes(),
+            wbuf: Vec::new(),
+            rlimit: 1,
+            wlimit: 1,
+            cursor: 0,
+        };
+
+        let mut buf = vec![0u8; 1024];
+
+        let e =
+            Endpoint::<_, Server>::accept(&mut rw, &mut buf, "www.example.com", "/ws").unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::SecWebSocketVersion)
+        );
+        assert_eq!(
+            rw.wbuf,
+            b"HTTP/1.1 426 Upgrade Required\r\nsec-websocket-version: 13\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn server_accept_with_key_scheme() {
+        // a dummy scheme, which reverses the standard one
+        fn scheme(sec_key: &[u8]) -> [u8; 28] {
+            let mut key = derive_accept_key(sec_key);
+            key.reverse();
+            key
+        }
+
+        for (scheme, sec_accept) in [
+            (
+                derive_accept_key as AcceptKeyScheme,
+                b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=",
+            ),
+            (scheme, b"=oOx+KbRZhzzGYk9QaxTiBMLPp3s"),
+        ] {
+            let mut rw = LimitReadWriter {
+                rbuf: Vec::from(REQUEST),
+                wbuf: Vec::new(),
+                rlimit: 1024,
+                wlimit: 1024,
+                cursor: 0,
+            };
+
+            let mut buf = vec![0u8; 1024];
+
+            Endpoint::<_, Server>::accept_with_key_scheme(
+                &mut rw,
+                &mut buf,
+                "www.example.com",
+                "/ws",
+                scheme,
+            )
+            .unwrap();
+
+            let mut headers = HttpHeader::new_storage();
+            let mut response = Response::new_storage(&mut headers);
+            assert_eq!(response.decode(&rw.wbuf).unwrap(), rw.wbuf.len());
+            assert_eq!(response.sec_accept, sec_accept);
+        }
+    }
+
+    #[test]
+    fn server_reject_method() {
+        use std::error::Error;
+
+        let request = std::str::from_utf8(REQUEST).unwrap().replace("GET", "POST");
+        let mut rw = LimitReadWriter {
+            rbuf: request.into_bytes(),
+            wbuf: Vec::new(),
+            rlimit: 1,
+            wlimit: 1,
+            cursor: 0,
+        };
+
+        let mut buf = vec![0u8; 1024];
+
+        let e =
+            Endpoint::<_, Server>::accept(&mut rw, &mut buf, "www.example.com", "/ws").unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::HttpMethod)
+        );
+        assert_eq!(
+            rw.wbuf,
+            b"HTTP/1.1 405 Method Not Allowed\r\nallow: GET\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn server_upgrade_from_request() {
+        use std::error::Error;
+        use std::io::{Read, Write};
+
+        // parsed by another HTTP server
+        let mut headers = [
+            HttpHeader::new(b"Upgrade", b"WebSocket"),
+            HttpHeader::new(b"Connection", b"keep-alive, Upgrade"),
+            HttpHeader::new(b"Sec-WebSocket-Protocol", b"chat, superchat"),
+        ];
+        let request = Request::new_with_headers(
+            b"/ws",
+            b"www.example.com",
+            b"dGhlIHNhbXBsZSBub25jZQ==",
+            &mut headers,
+        );
+
+        // a frame right after the handshake
+        let mut rw = LimitReadWriter {
+            rbuf: b"\x82\x85\x00\x00\x00\x00hello".to_vec(),
+            wbuf: Vec::new(),
+            rlimit: 1024,
+            wlimit: 1024,
+            cursor: 0,
+        };
+        let mut buf = vec![0u8; 1024];
+        let (stream, protocol) = Endpoint::<_, Server>::upgrade_from_request(
+            &mut rw,
+            &mut buf,
+            &request,
+            &["superchat"],
+            ProtocolPolicy::Required,
+        )
+        .unwrap();
+        assert_eq!(protocol, Some("superchat"));
+
+        let mut stream = stream.guard();
+        let mut data = [0u8; 16];
+        assert_eq!(stream.read(&mut data).unwrap(), 5);
+        assert_eq!(&data[..5], b"hello");
+        assert_eq!(stream.write(b"world").unwrap(), 5);
+        drop(stream);
+
+        let mut headers = HttpHeader::new_storage();
+        let mut response = Response::new_storage(&mut headers);
+        let n = response.decode(&rw.wbuf).unwrap();
+        assert_eq!(response.sec_accept, b"s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(response.other_headers[0].value, b"superchat");
+        assert_eq!(&rw.wbuf[n..], b"\x82\x05world");
+
+        // bad requests
+        for (sec_key, version, err) in [
+            (
+                &b"dGhlIHNhbXBsZQ=="[..],
+                &b"13"[..],
+                HandshakeError::SecWebSocketKey,
+            ),
+            (
+                b"dGhlIHNhbXBsZSBub25jZQ==",
+                b"8",
+                HandshakeError::SecWebSocketVersion,
+            ),
+        ] {
+            let mut headers = [HttpHeader::new(b"sec-websocket-version", version)];
+            let request =
+                Request::new_with_headers(b"/ws", b"www.example.com", sec_key, &mut headers);
+            let mut rw = LimitReadWriter {
+                rbuf: Vec::new(),
+                wbuf: Vec::new(),
+                rlimit: 0,
+                wlimit: 1024,
+                cursor: 0,
+            };
+            let e = Endpoint::<_, Server>::upgrade_from_request(
+                &mut rw,
+                &mut buf,
+                &request,
+                &[],
+                ProtocolPolicy::Optional,
+            )
+            .unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<HandshakeError>(),
+                Some(&err)
+            );
+            // only an unsupported version is replied
+            assert_eq!(
+                rw.wbuf.starts_with(b"HTTP/1.1 426"),
+                err == HandshakeError::SecWebSocketVersion
+            );
+        }
+    }
+
+    #[test]
+    fn server_reject_large_header() {
+        use std::error::Error;
+
+        // a header which never ends
+        let mut request = REQUEST[..REQUEST.len() - 2].to_vec();
+        request.extend_from_slice(b"x-slowloris: ");
+        request.resize(4096, b'a');
+        let mut rw = LimitReadWriter {
+            rbuf: request,
+            wbuf: Vec::new(),
+            rlimit: 7,
+            wlimit: 1,
+            cursor: 0,
+        };
+
+        let mut buf = vec![0u8; 1024];
+
+        let e =
+            Endpoint::<_, Server>::accept(&mut rw, &mut buf, "www.example.com", "/ws").unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::HeaderTooLarge)
+        );
+        assert_eq!(rw.cursor, 1024);
+        assert_eq!(
+            rw.wbuf,
+            b"HTTP/1.1 431 Request Header Fields Too Large\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn server_accept_with_protocols() {
+        use std::error::Error;
+
+        let request = std::str::from_utf8(REQUEST).unwrap().replace(
+            "sec-websocket-version: 13\r\n",
+            "sec-websocket-version: 13\r\nsec-websocket-protocol: chat, superchat\r\n",
+        );
+        let make_rw = || LimitReadWriter {
+            rbuf: request.clone().into_bytes(),
+            wbuf: Vec::new(),
+            rlimit: 1,
+            wlimit: 1,
+            cursor: 0,
+        };
+        let mut buf = vec![0u8; 1024];
+
+        // select in the order of preference
+        for policy in [ProtocolPolicy::Optional, ProtocolPolicy::Required] {
+            let mut rw = make_rw();
+            let (_, protocol) = Endpoint::<_, Server>::accept_with_protocols(
+                &mut rw,
+                &mut buf,
+                "www.example.com",
+                "/ws",
+                &["mqtt", "superchat", "chat"],
+                policy,
+            )
+            .unwrap();
+            assert_eq!(protocol, Some("superchat"));
+            let response = std::str::from_utf8(RESPONSE)
+                .unwrap()
+                .replace("\r\n\r\n", "\r\nsec-websocket-protocol: superchat\r\n\r\n");
+            assert_eq!(rw.wbuf, response.as_bytes());
+        }
+
+        // complete without a subprotocol
+        let mut rw = make_rw();
+        let (_, protocol) = Endpoint::<_, Server>::accept_with_protocols(
+            &mut rw,
+            &mut buf,
+            "www.example.com",
+            "/ws",
+            &["mqtt"],
+            ProtocolPolicy::Optional,
+        )
+        .unwrap();
+        assert_eq!(protocol, None);
+        assert_eq!(rw.wbuf, RESPONSE);
+
+        // reject without a subprotocol
+        let mut rw = make_rw();
+        let e = Endpoint::<_, Server>::accept_with_protocols(
+            &mut rw,
+            &mut buf,
+            "www.example.com",
+            "/ws",
+            &["mqtt"],
+            ProtocolPolicy::Required,
+        )
+        .unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::SecWebSocketProtocol)
+        );
+        assert_eq!(rw.wbuf, b"HTTP/1.1 400 Bad Request\r\n\r\n");
+    }
 }
@@ -0,0 +1,155 @@
+//! Connect to a websocket url over tcp.
+
+use std::io::Result;
+use std::borrow::Cow;
+use std::net::TcpStream;
+
+use super::Endpoint;
+
+use crate::role::Client;
+use crate::error::HandshakeError;
+use crate::stream::Stream;
+
+/// Size of the buffer to perform a handshake with.
+const HANDSHAKE_BUF_SIZE: usize = 4096;
+
+/// Parts of a `ws://host:port/path` url.
+#[derive(Debug, PartialEq, Eq)]
+struct WsUrl<'a> {
+    is_secure: bool,
+    // host, with brackets for an ipv6 address
+    host: &'a str,
+    port: u16,
+    // host and optional port, as the host header
+    authority: &'a str,
+    // path and query
+    path: Cow<'a, str>,
+}
+
+impl<'a> WsUrl<'a> {
+    /// Parse a url, where the scheme is either `ws` or `wss`.
+    /// The port defaults to 80 or 443, and the path defaults to `/`.
+    fn parse(url: &'a str) -> Result<Self> {
+        let (is_secure, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("ws") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("wss") => (true, rest),
+            _ => return Err(HandshakeError::Manual("illegal url scheme").into()),
+        };
+
+        // drop the fragment
+        let rest = match rest.split_once('#') {
+            Some((rest, _)) => rest,
+            None => rest,
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+        let path = match path {
+            "" => Cow::Borrowed("/"),
+            _ if path.starts_with('?') => Cow::Owned(format!("/{}", path)),
+            _ => Cow::Borrowed(path),
+        };
+
+        // the last colon of an ipv6 address is inside the brackets
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => {
+                let port = port
+                    .parse()
+                    .map_err(|_| HandshakeError::Manual("illegal url port"))?;
+                (host, port)
+            }
+            _ => (authority, if is_secure { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(HandshakeError::Manual("missing url host").into());
+        }
+
+        Ok(Self {
+            is_secure,
+            host,
+            port,
+            authority,
+            path,
+        })
+    }
+
+    /// Host to resolve, without brackets.
+    fn resolve_host(&self) -> &'a str { self.host.trim_start_matches('[').trim_end_matches(']') }
+
+    /// Fail on `wss`, since tls is not built in.
+    fn check_insecure(&self) -> Result<()> {
+        if self.is_secure {
+            return Err(HandshakeError::Manual(
+                "wss is not supported, use Endpoint::connect over a tls stream",
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// Open a tcp connection to a `ws://host:port/path` url, then perform a
+/// client handshake, return a new websocket stream.
+///
+/// The port defaults to 80, and the path defaults to `/`. A `wss` url is
+/// rejected, since tls is not built in. To connect over tls, establish
+/// a tls stream, then use [`Endpoint::connect`].
+pub fn connect(url: &str) -> Result<Stream<TcpStream, Client>> {
+    let url = WsUrl::parse(url)?;
+    url.check_insecure()?;
+    let tcp = TcpStream::connect((url.resolve_host(), url.port))?;
+    let mut buf = [0u8; HANDSHAKE_BUF_SIZE];
+    Endpoint::connect(tcp, &mut buf, url.authority, &url.path)
+}
+
+/// Async version of [`connect`].
+#[cfg(feature = "tokio")]
+pub async fn connect_async(url: &str) -> Result<Stream<tokio::net::TcpStream, Client>> {
+    let url = WsUrl::parse(url)?;
+    url.check_insecure()?;
+    let tcp = tokio::net::TcpStream::connect((url.resolve_host(), url.port)).await?;
+    let mut buf = [0u8; HANDSHAKE_BUF_SIZE];
+    Endpoint::connect_async(tcp, &mut buf, url.authority, &url.path).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_url() {
+        let url = WsUrl::parse("ws://example.com").unwrap();
+        assert!(!url.is_secure);
+        assert_eq!((url.host, url.port), ("example.com", 80));
+        assert_eq!((url.authority, &*url.path), ("example.com", "/"));
+
+        let url = WsUrl::parse("wss://example.com/ws?id=1#top").unwrap();
+        assert!(url.is_secure);
+        assert_eq!((url.host, url.port), ("example.com", 443));
+        assert_eq!((url.authority, &*url.path), ("example.com", "/ws?id=1"));
+        assert!(url.check_insecure().is_err());
+
+        let url = WsUrl::parse("ws://127.0.0.1:8080/a/b").unwrap();
+        assert_eq!((url.host, url.port), ("127.0.0.1", 8080));
+        assert_eq!((url.authority, &*url.path), ("127.0.0.1:8080", "/a/b"));
+
+        let url = WsUrl::parse("ws://[::1]:8080?x").unwrap();
+        assert_eq!((url.resolve_host(), url.port), ("::1", 8080));
+        assert_eq!((url.authority, &*url.path), ("[::1]:8080", "/?x"));
+
+        let url = WsUrl::parse("WS://[::1]").unwrap();
+        assert_eq!((url.resolve_host(), url.port), ("::1", 80));
+
+        for url in [
+            "http://example.com",
+            "example.com",
+            "ws://",
+            "ws://:80",
+            "ws://example.com:port",
+            "ws://example.com:65536",
+        ] {
+            assert!(WsUrl::parse(url).is_err(), "{}", url);
+        }
+    }
+}
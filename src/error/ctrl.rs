@@ -3,6 +3,22 @@ use std::fmt::{Display, Formatter};
 #[derive(Debug, PartialEq, Eq)]
 pub enum CtrlError {
     SetMaskInWrite,
+
+    CloseInWrite,
+
+    RawFrameInWrite,
+
+    ReadFrameInRead,
+
+    WriteAfterClose,
+
+    DataFrameInControl,
+
+    ControlQueueFull,
+
+    PeerTimeout,
+
+    PongInWrite,
 }
 
 impl Display for CtrlError {
@@ -10,6 +26,17 @@ impl Display for CtrlError {
         use CtrlError::*;
         match self {
             SetMaskInWrite => write!(f, "Set mask during an incomplete write"),
+            CloseInWrite => write!(f, "Close during an incomplete write"),
+            RawFrameInWrite => write!(f, "Write a raw frame during an incomplete write"),
+            ReadFrameInRead => write!(f, "Read a whole frame during an incomplete read"),
+            WriteAfterClose => write!(f, "Write data after a close frame is sent"),
+            DataFrameInControl => write!(f, "Read a data frame when expecting a control frame"),
+            ControlQueueFull => write!(f, "Receive a control frame when the queue is full"),
+            PeerTimeout => write!(f, "Peer does not reply a ping in time"),
+            PongInWrite => write!(
+                f,
+                "Queue a pong while the previous one is partially written"
+            ),
         }
     }
 }
@@ -1,36 +1,82 @@
 use std::fmt::{Display, Formatter};
 
+use crate::frame::CloseCode;
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum FrameError {
     IllegalFin,
 
+    IllegalRsv,
+
     IllegalMask,
 
     IllegalOpCode,
 
     IllegalData,
 
+    IllegalFragment,
+
+    IllegalContinuation,
+
     NotEnoughData,
 
     NotEnoughCapacity,
 
     UnsupportedOpcode,
+
+    ExceedFrameLimit,
+
+    ExceedPayloadLimit,
+
+    RejectedFrame(CloseCode),
+
+    BufferTooSmall(u64),
+
+    NotEnoughPayload(u64),
+}
+
+impl FrameError {
+    /// Get the status code to close the connection with.
+    pub const fn close_code(&self) -> CloseCode {
+        use FrameError::*;
+        match self {
+            IllegalFin | IllegalRsv | IllegalMask | IllegalOpCode | IllegalData
+            | IllegalFragment | IllegalContinuation => CloseCode::ProtocolError,
+            UnsupportedOpcode => CloseCode::Unsupported,
+            ExceedFrameLimit | ExceedPayloadLimit => CloseCode::PolicyViolation,
+            NotEnoughData | NotEnoughCapacity | BufferTooSmall(_) | NotEnoughPayload(_) => {
+                CloseCode::InternalError
+            }
+            RejectedFrame(code) => *code,
+        }
+    }
 }
 
 impl Display for FrameError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use FrameError::*;
         match self {
-            IllegalFin => write!(f, "Illegal fin value"),
+            IllegalFin => write!(
+                f,
+                "Illegal fin value, a control frame must not be fragmented"
+            ),
+            IllegalRsv => write!(f, "Illegal rsv value, no extension negotiated"),
             IllegalMask => write!(f, "Illegal mask value"),
             IllegalOpCode => write!(f, "Illegal opcode value"),
             IllegalData => write!(f, "Illegal data"),
+            IllegalFragment => write!(f, "Illegal fragment, expect a continuation frame"),
+            IllegalContinuation => write!(f, "Illegal continuation frame, no message to continue"),
             NotEnoughData => write!(f, "Not enough data to parse"),
             NotEnoughCapacity => write!(f, "Not enough space to write to"),
             UnsupportedOpcode => write!(
                 f,
                 "Unsupported opcode, only support binary, ping, pong, close"
             ),
+            ExceedFrameLimit => write!(f, "Exceed the max count of frames"),
+            ExceedPayloadLimit => write!(f, "Exceed the max count of payload bytes"),
+            RejectedFrame(code) => write!(f, "Reject a frame with status code {}", code.to_u16()),
+            BufferTooSmall(len) => write!(f, "Buffer is too small, need {} bytes", len),
+            NotEnoughPayload(len) => write!(f, "Not enough payload, only {} bytes left", len),
         }
     }
 }
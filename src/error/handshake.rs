@@ -22,6 +22,10 @@ pub enum HandshakeError {
 
     SecWebSocketVersion,
 
+    SecWebSocketExtensions,
+
+    SecWebSocketProtocol,
+
     // other error
 
     // read
@@ -30,6 +34,9 @@ pub enum HandshakeError {
     // write
     NotEnoughCapacity,
 
+    // read, the buffer is filled before the end of header
+    HeaderTooLarge,
+
     Httparse(httparse::Error),
 
     Manual(&'static str),
@@ -65,11 +72,21 @@ impl Display for HandshakeError {
                 write!(f, "Missing or illegal sec-websocket-version")
             }
 
+            SecWebSocketExtensions => {
+                write!(f, "Illegal or unexpected sec-websocket-extensions header")
+            }
+
+            SecWebSocketProtocol => {
+                write!(f, "Missing or unacceptable sec-websocket-protocol header")
+            }
+
             // other error
             NotEnoughData => write!(f, "Not enough data to parse"),
 
             NotEnoughCapacity => write!(f, "Not enough space to write to"),
 
+            HeaderTooLarge => write!(f, "Http header is too large"),
+
             Httparse(e) => write!(f, "Http parse error: {}", e),
 
             Manual(s) => write!(f, "Manual error: {}", s),
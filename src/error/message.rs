@@ -0,0 +1,53 @@
+use std::fmt::{Display, Formatter};
+
+use crate::frame::CloseCode;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MessageError {
+    IllegalUtf8,
+
+    NotText,
+
+    NotBinary,
+
+    Closed,
+
+    TooManyFragments,
+
+    TooManyPending,
+
+    TooBig,
+}
+
+impl MessageError {
+    /// Get the status code to close the connection with.
+    pub const fn close_code(&self) -> CloseCode {
+        use MessageError::*;
+        match self {
+            IllegalUtf8 => CloseCode::InvalidPayload,
+            NotText | NotBinary => CloseCode::Unsupported,
+            Closed => CloseCode::Normal,
+            TooManyFragments => CloseCode::TooBig,
+            TooManyPending => CloseCode::PolicyViolation,
+            TooBig => CloseCode::TooBig,
+        }
+    }
+}
+
+impl Display for MessageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        use MessageError::*;
+        match self {
+            IllegalUtf8 => write!(f, "Illegal utf-8 text"),
+            NotText => write!(f, "Not a text message, use read_binary instead"),
+            NotBinary => write!(f, "Not a binary message, use read_text instead"),
+            Closed => write!(f, "Close frame received"),
+            TooManyFragments => write!(f, "Exceed the max count of fragments in a message"),
+            TooManyPending => write!(f, "Exceed the max count of buffered messages"),
+            TooBig => write!(f, "Exceed the max size of a message"),
+        }
+    }
+}
+
+// use default impl
+impl std::error::Error for MessageError {}
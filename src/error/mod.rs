@@ -4,10 +4,12 @@
 mod ctrl;
 mod frame;
 mod handshake;
+mod message;
 
 pub use ctrl::CtrlError;
 pub use frame::FrameError;
 pub use handshake::HandshakeError;
+pub use message::MessageError;
 
 use std::fmt::{Display, Formatter};
 
@@ -18,6 +20,8 @@ pub enum Error {
     Frame(FrameError),
 
     Handshake(HandshakeError),
+
+    Message(MessageError),
 }
 
 impl From<FrameError> for Error {
@@ -28,6 +32,10 @@ impl From<HandshakeError> for Error {
     fn from(e: HandshakeError) -> Self { Error::Handshake(e) }
 }
 
+impl From<MessageError> for Error {
+    fn from(e: MessageError) -> Self { Error::Message(e) }
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         use Error::*;
@@ -35,6 +43,7 @@ impl Display for Error {
             Ctrl(e) => write!(f, "Control error: {}", e),
             Frame(e) => write!(f, "Frame error: {}", e),
             Handshake(e) => write!(f, "Handshake error: {}", e),
+            Message(e) => write!(f, "Message error: {}", e),
         }
     }
 }
@@ -47,6 +56,7 @@ impl std::error::Error for Error {
             Ctrl(e) => Some(e),
             Frame(e) => Some(e),
             Handshake(e) => Some(e),
+            Message(e) => Some(e),
         }
     }
 }
@@ -69,3 +79,7 @@ impl From<FrameError> for std::io::Error {
 impl From<HandshakeError> for std::io::Error {
     fn from(e: HandshakeError) -> Self { Error::Handshake(e).into() }
 }
+
+impl From<MessageError> for std::io::Error {
+    fn from(e: MessageError) -> Self { Error::Message(e).into() }
+}
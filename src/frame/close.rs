@@ -0,0 +1,268 @@
+//! Close status code.
+//!
+//! [RFC-6455 Section 7.4](https://datatracker.ietf.org/doc/html/rfc6455#section-7.4)
+//!
+//! The payload of a close frame is either empty, or
+//! a 2-byte status code followed by an utf-8 encoded reason.
+
+use crate::error::FrameError;
+
+/// Close status code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseCode {
+    /// normal closure, 1000
+    Normal,
+    /// endpoint is going away, 1001
+    GoingAway,
+    /// protocol error, 1002
+    ProtocolError,
+    /// unsupported data, 1003
+    Unsupported,
+    /// no status code is present, 1005
+    ///
+    /// This must not appear on the wire,
+    /// it is sent as a close frame without payload.
+    NoStatus,
+    /// connection closed abnormally, 1006
+    ///
+    /// This must not appear on the wire.
+    Abnormal,
+    /// invalid frame payload data, 1007
+    InvalidPayload,
+    /// policy violation, 1008
+    PolicyViolation,
+    /// message too big, 1009
+    TooBig,
+    /// mandatory extension, 1010
+    MandatoryExtension,
+    /// internal server error, 1011
+    InternalError,
+    /// tls handshake failure, 1015
+    ///
+    /// This must not appear on the wire.
+    Tls,
+    /// other status codes
+    Other(u16),
+}
+
+/// Max length of the reason in a close frame,
+/// which is 125 minus 2 bytes of status code.
+pub const MAX_CLOSE_REASON_LEN: usize = 123;
+
+impl CloseCode {
+    /// Convert from a 2-byte status code.
+    #[inline]
+    pub const fn from_u16(code: u16) -> Self {
+        use CloseCode::*;
+        match code {
+            1000 => Normal,
+            1001 => GoingAway,
+            1002 => ProtocolError,
+            1003 => Unsupported,
+            1005 => NoStatus,
+            1006 => Abnormal,
+            1007 => InvalidPayload,
+            1008 => PolicyViolation,
+            1009 => TooBig,
+            1010 => MandatoryExtension,
+            1011 => InternalError,
+            1015 => Tls,
+            x => Other(x),
+        }
+    }
+
+    /// Convert to a 2-byte status code.
+    #[inline]
+    pub const fn to_u16(self) -> u16 {
+        use CloseCode::*;
+        match self {
+            Normal => 1000,
+            GoingAway => 1001,
+            ProtocolError => 1002,
+            Unsupported => 1003,
+            NoStatus => 1005,
+            Abnormal => 1006,
+            InvalidPayload => 1007,
+            PolicyViolation => 1008,
+            TooBig => 1009,
+            MandatoryExtension => 1010,
+            InternalError => 1011,
+            Tls => 1015,
+            Other(x) => x,
+        }
+    }
+
+    /// Check if the status code is valid to receive from the wire.
+    ///
+    /// 1000-1003, 1007-1011, and 3000-4999 are valid. Others are either
+    /// reserved, or must not appear on the wire, e.g. [`CloseCode::NoStatus`].
+    #[inline]
+    pub const fn is_valid_received(&self) -> bool {
+        matches!(self.to_u16(), 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+/// Encode the payload of a close frame to provided buffer,
+/// return the count of written bytes.
+///
+/// [`CloseCode::NoStatus`] leads to an empty payload, where the reason must be empty.
+/// [`CloseCode::Abnormal`] and [`CloseCode::Tls`] are not allowed to send.
+/// Same for [`CloseCode::Other`] with a value of such a code.
+/// The reason should not be longer than [`MAX_CLOSE_REASON_LEN`].
+/// Otherwise a [`FrameError::IllegalData`] error will be returned.
+///
+/// Caller should make sure there is enough space to write,
+/// otherwise a [`FrameError::NotEnoughCapacity`] error will be returned.
+pub fn encode_close_payload(
+    code: CloseCode,
+    reason: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, FrameError> {
+    // `Other` may carry a reserved code
+    match CloseCode::from_u16(code.to_u16()) {
+        CloseCode::NoStatus if reason.is_empty() => return Ok(0),
+        CloseCode::NoStatus | CloseCode::Abnormal | CloseCode::Tls => {
+            return Err(FrameError::IllegalData)
+        }
+        _ => {}
+    }
+    if reason.len() > MAX_CLOSE_REASON_LEN {
+        return Err(FrameError::IllegalData);
+    }
+
+    let len = 2 + reason.len();
+    if buf.len() < len {
+        return Err(FrameError::NotEnoughCapacity);
+    }
+    buf[..2].copy_from_slice(&code.to_u16().to_be_bytes());
+    buf[2..len].copy_from_slice(reason);
+    Ok(len)
+}
+
+/// Decode the payload of a close frame, return the status code and reason.
+///
+/// An empty payload is decoded as [`CloseCode::NoStatus`].
+/// A payload of only 1 byte, or a status code which is not valid to receive
+/// (see [`CloseCode::is_valid_received`]), is invalid, which leads to a
+/// [`FrameError::IllegalData`] error.
+pub const fn decode_close_payload(payload: &[u8]) -> Result<(CloseCode, &[u8]), FrameError> {
+    match payload {
+        [] => Ok((CloseCode::NoStatus, &[])),
+        [_] => Err(FrameError::IllegalData),
+        [a, b, reason @ ..] => {
+            let code = CloseCode::from_u16(u16::from_be_bytes([*a, *b]));
+            if !code.is_valid_received() {
+                return Err(FrameError::IllegalData);
+            }
+            Ok((code, reason))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn close_code() {
+        for code in 0..=u16::MAX {
+            assert_eq!(CloseCode::from_u16(code).to_u16(), code);
+        }
+    }
+
+    #[test]
+    fn close_code_received() {
+        for (code, valid) in [
+            (0, false),
+            (999, false),
+            (1000, true),
+            (1003, true),
+            (1004, false),
+            (1005, false),
+            (1006, false),
+            (1007, true),
+            (1011, true),
+            (1012, false),
+            (1015, false),
+            (2999, false),
+            (3000, true),
+            (4000, true),
+            (4999, true),
+            (5000, false),
+            (u16::MAX, false),
+        ] {
+            assert_eq!(
+                CloseCode::from_u16(code).is_valid_received(),
+                valid,
+                "{}",
+                code
+            );
+            assert_eq!(
+                decode_close_payload(&code.to_be_bytes()).is_ok(),
+                valid,
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn close_payload() {
+        let mut buf = [0u8; 125];
+
+        // no status
+        let n = encode_close_payload(CloseCode::NoStatus, b"", &mut buf).unwrap();
+        assert_eq!(n, 0);
+        assert_eq!(
+            decode_close_payload(&buf[..n]),
+            Ok((CloseCode::NoStatus, &b""[..]))
+        );
+        assert_eq!(
+            encode_close_payload(CloseCode::NoStatus, b"bye", &mut buf),
+            Err(FrameError::IllegalData)
+        );
+        assert_eq!(
+            encode_close_payload(CloseCode::Other(1005), b"", &mut buf),
+            Ok(0)
+        );
+        assert_eq!(
+            encode_close_payload(CloseCode::Other(1005), b"bye", &mut buf),
+            Err(FrameError::IllegalData)
+        );
+
+        // never on the wire
+        for code in [
+            CloseCode::Abnormal,
+            CloseCode::Tls,
+            CloseCode::Other(1006),
+            CloseCode::Other(1015),
+        ] {
+            assert_eq!(
+                encode_close_payload(code, b"", &mut buf),
+                Err(FrameError::IllegalData)
+            );
+        }
+
+        // with reason
+        let n = encode_close_payload(CloseCode::GoingAway, b"bye", &mut buf).unwrap();
+        assert_eq!(&buf[..n], &[0x03, 0xe9, b'b', b'y', b'e']);
+        assert_eq!(
+            decode_close_payload(&buf[..n]),
+            Ok((CloseCode::GoingAway, &b"bye"[..]))
+        );
+
+        // reason too long
+        let reason = [b'x'; MAX_CLOSE_REASON_LEN + 1];
+        assert_eq!(
+            encode_close_payload(CloseCode::Normal, &reason, &mut buf),
+            Err(FrameError::IllegalData)
+        );
+        assert_eq!(
+            encode_close_payload(CloseCode::Normal, &reason[1..], &mut buf[..124]),
+            Err(FrameError::NotEnoughCapacity)
+        );
+
+        // incomplete code
+        assert_eq!(decode_close_payload(&[0x03]), Err(FrameError::IllegalData));
+    }
+}
@@ -0,0 +1,181 @@
+//! Frame codec over messages, without an IO source.
+//!
+//! This is used where raw sockets are not available, e.g. WASM in a browser,
+//! where bytes are received by a JS shim, then passed in as messages.
+//! Frames are encoded to and decoded from `Vec<u8>`, which involves
+//! [`FrameHead`] and masking only, never `std::io` or a random generator.
+
+use super::{FrameHead, Fin, Mask, OpCode, PayloadLen};
+use super::mask::apply_mask4;
+use crate::error::FrameError;
+
+/// Default max length of payload in a decoded frame.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 16 * 1024 * 1024;
+
+/// Encode and decode frames from messages supplied by the host.
+///
+/// A message may contain any number of frames, or a part of a frame,
+/// where the bytes of an incomplete frame are kept until the rest arrives.
+///
+/// The mask key is provided by the host, e.g. via `crypto.getRandomValues`.
+/// A server should use [`Mask::None`].
+#[derive(Debug)]
+pub struct WasmFrameCodec {
+    mask: Mask,
+    max_frame_len: usize,
+    buf: Vec<u8>,
+}
+
+impl WasmFrameCodec {
+    /// Create a codec, which masks encoded frames with `mask`.
+    #[inline]
+    pub const fn new(mask: Mask) -> Self {
+        Self {
+            mask,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Get mask for upcoming frames.
+    #[inline]
+    pub const fn mask(&self) -> Mask { self.mask }
+
+    /// Set mask for upcoming frames, a client should use a new key
+    /// for each frame.
+    #[inline]
+    pub fn set_mask(&mut self, mask: Mask) { self.mask = mask; }
+
+    /// Get the max length of payload in a decoded frame.
+    #[inline]
+    pub const fn max_frame_len(&self) -> usize { self.max_frame_len }
+
+    /// Set the max length of payload in a decoded frame. A frame head
+    /// which declares a longer payload fails [`WasmFrameCodec::decode`]
+    /// with [`FrameError::ExceedPayloadLimit`], before any of its payload
+    /// is kept. The default is [`DEFAULT_MAX_FRAME_LEN`].
+    #[inline]
+    pub fn set_max_frame_len(&mut self, len: usize) { self.max_frame_len = len; }
+
+    /// Get the count of kept bytes, which belong to an incomplete frame.
+    #[inline]
+    pub fn pending_len(&self) -> usize { self.buf.len() }
+
+    /// Encode a complete frame, returns its bytes.
+    pub fn encode(&self, fin: Fin, opcode: OpCode, payload: &[u8]) -> Vec<u8> {
+        let length = PayloadLen::from_num(payload.len() as u64);
+        let head = FrameHead::new(fin, opcode, self.mask, length);
+        let mut frame = vec![0; 14 + payload.len()];
+        // this is safe since a head takes at most 14 bytes
+        let head_len = unsafe { head.encode_unchecked(&mut frame) };
+        frame.truncate(head_len + payload.len());
+
+        let data = &mut frame[head_len..];
+        data.copy_from_slice(payload);
+        if let Mask::Key(key) = self.mask {
+            apply_mask4(key, data);
+        }
+        frame
+    }
+
+    /// Append a message, then decode all complete frames,
+    /// returns their heads and unmasked payloads.
+    ///
+    /// This is a codec only, RSV bits and the order of fragments
+    /// are not checked, which is left to the caller. After an error,
+    /// the codec should not be used any more.
+    pub fn decode(&mut self, msg: &[u8]) -> Result<Vec<(FrameHead, Vec<u8>)>, FrameError> {
+        self.buf.extend_from_slice(msg);
+
+        let mut frames = Vec::new();
+        let mut beg = 0;
+        loop {
+            let (head, head_len) = match FrameHead::decode(&self.buf[beg..]) {
+                Ok(x) => x,
+                Err(FrameError::NotEnoughData) => break,
+                Err(e) => return Err(e),
+            };
+            // never truncate a length beyond usize::MAX
+            let len = usize::try_from(head.length.to_num())
+                .map_err(|_| FrameError::ExceedPayloadLimit)?;
+            // never wait for a payload beyond the limit
+            if len > self.max_frame_len {
+                return Err(FrameError::ExceedPayloadLimit);
+            }
+            let payload_beg = beg + head_len;
+            if self.buf.len() - payload_beg < len {
+                break;
+            }
+
+            let mut payload = self.buf[payload_beg..payload_beg + len].to_vec();
+            if let Mask::Key(key) = head.mask {
+                apply_mask4(key, &mut payload);
+            }
+            frames.push((head, payload));
+            beg = payload_beg + len;
+        }
+
+        self.buf.drain(..beg);
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::new_mask_key;
+
+    #[test]
+    fn codec_roundtrip() {
+        let messages: Vec<(Fin, OpCode, Vec<u8>)> = [0, 1, 125, 126, 0x10000]
+            .into_iter()
+            .map(|n| (Fin::Y, OpCode::Binary, vec![n as u8; n]))
+            .chain([
+                (Fin::N, OpCode::Text, b"hello ".to_vec()),
+                (Fin::Y, OpCode::Continue, b"world".to_vec()),
+                (Fin::Y, OpCode::Ping, b"ping".to_vec()),
+            ])
+            .collect();
+
+        for mask in [Mask::None, Mask::Skip, Mask::Key(new_mask_key())] {
+            let client = WasmFrameCodec::new(mask);
+            let bytes: Vec<u8> = messages
+                .iter()
+                .flat_map(|(fin, opcode, payload)| client.encode(*fin, *opcode, payload))
+                .collect();
+
+            // split into messages of any size
+            for size in [1, 7, 4096, bytes.len()] {
+                let mut server = WasmFrameCodec::new(Mask::None);
+                let mut frames = Vec::new();
+                for msg in bytes.chunks(size) {
+                    frames.extend(server.decode(msg).unwrap());
+                }
+                assert_eq!(server.pending_len(), 0);
+
+                assert_eq!(frames.len(), messages.len());
+                for ((head, payload), (fin, opcode, data)) in frames.iter().zip(&messages) {
+                    assert_eq!((head.fin, head.opcode, head.mask), (*fin, *opcode, mask));
+                    assert_eq!(payload, data);
+                }
+            }
+        }
+
+        // invalid opcode
+        let mut codec = WasmFrameCodec::new(Mask::None);
+        assert_eq!(codec.decode(&[0x83, 0x00]), Err(FrameError::IllegalOpCode));
+
+        // a head beyond the limit is rejected before its payload arrives
+        for (len, ok) in [(125, true), (126, false), (0x10000, false)] {
+            let frame = codec.encode(Fin::Y, OpCode::Binary, &vec![0; len]);
+            let mut codec = WasmFrameCodec::new(Mask::None);
+            codec.set_max_frame_len(125);
+            let res = codec.decode(&frame[..frame.len() - len]);
+            if ok {
+                assert_eq!(res, Ok(Vec::new()));
+            } else {
+                assert_eq!(res, Err(FrameError::ExceedPayloadLimit));
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! RSV bits of extensions.
+//!
+//! [RFC-6455 Section 5.2](https://datatracker.ietf.org/doc/html/rfc6455#section-5.2)
+//!
+//! An extension may own one or more RSV bits, which are only meaningful
+//! to that extension. Extensions could be chained, where each layer
+//! takes its own bits from an inbound frame, and sets its own bits
+//! on an outbound frame, leaving the bits of other layers unchanged.
+//!
+//! If an inbound frame has a RSV bit that no extension owns,
+//! the frame is invalid.
+
+use super::{FrameHead, Rsv};
+use crate::error::FrameError;
+
+/// An extension which owns some RSV bits.
+pub trait RsvExtension {
+    /// RSV bits owned by this extension.
+    fn owned_rsv(&self) -> Rsv;
+
+    /// Handle RSV bits of an inbound frame.
+    ///
+    /// Only the bits owned by this extension are provided.
+    fn on_inbound(&mut self, head: &FrameHead, rsv: Rsv);
+
+    /// Return RSV bits of an outbound frame.
+    ///
+    /// Bits not owned by this extension are ignored.
+    fn on_outbound(&mut self, head: &FrameHead) -> Rsv;
+}
+
+/// Pass RSV bits of an inbound frame through a chain of extensions.
+///
+/// Each extension takes and clears its own bits from the frame head.
+/// Two extensions should not own the same bit, for which the first one
+/// takes the bit. If any bit is left, a [`FrameError::IllegalRsv`]
+/// error will be returned.
+pub fn inbound_rsv(
+    chain: &mut [&mut dyn RsvExtension],
+    head: &mut FrameHead,
+) -> Result<(), FrameError> {
+    for ext in chain.iter_mut() {
+        let rsv = head.rsv.take(ext.owned_rsv());
+        ext.on_inbound(head, rsv);
+    }
+    if !head.rsv.is_empty() {
+        return Err(FrameError::IllegalRsv);
+    }
+    Ok(())
+}
+
+/// Pass an outbound frame through a chain of extensions,
+/// where each extension sets its own bits on the frame head.
+///
+/// Bits set by other extensions are preserved.
+pub fn outbound_rsv(chain: &mut [&mut dyn RsvExtension], head: &mut FrameHead) {
+    for ext in chain.iter_mut() {
+        let owned = ext.owned_rsv();
+        let rsv = ext.on_outbound(head).intersection(owned);
+        head.rsv.remove(owned);
+        head.rsv.insert(rsv);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::{Fin, OpCode, Mask, PayloadLen};
+
+    // count frames with the owned bit set,
+    // set the bit on every outbound frame
+    struct Marker {
+        owned: Rsv,
+        count: usize,
+    }
+
+    impl RsvExtension for Marker {
+        fn owned_rsv(&self) -> Rsv { self.owned }
+
+        fn on_inbound(&mut self, _: &FrameHead, rsv: Rsv) {
+            assert!(rsv.is_empty() || rsv == self.owned);
+            if !rsv.is_empty() {
+                self.count += 1;
+            }
+        }
+
+        // try to set all the bits
+        fn on_outbound(&mut self, _: &FrameHead) -> Rsv { Rsv::ALL }
+    }
+
+    fn make_head(rsv: Rsv) -> FrameHead {
+        FrameHead::new(Fin::Y, OpCode::Binary, Mask::None, PayloadLen::from_num(0)).with_rsv(rsv)
+    }
+
+    #[test]
+    fn chain_extensions() {
+        let mut ext1 = Marker {
+            owned: Rsv::RSV1,
+            count: 0,
+        };
+        let mut ext2 = Marker {
+            owned: Rsv::RSV2,
+            count: 0,
+        };
+
+        // outbound, each layer only sets its own bit
+        let mut head = make_head(Rsv::NONE);
+        outbound_rsv(&mut [&mut ext1, &mut ext2], &mut head);
+        assert_eq!(head.rsv, Rsv::RSV1.union(Rsv::RSV2));
+
+        // round trip
+        let mut buf = [0u8; 14];
+        let n = unsafe { head.encode_unchecked(&mut buf) };
+        let (mut head, _) = FrameHead::decode(&buf[..n]).unwrap();
+        assert_eq!(head.rsv, Rsv::RSV1.union(Rsv::RSV2));
+
+        // inbound, each layer takes its own bit
+        inbound_rsv(&mut [&mut ext1, &mut ext2], &mut head).unwrap();
+        assert!(head.rsv.is_empty());
+        assert_eq!((ext1.count, ext2.count), (1, 1));
+
+        let mut head = make_head(Rsv::RSV2);
+        inbound_rsv(&mut [&mut ext1, &mut ext2], &mut head).unwrap();
+        assert_eq!((ext1.count, ext2.count), (1, 2));
+
+        // nobody owns rsv3
+        let mut head = make_head(Rsv::RSV1.union(Rsv::RSV3));
+        assert_eq!(
+            inbound_rsv(&mut [&mut ext1, &mut ext2], &mut head),
+            Err(FrameError::IllegalRsv)
+        );
+        assert_eq!((ext1.count, ext2.count), (2, 2));
+    }
+}
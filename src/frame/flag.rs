@@ -1,4 +1,4 @@
-//! Fin flag and opcode.
+//! Fin flag, RSV bits and opcode.
 
 use crate::error::FrameError;
 
@@ -12,6 +12,10 @@ pub enum Fin {
     N = 0x00,
 }
 
+/// RSV bits, which are reserved for extensions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rsv(u8);
+
 /// Frame opcode.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
@@ -34,7 +38,7 @@ impl Fin {
     /// Parse from byte.
     #[inline]
     pub const fn from_flag(b: u8) -> Result<Self, FrameError> {
-        let fin = match b & 0xf0 {
+        let fin = match b & 0x80 {
             0x80 => Fin::Y,
             0x00 => Fin::N,
             _ => return Err(FrameError::IllegalFin),
@@ -43,6 +47,65 @@ impl Fin {
     }
 }
 
+impl Rsv {
+    /// no RSV bit is set
+    pub const NONE: Self = Rsv(0x00);
+    /// RSV1, a byte of 0x40
+    pub const RSV1: Self = Rsv(0x40);
+    /// RSV2, a byte of 0x20
+    pub const RSV2: Self = Rsv(0x20);
+    /// RSV3, a byte of 0x10
+    pub const RSV3: Self = Rsv(0x10);
+    /// all RSV bits
+    pub const ALL: Self = Rsv(0x70);
+
+    /// Parse from byte, other bits are ignored.
+    #[inline]
+    pub const fn from_flag(b: u8) -> Self { Rsv(b & 0x70) }
+
+    /// Convert to byte.
+    #[inline]
+    pub const fn to_flag(self) -> u8 { self.0 }
+
+    /// Check if no bit is set.
+    #[inline]
+    pub const fn is_empty(self) -> bool { self.0 == 0 }
+
+    /// Check if all bits of `other` are set.
+    #[inline]
+    pub const fn contains(self, other: Self) -> bool { self.0 & other.0 == other.0 }
+
+    /// Check if any bit of `other` is set.
+    #[inline]
+    pub const fn intersects(self, other: Self) -> bool { self.0 & other.0 != 0 }
+
+    /// Set bits of `other`.
+    #[inline]
+    pub const fn insert(&mut self, other: Self) { self.0 |= other.0 }
+
+    /// Clear bits of `other`.
+    #[inline]
+    pub const fn remove(&mut self, other: Self) { self.0 &= !other.0 }
+
+    /// Clear bits of `other`, returns the bits which were set.
+    ///
+    /// An extension could take its own bits, leaving the rest unchanged.
+    #[inline]
+    pub const fn take(&mut self, other: Self) -> Self {
+        let bits = self.intersection(other);
+        self.remove(other);
+        bits
+    }
+
+    /// Union of bits.
+    #[inline]
+    pub const fn union(self, other: Self) -> Self { Rsv(self.0 | other.0) }
+
+    /// Intersection of bits.
+    #[inline]
+    pub const fn intersection(self, other: Self) -> Self { Rsv(self.0 & other.0) }
+}
+
 impl OpCode {
     /// Parse from byte.
     #[inline]
@@ -83,4 +146,23 @@ mod test {
     fn opcode() {
         enc_dec!(OpCode, 0x00, 0x01, 0x02, 0x08, 0x09, 0x0a);
     }
+
+    #[test]
+    fn rsv() {
+        for b in 0..=u8::MAX {
+            let rsv = Rsv::from_flag(b);
+            assert_eq!(rsv.to_flag(), b & 0x70);
+            assert_eq!(Fin::from_flag(b).unwrap() as u8, b & 0x80);
+        }
+
+        let mut rsv = Rsv::RSV1.union(Rsv::RSV3);
+        assert!(rsv.contains(Rsv::RSV1));
+        assert!(!rsv.contains(Rsv::RSV2));
+        assert_eq!(rsv.take(Rsv::RSV1.union(Rsv::RSV2)), Rsv::RSV1);
+        assert_eq!(rsv, Rsv::RSV3);
+        rsv.insert(Rsv::RSV2);
+        rsv.remove(Rsv::RSV3);
+        assert_eq!(rsv, Rsv::RSV2);
+        assert!(!rsv.intersects(Rsv::RSV1));
+    }
 }
@@ -14,7 +14,7 @@ pub enum PayloadLen {
 }
 
 impl PayloadLen {
-    /// Parse from number.
+    /// Parse from number, always use the minimal form.
     #[inline]
     pub const fn from_num(n: u64) -> Self {
         if n < 126 {
@@ -47,9 +47,30 @@ impl Mask {
     }
 }
 
-/// Generate a new random mask key.
+/// Generate a new random mask key, which is never all-zero.
 #[inline]
-pub fn new_mask_key() -> [u8; 4] { rand::random::<[u8; 4]>() }
+pub fn new_mask_key() -> [u8; 4] {
+    loop {
+        let key = rand::random::<[u8; 4]>();
+        // an all-zero key provides no masking
+        if u32::from_ne_bytes(key) != 0 {
+            return key;
+        }
+    }
+}
+
+/// Rotate the mask key, so that masking could continue
+/// from the `offset`th byte of a payload.
+#[inline]
+pub const fn rotate_mask_key(key: [u8; 4], offset: usize) -> [u8; 4] {
+    let i = offset & 0x03;
+    [
+        key[i],
+        key[(i + 1) & 0x03],
+        key[(i + 2) & 0x03],
+        key[(i + 3) & 0x03],
+    ]
+}
 
 /// Mask the buffer, byte by byte.
 #[inline]
@@ -85,6 +106,66 @@ pub fn apply_mask4(key: [u8; 4], buf: &mut [u8]) {
     apply_mask(key4.to_ne_bytes(), suffix);
 }
 
+/// Max length of an extended mask key, which is 32.
+#[cfg(feature = "extended_mask")]
+pub const MAX_EXTENDED_MASK_LEN: usize = 32;
+
+/// Mask with a key of configurable length, **which is not part of the spec**.
+///
+/// This is used to layer custom obfuscation over the transport,
+/// e.g. by a proxy, where the application masks payload data before
+/// a write and unmasks them after a read. Frame heads are not affected,
+/// which still carry a 4-byte key as the spec requires.
+#[cfg(feature = "extended_mask")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExtendedMask {
+    key: [u8; MAX_EXTENDED_MASK_LEN],
+    len: u8,
+}
+
+#[cfg(feature = "extended_mask")]
+impl ExtendedMask {
+    /// Create with a key, whose length should be within
+    /// 1..=[`MAX_EXTENDED_MASK_LEN`], otherwise
+    /// a [`FrameError::IllegalMask`] error is returned.
+    pub fn new(key: &[u8]) -> Result<Self, FrameError> {
+        if key.is_empty() || key.len() > MAX_EXTENDED_MASK_LEN {
+            return Err(FrameError::IllegalMask);
+        }
+        let mut buf = [0u8; MAX_EXTENDED_MASK_LEN];
+        buf[..key.len()].copy_from_slice(key);
+        Ok(Self {
+            key: buf,
+            len: key.len() as u8,
+        })
+    }
+
+    /// Create with a 4-byte key, which is the same as the spec.
+    #[inline]
+    pub fn from_key4(key: [u8; 4]) -> Self {
+        let mut buf = [0u8; MAX_EXTENDED_MASK_LEN];
+        buf[..4].copy_from_slice(&key);
+        Self { key: buf, len: 4 }
+    }
+
+    /// Get the key.
+    #[inline]
+    pub fn key(&self) -> &[u8] { &self.key[..self.len as usize] }
+
+    /// Mask the buffer, continue from the `offset`th byte of a payload.
+    /// A 4-byte key is applied 4 bytes at a time.
+    pub fn apply(&self, offset: usize, buf: &mut [u8]) {
+        let len = self.len as usize;
+        if len == 4 {
+            let key = [self.key[0], self.key[1], self.key[2], self.key[3]];
+            return apply_mask4(rotate_mask_key(key, offset), buf);
+        }
+        for (i, b) in buf.iter_mut().enumerate() {
+            *b ^= self.key[(offset + i) % len];
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -125,4 +206,62 @@ mod test {
             assert_eq!(buf, buf2);
         }
     }
+
+    #[test]
+    fn mask_rotate_key() {
+        for offset in 0..64 {
+            let key: [u8; 4] = rand::random();
+            let buf: Vec<u8> = std::iter::repeat(rand::random::<u8>()).take(256).collect();
+
+            let mut buf2 = buf.clone();
+            apply_mask4(key, &mut buf2);
+
+            let mut buf3 = buf.clone();
+            let (left, right) = buf3.split_at_mut(offset);
+            apply_mask4(key, left);
+            apply_mask4(rotate_mask_key(key, offset), right);
+
+            assert_eq!(buf2, buf3);
+        }
+    }
+
+    #[cfg(feature = "extended_mask")]
+    #[test]
+    fn extended_mask() {
+        use crate::frame::{FrameHead, Fin, OpCode, PayloadLen};
+
+        let buf: Vec<u8> = (0..1024).map(|_| rand::random::<u8>()).collect();
+
+        // 8-byte key, masked in several pieces
+        let mask = ExtendedMask::new(&rand::random::<[u8; 8]>()).unwrap();
+        assert_eq!(mask.key().len(), 8);
+        let mut buf2 = buf.clone();
+        mask.apply(0, &mut buf2);
+        assert_ne!(buf2, buf);
+        let (left, right) = buf2.split_at_mut(13);
+        mask.apply(0, left);
+        mask.apply(13, right);
+        assert_eq!(buf2, buf);
+
+        // 4-byte key, same as the spec
+        let key = new_mask_key();
+        let mut buf2 = buf.clone();
+        let mut buf3 = buf.clone();
+        ExtendedMask::from_key4(key).apply(0, &mut buf2);
+        apply_mask4(key, &mut buf3);
+        assert_eq!(buf2, buf3);
+
+        // frame heads still carry a 4-byte key
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Binary,
+            Mask::Key(key),
+            PayloadLen::from_num(100),
+        );
+        let mut buf = [0u8; 14];
+        assert_eq!(head.encode(&mut buf).unwrap(), 2 + 4);
+
+        assert!(ExtendedMask::new(&[]).is_err());
+        assert!(ExtendedMask::new(&[0; MAX_EXTENDED_MASK_LEN + 1]).is_err());
+    }
 }
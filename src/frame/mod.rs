@@ -27,15 +27,24 @@
 pub mod flag;
 pub mod length;
 pub mod mask;
+pub mod close;
+pub mod extension;
+pub mod codec;
 
-pub use flag::{Fin, OpCode};
+pub use flag::{Fin, Rsv, OpCode};
 pub use length::PayloadLen;
 pub use mask::{Mask, new_mask_key, apply_mask4};
+#[cfg(feature = "extended_mask")]
+pub use mask::ExtendedMask;
+pub use close::{CloseCode, encode_close_payload, decode_close_payload};
+pub use extension::{RsvExtension, inbound_rsv, outbound_rsv};
+pub use codec::{WasmFrameCodec, DEFAULT_MAX_FRAME_LEN};
 
 /// Websocket frame head.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FrameHead {
     pub fin: Fin,
+    pub rsv: Rsv,
     pub opcode: OpCode,
     pub mask: Mask,
     pub length: PayloadLen,
@@ -46,22 +55,38 @@ use crate::bleed::{slice, slice_to_array};
 use crate::error::FrameError;
 
 impl FrameHead {
-    /// Constructor.
+    /// Constructor, without any RSV bit.
     #[inline]
     pub const fn new(fin: Fin, opcode: OpCode, mask: Mask, length: PayloadLen) -> Self {
         Self {
             fin,
+            rsv: Rsv::NONE,
             opcode,
             mask,
             length,
         }
     }
 
+    /// Set RSV bits.
+    #[inline]
+    pub const fn with_rsv(mut self, rsv: Rsv) -> Self {
+        self.rsv = rsv;
+        self
+    }
+
     /// Encode to provided buffer, return the count of written bytes.
     ///
+    /// Payload length is always encoded in the minimal form,
+    /// regardless of the variant of [`PayloadLen`]. The most significant bit
+    /// of a 64-bit length must be 0, a length beyond `i64::MAX` leads to
+    /// a [`FrameError::IllegalData`] error.
+    ///
     /// Caller should ensure there is enough space to write,
     /// otherwise a [`FrameError::NotEnoughCapacity`] error will be returned.
     pub fn encode(&self, buf: &mut [u8]) -> Result<usize, FrameError> {
+        if self.length.to_num() > i64::MAX as u64 {
+            return Err(FrameError::IllegalData);
+        }
         if buf.len() < 2 {
             return Err(FrameError::NotEnoughCapacity);
         }
@@ -80,16 +105,19 @@ impl FrameHead {
             };
         }
 
-        // fin, opcode
-        let b1 = self.fin as u8 | self.opcode as u8;
+        // fin, rsv, opcode
+        let b1 = self.fin as u8 | self.rsv.to_flag() | self.opcode as u8;
+
+        // always use the minimal length encoding
+        let length = PayloadLen::from_num(self.length.to_num());
 
         // mask, payload length
-        let b2 = self.mask.to_flag() | self.length.to_flag();
+        let b2 = self.mask.to_flag() | length.to_flag();
 
         writex!(&[b1, b2]);
 
         // extended payload length
-        match &self.length {
+        match &length {
             PayloadLen::Standard(_) => {}
             PayloadLen::Extended1(v) => writex!(&v.to_be_bytes()),
             PayloadLen::Extended2(v) => writex!(&v.to_be_bytes()),
@@ -111,7 +139,9 @@ impl FrameHead {
     ///
     /// Caller must ensure there is enough space to write,
     /// otherwise it is **Undefined Behavior!**
+    /// Caller must also ensure the payload length does not exceed `i64::MAX`.
     pub unsafe fn encode_unchecked(&self, buf: &mut [u8]) -> usize {
+        debug_assert!(self.length.to_num() <= i64::MAX as u64);
         let mut writer = Writer::new(buf);
 
         macro_rules! writex {
@@ -120,16 +150,19 @@ impl FrameHead {
             }};
         }
 
-        // fin, opcode
-        let b1 = self.fin as u8 | self.opcode as u8;
+        // fin, rsv, opcode
+        let b1 = self.fin as u8 | self.rsv.to_flag() | self.opcode as u8;
+
+        // always use the minimal length encoding
+        let length = PayloadLen::from_num(self.length.to_num());
 
         // mask, payload length
-        let b2 = self.mask.to_flag() | self.length.to_flag();
+        let b2 = self.mask.to_flag() | length.to_flag();
 
         writex!(&[b1, b2]);
 
         // extended payload length
-        match &self.length {
+        match &length {
             PayloadLen::Standard(_) => {}
             PayloadLen::Extended1(v) => writex!(&v.to_be_bytes()),
             PayloadLen::Extended2(v) => writex!(&v.to_be_bytes()),
@@ -154,8 +187,6 @@ impl FrameHead {
             return Err(FrameError::NotEnoughData);
         }
 
-        let mut n: usize = 2;
-
         // fin, opcode
         let b1 = unsafe { *buf.get_unchecked(0) };
 
@@ -163,66 +194,146 @@ impl FrameHead {
         let b2 = unsafe { *buf.get_unchecked(1) };
 
         let fin = Fin::from_flag(b1)?;
+        let rsv = Rsv::from_flag(b1);
         let opcode = OpCode::from_flag(b1)?;
 
-        let mut mask = Mask::from_flag(b2)?;
-        let mut length = PayloadLen::from_flag(b2);
+        // check the length of the whole head at once
+        let is_masked = b2 & 0x80 != 0;
+        let n = match b2 & 0x7f {
+            126 => 4,
+            127 => 10,
+            _ => 2,
+        };
+        let head_len = if is_masked { n + 4 } else { n };
+        if buf.len() < head_len {
+            return Err(FrameError::NotEnoughData);
+        }
+
+        let length = match n {
+            4 => PayloadLen::from_byte2(unsafe { *slice_to_array::<_, 2>(slice(buf, 2, 4)) }),
+            10 => PayloadLen::from_byte8(unsafe { *slice_to_array::<_, 8>(slice(buf, 2, 10)) }),
+            _ => PayloadLen::Standard(b2 & 0x7f),
+        };
 
-        match length {
-            PayloadLen::Standard(_) => {}
+        let mask = if is_masked {
+            let key = *unsafe { slice_to_array::<_, 4>(slice(buf, n, n + 4)) };
+            if u32::from_ne_bytes(key) == 0 {
+                Mask::Skip
+            } else {
+                Mask::Key(key)
+            }
+        } else {
+            Mask::None
+        };
+
+        Ok((
+            FrameHead {
+                fin,
+                rsv,
+                opcode,
+                mask,
+                length,
+            },
+            head_len,
+        ))
+    }
+}
+
+impl FrameHead {
+    /// Parse from provided buffer, where the MASK bit must be set,
+    /// returns [`FrameHead`] and the count of read bytes.
+    ///
+    /// This is used by a server, which only accepts masked frames from clients.
+    /// The 4-byte mask key is always read, without checking the MASK bit again.
+    /// If the MASK bit is not set, a [`FrameError::IllegalMask`] error will be returned.
+    ///
+    /// If there is not enough data to parse, a [`FrameError::NotEnoughData`] error
+    /// will be returned.
+    pub fn decode_masked(buf: &[u8]) -> Result<(Self, usize), FrameError> {
+        if buf.len() < 2 {
+            return Err(FrameError::NotEnoughData);
+        }
+
+        // fin, opcode
+        let b1 = unsafe { *buf.get_unchecked(0) };
+
+        // mask, payload length
+        let b2 = unsafe { *buf.get_unchecked(1) };
+
+        if b2 & 0x80 == 0 {
+            return Err(FrameError::IllegalMask);
+        }
+
+        let fin = Fin::from_flag(b1)?;
+        let rsv = Rsv::from_flag(b1);
+        let opcode = OpCode::from_flag(b1)?;
+
+        let (length, n) = match PayloadLen::from_flag(b2) {
             PayloadLen::Extended1(_) => {
-                if buf.len() - n < 2 {
+                if buf.len() < 2 + 2 + 4 {
                     return Err(FrameError::NotEnoughData);
                 }
-
-                length =
+                let length =
                     PayloadLen::from_byte2(unsafe { *slice_to_array::<_, 2>(slice(buf, 2, 4)) });
-
-                n += 2;
+                (length, 4)
             }
             PayloadLen::Extended2(_) => {
-                if buf.len() - n < 8 {
+                if buf.len() < 2 + 8 + 4 {
                     return Err(FrameError::NotEnoughData);
                 }
-
-                length =
+                let length =
                     PayloadLen::from_byte8(unsafe { *slice_to_array::<_, 8>(slice(buf, 2, 10)) });
-
-                n += 8;
+                (length, 10)
             }
-        };
-
-        match mask {
-            Mask::None => {}
-            _ => {
-                if buf.len() - n < 4 {
+            length => {
+                if buf.len() < 2 + 4 {
                     return Err(FrameError::NotEnoughData);
                 }
-
-                let key = *unsafe { slice_to_array::<_, 4>(slice(buf, n, n + 4)) };
-
-                if key.into_iter().all(|b| b == 0) {
-                    mask = Mask::Skip
-                } else {
-                    mask = Mask::Key(key)
-                }
-
-                n += 4;
+                (length, 2)
             }
-        }
+        };
+
+        let key = *unsafe { slice_to_array::<_, 4>(slice(buf, n, n + 4)) };
+        let mask = if u32::from_ne_bytes(key) == 0 {
+            Mask::Skip
+        } else {
+            Mask::Key(key)
+        };
 
         Ok((
             FrameHead {
                 fin,
+                rsv,
                 opcode,
                 mask,
                 length,
             },
-            n,
+            n + 4,
         ))
     }
 }
 
+/// Encode a complete frame with server role, which means the payload is not masked.
+///
+/// These bytes could be written to many clients without re-encoding,
+/// see [`Stream::write_prebuilt`](crate::stream::Stream::write_prebuilt).
+pub fn encode_broadcast_frame(opcode: OpCode, payload: &[u8]) -> Vec<u8> {
+    let head = FrameHead::new(
+        Fin::Y,
+        opcode,
+        Mask::None,
+        PayloadLen::from_num(payload.len() as u64),
+    );
+
+    let mut frame = Vec::with_capacity(14 + payload.len());
+    let mut buf = [0_u8; 14];
+    // The buffer is large enough to accommodate any kind of frame head.
+    let head_len = unsafe { head.encode_unchecked(&mut buf) };
+    frame.extend_from_slice(&buf[..head_len]);
+    frame.extend_from_slice(payload);
+    frame
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -231,6 +342,7 @@ mod test {
     fn frame_head() {
         let head = FrameHead {
             fin: Fin::Y,
+            rsv: Rsv::NONE,
             opcode: OpCode::Binary,
             mask: Mask::Key(mask::new_mask_key()),
             length: PayloadLen::from_num(4096),
@@ -238,12 +350,16 @@ mod test {
 
         let head2 = FrameHead {
             fin: Fin::N,
+            rsv: Rsv::NONE,
             opcode: OpCode::Binary,
             mask: Mask::Key(mask::new_mask_key()),
             length: PayloadLen::from_num(64),
         };
 
-        for head in [head, head2] {
+        let head3 = FrameHead::new(Fin::Y, OpCode::Text, Mask::None, PayloadLen::from_num(0))
+            .with_rsv(Rsv::ALL);
+
+        for head in [head, head2, head3] {
             let mut buf = vec![0; 1024];
 
             let encode_n = head.encode(&mut buf).unwrap();
@@ -262,4 +378,140 @@ mod test {
             assert_eq!(&buf[0..encode_n], &buf2[0..encode_n2]);
         }
     }
+
+    #[test]
+    fn frame_head_masked() {
+        for n in [0, 125, 126, 65535, 65536] {
+            for mask in [Mask::Key(mask::new_mask_key()), Mask::Skip] {
+                let head = FrameHead::new(Fin::Y, OpCode::Binary, mask, PayloadLen::from_num(n));
+                let mut buf = vec![0; 14];
+                let encode_n = head.encode(&mut buf).unwrap();
+
+                assert_eq!(
+                    FrameHead::decode_masked(&buf[..encode_n]),
+                    FrameHead::decode(&buf[..encode_n])
+                );
+                assert_eq!(
+                    FrameHead::decode_masked(&buf[..encode_n - 1]),
+                    Err(FrameError::NotEnoughData)
+                );
+            }
+
+            // unmasked
+            let head = FrameHead::new(Fin::Y, OpCode::Binary, Mask::None, PayloadLen::from_num(n));
+            let mut buf = vec![0; 14];
+            head.encode(&mut buf).unwrap();
+            assert_eq!(FrameHead::decode_masked(&buf), Err(FrameError::IllegalMask));
+        }
+    }
+
+    #[test]
+    fn frame_head_truncated() {
+        for n in [0, 1, 125, 126, 65535, 65536] {
+            for mask in [Mask::None, Mask::Skip, Mask::Key(mask::new_mask_key())] {
+                for (fin, opcode) in [(Fin::Y, OpCode::Text), (Fin::N, OpCode::Continue)] {
+                    let head = FrameHead::new(fin, opcode, mask, PayloadLen::from_num(n));
+                    let mut buf = vec![0; 14];
+                    let encode_n = head.encode(&mut buf).unwrap();
+
+                    assert_eq!(FrameHead::decode(&buf[..encode_n]), Ok((head, encode_n)));
+                    assert_eq!(FrameHead::decode(&buf), Ok((head, encode_n)));
+                    for i in 0..encode_n {
+                        assert_eq!(FrameHead::decode(&buf[..i]), Err(FrameError::NotEnoughData));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn broadcast_frame() {
+        for n in [0, 1, 125, 126, 65535, 65536] {
+            let payload = vec![rand::random::<u8>(); n];
+            let frame = encode_broadcast_frame(OpCode::Binary, &payload);
+
+            let (head, decode_n) = FrameHead::decode(&frame).unwrap();
+            assert_eq!(head.fin, Fin::Y);
+            assert_eq!(head.opcode, OpCode::Binary);
+            assert_eq!(head.mask, Mask::None);
+            assert_eq!(head.length.to_num(), n as u64);
+            assert_eq!(&frame[decode_n..], &payload);
+        }
+    }
+
+    #[test]
+    fn frame_head_mask_bit() {
+        let key = [0x37, 0xfa, 0x21, 0x3d];
+        // 7-bit length, extended length, head length without a mask key
+        for (flag, ext, n, head_len) in [
+            (125_u8, &[][..], 125_u64, 2),
+            (126, &[0x00, 0x7e][..], 126, 4),
+            (126, &[0xff, 0xff][..], 65535, 4),
+            (127, &[0, 0, 0, 0, 0, 0, 0x00, 0x7f][..], 127, 10),
+            (127, &[0, 0, 0, 0, 0, 1, 0x00, 0x00][..], 65536, 10),
+        ] {
+            // unmasked
+            let mut buf = vec![0x82, flag];
+            buf.extend_from_slice(ext);
+            let (head, decode_n) = FrameHead::decode(&buf).unwrap();
+            assert_eq!(decode_n, head_len);
+            assert_eq!(head.mask, Mask::None);
+            assert_eq!(head.length.to_num(), n);
+            assert_eq!(FrameHead::decode_masked(&buf), Err(FrameError::IllegalMask));
+
+            // masked, the length is never affected by the MASK bit
+            let mut buf = vec![0x82, flag | 0x80];
+            buf.extend_from_slice(ext);
+            buf.extend_from_slice(&key);
+            for decode in [FrameHead::decode, FrameHead::decode_masked] {
+                let (head, decode_n) = decode(&buf).unwrap();
+                assert_eq!(decode_n, head_len + 4);
+                assert_eq!(head.mask, Mask::Key(key));
+                assert_eq!(head.length.to_num(), n);
+                assert_eq!(decode(&buf[..head_len + 3]), Err(FrameError::NotEnoughData));
+            }
+        }
+    }
+
+    #[test]
+    fn frame_head_minimal_length() {
+        for (n, head_len) in [
+            (0, 2),
+            (125, 2),
+            (126, 4),
+            (65535, 4),
+            (65536, 10),
+            (i64::MAX as u64, 10),
+        ] {
+            // non-minimal forms
+            let lengths = [
+                PayloadLen::from_num(n),
+                PayloadLen::Extended2(n),
+                PayloadLen::Extended1(n as u16),
+            ];
+            let lengths = if n <= 65535 {
+                &lengths[..]
+            } else {
+                &lengths[..2]
+            };
+
+            for length in lengths {
+                let head = FrameHead::new(Fin::Y, OpCode::Binary, Mask::None, *length);
+                let mut buf = vec![0; 14];
+                let encode_n = head.encode(&mut buf).unwrap();
+                assert_eq!(encode_n, head_len);
+                assert_eq!(unsafe { head.encode_unchecked(&mut buf) }, head_len);
+
+                let (head2, _) = FrameHead::decode(&buf).unwrap();
+                assert_eq!(head2.length, PayloadLen::from_num(n));
+            }
+        }
+
+        // the most significant bit must be 0
+        for n in [i64::MAX as u64 + 1, u64::MAX] {
+            let head = FrameHead::new(Fin::Y, OpCode::Binary, Mask::None, PayloadLen::from_num(n));
+            let mut buf = vec![0; 14];
+            assert_eq!(head.encode(&mut buf), Err(FrameError::IllegalData));
+        }
+    }
 }
@@ -0,0 +1,171 @@
+//! Websocket extensions.
+//!
+//! From [RFC-6455 Section 9.1](https://datatracker.ietf.org/doc/html/rfc6455#section-9.1):
+//!
+//! ```text
+//! Sec-WebSocket-Extensions = extension-list
+//! extension-list = 1#extension
+//! extension = extension-token *( ";" extension-param )
+//! extension-token = registered-token
+//! registered-token = token
+//! extension-param = token [ "=" (token | quoted-string) ]
+//! ```
+//!
+//! Example:
+//!
+//! ```text
+//! sec-websocket-extensions: permessage-deflate; server_max_window_bits=12
+//! ```
+//!
+//! Unlike other handshake types, extensions are parsed into owned values,
+//! so that they could be kept by [`Stream`](crate::stream::Stream)
+//! after the handshake.
+
+use super::HttpHeader;
+use super::static_headers::HEADER_SEC_WEBSOCKET_EXTENSIONS_NAME;
+
+use crate::error::HandshakeError;
+
+/// An extension with its params.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Extension {
+    pub name: String,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl Extension {
+    /// Get the value of a param, returns `Some(None)`
+    /// if the param is present without a value.
+    pub fn param(&self, name: &str) -> Option<Option<&str>> {
+        self.params
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_deref())
+    }
+}
+
+/// A list of extensions, in the order of negotiation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Extensions {
+    pub list: Vec<Extension>,
+}
+
+impl Extensions {
+    /// Create an empty list.
+    #[inline]
+    pub const fn new() -> Self { Self { list: Vec::new() } }
+
+    /// Check if there is no extension.
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.list.is_empty() }
+
+    /// Get an extension by its name (case insensitive).
+    pub fn get(&self, name: &str) -> Option<&Extension> {
+        self.list.iter().find(|e| e.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Parse the value of a `sec-websocket-extensions` header,
+    /// and append extensions to the list.
+    ///
+    /// An empty extension or param name leads to
+    /// a [`HandshakeError::SecWebSocketExtensions`] error.
+    pub fn parse(&mut self, value: &[u8]) -> Result<(), HandshakeError> {
+        let value =
+            std::str::from_utf8(value).map_err(|_| HandshakeError::SecWebSocketExtensions)?;
+
+        for ext in value.split(',') {
+            let mut items = ext.split(';').map(str::trim);
+            // the first item is always present
+            let name = items.next().unwrap();
+            if name.is_empty() {
+                return Err(HandshakeError::SecWebSocketExtensions);
+            }
+
+            let mut params = Vec::new();
+            for param in items {
+                let (k, v) = match param.split_once('=') {
+                    Some((k, v)) => (k.trim(), Some(v.trim().trim_matches('"').to_string())),
+                    None => (param, None),
+                };
+                if k.is_empty() {
+                    return Err(HandshakeError::SecWebSocketExtensions);
+                }
+                params.push((k.to_string(), v));
+            }
+
+            self.list.push(Extension {
+                name: name.to_string(),
+                params,
+            });
+        }
+        Ok(())
+    }
+
+    /// Parse all `sec-websocket-extensions` headers.
+    pub fn from_headers(headers: &[HttpHeader]) -> Result<Self, HandshakeError> {
+        let mut extensions = Self::new();
+        for hdr in headers.iter().filter(|h| {
+            h.name
+                .eq_ignore_ascii_case(HEADER_SEC_WEBSOCKET_EXTENSIONS_NAME)
+        }) {
+            extensions.parse(hdr.value)?;
+        }
+        Ok(extensions)
+    }
+
+    /// Check if each extension (accepted by server) has been offered (by client),
+    /// otherwise a [`HandshakeError::SecWebSocketExtensions`] error is returned.
+    pub fn check_offered(&self, offered: &Extensions) -> Result<(), HandshakeError> {
+        if self.list.iter().all(|e| offered.get(&e.name).is_some()) {
+            Ok(())
+        } else {
+            Err(HandshakeError::SecWebSocketExtensions)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_extensions() {
+        let mut extensions = Extensions::new();
+        extensions
+            .parse(b"permessage-deflate; client_max_window_bits; server_max_window_bits=\"10\"")
+            .unwrap();
+        extensions.parse(b"foo, bar; baz=1").unwrap();
+
+        let names: Vec<&str> = extensions.list.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["permessage-deflate", "foo", "bar"]);
+
+        let deflate = extensions.get("PerMessage-Deflate").unwrap();
+        assert_eq!(deflate.param("client_max_window_bits"), Some(None));
+        assert_eq!(deflate.param("server_max_window_bits"), Some(Some("10")));
+        assert_eq!(deflate.param("server_no_context_takeover"), None);
+        assert_eq!(extensions.get("bar").unwrap().param("baz"), Some(Some("1")));
+
+        for value in [&b""[..], b"foo,", b"foo; =1", b"\xff"] {
+            let e = Extensions::new().parse(value).unwrap_err();
+            assert_eq!(e, HandshakeError::SecWebSocketExtensions);
+        }
+    }
+
+    #[test]
+    fn check_offered_extensions() {
+        let mut offered = Extensions::new();
+        offered
+            .parse(b"permessage-deflate; client_max_window_bits")
+            .unwrap();
+
+        let mut accepted = Extensions::new();
+        accepted
+            .parse(b"permessage-deflate; server_max_window_bits=12")
+            .unwrap();
+        assert!(accepted.check_offered(&offered).is_ok());
+        assert!(Extensions::new().check_offered(&offered).is_ok());
+
+        let e = accepted.check_offered(&Extensions::new()).unwrap_err();
+        assert_eq!(e, HandshakeError::SecWebSocketExtensions);
+    }
+}
@@ -13,6 +13,13 @@ pub fn new_sec_key() -> [u8; 24] {
     output
 }
 
+/// Scheme to derive `sec-websocket-accept` from `sec-websocket-key`.
+///
+/// The default is [`derive_accept_key`], as specified by RFC-6455.
+/// Other schemes are for interop with legacy peers only,
+/// which speak an older draft of the protocol. Never use them otherwise.
+pub type AcceptKeyScheme = fn(&[u8]) -> [u8; 28];
+
 /// Derive `sec-websocket-accept` from `sec-websocket-key`.
 #[inline]
 pub fn derive_accept_key(sec_key: &[u8]) -> [u8; 28] {
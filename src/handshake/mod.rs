@@ -3,10 +3,17 @@
 pub mod key;
 pub mod request;
 pub mod response;
+pub mod extension;
+pub mod transcript;
+pub mod probe;
 
 pub use request::Request;
-pub use response::Response;
-pub use key::{new_sec_key, derive_accept_key};
+pub use response::{Response, encode_version_rejection, encode_protocol_rejection};
+pub use response::{encode_header_rejection, encode_method_rejection};
+pub use key::{new_sec_key, derive_accept_key, AcceptKeyScheme};
+pub use extension::{Extension, Extensions};
+pub use transcript::{Transcript, DEFAULT_TRANSCRIPT_LIMIT};
+pub use probe::{Probe, looks_like_ws_upgrade};
 
 /// 32
 pub const MAX_ALLOW_HEADERS: usize = 32;
@@ -32,6 +39,18 @@ pub const HTTP_HEADER_SP: &[u8] = b": ";
 /// HTTP/1.1 101 Switching Protocols
 pub const HTTP_STATUS_LINE: &[u8] = b"HTTP/1.1 101 Switching Protocols";
 
+/// HTTP/1.1 426 Upgrade Required
+pub const HTTP_UPGRADE_REQUIRED_LINE: &[u8] = b"HTTP/1.1 426 Upgrade Required";
+
+/// HTTP/1.1 400 Bad Request
+pub const HTTP_BAD_REQUEST_LINE: &[u8] = b"HTTP/1.1 400 Bad Request";
+
+/// HTTP/1.1 405 Method Not Allowed
+pub const HTTP_METHOD_NOT_ALLOWED_LINE: &[u8] = b"HTTP/1.1 405 Method Not Allowed";
+
+/// HTTP/1.1 431 Request Header Fields Too Large
+pub const HTTP_HEADER_TOO_LARGE_LINE: &[u8] = b"HTTP/1.1 431 Request Header Fields Too Large";
+
 /// Http header, take two references
 #[allow(clippy::len_without_is_empty)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -189,6 +208,9 @@ pub mod static_headers {
 
         /// sec-webSocket-version: 13
         (HEADER_SEC_WEBSOCKET_VERSION => b"sec-webSocket-version", b"");
+
+        /// allow: GET
+        (HEADER_ALLOW => b"allow", b"GET");
     );
 
     // header name
@@ -204,6 +226,10 @@ pub mod static_headers {
         (HEADER_SEC_WEBSOCKET_ACCEPT_NAME => b"sec-websocket-accept");
 
         (HEADER_SEC_WEBSOCKET_VERSION_NAME => b"sec-websocket-version");
+
+        (HEADER_SEC_WEBSOCKET_EXTENSIONS_NAME => b"sec-websocket-extensions");
+
+        (HEADER_SEC_WEBSOCKET_PROTOCOL_NAME => b"sec-websocket-protocol");
     }
 
     // header value
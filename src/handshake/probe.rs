@@ -0,0 +1,104 @@
+//! Detect a websocket handshake from the initial bytes.
+//!
+//! This is used to serve websocket and other protocols on the same port,
+//! where the first bytes are peeked (e.g. [`std::net::TcpStream::peek`])
+//! before deciding how to handle the connection.
+
+use super::static_headers::{HEADER_UPGRADE_NAME, HEADER_UPGRADE_VALUE};
+
+/// Result of [`looks_like_ws_upgrade`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// a request to upgrade to websocket
+    Yes,
+    /// not a request to upgrade to websocket
+    No,
+    /// not enough bytes to decide, peek more then try again
+    Maybe,
+}
+
+/// Check if the bytes look like an http request to upgrade to websocket,
+/// which is a `GET` request line followed by an `upgrade: websocket` header.
+///
+/// The request is not validated, other headers are left to
+/// [`Request::decode`](super::Request::decode). If the bytes end before
+/// the `upgrade` header or the end of headers, [`Probe::Maybe`] is returned.
+pub fn looks_like_ws_upgrade(buf: &[u8]) -> Probe {
+    const METHOD: &[u8] = b"GET ";
+    if buf.len() < METHOD.len() {
+        return if buf == &METHOD[..buf.len()] {
+            Probe::Maybe
+        } else {
+            Probe::No
+        };
+    }
+    if &buf[..METHOD.len()] != METHOD {
+        return Probe::No;
+    }
+
+    let mut lines = buf.split(|x| *x == b'\n');
+    // the last piece is incomplete
+    lines.next_back();
+
+    let mut lines = lines.map(|line| line.strip_suffix(b"\r").unwrap_or(line));
+    match lines.next() {
+        Some(line) if line.windows(6).any(|x| x == b" HTTP/") => {}
+        Some(_) => return Probe::No,
+        None => return Probe::Maybe,
+    }
+    for line in lines {
+        // end of headers
+        if line.is_empty() {
+            return Probe::No;
+        }
+        let (name, value) = match line.iter().position(|x| *x == b':') {
+            Some(i) => (&line[..i], &line[i + 1..]),
+            None => return Probe::No,
+        };
+        if name.trim_ascii().eq_ignore_ascii_case(HEADER_UPGRADE_NAME) {
+            let is_ws = value
+                .split(|x| *x == b',')
+                .any(|x| x.trim_ascii().eq_ignore_ascii_case(HEADER_UPGRADE_VALUE));
+            return if is_ws { Probe::Yes } else { Probe::No };
+        }
+    }
+    Probe::Maybe
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const REQUEST: &[u8] = b"\
+        GET /ws HTTP/1.1\r\n\
+        host: www.example.com\r\n\
+        Upgrade: WebSocket\r\n\
+        connection: upgrade\r\n\
+        sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+        sec-websocket-version: 13\r\n\r\n";
+
+    #[test]
+    fn probe_upgrade() {
+        assert_eq!(looks_like_ws_upgrade(REQUEST), Probe::Yes);
+        // until the upgrade header is complete
+        let n = REQUEST
+            .windows(12)
+            .position(|x| x == b"\r\nconnection")
+            .unwrap();
+        for i in 0..=n {
+            assert_eq!(looks_like_ws_upgrade(&REQUEST[..i]), Probe::Maybe, "{}", i);
+        }
+        assert_eq!(looks_like_ws_upgrade(&REQUEST[..n + 2]), Probe::Yes);
+
+        for buf in [
+            &b"GET / HTTP/1.1\r\nhost: example.com\r\n\r\n"[..],
+            b"GET / HTTP/1.1\r\nupgrade: h2c\r\n\r\n",
+            b"POST /ws HTTP/1.1\r\nupgrade: websocket\r\n\r\n",
+            b"GET /ws\r\n",
+            b"PRI * HTTP/2.0\r\n",
+            b"\x16\x03\x01",
+        ] {
+            assert_eq!(looks_like_ws_upgrade(buf), Probe::No);
+        }
+    }
+}
@@ -27,6 +27,7 @@ use super::{HttpHeader, HeaderHelper};
 use super::{write_header, filter_header};
 use super::handshake_check;
 use super::MAX_ALLOW_HEADERS;
+use super::Extensions;
 use super::{HTTP_METHOD, HTTP_VERSION, HTTP_LINE_BREAK, HTTP_HEADER_SP};
 use super::static_headers::*;
 
@@ -105,6 +106,72 @@ impl<'h, 'b: 'h, const N: usize> Request<'h, 'b, N> {
         }
     }
 
+    /// Parse `sec-websocket-extensions` headers of the request.
+    ///
+    /// See also: [`Extensions::from_headers`].
+    #[inline]
+    pub fn extensions(&self) -> Result<Extensions, HandshakeError> {
+        Extensions::from_headers(self.other_headers)
+    }
+
+    /// Iterate over subprotocols offered in `sec-websocket-protocol` headers,
+    /// in the order of preference.
+    pub fn protocols(&self) -> impl Iterator<Item = &'b [u8]> + '_ {
+        self.other_headers
+            .iter()
+            .filter(|h| {
+                h.name
+                    .eq_ignore_ascii_case(HEADER_SEC_WEBSOCKET_PROTOCOL_NAME)
+            })
+            .flat_map(|h| h.value.split(|&b| b == b','))
+            .map(|p| p.trim_ascii())
+            .filter(|p| !p.is_empty())
+    }
+
+    /// Get the path without the query string, e.g. `/chat` of `/chat?room=1`,
+    /// which is used to route the request.
+    #[inline]
+    pub fn path_only(&self) -> &'b [u8] {
+        match self.path.iter().position(|&b| b == b'?') {
+            Some(n) => &self.path[..n],
+            None => self.path,
+        }
+    }
+
+    /// Get the query string after `?` of the path, e.g. `room=1` of `/chat?room=1`.
+    /// It is not decoded.
+    #[inline]
+    pub fn query(&self) -> Option<&'b [u8]> {
+        self.path
+            .iter()
+            .position(|&b| b == b'?')
+            .map(|n| &self.path[n + 1..])
+    }
+
+    /// Get the value of the first header with the name (case insensitive).
+    ///
+    /// Besides `other_headers`, `host` and `sec-websocket-key` are looked up.
+    /// Other required headers are checked then dropped by
+    /// [`decode`](Self::decode), which are not available.
+    #[inline]
+    pub fn header(&self, name: &[u8]) -> Option<&'b [u8]> { self.headers(name).next() }
+
+    /// Iterate over values of all headers with the name (case insensitive),
+    /// in the order of appearance, see also [`header`](Self::header).
+    pub fn headers<'a>(&'a self, name: &'a [u8]) -> impl Iterator<Item = &'b [u8]> + 'a {
+        let required = [
+            (HEADER_HOST_NAME, self.host),
+            (HEADER_SEC_WEBSOCKET_KEY_NAME, self.sec_key),
+        ];
+        let other = self.other_headers.iter().map(|h| (h.name, h.value));
+        required
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .chain(other)
+            .filter(move |(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
     /// Encode to a provided buffer, return the number of written bytes.
     ///
     /// Necessary headers, including `host`, `upgrade`, `connection`,
@@ -113,7 +180,25 @@ impl<'h, 'b: 'h, const N: usize> Request<'h, 'b, N> {
     ///
     /// Caller should make sure there is enough space to write,
     /// otherwise a [`HandshakeError::NotEnoughCapacity`] error will be returned.
+    #[inline]
     pub fn encode(&self, buf: &mut [u8]) -> Result<usize, HandshakeError> {
+        self.encode_with_order(&[], buf)
+    }
+
+    /// Encode to a provided buffer like [`Request::encode`], where headers
+    /// are written in the order of `order`, e.g. to match a browser.
+    ///
+    /// Each name in `order` is matched against all headers(case insensitive),
+    /// including necessary ones and other headers, and the matched headers are
+    /// written with the name as provided, so that its casing is also kept.
+    /// Other headers with the same name are written together, in their order.
+    /// Then headers not listed are written as `Request::encode` does.
+    /// Header values are written as is.
+    pub fn encode_with_order(
+        &self,
+        order: &[&[u8]],
+        buf: &mut [u8],
+    ) -> Result<usize, HandshakeError> {
         debug_assert!(buf.len() > 80);
 
         let mut w = Writer::new(buf);
@@ -128,28 +213,59 @@ impl<'h, 'b: 'h, const N: usize> Request<'h, 'b, N> {
             w.write_unchecked(HTTP_LINE_BREAK);
         }
 
-        // host: {host}
-        write_header!(w, HEADER_HOST_NAME, self.host);
-
-        // upgrade: websocket
-        write_header!(w, HEADER_UPGRADE_NAME, HEADER_UPGRADE_VALUE);
-
-        // connection: upgrade
-        write_header!(w, HEADER_CONNECTION_NAME, HEADER_CONNECTION_VALUE);
-
-        // sec-websocket-key: {sec_key}
-        write_header!(w, HEADER_SEC_WEBSOCKET_KEY_NAME, self.sec_key);
+        let required_headers = [
+            // host: {host}
+            HttpHeader::new(HEADER_HOST_NAME, self.host),
+            // upgrade: websocket
+            HttpHeader::new(HEADER_UPGRADE_NAME, HEADER_UPGRADE_VALUE),
+            // connection: upgrade
+            HttpHeader::new(HEADER_CONNECTION_NAME, HEADER_CONNECTION_VALUE),
+            // sec-websocket-key: {sec_key}
+            HttpHeader::new(HEADER_SEC_WEBSOCKET_KEY_NAME, self.sec_key),
+            // sec-websocket-version: 13
+            HttpHeader::new(
+                HEADER_SEC_WEBSOCKET_VERSION_NAME,
+                HEADER_SEC_WEBSOCKET_VERSION_VALUE,
+            ),
+        ];
+        let mut is_written = [false; 5];
+        let is_listed =
+            |names: &[&[u8]], name: &[u8]| names.iter().any(|n| n.eq_ignore_ascii_case(name));
+
+        // listed headers
+        for (i, name) in order.iter().enumerate() {
+            if is_listed(&order[..i], name) {
+                continue;
+            }
+            if let Some(idx) = required_headers
+                .iter()
+                .position(|h| h.name.eq_ignore_ascii_case(name))
+            {
+                write_header!(w, name, required_headers[idx].value);
+                is_written[idx] = true;
+                continue;
+            }
+            for hdr in self
+                .other_headers
+                .iter()
+                .filter(|h| h.name.eq_ignore_ascii_case(name))
+            {
+                write_header!(w, name, hdr.value);
+            }
+        }
 
-        // sec-websocket-version: 13
-        write_header!(
-            w,
-            HEADER_SEC_WEBSOCKET_VERSION_NAME,
-            HEADER_SEC_WEBSOCKET_VERSION_VALUE
-        );
+        // necessary headers
+        for (hdr, is_written) in required_headers.iter().zip(is_written) {
+            if !is_written {
+                write_header!(w, hdr);
+            }
+        }
 
         // other headers
         for hdr in self.other_headers.iter() {
-            write_header!(w, hdr)
+            if !is_listed(order, hdr.name) {
+                write_header!(w, hdr);
+            }
         }
 
         // finish with CRLF
@@ -343,5 +459,111 @@ mod test {
         run!("wwww.www.ww.w", "/path/to/to/path", "xxxxxxyyyy");
     }
 
+    #[test]
+    fn client_handshake_with_order() {
+        let mut other_headers = [
+            HttpHeader::new(b"user-agent", b"Mozilla/5.0"),
+            HttpHeader::new(b"pragma", b"no-cache"),
+            HttpHeader::new(b"origin", b"http://www.example.com"),
+            HttpHeader::new(b"Cache-Control", b"no-cache"),
+        ];
+        let request = Request::new_with_headers(
+            b"/ws",
+            b"www.example.com",
+            b"dGhlIHNhbXBsZSBub25jZQ==",
+            &mut other_headers,
+        );
+        let order: [&[u8]; 8] = [
+            b"Host",
+            b"Connection",
+            b"Pragma",
+            b"Cache-Control",
+            b"User-Agent",
+            b"Upgrade",
+            b"Origin",
+            b"Sec-WebSocket-Version",
+        ];
+
+        let mut buf: Vec<u8> = vec![0; 0x4000];
+        let n = request.encode_with_order(&order, &mut buf).unwrap();
+        assert_eq!(
+            std::str::from_utf8(&buf[..n]).unwrap(),
+            "GET /ws HTTP/1.1\r\n\
+            Host: www.example.com\r\n\
+            Connection: upgrade\r\n\
+            Pragma: no-cache\r\n\
+            Cache-Control: no-cache\r\n\
+            User-Agent: Mozilla/5.0\r\n\
+            Upgrade: websocket\r\n\
+            Origin: http://www.example.com\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            \r\n"
+        );
+
+        // decoded as usual
+        let mut other_headers = HttpHeader::new_storage();
+        let mut request2 = Request::new_storage(&mut other_headers);
+        assert_eq!(request2.decode(&buf[..n]).unwrap(), n);
+        assert_eq!(request2.sec_key, request.sec_key);
+
+        // same as encode without an order
+        let n = request.encode_with_order(&[], &mut buf).unwrap();
+        let mut buf2: Vec<u8> = vec![0; 0x4000];
+        let n2 = request.encode(&mut buf2).unwrap();
+        assert_eq!(&buf[..n], &buf2[..n2]);
+    }
+
+    #[test]
+    fn server_handshake_path_and_headers() {
+        let buf = b"\
+            GET /chat?room=1&token=abc HTTP/1.1\r\n\
+            Host: www.example.com\r\n\
+            Upgrade: websocket\r\n\
+            Connection: Upgrade\r\n\
+            Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+            Sec-WebSocket-Version: 13\r\n\
+            Authorization: Bearer abc\r\n\
+            Cookie: a=1\r\n\
+            cookie: b=2\r\n\r\n";
+
+        let mut other_headers = HttpHeader::new_storage();
+        let mut request = Request::new_storage(&mut other_headers);
+        assert_eq!(request.decode(buf).unwrap(), buf.len());
+
+        assert_eq!(request.path, b"/chat?room=1&token=abc");
+        assert_eq!(request.path_only(), b"/chat");
+        assert_eq!(request.query(), Some(&b"room=1&token=abc"[..]));
+
+        assert_eq!(request.header(b"authorization"), Some(&b"Bearer abc"[..]));
+        assert_eq!(request.header(b"HOST"), Some(&b"www.example.com"[..]));
+        assert_eq!(
+            request.header(b"sec-websocket-key"),
+            Some(&b"dGhlIHNhbXBsZSBub25jZQ=="[..])
+        );
+        assert_eq!(request.header(b"origin"), None);
+        let cookies: Vec<&[u8]> = request.headers(b"COOKIE").collect();
+        assert_eq!(cookies, [b"a=1", b"b=2"]);
+
+        // without a query string
+        let buf = std::str::from_utf8(buf)
+            .unwrap()
+            .replace("/chat?room=1&token=abc", "/chat");
+        let mut other_headers = HttpHeader::new_storage();
+        let mut request = Request::new_storage(&mut other_headers);
+        request.decode(buf.as_bytes()).unwrap();
+        assert_eq!(request.path_only(), b"/chat");
+        assert_eq!(request.query(), None);
+
+        // not a GET request
+        let buf = buf.replace("GET", "POST");
+        let mut other_headers = HttpHeader::new_storage();
+        let mut request = Request::new_storage(&mut other_headers);
+        assert_eq!(
+            request.decode(buf.as_bytes()),
+            Err(HandshakeError::HttpMethod)
+        );
+    }
+
     // catch errors ...
 }
@@ -24,7 +24,10 @@ use super::{HttpHeader, HeaderHelper};
 use super::{write_header, filter_header};
 use super::handshake_check;
 use super::MAX_ALLOW_HEADERS;
-use super::{HTTP_STATUS_LINE, HTTP_LINE_BREAK, HTTP_HEADER_SP};
+use super::Extensions;
+use super::{HTTP_STATUS_LINE, HTTP_UPGRADE_REQUIRED_LINE, HTTP_BAD_REQUEST_LINE};
+use super::{HTTP_METHOD_NOT_ALLOWED_LINE, HTTP_HEADER_TOO_LARGE_LINE};
+use super::{HTTP_LINE_BREAK, HTTP_HEADER_SP};
 use super::static_headers::*;
 
 use crate::bleed::Writer;
@@ -90,6 +93,14 @@ impl<'h, 'b: 'h, const N: usize> Response<'h, 'b, N> {
         }
     }
 
+    /// Parse `sec-websocket-extensions` headers of the response.
+    ///
+    /// See also: [`Extensions::from_headers`].
+    #[inline]
+    pub fn extensions(&self) -> Result<Extensions, HandshakeError> {
+        Extensions::from_headers(self.other_headers)
+    }
+
     /// Encode to a provided buffer, return the number of written bytes.
     ///
     /// Necessary headers, including `upgrade`, `connection`, and
@@ -219,6 +230,113 @@ impl<'h, 'b: 'h, const N: usize> Response<'h, 'b, N> {
     }
 }
 
+/// Encode a response to reject an unsupported websocket version,
+/// return the number of written bytes.
+///
+/// From [RFC-6455 Section 4.4](https://datatracker.ietf.org/doc/html/rfc6455#section-4.4),
+/// the response has a status code of 426, and a `sec-websocket-version`
+/// header listing the supported version, which is 13.
+/// This is usually used after a request fails with
+/// [`HandshakeError::SecWebSocketVersion`].
+///
+/// Caller should make sure there is enough space to write,
+/// otherwise a [`HandshakeError::NotEnoughCapacity`] error will be returned.
+pub fn encode_version_rejection(buf: &mut [u8]) -> Result<usize, HandshakeError> {
+    let mut w = Writer::new(buf);
+
+    // HTTP/1.1 426 Upgrade Required
+    w.write_or_err(HTTP_UPGRADE_REQUIRED_LINE, || {
+        HandshakeError::NotEnoughCapacity
+    })?;
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    // sec-websocket-version: 13
+    write_header!(
+        w,
+        HEADER_SEC_WEBSOCKET_VERSION_NAME,
+        HEADER_SEC_WEBSOCKET_VERSION_VALUE
+    );
+
+    // finish with CRLF
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    Ok(w.pos())
+}
+
+/// Encode a response to reject a request where none of the offered
+/// subprotocols is acceptable, return the number of written bytes.
+///
+/// The response has a status code of 400, without any header.
+/// This is usually used after a handshake fails with
+/// [`HandshakeError::SecWebSocketProtocol`].
+///
+/// Caller should make sure there is enough space to write,
+/// otherwise a [`HandshakeError::NotEnoughCapacity`] error will be returned.
+pub fn encode_protocol_rejection(buf: &mut [u8]) -> Result<usize, HandshakeError> {
+    let mut w = Writer::new(buf);
+
+    // HTTP/1.1 400 Bad Request
+    w.write_or_err(HTTP_BAD_REQUEST_LINE, || HandshakeError::NotEnoughCapacity)?;
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    // finish with CRLF
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    Ok(w.pos())
+}
+
+/// Encode a response to reject a request with a header which is too large,
+/// return the number of written bytes.
+///
+/// The response has a status code of 431, without any header.
+/// This is usually used after a request fails with
+/// [`HandshakeError::HeaderTooLarge`].
+///
+/// Caller should make sure there is enough space to write,
+/// otherwise a [`HandshakeError::NotEnoughCapacity`] error will be returned.
+pub fn encode_header_rejection(buf: &mut [u8]) -> Result<usize, HandshakeError> {
+    let mut w = Writer::new(buf);
+
+    // HTTP/1.1 431 Request Header Fields Too Large
+    w.write_or_err(HTTP_HEADER_TOO_LARGE_LINE, || {
+        HandshakeError::NotEnoughCapacity
+    })?;
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    // finish with CRLF
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    Ok(w.pos())
+}
+
+/// Encode a response to reject a request with a method other than `GET`,
+/// return the number of written bytes.
+///
+/// The response has a status code of 405, and an `allow` header
+/// listing the only allowed method, which is `GET`.
+/// This is usually used after a request fails with
+/// [`HandshakeError::HttpMethod`].
+///
+/// Caller should make sure there is enough space to write,
+/// otherwise a [`HandshakeError::NotEnoughCapacity`] error will be returned.
+pub fn encode_method_rejection(buf: &mut [u8]) -> Result<usize, HandshakeError> {
+    let mut w = Writer::new(buf);
+
+    // HTTP/1.1 405 Method Not Allowed
+    w.write_or_err(HTTP_METHOD_NOT_ALLOWED_LINE, || {
+        HandshakeError::NotEnoughCapacity
+    })?;
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    // allow: GET
+    write_header!(w, HEADER_ALLOW);
+
+    // finish with CRLF
+    w.write_or_err(HTTP_LINE_BREAK, || HandshakeError::NotEnoughCapacity)?;
+
+    Ok(w.pos())
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -298,5 +416,56 @@ mod test {
         run!("xxxxxxxxx==");
     }
 
+    #[test]
+    fn version_negotiation() {
+        use super::super::Request;
+
+        fn make_request(version: &str) -> String {
+            format!(
+                "GET /ws HTTP/1.1\r\n\
+                host: www.example.com\r\n\
+                upgrade: websocket\r\n\
+                connection: upgrade\r\n\
+                sec-websocket-key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                sec-websocket-version: {}\r\n\r\n",
+                version
+            )
+        }
+
+        // accepted
+        let request = make_request("13");
+        let mut other_headers = HttpHeader::new_storage();
+        let mut req = Request::new_storage(&mut other_headers);
+        assert!(req.decode(request.as_bytes()).is_ok());
+
+        // rejected
+        let request = make_request("8");
+        let mut other_headers = HttpHeader::new_storage();
+        let mut req = Request::new_storage(&mut other_headers);
+        assert_eq!(
+            req.decode(request.as_bytes()),
+            Err(HandshakeError::SecWebSocketVersion)
+        );
+
+        let mut buf = [0u8; 128];
+        let n = encode_version_rejection(&mut buf).unwrap();
+        assert_eq!(
+            &buf[..n],
+            b"HTTP/1.1 426 Upgrade Required\r\nsec-websocket-version: 13\r\n\r\n"
+        );
+
+        let mut headers = [httparse::EMPTY_HEADER; 4];
+        let mut response = httparse::Response::new(&mut headers);
+        assert!(response.parse(&buf[..n]).unwrap().is_complete());
+        assert_eq!(response.code, Some(426));
+        assert_eq!(response.headers[0].name, "sec-websocket-version");
+        assert_eq!(response.headers[0].value, b"13");
+
+        assert_eq!(
+            encode_version_rejection(&mut buf[..n - 1]),
+            Err(HandshakeError::NotEnoughCapacity)
+        );
+    }
+
     // catch errors ...
 }
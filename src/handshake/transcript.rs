@@ -0,0 +1,94 @@
+//! Raw bytes of a handshake.
+//!
+//! A [`Transcript`] is used for debugging, e.g. to find out which header
+//! is modified by a misbehaving proxy. It allocates, so it is only
+//! used when explicitly provided, see
+//! [`Endpoint::connect_with_transcript`](crate::endpoint::Endpoint::connect_with_transcript)
+//! and [`Endpoint::accept_with_transcript`](crate::endpoint::Endpoint::accept_with_transcript).
+
+/// 4096
+pub const DEFAULT_TRANSCRIPT_LIMIT: usize = 4096;
+
+/// Retained request and response bytes.
+///
+/// At most `limit` bytes are kept for each of them,
+/// the rest are dropped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transcript {
+    request: Vec<u8>,
+    response: Vec<u8>,
+    limit: usize,
+    is_truncated: bool,
+}
+
+impl Default for Transcript {
+    fn default() -> Self { Self::new() }
+}
+
+impl Transcript {
+    /// Create with the default limit, [`DEFAULT_TRANSCRIPT_LIMIT`].
+    #[inline]
+    pub const fn new() -> Self { Self::with_limit(DEFAULT_TRANSCRIPT_LIMIT) }
+
+    /// Create with a limit on the retained size of the request or response.
+    #[inline]
+    pub const fn with_limit(limit: usize) -> Self {
+        Self {
+            request: Vec::new(),
+            response: Vec::new(),
+            limit,
+            is_truncated: false,
+        }
+    }
+
+    /// Raw bytes of the request.
+    #[inline]
+    pub fn request_bytes(&self) -> &[u8] { &self.request }
+
+    /// Raw bytes of the response.
+    #[inline]
+    pub fn response_bytes(&self) -> &[u8] { &self.response }
+
+    /// Check if some bytes are dropped due to the limit.
+    #[inline]
+    pub const fn is_truncated(&self) -> bool { self.is_truncated }
+
+    #[inline]
+    pub(crate) fn record_request(&mut self, bytes: &[u8]) {
+        Self::record(&mut self.request, self.limit, &mut self.is_truncated, bytes)
+    }
+
+    #[inline]
+    pub(crate) fn record_response(&mut self, bytes: &[u8]) {
+        Self::record(
+            &mut self.response,
+            self.limit,
+            &mut self.is_truncated,
+            bytes,
+        )
+    }
+
+    fn record(store: &mut Vec<u8>, limit: usize, is_truncated: &mut bool, bytes: &[u8]) {
+        let n = std::cmp::min(bytes.len(), limit);
+        store.clear();
+        store.extend_from_slice(&bytes[..n]);
+        *is_truncated |= n < bytes.len();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn transcript_limit() {
+        let mut transcript = Transcript::with_limit(4);
+        transcript.record_request(b"GET");
+        assert_eq!(transcript.request_bytes(), b"GET");
+        assert!(!transcript.is_truncated());
+
+        transcript.record_response(b"HTTP/1.1");
+        assert_eq!(transcript.response_bytes(), b"HTTP");
+        assert!(transcript.is_truncated());
+    }
+}
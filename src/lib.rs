@@ -114,3 +114,7 @@ pub mod frame;
 pub mod stream;
 pub mod endpoint;
 pub mod handshake;
+
+pub use endpoint::connect;
+#[cfg(feature = "tokio")]
+pub use endpoint::connect_async;
@@ -7,7 +7,7 @@
 //! a connection and returns `Stream<IO, Server>`.
 //!
 //! Both client and server meet [`RoleHelper`], which indicates frame head length
-//! (currently unused), and how to mask payload data. Only client meets [`ClientRole`],
+//! (currently unused), and how to mask or unmask payload data. Only client meets [`ClientRole`],
 //! and only server meets [`ServerRole`].
 //!
 //! Any type implements these traits will be treated as a `client` or `server`.
@@ -19,6 +19,10 @@ pub trait RoleHelper: Clone + Copy {
     const SHORT_FRAME_HEAD_LEN: u8;
     const COMMON_FRAME_HEAD_LEN: u8;
     const LONG_FRAME_HEAD_LEN: u8;
+    /// Whether inbound frames are expected to be masked,
+    /// so that the mask key is read without checking the MASK bit.
+    /// An unmasked frame takes a slower path.
+    const READ_MASKED: bool = false;
 
     fn new() -> Self;
     fn mask_key(&self) -> Mask;
@@ -9,6 +9,7 @@ impl RoleHelper for Server {
     const SHORT_FRAME_HEAD_LEN: u8 = 2 + 4;
     const COMMON_FRAME_HEAD_LEN: u8 = 2 + 2 + 4;
     const LONG_FRAME_HEAD_LEN: u8 = 2 + 8 + 4;
+    const READ_MASKED: bool = true;
 
     #[inline]
     fn new() -> Self { Self {} }
@@ -1,13 +1,46 @@
 use std::io::Result;
 use std::pin::Pin;
-use std::task::{Poll, Context};
+use std::task::{Poll, Context, ready};
 
-use tokio::io::AsyncRead;
+use tokio::io::{AsyncRead, AsyncBufRead};
 use tokio::io::ReadBuf;
 
 use super::{Stream, RoleHelper, Guarded};
 use super::detail::read_some;
 
+/// Read from the inner IO into a plain buffer.
+trait PollReadIO {
+    fn poll_read_io(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>>;
+}
+
+// use default impl
+impl<IO: AsyncRead + Unpin> PollReadIO for IO {
+    #[inline]
+    default fn poll_read_io(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        Pin::new(self)
+            .poll_read(cx, &mut buf)
+            .map_ok(|_| buf.filled().len())
+    }
+}
+
+// specialize
+// copy from the filled buffer, without a `poll_read`
+impl<IO: AsyncRead + AsyncBufRead + Unpin> PollReadIO for IO {
+    #[inline]
+    fn poll_read_io(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<Result<usize>> {
+        let data = ready!(Pin::new(&mut *self).poll_fill_buf(cx))?;
+        let n = std::cmp::min(data.len(), buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Pin::new(self).consume(n);
+        Poll::Ready(Ok(n))
+    }
+}
+
 impl<IO, Role> AsyncRead for Stream<IO, Role>
 where
     IO: AsyncRead + Unpin,
@@ -15,19 +48,20 @@ where
     Role: RoleHelper,
 {
     /// Async version of `Stream::read`.
-    #[rustfmt::skip]
+    ///
+    /// If the inner IO is also [`AsyncBufRead`], frames are decoded
+    /// from its filled buffer, rather than via `poll_read`.
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<Result<()>> {
-        read_some(self.get_mut(), |io, buf| {
-                let mut buf = ReadBuf::new(buf);
-                Pin::new(io).poll_read(cx, &mut buf)
-                .map_ok(|_| buf.filled().len())
-            },
+        read_some(
+            self.get_mut(),
+            |io, buf| io.poll_read_io(cx, buf),
             buf.initialize_unfilled(),
-        ).map_ok(|n| buf.advance(n))
+        )
+        .map_ok(|n| buf.advance(n))
     }
 }
 
@@ -39,6 +73,9 @@ where
 {
     /// Async version of `Stream::read`.
     /// Continue to read if frame head is not complete.
+    ///
+    /// If the inner IO is also [`AsyncBufRead`], frames are decoded
+    /// from its filled buffer, rather than via `poll_read`.
     fn poll_read(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
@@ -49,12 +86,7 @@ where
         loop {
             match read_some(
                 this,
-                |io, buf| {
-                    let mut buf = ReadBuf::new(buf);
-                    Pin::new(io)
-                        .poll_read(cx, &mut buf)
-                        .map_ok(|_| buf.filled().len())
-                },
+                |io, buf| io.poll_read_io(cx, buf),
                 buf.initialize_unfilled(),
             ) {
                 Poll::Ready(Ok(0)) if this.is_read_partial_head() || !this.is_read_end() => {
@@ -70,3 +102,89 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use tokio::io::AsyncReadExt;
+    use super::*;
+    use super::super::test::make_frame;
+    use crate::frame::OpCode;
+    use crate::role::*;
+
+    // buffered source, which counts the calls
+    struct CountBufReader {
+        buf: Vec<u8>,
+        cursor: usize,
+        limit: usize,
+        reads: usize,
+        fills: usize,
+    }
+
+    impl AsyncRead for CountBufReader {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<Result<()>> {
+            self.reads += 1;
+            let n = std::cmp::min(buf.remaining(), self.buf.len() - self.cursor);
+            buf.put_slice(&self.buf[self.cursor..self.cursor + n]);
+            self.cursor += n;
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncBufRead for CountBufReader {
+        fn poll_fill_buf(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<&[u8]>> {
+            let this = self.get_mut();
+            this.fills += 1;
+            let end = std::cmp::min(this.cursor + this.limit, this.buf.len());
+            Poll::Ready(Ok(&this.buf[this.cursor..end]))
+        }
+
+        fn consume(mut self: Pin<&mut Self>, amt: usize) { self.cursor += amt; }
+    }
+
+    #[tokio::test]
+    async fn read_from_buffered() {
+        let mut frames = Vec::new();
+        let mut data = Vec::new();
+        for len in [10, 100, 1000, 0, 20] {
+            let (frame, payload) = make_frame::<Client>(OpCode::Binary, len);
+            frames.extend_from_slice(&frame);
+            data.extend_from_slice(&payload);
+        }
+
+        let io = CountBufReader {
+            buf: frames.clone(),
+            cursor: 0,
+            limit: 4096,
+            reads: 0,
+            fills: 0,
+        };
+        let mut stream = Stream::new(io, Server::new()).guard();
+        let mut buf = vec![0; 4096];
+        let mut data2 = Vec::new();
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            if n == 0 {
+                break;
+            }
+            data2.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(data2, data);
+
+        // all frames are in the first filled buffer,
+        // then an empty buffer indicates EOF
+        let io = stream.as_ref();
+        assert_eq!(io.reads, 0);
+        assert_eq!(io.fills, 2);
+
+        // same as a sync read
+        let mut stream = Stream::new(frames.as_slice(), Server::new()).guard();
+        let mut data3 = Vec::new();
+        Read::read_to_end(&mut stream, &mut data3).unwrap();
+        assert_eq!(data3, data);
+    }
+}
@@ -1,11 +1,16 @@
-use std::io::Result;
+use std::io::{Result, ErrorKind, IoSlice};
 use std::pin::Pin;
-use std::task::{Poll, Context};
+use std::future::poll_fn;
+use std::task::{Poll, Context, ready};
 
 use tokio::io::AsyncWrite;
 
 use super::{Stream, RoleHelper, Guarded};
-use super::detail::write_some;
+use super::state::WriteState;
+use super::detail::{write_some, write_frame, write_frame_vectored, write_ctrl};
+
+use crate::frame::{OpCode, CloseCode};
+use crate::error::{CtrlError, FrameError};
 
 impl<IO, Role> AsyncWrite for Stream<IO, Role>
 where
@@ -19,14 +24,36 @@ where
         write_some(self.get_mut(), |io, buf| Pin::new(io).poll_write_vectored(cx, buf), buf)
     }
 
+    /// Async version of `Stream::write`, where the payload of a frame
+    /// is gathered from `bufs`. The frame head and payload are written
+    /// with a single vectored write.
+    #[rustfmt::skip]
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<Result<usize>> {
+        write_frame_vectored(self.get_mut(), |io, iovec| Pin::new(io).poll_write_vectored(cx, iovec), OpCode::Binary, bufs)
+    }
+
+    /// Check if the underlying IO source supports vectored writes.
+    fn is_write_vectored(&self) -> bool { self.io.is_write_vectored() }
+
     /// This is a no-op since we do not buffer any data.
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
         Pin::new(&mut self.get_mut().io).poll_flush(cx)
     }
 
-    /// Shutdown the underlying IO source.
+    /// Send a `Close` frame with a normal closure status code(if not sent),
+    /// flush, then shutdown the underlying IO source.
+    ///
+    /// This does not wait for the peer's `Close` frame. To complete
+    /// the close handshake, caller should continue to read until
+    /// [`Stream::is_read_close`] or [`Stream::is_read_eof`].
+    ///
+    /// This must be called at a frame boundary, since the rest of a
+    /// partially written frame is unknown, otherwise a
+    /// [`CtrlError::CloseInWrite`](crate::error::CtrlError::CloseInWrite)
+    /// error is returned and the underlying
+    /// IO source is not shut down. Finish the frame, then retry.
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+        poll_shutdown(self.get_mut(), cx)
     }
 }
 
@@ -51,13 +78,413 @@ where
         }
     }
 
+    /// Async version of `Stream::write`, where the payload of a frame
+    /// is gathered from `bufs`.
+    /// Continue to write if frame head is not completely written.
+    #[rustfmt::skip]
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[IoSlice<'_>]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            match write_frame_vectored(this, |io, iovec| Pin::new(io).poll_write_vectored(cx, iovec), OpCode::Binary, bufs) {
+                Poll::Ready(Ok(0)) if this.is_write_partial_head() || !this.is_write_zero()=> continue,
+                Poll::Ready(Ok(n)) => return Poll::Ready(Ok(n)),
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Check if the underlying IO source supports vectored writes.
+    fn is_write_vectored(&self) -> bool { self.io.is_write_vectored() }
+
     /// This is a no-op since we do not buffer any data.
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
         Pin::new(&mut self.get_mut().io).poll_flush(cx)
     }
 
-    /// Shutdown the underlying IO source.
+    /// Send a `Close` frame with a normal closure status code(if not sent),
+    /// flush, then shutdown the underlying IO source.
+    ///
+    /// This does not wait for the peer's `Close` frame. To complete
+    /// the close handshake, caller should continue to read until
+    /// [`Stream::is_read_close`] or [`Stream::is_read_eof`].
+    ///
+    /// This must be called at a frame boundary, since the rest of a
+    /// partially written frame is unknown, otherwise a
+    /// [`CtrlError::CloseInWrite`](crate::error::CtrlError::CloseInWrite)
+    /// error is returned and the underlying
+    /// IO source is not shut down. Finish the frame, then retry.
     fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        Pin::new(&mut self.get_mut().io).poll_shutdown(cx)
+        poll_shutdown(self.get_mut(), cx)
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard>
+where
+    IO: AsyncWrite + Unpin,
+    Role: RoleHelper,
+{
+    /// Write a complete message within a single frame.
+    /// The opcode should be either `Text` or `Binary`, otherwise
+    /// a [`FrameError::IllegalOpCode`] error is returned.
+    ///
+    /// Only single-frame messages are written, a message is never fragmented.
+    ///
+    /// # Cancel safety
+    ///
+    /// Progress is saved in the stream. If the future is dropped before
+    /// completion, the message may be partially written. Then the next write
+    /// **must** be another `write_message` with the same opcode and payload,
+    /// which continues from where it left off, instead of writing the message again.
+    /// If nothing has been written, a retry simply starts a new message.
+    /// A retry with a payload that does not match the partially written frame
+    /// fails with a [`CtrlError::RawFrameInWrite`] error.
+    pub async fn write_message(&mut self, opcode: OpCode, payload: &[u8]) -> Result<()> {
+        if !matches!(opcode, OpCode::Text | OpCode::Binary) {
+            return Err(FrameError::IllegalOpCode.into());
+        }
+        let mut is_started = false;
+        poll_fn(|cx| self.poll_write_message(cx, opcode, payload, &mut is_started)).await
+    }
+
+    /// Write queued control frames, i.e. a `Pong` frame
+    /// (see [`Stream::queue_pong`]) and a `Close` frame, then flush the
+    /// underlying IO source. Coalesced writes are left buffered.
+    ///
+    /// A control frame must not be inserted into a data frame. If a data
+    /// frame is partially written, nothing is written or flushed and this
+    /// returns `Ready(Ok(()))` immediately, the queued frames are preserved.
+    /// A queued pong is written before the next data frame,
+    /// or by a later call once the data frame is completed.
+    pub fn poll_flush_control(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        match self.write_state {
+            WriteState::WriteHead(head) if head.is_empty() => {}
+            WriteState::WriteZero => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+            _ => return Poll::Ready(Ok(())),
+        }
+        ready!(write_ctrl(self, |io, iovec| Pin::new(io)
+            .poll_write_vectored(cx, iovec)))?;
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    /// Async version of [`Stream::poll_flush_control`].
+    pub async fn flush_control(&mut self) -> Result<()> {
+        poll_fn(|cx| self.poll_flush_control(cx)).await
+    }
+
+    fn poll_write_message(
+        &mut self,
+        cx: &mut Context<'_>,
+        opcode: OpCode,
+        payload: &[u8],
+        is_started: &mut bool,
+    ) -> Poll<Result<()>> {
+        loop {
+            // find out how much payload has been written
+            let offset = match self.write_state {
+                WriteState::WriteHead(head) if head.is_empty() => {
+                    if *is_started {
+                        return Poll::Ready(Ok(()));
+                    }
+                    0
+                }
+                // the head is built for the original payload
+                WriteState::WriteHead(head)
+                    if self.last_frame_len != (head.wr_pos() + payload.len()) as u64 =>
+                {
+                    return Poll::Ready(Err(CtrlError::RawFrameInWrite.into()));
+                }
+                WriteState::WriteHead(_) => 0,
+                WriteState::WriteData(next) if next > payload.len() as u64 => {
+                    return Poll::Ready(Err(CtrlError::RawFrameInWrite.into()));
+                }
+                WriteState::WriteData(next) => payload.len() - next as usize,
+                WriteState::WriteZero => return Poll::Ready(Err(ErrorKind::WriteZero.into())),
+            };
+            *is_started = true;
+            ready!(write_frame(
+                self,
+                |io, iovec| Pin::new(io).poll_write_vectored(cx, iovec),
+                opcode,
+                &payload[offset..]
+            ))?;
+        }
+    }
+}
+
+fn poll_shutdown<IO, Role, Guard>(
+    stream: &mut Stream<IO, Role, Guard>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<()>>
+where
+    IO: AsyncWrite + Unpin,
+    Role: RoleHelper,
+{
+    if !stream.close.is_queued {
+        stream.queue_close(CloseCode::Normal, b"")?;
+    }
+    ready!(write_ctrl(stream, |io, iovec| Pin::new(io)
+        .poll_write_vectored(cx, iovec)))?;
+    ready!(Pin::new(&mut stream.io).poll_flush(cx))?;
+    Pin::new(&mut stream.io).poll_shutdown(cx)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::test::{BlockWriter, make_head, make_data, make_frame};
+    use crate::frame::*;
+    use crate::role::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn write_message_cancelled() {
+        use std::future::Future;
+
+        async fn write<R: RoleHelper + Unpin>(n: usize, limit: usize) {
+            let io = BlockWriter {
+                alternate: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, R::new());
+            let payload = make_data(n);
+
+            // poll once, then drop the future
+            loop {
+                let mut fut = Box::pin(stream.write_message(OpCode::Text, &payload));
+                let ready = poll_fn(|cx| Poll::Ready(fut.as_mut().poll(cx).is_ready())).await;
+                drop(fut);
+                if ready {
+                    break;
+                }
+            }
+
+            let mut frame = make_head(OpCode::Text, stream.mask_key(), n);
+            frame.extend_from_slice(&payload);
+            assert_eq!(stream.as_ref().buf, frame);
+        }
+
+        for n in [0, 1, 10, 200] {
+            for limit in 1..=16 {
+                write::<Client>(n, limit).await;
+                write::<Server>(n, limit).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn write_message_retry_mismatch() {
+        use std::future::Future;
+        use std::error::Error;
+
+        async fn write(limit: usize) {
+            let io = BlockWriter {
+                alternate: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, Client::new());
+            let payload = make_data(200);
+
+            // poll until something is written, then drop the future
+            while stream.as_ref().buf.is_empty() {
+                let mut fut = Box::pin(stream.write_message(OpCode::Binary, &payload));
+                let _ = poll_fn(|cx| Poll::Ready(fut.as_mut().poll(cx).is_ready())).await;
+            }
+
+            // a shorter payload does not match the partial frame
+            let e = stream
+                .write_message(OpCode::Binary, &payload[..10])
+                .await
+                .unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<CtrlError>(),
+                Some(&CtrlError::RawFrameInWrite)
+            );
+
+            // the same payload completes it
+            stream
+                .write_message(OpCode::Binary, &payload)
+                .await
+                .unwrap();
+            let mut frame = make_head(OpCode::Binary, stream.mask_key(), 200);
+            frame.extend_from_slice(&payload);
+            assert_eq!(stream.as_ref().buf, frame);
+        }
+
+        // the head is partially written, or the payload is
+        for limit in [1, 3, 16] {
+            write(limit).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn write_vectored_to_stream() {
+        async fn write_all_vectored<S: AsyncWrite + Unpin>(
+            stream: &mut S,
+            mut bufs: &mut [IoSlice<'_>],
+        ) {
+            while !bufs.is_empty() {
+                let n = stream.write_vectored(bufs).await.unwrap();
+                IoSlice::advance_slices(&mut bufs, n);
+            }
+        }
+
+        async fn write<R: RoleHelper + Unpin>(n: usize, limit: usize) {
+            let (frame, data) = make_frame::<R>(OpCode::Binary, n);
+            let (a, b) = data.split_at(n / 3);
+            let (b, c) = b.split_at(n / 3);
+            let (a, b, c) = (a.to_vec(), b.to_vec(), c.to_vec());
+
+            let io = BlockWriter {
+                alternate: true,
+                vectored: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, R::new());
+            assert!(stream.is_write_vectored());
+            // the head may be partially written
+            let mut bufs = [IoSlice::new(&a), IoSlice::new(&b), IoSlice::new(&c)];
+            write_all_vectored(&mut stream, &mut bufs).await;
+            assert_eq!(stream.as_ref().buf, frame);
+
+            let io = BlockWriter {
+                alternate: true,
+                vectored: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, R::new()).guard();
+            let (a, b, c) = (
+                data[..n / 3].to_vec(),
+                data[n / 3..n / 2].to_vec(),
+                data[n / 2..].to_vec(),
+            );
+            let mut bufs = [IoSlice::new(&a), IoSlice::new(&b), IoSlice::new(&c)];
+            write_all_vectored(&mut stream, &mut bufs).await;
+            assert_eq!(stream.as_ref().buf, frame);
+        }
+
+        for n in [3, 10, 200, 65536] {
+            for limit in [1, 2, 3, 7, 64, 4096, 0x20000] {
+                write::<Client>(n, limit).await;
+                write::<Server>(n, limit).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn flush_control_at_frame_boundary() {
+        async fn write<R: RoleHelper + Unpin>(n: usize, limit: usize) {
+            let io = BlockWriter {
+                alternate: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, R::new()).guard();
+            let mask = stream.mask_key();
+            let data = make_data(n);
+            let mut frame = make_head(OpCode::Binary, mask, n);
+            frame.extend_from_slice(&data);
+
+            let mut pong = make_head(OpCode::Pong, mask, 4);
+            let mut payload = b"pong".to_vec();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut payload);
+            }
+            pong.append(&mut payload);
+
+            // the data frame is paused
+            let mut offset = stream.write(&data).await.unwrap();
+            assert!(offset < n);
+            stream.queue_pong(b"pong").unwrap();
+
+            // not inserted into the data frame
+            stream.flush_control().await.unwrap();
+            assert!(stream.is_pong_queued());
+            assert_eq!(stream.as_ref().buf, frame[..frame.len() - n + offset]);
+
+            while offset < n {
+                offset += stream.write(&data[offset..]).await.unwrap();
+            }
+            assert!(stream.is_pong_queued());
+            assert_eq!(stream.as_ref().buf, frame);
+
+            // flushed after the data frame completes
+            stream.flush_control().await.unwrap();
+            assert!(!stream.is_pong_queued());
+            assert!(!stream.has_pending_write());
+            let mut expect = frame.clone();
+            expect.extend_from_slice(&pong);
+            assert_eq!(stream.as_ref().buf, expect);
+        }
+
+        for n in [10, 200] {
+            for limit in [1, 3, 7] {
+                write::<Client>(n, limit).await;
+                write::<Server>(n, limit).await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_stream() {
+        async fn shutdown<R: RoleHelper + Unpin>() {
+            let io: Vec<u8> = Vec::new();
+            let mut stream = Stream::new(io, R::new());
+
+            let mask = stream.mask_key();
+            let mut frame = make_head(OpCode::Close, mask, 2);
+            let mut data = 1000_u16.to_be_bytes().to_vec();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut data);
+            }
+            frame.append(&mut data);
+
+            stream.shutdown().await.unwrap();
+            assert!(stream.is_write_close());
+            assert_eq!(stream.as_ref(), &frame);
+
+            // close frame is sent only once
+            stream.shutdown().await.unwrap();
+            assert_eq!(stream.as_ref(), &frame);
+        }
+
+        shutdown::<Client>().await;
+        shutdown::<Server>().await;
+        shutdown::<FixedMaskClient>().await;
+    }
+
+    #[tokio::test]
+    async fn shutdown_in_write() {
+        use std::error::Error;
+        use crate::error::CtrlError;
+
+        let io = BlockWriter {
+            alternate: true,
+            ..BlockWriter::new(4)
+        };
+        let mut stream = Stream::new(io, Server::new()).guard();
+        let data = make_data(10);
+
+        // the data frame is partially written
+        let mut offset = stream.write(&data).await.unwrap();
+        assert!(offset < data.len());
+        let e = stream.shutdown().await.unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<CtrlError>(),
+            Some(&CtrlError::CloseInWrite)
+        );
+        assert!(!stream.is_write_close());
+
+        // finish the frame, then retry
+        while offset < data.len() {
+            offset += stream.write(&data[offset..]).await.unwrap();
+        }
+        stream.shutdown().await.unwrap();
+        assert!(stream.is_write_close());
+
+        let mut frame = make_head(OpCode::Binary, Mask::None, 10);
+        frame.extend_from_slice(&data);
+        frame.append(&mut make_head(OpCode::Close, Mask::None, 2));
+        frame.extend_from_slice(&1000_u16.to_be_bytes());
+        assert_eq!(stream.as_ref().buf, frame);
     }
 }
@@ -0,0 +1,363 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::time::Duration;
+
+use super::{Stream, ControlOverflow, OrphanContinuation};
+use super::state::Limit;
+use super::control::ControlQueue;
+use super::coalesce::Coalesce;
+use super::liveness::Liveness;
+use crate::role::RoleHelper;
+use crate::handshake::Extensions;
+
+/// Websocket stream builder.
+///
+/// Collect options, then create a [`Stream`] from IO source in one expression.
+///
+/// Each option has the same default and meaning as the setter on [`Stream`].
+/// Per-connection states, e.g. statistics or a queued `Close` frame,
+/// are never set here, as well as options of stream wrappers
+/// like [`MessageStream`](super::MessageStream).
+///
+/// ```no_run
+/// use std::net::TcpStream;
+/// use lightws::role::FixedMaskClient;
+/// use lightws::stream::Stream;
+/// fn build() -> std::io::Result<()> {
+///     let tcp = TcpStream::connect("example.com:80")?;
+///     let ws = Stream::<_, FixedMaskClient>::builder()
+///         .mask_key([1, 2, 3, 4])
+///         .build(tcp);
+///     Ok(())
+/// }
+/// ```
+pub struct StreamBuilder<IO, Role> {
+    role: Role,
+    limit: Limit,
+    extensions: Extensions,
+    discard_payloads: bool,
+    control: ControlQueue,
+    strict: bool,
+    orphan: OrphanContinuation,
+    coalesce: Coalesce,
+    zero_read_is_eof: bool,
+    cancel: Option<Arc<AtomicBool>>,
+    liveness: Liveness,
+    flush_on_message_end: bool,
+    combine_head_payload: bool,
+    __marker: PhantomData<IO>,
+}
+
+impl<IO, Role: RoleHelper> Default for StreamBuilder<IO, Role> {
+    fn default() -> Self { Self::new() }
+}
+
+impl<IO, Role: RoleHelper> StreamBuilder<IO, Role> {
+    /// Create a builder with default options.
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            role: Role::new(),
+            limit: Limit::new(),
+            extensions: Extensions::new(),
+            discard_payloads: false,
+            control: ControlQueue::new(),
+            strict: true,
+            orphan: OrphanContinuation::Error,
+            coalesce: Coalesce::new(),
+            zero_read_is_eof: true,
+            cancel: None,
+            liveness: Liveness::new(),
+            flush_on_message_end: false,
+            combine_head_payload: false,
+            __marker: PhantomData,
+        }
+    }
+
+    /// Use the provided role.
+    #[inline]
+    pub fn role(mut self, role: Role) -> Self {
+        self.role = role;
+        self
+    }
+
+    /// Set mask for upcoming writes.
+    #[inline]
+    pub fn mask_key(mut self, key: [u8; 4]) -> Self {
+        self.role.set_mask_key(key);
+        self
+    }
+
+    /// Set the max count of frames to receive during the connection lifetime.
+    ///
+    /// See also: [`Stream::set_max_total_frames`].
+    #[inline]
+    pub fn max_total_frames(mut self, n: u64) -> Self {
+        self.limit.max_frames = n;
+        self
+    }
+
+    /// Set the max count of payload bytes to receive during the connection lifetime.
+    ///
+    /// See also: [`Stream::set_max_total_payload_bytes`].
+    #[inline]
+    pub fn max_total_payload_bytes(mut self, n: u64) -> Self {
+        self.limit.max_payload_bytes = n;
+        self
+    }
+
+    /// Set extensions negotiated during the handshake.
+    ///
+    /// See also: [`Stream::set_extensions`].
+    #[inline]
+    pub fn extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Set whether to check frames strictly.
+    ///
+    /// See also: [`Stream::set_strict`].
+    #[inline]
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Set the policy to handle an orphan continuation frame.
+    ///
+    /// See also: [`Stream::set_orphan_continuation_policy`].
+    #[inline]
+    pub fn orphan_continuation_policy(mut self, policy: OrphanContinuation) -> Self {
+        self.orphan = policy;
+        self
+    }
+
+    /// Set whether an `Ok(0)` from the underlying IO source means `EOF`.
+    ///
+    /// See also: [`Stream::set_zero_read_is_eof`].
+    #[inline]
+    pub fn zero_read_is_eof(mut self, eof: bool) -> Self {
+        self.zero_read_is_eof = eof;
+        self
+    }
+
+    /// Set whether to read and drop the payload of data frames.
+    ///
+    /// See also: [`Stream::set_discard_payloads`].
+    #[inline]
+    pub fn discard_payloads(mut self, discard: bool) -> Self {
+        self.discard_payloads = discard;
+        self
+    }
+
+    /// Set whether to queue received control frames.
+    ///
+    /// See also: [`Stream::set_manual_control`].
+    #[inline]
+    pub fn manual_control(mut self, manual: bool) -> Self {
+        self.control.is_manual = manual;
+        self
+    }
+
+    /// Set the capacity of the control frame queue, and the overflow policy.
+    ///
+    /// See also: [`Stream::set_control_queue`].
+    #[inline]
+    pub fn control_queue(mut self, capacity: usize, overflow: ControlOverflow) -> Self {
+        self.control.capacity = std::cmp::max(capacity, 1);
+        self.control.overflow = overflow;
+        self
+    }
+
+    /// Set whether to coalesce small writes into one frame.
+    ///
+    /// See also: [`Stream::set_coalesce_writes`].
+    #[inline]
+    pub fn coalesce_writes(mut self, coalesce: bool) -> Self {
+        self.coalesce.is_enabled = coalesce;
+        self
+    }
+
+    /// Set the max size of coalesced payload.
+    ///
+    /// See also: [`Stream::set_coalesce_limit`].
+    #[inline]
+    pub fn coalesce_limit(mut self, limit: usize) -> Self {
+        self.coalesce.limit = std::cmp::max(limit, 1);
+        self
+    }
+
+    /// Set whether to send a frame head along with its payload in one write.
+    ///
+    /// See also: [`Stream::set_combine_head_payload`].
+    #[inline]
+    pub fn combine_head_payload(mut self, combine: bool) -> Self {
+        self.combine_head_payload = combine;
+        self
+    }
+
+    /// Set whether to flush once a message written by
+    /// [`MessageWriter`](super::MessageWriter) ends.
+    ///
+    /// See also: [`Stream::set_flush_on_message_end`].
+    #[inline]
+    pub fn flush_on_message_end(mut self, flush: bool) -> Self {
+        self.flush_on_message_end = flush;
+        self
+    }
+
+    /// Set the timeout to wait for a reply after sending a ping.
+    ///
+    /// See also: [`Stream::set_liveness_timeout`].
+    #[inline]
+    pub fn liveness_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.liveness.timeout = timeout;
+        self
+    }
+
+    /// Set a flag to cancel reads and writes cooperatively.
+    ///
+    /// See also: [`Stream::set_cancel_flag`].
+    #[inline]
+    pub fn cancel_flag(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancel = Some(flag);
+        self
+    }
+
+    /// Create websocket stream from IO source directly,
+    /// without a handshake.
+    #[inline]
+    pub fn build(self, io: IO) -> Stream<IO, Role> {
+        let mut stream = Stream::new(io, self.role);
+        stream.limit = self.limit;
+        stream.extensions = self.extensions;
+        stream.discard_payloads = self.discard_payloads;
+        stream.control = self.control;
+        stream.strict = self.strict;
+        stream.orphan = self.orphan;
+        stream.coalesce = self.coalesce;
+        stream.zero_read_is_eof = self.zero_read_is_eof;
+        stream.cancel = self.cancel;
+        stream.liveness = self.liveness;
+        stream.flush_on_message_end = self.flush_on_message_end;
+        stream.combine_head_payload = self.combine_head_payload;
+        stream
+    }
+}
+
+impl<IO, Role: RoleHelper> Stream<IO, Role> {
+    /// Create a [`StreamBuilder`] with default options.
+    #[inline]
+    pub fn builder() -> StreamBuilder<IO, Role> { StreamBuilder::new() }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::*;
+    use super::super::test::{make_data, make_frame};
+    use crate::frame::*;
+    use crate::role::*;
+
+    #[test]
+    fn build_stream() {
+        fn build<R: RoleHelper>() {
+            let stream = Stream::<Vec<u8>, R>::builder().build(Vec::new());
+            assert_eq!(stream.mask_key(), R::new().mask_key());
+        }
+
+        build::<Client>();
+        build::<StandardClient>();
+        build::<Server>();
+    }
+
+    #[test]
+    fn build_stream_with_mask_key() {
+        for _ in 0..64 {
+            let mut key = new_mask_key();
+            // an all-zero key is decoded as Mask::Skip
+            key[0] |= 0x01;
+
+            let mut stream = Stream::<_, FixedMaskClient>::builder()
+                .mask_key(key)
+                .build(Vec::new());
+            assert_eq!(stream.mask_key(), Mask::Key(key));
+
+            let data = make_data(256);
+            let write_n = stream.write(&data).unwrap();
+            assert_eq!(write_n, data.len());
+
+            let (head, _) = FrameHead::decode(stream.as_ref()).unwrap();
+            assert_eq!(head.mask, Mask::Key(key));
+        }
+    }
+
+    #[test]
+    fn build_stream_with_limit() {
+        let (mut frame, _) = make_frame::<Client>(OpCode::Binary, 16);
+        let (mut frame2, _) = make_frame::<Client>(OpCode::Binary, 16);
+        frame.append(&mut frame2);
+
+        let mut buf = vec![0; 64];
+        let mut stream = Stream::<_, Server>::builder()
+            .max_total_frames(1)
+            .build(frame.as_slice());
+
+        assert!(stream.read(&mut buf).is_err());
+        assert_eq!(stream.stats().read_frames, 2);
+
+        let stream = Stream::<&[u8], Server>::builder()
+            .max_total_payload_bytes(100)
+            .build(&[]);
+        assert_eq!(stream.limit.max_frames, 0);
+        assert_eq!(stream.limit.max_payload_bytes, 100);
+    }
+
+    #[test]
+    fn build_stream_with_options() {
+        // defaults are the same as a new stream
+        let stream = Stream::<Vec<u8>, Server>::builder().build(Vec::new());
+        let stream2 = Stream::new(Vec::<u8>::new(), Server::new());
+        assert_eq!(format!("{:?}", stream), format!("{:?}", stream2));
+
+        let flag = Arc::new(AtomicBool::new(false));
+        let timeout = Some(Duration::from_secs(1));
+        let mut extensions = Extensions::new();
+        extensions.parse(b"foo; bar").unwrap();
+        let stream = Stream::<Vec<u8>, Server>::builder()
+            .extensions(extensions.clone())
+            .strict(false)
+            .orphan_continuation_policy(OrphanContinuation::Ignore)
+            .zero_read_is_eof(false)
+            .discard_payloads(true)
+            .manual_control(true)
+            .control_queue(0, ControlOverflow::Error)
+            .coalesce_writes(true)
+            .coalesce_limit(256)
+            .combine_head_payload(true)
+            .flush_on_message_end(true)
+            .liveness_timeout(timeout)
+            .cancel_flag(flag.clone())
+            .build(Vec::new());
+
+        assert_eq!(stream.extensions(), &extensions);
+        assert!(!stream.is_strict());
+        assert_eq!(
+            stream.orphan_continuation_policy(),
+            OrphanContinuation::Ignore
+        );
+        assert!(!stream.is_zero_read_eof());
+        assert!(stream.is_discard_payloads());
+        assert!(stream.is_manual_control());
+        assert_eq!(stream.control.capacity, 1);
+        assert_eq!(stream.control.overflow, ControlOverflow::Error);
+        assert!(stream.is_coalesce_writes());
+        assert_eq!(stream.coalesce.limit, 256);
+        assert!(stream.is_combine_head_payload());
+        assert!(stream.is_flush_on_message_end());
+        assert_eq!(stream.liveness_timeout(), timeout);
+        assert!(Arc::ptr_eq(stream.cancel_flag().unwrap(), &flag));
+    }
+}
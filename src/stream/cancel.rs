@@ -0,0 +1,132 @@
+use std::io::{Error, ErrorKind, Result};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
+
+use super::Stream;
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Get the flag to cancel reads and writes, if any.
+    #[inline]
+    pub const fn cancel_flag(&self) -> Option<&Arc<AtomicBool>> { self.cancel.as_ref() }
+
+    /// Set a flag to cancel reads and writes cooperatively, e.g. on shutdown,
+    /// which is shared with another thread or a signal handler.
+    ///
+    /// Once the flag is set, a sync read or write fails with an `Interrupted`
+    /// error before the next call to the underlying IO source, which is never
+    /// closed. States are preserved as other IO errors, a later read or write
+    /// resumes the incomplete frame once the flag is cleared.
+    ///
+    /// Helpers like [`Write::write_all`](std::io::Write::write_all) retry
+    /// on `Interrupted`, which spin until the flag is cleared. Bytes written
+    /// with [`Stream::write_frame`] and the like are not cancelled.
+    #[inline]
+    pub fn set_cancel_flag(&mut self, flag: Arc<AtomicBool>) { self.cancel = Some(flag); }
+
+    /// Remove the flag to cancel reads and writes, returns it if any.
+    #[inline]
+    pub fn take_cancel_flag(&mut self) -> Option<Arc<AtomicBool>> { self.cancel.take() }
+
+    /// Check if the flag to cancel reads and writes is set.
+    #[inline]
+    pub fn is_cancelled(&self) -> bool { is_cancelled(&self.cancel) }
+}
+
+#[inline]
+pub(super) fn is_cancelled(flag: &Option<Arc<AtomicBool>>) -> bool {
+    flag.as_ref().is_some_and(|x| x.load(Ordering::Acquire))
+}
+
+/// Call the underlying IO source unless cancelled.
+#[inline]
+pub(super) fn poll_unless_cancelled<T, F>(flag: &Option<Arc<AtomicBool>>, f: F) -> Poll<Result<T>>
+where
+    F: FnOnce() -> Result<T>,
+{
+    if is_cancelled(flag) {
+        return Poll::Ready(Err(Error::from(ErrorKind::Interrupted)));
+    }
+    Poll::Ready(f())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::*;
+    use super::super::test::make_frame;
+    use crate::frame::OpCode;
+    use crate::role::*;
+
+    // set the flag once some bytes are read
+    struct CancelReader<'a> {
+        buf: &'a [u8],
+        flag: Arc<AtomicBool>,
+        cancel_at: usize,
+        cursor: usize,
+    }
+
+    impl Read for CancelReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            let end = if self.cursor < self.cancel_at {
+                self.cancel_at
+            } else {
+                self.buf.len()
+            };
+            let n = std::cmp::min(buf.len(), end - self.cursor);
+            buf[..n].copy_from_slice(&self.buf[self.cursor..self.cursor + n]);
+            self.cursor += n;
+            if self.cursor == self.cancel_at {
+                self.flag.store(true, Ordering::Release);
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for CancelReader<'_> {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> { Ok(buf.len()) }
+
+        fn flush(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn cancel_mid_read() {
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 1000);
+        let flag = Arc::new(AtomicBool::new(false));
+        let io = CancelReader {
+            buf: &frame,
+            flag: flag.clone(),
+            cancel_at: 500,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new()).guard();
+        stream.set_cancel_flag(flag.clone());
+        assert!(!stream.is_cancelled());
+
+        let mut buf = vec![0; 4096];
+        let mut data2 = Vec::new();
+        let n = stream.read(&mut buf).unwrap();
+        data2.extend_from_slice(&buf[..n]);
+        assert!(stream.is_cancelled());
+
+        // cancelled before the next read, states are preserved
+        let e = stream.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Interrupted);
+        let e = stream.write(b"hello").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Interrupted);
+        assert!(stream.close_reason().is_none());
+        assert_eq!(stream.as_ref().cursor, 500);
+
+        // resumed
+        flag.store(false, Ordering::Release);
+        while data2.len() < data.len() {
+            let n = stream.read(&mut buf).unwrap();
+            data2.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(data2, data);
+        assert_eq!(stream.write(b"hello").unwrap(), 5);
+
+        assert!(stream.take_cancel_flag().is_some());
+        assert!(stream.cancel_flag().is_none());
+    }
+}
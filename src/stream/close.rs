@@ -0,0 +1,509 @@
+use std::io::{Write, Result, Error, ErrorKind};
+use std::task::Poll;
+
+use super::Stream;
+use super::state::WriteState;
+use super::detail::{trace_head, write_ctrl};
+
+use crate::frame::{FrameHead, Fin, Mask, OpCode, PayloadLen};
+use crate::frame::{CloseCode, encode_close_payload, decode_close_payload};
+use crate::frame::close::MAX_CLOSE_REASON_LEN;
+use crate::frame::mask::apply_mask4;
+use crate::role::RoleHelper;
+use crate::error::{CtrlError, FrameError};
+
+/// Reason of an abnormal closure, where no `Close` frame is received.
+///
+/// Applications could treat it like [`CloseCode::Abnormal`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// connection is reset or aborted by the peer, or the pipe is broken
+    Reset,
+    /// connection is closed in the middle of a frame
+    Truncated,
+    /// peer does not reply a ping in time, see
+    /// [`Stream::set_liveness_timeout`]
+    Timeout,
+}
+
+impl CloseReason {
+    /// Infer from an IO error.
+    #[inline]
+    const fn from_io_error_kind(kind: ErrorKind) -> Option<Self> {
+        match kind {
+            ErrorKind::ConnectionReset | ErrorKind::ConnectionAborted | ErrorKind::BrokenPipe => {
+                Some(CloseReason::Reset)
+            }
+            ErrorKind::UnexpectedEof => Some(CloseReason::Truncated),
+            _ => None,
+        }
+    }
+}
+
+/// Liveness of a connection, which summarizes read, write and close states.
+///
+/// Valid transitions are:
+///
+/// ```text
+/// Open -> ClosingSent -> Closed
+/// Open -> ClosingReceived -> Closed
+/// Open | ClosingSent | ClosingReceived -> Failed
+/// ```
+///
+/// `Closed` and `Failed` are terminal,
+/// a connection never goes back to `Open`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// no `Close` frame is sent or received
+    Open,
+    /// a `Close` frame is sent, waiting for the peer's `Close` frame
+    ClosingSent,
+    /// a `Close` frame is received, but not replied
+    ClosingReceived,
+    /// close handshake is completed
+    Closed,
+    /// connection is closed abnormally before the close handshake completes,
+    /// see [`Stream::close_reason`]
+    Failed,
+}
+
+/// Record the reason of an abnormal closure(if any),
+/// then return the error as is.
+#[inline]
+pub(super) fn record_io_error(reason: &mut Option<CloseReason>, e: Error) -> Error {
+    if reason.is_none() {
+        *reason = CloseReason::from_io_error_kind(e.kind());
+    }
+    e
+}
+
+/// Reaching `EOF` in the middle of a frame, record the closure
+/// as [`CloseReason::Truncated`], then return an `UnexpectedEof` error.
+#[inline]
+pub(super) fn truncated_eof(reason: &mut Option<CloseReason>) -> Error {
+    record_io_error(reason, ErrorKind::UnexpectedEof.into())
+}
+
+impl<IO, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Build a `Close` frame in place, which will be written later.
+    /// An attempt to close during a write will fail with [`CtrlError::CloseInWrite`].
+    ///
+    /// See [`encode_close_payload`] for restrictions on the status code and reason,
+    /// where a reason longer than [`MAX_CLOSE_REASON_LEN`] is rejected.
+    pub(super) fn queue_close(&mut self, code: CloseCode, reason: &[u8]) -> Result<()> {
+        // a control frame must not be inserted into a data frame
+        match self.write_state {
+            WriteState::WriteHead(head) if head.is_empty() => {}
+            _ => return Err(CtrlError::CloseInWrite.into()),
+        }
+
+        // the reason must fit in a control frame
+        if reason.len() > MAX_CLOSE_REASON_LEN {
+            return Err(FrameError::IllegalData.into());
+        }
+        let payload_len = match CloseCode::from_u16(code.to_u16()) {
+            CloseCode::NoStatus => 0,
+            _ => 2 + reason.len(),
+        };
+
+        let mask = self.role.mask_key();
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Close,
+            mask,
+            PayloadLen::from_num(payload_len as u64),
+        );
+
+        let store = &mut self.close.close_store;
+        // The buffer is large enough to accommodate any kind of control frame.
+        let head_len = unsafe { head.encode_unchecked(store.as_mut()) };
+
+        // encode payload right after the head, without a copy
+        let data = &mut store.as_mut()[head_len..head_len + payload_len];
+        encode_close_payload(code, reason, data)?;
+        let frame_len = head_len + payload_len;
+
+        trace_head("write", &head);
+
+        // payload is owned by us, so it is always masked
+        if let Mask::Key(key) = mask {
+            apply_mask4(key, data);
+        }
+
+        store.set_rd_pos(0);
+        store.set_wr_pos(frame_len);
+        self.close.is_queued = true;
+        self.last_frame_len = frame_len as u64;
+        Ok(())
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Get the status code and reason of a received `Close` frame.
+    ///
+    /// Returns `None` if a `Close` frame is not completely read,
+    /// or its payload is invalid.
+    #[inline]
+    pub fn peer_close(&self) -> Option<(CloseCode, &[u8])> {
+        if !self.close.is_received {
+            return None;
+        }
+        decode_close_payload(self.close.recv_store.read()).ok()
+    }
+
+    /// Get the liveness of the connection.
+    ///
+    /// See [`ConnectionState`] for valid transitions.
+    pub const fn connection_state(&self) -> ConnectionState {
+        if self.close.reason.is_some() || self.is_read_eof() || self.is_write_zero() {
+            return ConnectionState::Failed;
+        }
+        match (self.is_write_close(), self.is_read_close()) {
+            (true, true) => ConnectionState::Closed,
+            (true, false) => ConnectionState::ClosingSent,
+            (false, true) => ConnectionState::ClosingReceived,
+            (false, false) => ConnectionState::Open,
+        }
+    }
+
+    /// Get the reason of an abnormal closure, which is detected
+    /// from an error of the underlying IO source during a read or write.
+    #[inline]
+    pub const fn close_reason(&self) -> Option<CloseReason> { self.close.reason }
+
+    /// Abort the connection without a close handshake,
+    /// return the underlying IO source immediately.
+    ///
+    /// No `Close` frame is sent, and an incomplete frame is discarded.
+    /// Caller should shutdown the transport directly,
+    /// then the peer would detect an abnormal closure([`CloseCode::Abnormal`]).
+    #[inline]
+    pub fn abort(self) -> IO { self.io }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Send a `Close` frame with the status code and reason.
+    ///
+    /// [`CloseCode::NoStatus`] leads to a `Close` frame without payload,
+    /// see [`encode_close_payload`] for other restrictions.
+    ///
+    /// This must be called at a frame boundary. Coalesced writes are
+    /// emitted before the `Close` frame (see [`Stream::set_coalesce_writes`]).
+    /// If the underlying IO source returns an error(e.g. `WouldBlock`),
+    /// the frame is preserved, a later call continues to write it
+    /// and ignores the provided arguments.
+    ///
+    /// Once a read detects a broken fragmented message, a `Close` frame with
+    /// [`CloseCode::ProtocolError`] is queued, which is written here instead.
+    /// So is a `Close` frame with [`CloseCode::PolicyViolation`] once a read
+    /// exceeds a limit set by [`Stream::set_max_total_frames`].
+    pub fn send_close(&mut self, code: CloseCode, reason: &[u8]) -> Result<()> {
+        if !self.close.is_queued {
+            // emit coalesced writes first
+            self.flush_coalesced()?;
+            self.queue_close(code, reason)?;
+        }
+        match write_ctrl(self, |io, iovec| io.write_vectored(iovec).into()) {
+            Poll::Ready(x) => x,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    /// Send a `Close` frame with [`Stream::send_close`], then flush
+    /// the underlying IO source.
+    ///
+    /// This does not wait for the peer's `Close` frame. To complete
+    /// the close handshake, caller should continue to read until
+    /// [`Stream::is_read_close`] or [`Stream::is_read_eof`].
+    pub fn close(&mut self, code: CloseCode, reason: &[u8]) -> Result<()> {
+        self.send_close(code, reason)?;
+        self.io.flush()
+    }
+
+    /// Best-effort clean shutdown, for an application that will not
+    /// read the peer's `Close` frame.
+    ///
+    /// Send a `Close` frame with a normal closure status code(if not sent),
+    /// continue to write it if it is partially written, then flush the
+    /// underlying IO source. Inbound frames are not read.
+    ///
+    /// Unlike [`Stream::close`], which is followed by reads to complete the
+    /// close handshake, the stream is neither read nor drained here.
+    /// This must be called at a frame boundary, since the rest of a
+    /// partially written frame is unknown, otherwise a
+    /// [`CtrlError::CloseInWrite`] error will be returned.
+    pub fn finalize(&mut self) -> Result<()> { self.close(CloseCode::Normal, b"") }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::error::Error;
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_head};
+    use crate::role::*;
+
+    #[test]
+    fn close_without_status() {
+        fn close<R: RoleHelper>(code: CloseCode, rlimit: usize, wlimit: usize) {
+            let io = LimitReadWriter {
+                buf: Vec::new(),
+                rlimit,
+                wlimit,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, R::new());
+            stream.close(code, b"").unwrap();
+            assert!(stream.is_write_close());
+
+            // zero payload bytes
+            let frame = make_head(OpCode::Close, stream.mask_key(), 0);
+            assert_eq!(&stream.as_ref().buf, &frame);
+
+            // read it back
+            let mut buf = vec![0; 32];
+            while !stream.is_read_end() {
+                assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            }
+            assert!(stream.is_read_close());
+            assert_eq!(stream.peer_close(), Some((CloseCode::NoStatus, &b""[..])));
+        }
+
+        for rlimit in 1..=8 {
+            for wlimit in 1..=8 {
+                // the same code in another form
+                for code in [CloseCode::NoStatus, CloseCode::Other(1005)] {
+                    close::<Client>(code, rlimit, wlimit);
+                    close::<Server>(code, rlimit, wlimit);
+                    close::<StandardClient>(code, rlimit, wlimit);
+                    close::<FixedMaskClient>(code, rlimit, wlimit);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn close_with_reason() {
+        fn close<R: RoleHelper>(rlimit: usize) {
+            let io = LimitReadWriter {
+                buf: Vec::new(),
+                rlimit,
+                wlimit: 256,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, R::new());
+            stream.close(CloseCode::GoingAway, b"bye").unwrap();
+
+            let mut buf = vec![0; 32];
+            while !stream.is_read_end() {
+                assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            }
+            assert_eq!(
+                stream.peer_close(),
+                Some((CloseCode::GoingAway, &b"bye"[..]))
+            );
+        }
+
+        for rlimit in 1..=16 {
+            close::<Client>(rlimit);
+            close::<Server>(rlimit);
+            close::<StandardClient>(rlimit);
+            close::<FixedMaskClient>(rlimit);
+        }
+    }
+
+    #[test]
+    fn close_reason_limit() {
+        fn close<R: RoleHelper>() {
+            let reason = [b'x'; MAX_CLOSE_REASON_LEN + 1];
+
+            // too long, nothing is queued
+            let mut stream = Stream::new(Vec::new(), R::new());
+            let e = stream.close(CloseCode::Normal, &reason).unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<FrameError>(),
+                Some(&FrameError::IllegalData)
+            );
+            assert!(!stream.is_write_close());
+            assert!(stream.as_ref().is_empty());
+
+            // the longest reason
+            stream.close(CloseCode::Normal, &reason[1..]).unwrap();
+            assert!(stream.is_write_close());
+
+            let mask = stream.mask_key();
+            let mut frame = make_head(OpCode::Close, mask, 125);
+            let mut payload = [&[0x03, 0xe8][..], &reason[1..]].concat();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut payload);
+            }
+            frame.append(&mut payload);
+            assert_eq!(stream.as_ref(), &frame);
+        }
+
+        close::<Client>();
+        close::<Server>();
+        close::<StandardClient>();
+        close::<FixedMaskClient>();
+    }
+
+    #[test]
+    fn finalize_stream() {
+        struct FlushIO {
+            buf: Vec<u8>,
+            flushed: usize,
+        }
+
+        impl Write for FlushIO {
+            fn write(&mut self, buf: &[u8]) -> Result<usize> { self.buf.write(buf) }
+
+            fn flush(&mut self) -> Result<()> {
+                self.flushed = self.buf.len();
+                Ok(())
+            }
+        }
+
+        fn finalize<R: RoleHelper>() {
+            let io = FlushIO {
+                buf: Vec::new(),
+                flushed: 0,
+            };
+            let mut stream = Stream::new(io, R::new());
+            stream.finalize().unwrap();
+            assert!(stream.is_write_close());
+
+            // normal closure, flushed
+            let mask = stream.mask_key();
+            let mut frame = make_head(OpCode::Close, mask, 2);
+            let mut payload = [0x03, 0xe8];
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut payload);
+            }
+            frame.extend_from_slice(&payload);
+            assert_eq!(stream.as_ref().buf, frame);
+            assert_eq!(stream.as_ref().flushed, frame.len());
+
+            // reads are untouched
+            assert!(!stream.is_read_end());
+            assert_eq!(stream.peer_close(), None);
+
+            // sent only once
+            stream.finalize().unwrap();
+            assert_eq!(stream.as_ref().buf, frame);
+        }
+
+        finalize::<Client>();
+        finalize::<Server>();
+        finalize::<StandardClient>();
+        finalize::<FixedMaskClient>();
+    }
+
+    #[test]
+    fn abort_stream() {
+        let mut stream = Stream::new(Vec::new(), Client::new());
+        assert_eq!(stream.write(&[0; 16]).unwrap(), 16);
+        let n = stream.as_ref().len();
+
+        // no more bytes are written
+        let io = stream.abort();
+        assert_eq!(io.len(), n);
+    }
+
+    struct ResetIO(ErrorKind);
+
+    impl Read for ResetIO {
+        fn read(&mut self, _: &mut [u8]) -> Result<usize> { Err(self.0.into()) }
+    }
+
+    impl Write for ResetIO {
+        fn write(&mut self, _: &[u8]) -> Result<usize> { Err(self.0.into()) }
+
+        fn flush(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn close_reason_from_io_error() {
+        let mut buf = [0u8; 32];
+
+        let mut stream = Stream::new(ResetIO(ErrorKind::ConnectionReset), Client::new());
+        assert_eq!(stream.close_reason(), None);
+        let e = stream.read(&mut buf).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ConnectionReset);
+        assert_eq!(stream.close_reason(), Some(CloseReason::Reset));
+
+        let mut stream = Stream::new(ResetIO(ErrorKind::BrokenPipe), Client::new());
+        let e = stream.write(&buf).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::BrokenPipe);
+        assert_eq!(stream.close_reason(), Some(CloseReason::Reset));
+
+        // other errors are not related to a closure
+        let mut stream = Stream::new(ResetIO(ErrorKind::WouldBlock), Client::new());
+        stream.read(&mut buf).unwrap_err();
+        stream.write(&buf).unwrap_err();
+        assert_eq!(stream.close_reason(), None);
+    }
+
+    #[test]
+    fn connection_state_transitions() {
+        use std::io::Cursor;
+
+        let mut client = Stream::new(Cursor::new(Vec::new()), Client::new());
+        let mut server = Stream::new(Cursor::new(Vec::new()), Server::new());
+        let mut buf = vec![0; 32];
+        assert_eq!(client.connection_state(), ConnectionState::Open);
+        assert_eq!(server.connection_state(), ConnectionState::Open);
+
+        // client starts the close handshake
+        client.close(CloseCode::Normal, b"").unwrap();
+        assert_eq!(client.connection_state(), ConnectionState::ClosingSent);
+        let e = client.write(b"data").unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<CtrlError>(),
+            Some(&CtrlError::WriteAfterClose)
+        );
+
+        // server receives the close frame
+        *server.as_mut().get_mut() = client.as_ref().get_ref().clone();
+        assert_eq!(server.read(&mut buf).unwrap(), 0);
+        assert_eq!(server.connection_state(), ConnectionState::ClosingReceived);
+
+        // server replies
+        server.as_mut().get_mut().clear();
+        server.as_mut().set_position(0);
+        server.close(CloseCode::Normal, b"").unwrap();
+        assert_eq!(server.connection_state(), ConnectionState::Closed);
+
+        // client receives the reply
+        *client.as_mut() = Cursor::new(server.as_ref().get_ref().clone());
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        assert_eq!(client.connection_state(), ConnectionState::Closed);
+
+        // terminal
+        assert_eq!(client.read(&mut buf).unwrap(), 0);
+        assert_eq!(client.connection_state(), ConnectionState::Closed);
+    }
+
+    #[test]
+    fn connection_state_failed() {
+        let mut buf = vec![0; 32];
+
+        // EOF before the close handshake
+        let mut stream = Stream::new(std::io::empty(), Client::new());
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        assert_eq!(stream.connection_state(), ConnectionState::Failed);
+
+        // connection reset after a close frame is sent
+        let mut stream = Stream::new(ResetIO(ErrorKind::ConnectionReset), Client::new());
+        stream.close.is_queued = true;
+        stream.read(&mut buf).unwrap_err();
+        assert_eq!(stream.connection_state(), ConnectionState::Failed);
+    }
+
+    #[test]
+    fn close_never_on_wire() {
+        let mut stream = Stream::new(Vec::new(), Client::new());
+        assert!(stream.close(CloseCode::Abnormal, b"").is_err());
+        assert!(stream.close(CloseCode::NoStatus, b"bye").is_err());
+        assert!(stream.as_ref().is_empty());
+    }
+}
@@ -0,0 +1,195 @@
+use std::io::{Write, Result};
+use std::task::Poll;
+
+use super::{Stream, RoleHelper};
+use super::detail::write_frame;
+
+use crate::frame::OpCode;
+
+/// Default max size of coalesced payload, which is 16K.
+pub const DEFAULT_COALESCE_LIMIT: usize = 16 * 1024;
+
+/// Payload of consecutive writes, which is emitted as one frame.
+#[derive(Debug)]
+pub(super) struct Coalesce {
+    pub is_enabled: bool,
+    pub buf: Vec<u8>,
+    pub written: usize,
+    pub limit: usize,
+}
+
+impl Coalesce {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            is_enabled: false,
+            buf: Vec::new(),
+            written: 0,
+            limit: DEFAULT_COALESCE_LIMIT,
+        }
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if consecutive writes are coalesced into one frame.
+    #[inline]
+    pub const fn is_coalesce_writes(&self) -> bool { self.coalesce.is_enabled }
+
+    /// Coalesce consecutive writes into one frame, which is disabled by default.
+    ///
+    /// Once enabled, `Stream::write` no longer writes a frame for each call,
+    /// but copies the data into an internal buffer, which requires heap allocation.
+    /// The buffered data are emitted as a single binary frame once `Stream::flush`
+    /// or [`Stream::close`] is called, or the buffer reaches the limit
+    /// (see [`Stream::set_coalesce_limit`]) before the next write.
+    /// A write may accept less data than provided once the buffer is nearly full.
+    ///
+    /// Message boundaries are kept: an explicit frame write, e.g.
+    /// [`Stream::write_raw_frame`], fails with
+    /// [`CtrlError::RawFrameInWrite`](crate::error::CtrlError::RawFrameInWrite)
+    /// until the buffered data are flushed.
+    /// Buffered data are never masked until they are emitted.
+    ///
+    /// This only affects `std::io::Write`, and should be set at a frame boundary.
+    /// Buffered data are still flushed after this is disabled.
+    #[inline]
+    pub fn set_coalesce_writes(&mut self, coalesce: bool) { self.coalesce.is_enabled = coalesce; }
+
+    /// Set the max size of coalesced payload, a value of 0 is treated as 1.
+    /// The default is [`DEFAULT_COALESCE_LIMIT`].
+    #[inline]
+    pub fn set_coalesce_limit(&mut self, limit: usize) {
+        self.coalesce.limit = std::cmp::max(limit, 1);
+    }
+
+    /// Get the count of buffered bytes, which have not been emitted.
+    #[inline]
+    pub fn coalesced_len(&self) -> usize { self.coalesce.buf.len() - self.coalesce.written }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Buffer some data, emit the buffered frame first if it is full.
+    pub(super) fn write_coalesced(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.coalesce.buf.len() >= self.coalesce.limit {
+            self.flush_coalesced()?;
+        }
+        // no more data frames after a close frame
+        if self.close.is_queued {
+            return Err(crate::error::CtrlError::WriteAfterClose.into());
+        }
+        let n = std::cmp::min(buf.len(), self.coalesce.limit - self.coalesce.buf.len());
+        self.coalesce.buf.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    /// Emit buffered data as a single frame, loop until it is completely written.
+    ///
+    /// If the underlying IO source returns an error(e.g. `WouldBlock`),
+    /// progress is saved, a later call continues to write the frame.
+    pub(super) fn flush_coalesced(&mut self) -> Result<()> {
+        let mut buf = std::mem::take(&mut self.coalesce.buf);
+        let mut ret = Ok(());
+        while self.coalesce.written < buf.len() {
+            let offset = self.coalesce.written;
+            match write_frame(
+                self,
+                |io, iovec| io.write_vectored(iovec).into(),
+                OpCode::Binary,
+                &buf[offset..],
+            ) {
+                Poll::Ready(Ok(0)) if self.is_write_zero() => {
+                    ret = Err(std::io::ErrorKind::WriteZero.into());
+                    break;
+                }
+                Poll::Ready(Ok(n)) => self.coalesce.written += n,
+                Poll::Ready(Err(e)) => {
+                    ret = Err(e);
+                    break;
+                }
+                Poll::Pending => unreachable!(),
+            }
+        }
+        if ret.is_ok() {
+            buf.clear();
+            self.coalesce.written = 0;
+        }
+        self.coalesce.buf = buf;
+        ret
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use std::error::Error;
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_frame};
+    use crate::error::CtrlError;
+    use crate::role::*;
+
+    fn coalesce<R: RoleHelper, Guard>(stream: &mut Stream<LimitReadWriter, R, Guard>)
+    where
+        Stream<LimitReadWriter, R, Guard>: Write,
+    {
+        let (frame, data) = make_frame::<R>(OpCode::Binary, 60);
+        stream.set_coalesce_writes(true);
+        assert!(stream.is_coalesce_writes());
+
+        for chunk in data.chunks(20) {
+            assert_eq!(stream.write(chunk).unwrap(), 20);
+        }
+        // nothing is written before a flush
+        assert!(stream.as_ref().buf.is_empty());
+        assert_eq!(stream.coalesced_len(), 60);
+        let e = stream.write_raw_frame(&frame).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<CtrlError>(),
+            Some(&CtrlError::RawFrameInWrite)
+        );
+
+        stream.flush().unwrap();
+        assert_eq!(stream.as_ref().buf, frame);
+        assert_eq!(stream.coalesced_len(), 0);
+
+        // nothing to emit
+        stream.flush().unwrap();
+        assert_eq!(stream.as_ref().buf, frame);
+    }
+
+    #[test]
+    fn coalesce_writes() {
+        for wlimit in [1, 7, 4096] {
+            let io = LimitReadWriter {
+                buf: Vec::new(),
+                rlimit: 0,
+                wlimit,
+                cursor: 0,
+            };
+            coalesce(&mut Stream::new(io, Client::new()));
+
+            let io = LimitReadWriter {
+                buf: Vec::new(),
+                rlimit: 0,
+                wlimit,
+                cursor: 0,
+            };
+            coalesce(&mut Stream::new(io, Server::new()).guard());
+        }
+
+        // emit a frame once the buffer is full
+        let io = LimitReadWriter {
+            buf: Vec::new(),
+            rlimit: 0,
+            wlimit: 4096,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+        let (frame, data) = make_frame::<Server>(OpCode::Binary, 32);
+        stream.set_coalesce_writes(true);
+        stream.set_coalesce_limit(32);
+        assert_eq!(stream.write(&data[..20]).unwrap(), 20);
+        assert_eq!(stream.write(&data[20..]).unwrap(), 12);
+        assert_eq!(stream.write(&data[..20]).unwrap(), 20);
+        assert_eq!(stream.as_ref().buf, frame);
+    }
+}
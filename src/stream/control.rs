@@ -0,0 +1,236 @@
+use std::collections::VecDeque;
+
+use super::Stream;
+use crate::frame::OpCode;
+use crate::error::CtrlError;
+
+/// Default capacity of the control frame queue.
+pub const DEFAULT_CONTROL_QUEUE_SIZE: usize = 16;
+
+/// Policy to handle a control frame when the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControlOverflow {
+    /// Drop the oldest frame, which is the default.
+    #[default]
+    DropOldest,
+    /// Fail the read with [`CtrlError::ControlQueueFull`].
+    Error,
+}
+
+/// Received control frames in manual mode.
+#[derive(Debug)]
+pub(super) struct ControlQueue {
+    pub is_manual: bool,
+    pub frames: VecDeque<(OpCode, Vec<u8>)>,
+    pub capacity: usize,
+    pub overflow: ControlOverflow,
+}
+
+impl ControlQueue {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            is_manual: false,
+            frames: VecDeque::new(),
+            capacity: DEFAULT_CONTROL_QUEUE_SIZE,
+            overflow: ControlOverflow::DropOldest,
+        }
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if control frames are queued for the application.
+    #[inline]
+    pub const fn is_manual_control(&self) -> bool { self.control.is_manual }
+
+    /// Queue received control frames, which are retrieved
+    /// via [`Stream::next_control`].
+    ///
+    /// Once enabled, a `Pong` frame is accepted and queued, rather than
+    /// rejected. A `Ping` frame is queued, and still saved
+    /// (see [`Stream::ping_data`]). A `Close` frame is queued, and
+    /// still ends the stream. Data frames are returned by reads as usual.
+    #[inline]
+    pub fn set_manual_control(&mut self, manual: bool) { self.control.is_manual = manual; }
+
+    /// Set the capacity of the control frame queue, and the policy to
+    /// handle a new frame when the queue is full. The default is
+    /// [`DEFAULT_CONTROL_QUEUE_SIZE`] and [`ControlOverflow::DropOldest`].
+    ///
+    /// A capacity of 0 is treated as 1.
+    #[inline]
+    pub fn set_control_queue(&mut self, capacity: usize, overflow: ControlOverflow) {
+        self.control.capacity = std::cmp::max(capacity, 1);
+        self.control.overflow = overflow;
+    }
+
+    /// Pop the oldest queued control frame, returns its opcode and payload.
+    #[inline]
+    pub fn next_control(&mut self) -> Option<(OpCode, Vec<u8>)> { self.control.frames.pop_front() }
+
+    /// Queue a complete control frame if in manual mode.
+    ///
+    /// A pong is read into the close store, which is not used
+    /// until a close frame arrives, since control frames never interleave.
+    pub(super) fn queue_control(&mut self, opcode: OpCode) -> Result<(), CtrlError> {
+        if !self.control.is_manual {
+            return Ok(());
+        }
+        let payload = match opcode {
+            OpCode::Ping => self.heartbeat.ping_store.read(),
+            _ => self.close.recv_store.read(),
+        }
+        .to_vec();
+        let control = &mut self.control;
+        if control.frames.len() >= control.capacity {
+            match control.overflow {
+                ControlOverflow::DropOldest => {
+                    control.frames.pop_front();
+                }
+                ControlOverflow::Error => return Err(CtrlError::ControlQueueFull),
+            }
+        }
+        control.frames.push_back((opcode, payload));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::error::Error;
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_frame_with_mask};
+    use crate::frame::{Mask, new_mask_key, apply_mask4};
+    use crate::error::FrameError;
+    use crate::role::*;
+
+    type Control = (OpCode, Vec<u8>);
+
+    // frames, control frames, data
+    fn make_frames() -> (Vec<u8>, Vec<Control>, Vec<u8>) {
+        let mut buf = Vec::new();
+        let mut ctrls = Vec::new();
+        let mut data = Vec::new();
+        for (opcode, len) in [
+            (OpCode::Ping, 4),
+            (OpCode::Binary, 100),
+            (OpCode::Pong, 125),
+            (OpCode::Binary, 10),
+            (OpCode::Pong, 0),
+            (OpCode::Close, 2),
+        ] {
+            let key = new_mask_key();
+            let (mut frame, payload) = make_frame_with_mask(opcode, Mask::Key(key), len);
+            let offset = frame.len() - len;
+            apply_mask4(key, &mut frame[offset..]);
+            buf.append(&mut frame);
+            match opcode {
+                OpCode::Binary => data.extend_from_slice(&payload),
+                _ => ctrls.push((opcode, payload)),
+            }
+        }
+        (buf, ctrls, data)
+    }
+
+    fn make_stream(buf: Vec<u8>, rlimit: usize) -> Stream<LimitReadWriter, Server> {
+        let io = LimitReadWriter {
+            buf,
+            rlimit,
+            wlimit: 0,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+        stream.set_manual_control(true);
+        assert!(stream.is_manual_control());
+        stream
+    }
+
+    fn check_queue<Guard>(stream: &mut Stream<LimitReadWriter, Server, Guard>, ctrls: &[Control]) {
+        for ctrl in ctrls {
+            assert_eq!(stream.next_control().as_ref(), Some(ctrl));
+        }
+        assert_eq!(stream.next_control(), None);
+        assert!(stream.is_read_close());
+        assert_eq!(stream.ping_data(), &ctrls[0].1);
+    }
+
+    #[test]
+    fn manual_control() {
+        let (frames, ctrls, data) = make_frames();
+        for rlimit in [1, 7, 64, 4096] {
+            // stream read
+            let mut stream = make_stream(frames.clone(), rlimit);
+            let mut buf = vec![0; 4096];
+            let mut data2 = Vec::new();
+            while !stream.is_read_end() {
+                let n = stream.read(&mut buf).unwrap();
+                data2.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(data2, data);
+            check_queue(&mut stream, &ctrls);
+            // nothing is replied
+            assert_eq!(stream.as_ref().buf, frames);
+
+            // data read
+            let mut stream = make_stream(frames.clone(), rlimit);
+            let mut data2 = Vec::new();
+            loop {
+                let (opcode, n) = stream.read_data(&mut buf).unwrap();
+                if opcode == OpCode::Close {
+                    break;
+                }
+                data2.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(data2, data);
+            check_queue(&mut stream, &ctrls);
+        }
+
+        // pong is rejected by default
+        let mut stream = make_stream(frames.clone(), 4096);
+        stream.set_manual_control(false);
+        let mut buf = vec![0; 4096];
+        let e = loop {
+            if let Err(e) = stream.read(&mut buf) {
+                break e;
+            }
+        };
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::UnsupportedOpcode)
+        );
+        assert_eq!(stream.next_control(), None);
+    }
+
+    #[test]
+    fn control_queue_overflow() {
+        let (frames, ctrls, data) = make_frames();
+        let mut buf = vec![0; 4096];
+
+        // keep the latest
+        let mut stream = make_stream(frames.clone(), 4096);
+        stream.set_control_queue(1, ControlOverflow::DropOldest);
+        let mut data2 = Vec::new();
+        while !stream.is_read_end() {
+            let n = stream.read(&mut buf).unwrap();
+            data2.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(data2, data);
+        assert_eq!(stream.next_control().as_ref(), ctrls.last());
+        assert_eq!(stream.next_control(), None);
+
+        // fail on the second frame
+        let mut stream = make_stream(frames, 4096);
+        stream.set_control_queue(1, ControlOverflow::Error);
+        let e = loop {
+            if let Err(e) = stream.read(&mut buf) {
+                break e;
+            }
+        };
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<CtrlError>(),
+            Some(&CtrlError::ControlQueueFull)
+        );
+        assert_eq!(stream.next_control().as_ref(), ctrls.first());
+    }
+}
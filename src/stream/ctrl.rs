@@ -1,9 +1,26 @@
 use super::Stream;
 use super::state::WriteState;
 
-use crate::frame::Mask;
+use crate::frame::{Fin, Mask, OpCode, CloseCode, decode_close_payload};
 use crate::role::RoleHelper;
-use crate::error::CtrlError;
+use crate::error::{CtrlError, FrameError};
+use crate::handshake::Extensions;
+
+/// Policy to handle a continuation frame which does not continue
+/// any message, see [`Stream::set_orphan_continuation_policy`].
+///
+/// Only [`OrphanContinuation::Error`] is compliant with the spec,
+/// others are used to work with broken peers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OrphanContinuation {
+    /// Fail the read with [`FrameError::IllegalContinuation`], which is the default.
+    #[default]
+    Error,
+    /// Treat the frame as a `Binary` frame, which begins a new message.
+    TreatAsBinary,
+    /// Drop the frame, whose payload is read and discarded.
+    Ignore,
+}
 
 impl<IO, Role, Guard> Stream<IO, Role, Guard>
 where
@@ -26,4 +43,360 @@ where
         }
         Err(CtrlError::SetMaskInWrite)
     }
+
+    /// Get extensions negotiated during the handshake.
+    #[inline]
+    pub const fn extensions(&self) -> &Extensions { &self.extensions }
+
+    /// Set extensions negotiated during the handshake.
+    /// This is usually used after a handshake with detailed control,
+    /// see [`Response::extensions`](crate::handshake::Response::extensions).
+    ///
+    /// Extensions only take effect on stream wrappers which handle them.
+    #[inline]
+    pub fn set_extensions(&mut self, extensions: Extensions) { self.extensions = extensions; }
+
+    /// Set the max count of frames to receive during the connection lifetime.
+    /// Once exceeded, a read will fail with
+    /// [`FrameError::ExceedFrameLimit`](crate::error::FrameError::ExceedFrameLimit),
+    /// and a `Close` frame with [`CloseCode::PolicyViolation`] is queued,
+    /// to be written by [`Stream::send_close`] or [`Stream::close`].
+    /// The stream should not be read any more after that.
+    ///
+    /// A value of 0 means unlimited, which is the default.
+    #[inline]
+    pub fn set_max_total_frames(&mut self, n: u64) { self.limit.max_frames = n; }
+
+    /// Set the max count of payload bytes to receive during the connection lifetime.
+    /// Once exceeded, a read will fail with
+    /// [`FrameError::ExceedPayloadLimit`](crate::error::FrameError::ExceedPayloadLimit),
+    /// and a `Close` frame with [`CloseCode::PolicyViolation`] is queued,
+    /// the same as [`Stream::set_max_total_frames`].
+    ///
+    /// A value of 0 means unlimited, which is the default.
+    #[inline]
+    pub fn set_max_total_payload_bytes(&mut self, n: u64) { self.limit.max_payload_bytes = n; }
+
+    /// Check a new data frame against the fragmented message.
+    /// Returns true if it is an orphan continuation frame to be ignored.
+    ///
+    /// A `Close` frame with [`CloseCode::ProtocolError`] is queued on error.
+    pub(super) fn check_data_frame(
+        &mut self,
+        fin: Fin,
+        opcode: OpCode,
+    ) -> Result<bool, FrameError> {
+        // a new message must not begin before
+        // the fragmented one is finished
+        if opcode != OpCode::Continue && self.fragment.is_fragmented {
+            return Err(self.protocol_violation(FrameError::IllegalFragment));
+        }
+        // a message must begin with a text or binary frame
+        self.fragment.is_ignored = false;
+        if opcode == OpCode::Continue && !self.fragment.is_fragmented {
+            match self.orphan {
+                _ if self.strict => {
+                    return Err(self.protocol_violation(FrameError::IllegalContinuation))
+                }
+                OrphanContinuation::Error => {
+                    return Err(self.protocol_violation(FrameError::IllegalContinuation))
+                }
+                OrphanContinuation::TreatAsBinary => self.fragment.opcode = OpCode::Binary,
+                OrphanContinuation::Ignore => {
+                    self.fragment.is_ignored = true;
+                    return Ok(true);
+                }
+            }
+        }
+        self.fragment.is_fragmented = fin == Fin::N;
+        Ok(false)
+    }
+
+    /// Check the payload of a received `Close` frame, see [`decode_close_payload`].
+    ///
+    /// A `Close` frame with [`CloseCode::ProtocolError`] is queued on error.
+    pub(super) fn check_close_payload(&mut self) -> Result<(), FrameError> {
+        match decode_close_payload(self.close.recv_store.read()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(self.protocol_violation(e)),
+        }
+    }
+
+    /// Queue a `Close` frame with [`CloseCode::ProtocolError`], which is
+    /// written by the next [`Stream::send_close`] or [`Stream::close`].
+    #[inline]
+    fn protocol_violation(&mut self, e: FrameError) -> FrameError {
+        self.queue_violation(CloseCode::ProtocolError, e)
+    }
+
+    /// Queue a `Close` frame with `code`, then return `e`.
+    ///
+    /// Nothing is queued if a close is already queued, or a frame is
+    /// partially written, where the caller has to abort the connection.
+    pub(super) fn queue_violation(&mut self, code: CloseCode, e: FrameError) -> FrameError {
+        if !self.close.is_queued {
+            let _ = self.queue_close(code, b"");
+        }
+        e
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if frames are checked strictly, which is the default.
+    #[inline]
+    pub const fn is_strict(&self) -> bool { self.strict }
+
+    /// Set whether to check frames strictly, which is the default.
+    ///
+    /// Lenient mode is used to work with legacy or buggy peers, where only
+    /// these relaxations are applied, others are still rejected:
+    ///
+    /// - A control frame with `FIN` unset is treated as if `FIN` were set,
+    ///   since a control frame can never be fragmented. Strict mode rejects it
+    ///   with [`FrameError::IllegalFin`].
+    /// - An unsolicited `Pong` frame is read and ignored. Strict mode rejects it
+    ///   with [`FrameError::UnsupportedOpcode`], unless control frames are
+    ///   queued (see [`Stream::set_manual_control`]).
+    /// - An orphan continuation frame is handled as set by
+    ///   [`Stream::set_orphan_continuation_policy`], which rejects it by default.
+    ///   Strict mode always rejects it with [`FrameError::IllegalContinuation`].
+    #[inline]
+    pub fn set_strict(&mut self, strict: bool) { self.strict = strict; }
+
+    /// Get the policy to handle an orphan continuation frame.
+    #[inline]
+    pub const fn orphan_continuation_policy(&self) -> OrphanContinuation { self.orphan }
+
+    /// Set the policy to handle a continuation frame which does not continue
+    /// any message, the default is [`OrphanContinuation::Error`].
+    ///
+    /// This only takes effect in lenient mode(see [`Stream::set_strict`]).
+    /// Other policies are **NOT** compliant with the spec, which are only
+    /// used to talk to a broken peer. An ignored frame is never returned,
+    /// where a non-final one leaves later continuation frames orphaned,
+    /// which are handled in the same way.
+    #[inline]
+    pub fn set_orphan_continuation_policy(&mut self, policy: OrphanContinuation) {
+        self.orphan = policy;
+    }
+
+    /// Check the `FIN` flag of a control frame.
+    ///
+    /// A control frame may appear between fragments of a message,
+    /// where it is read as a whole before the next fragment.
+    #[inline]
+    pub(super) const fn check_ctrl_fin(&self, fin: Fin) -> Result<(), FrameError> {
+        if self.strict && matches!(fin, Fin::N) {
+            return Err(FrameError::IllegalFin);
+        }
+        Ok(())
+    }
+
+    /// Check if a `Pong` frame is accepted, which is expected
+    /// once a liveness timeout is set.
+    #[inline]
+    pub(super) const fn accept_pong(&self) -> bool {
+        self.control.is_manual || !self.strict || self.liveness.timeout.is_some()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use std::error::Error;
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_head_with_fin, make_data};
+    use crate::frame::OpCode;
+    use crate::role::*;
+
+    // frames, data
+    fn make_frames(ctrl: (Fin, OpCode)) -> (Vec<u8>, Vec<u8>) {
+        let mut buf = Vec::new();
+        let mut data = Vec::new();
+        for (fin, opcode, len) in [
+            (Fin::Y, OpCode::Binary, 10),
+            (ctrl.0, ctrl.1, 4),
+            (Fin::Y, OpCode::Binary, 5),
+        ] {
+            let payload = make_data(len);
+            buf.extend_from_slice(&make_head_with_fin(fin, opcode, Mask::None, len));
+            buf.extend_from_slice(&payload);
+            if opcode == OpCode::Binary {
+                data.extend_from_slice(&payload);
+            }
+        }
+        (buf, data)
+    }
+
+    fn make_stream(buf: Vec<u8>, strict: bool) -> Stream<LimitReadWriter, Client> {
+        let io = LimitReadWriter {
+            buf,
+            rlimit: 4096,
+            wlimit: 4096,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Client::new());
+        assert!(stream.is_strict());
+        stream.set_strict(strict);
+        assert_eq!(stream.is_strict(), strict);
+        stream
+    }
+
+    fn read_all(stream: &mut Stream<LimitReadWriter, Client>) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; 4096];
+        let mut data = Vec::new();
+        while !stream.is_read_end() {
+            let n = stream.read(&mut buf)?;
+            data.extend_from_slice(&buf[..n]);
+        }
+        Ok(data)
+    }
+
+    fn read_all_data(stream: &mut Stream<LimitReadWriter, Client>) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; 4096];
+        let mut data = Vec::new();
+        for _ in 0..2 {
+            let (opcode, n) = stream.read_data(&mut buf)?;
+            assert_eq!(opcode, OpCode::Binary);
+            data.extend_from_slice(&buf[..n]);
+        }
+        Ok(data)
+    }
+
+    fn read_all_messages(stream: &mut Stream<LimitReadWriter, Client>) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0; 4096];
+        let mut data = Vec::new();
+        loop {
+            let (opcode, n) = stream.read_data(&mut buf)?;
+            if opcode == OpCode::Close {
+                return Ok(data);
+            }
+            assert_eq!(opcode, OpCode::Binary);
+            data.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    #[test]
+    fn lenient_ctrl_fin() {
+        let (frames, data) = make_frames((Fin::N, OpCode::Ping));
+        for read in [read_all, read_all_data] {
+            let e = read(&mut make_stream(frames.clone(), true)).unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<FrameError>(),
+                Some(&FrameError::IllegalFin)
+            );
+
+            let mut stream = make_stream(frames.clone(), false);
+            assert_eq!(read(&mut stream).unwrap(), data);
+            assert_eq!(stream.ping_data(), &frames[14..18]);
+        }
+    }
+
+    #[test]
+    fn lenient_pong() {
+        let (frames, data) = make_frames((Fin::Y, OpCode::Pong));
+        for read in [read_all, read_all_data] {
+            let e = read(&mut make_stream(frames.clone(), true)).unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<FrameError>(),
+                Some(&FrameError::UnsupportedOpcode)
+            );
+
+            let mut stream = make_stream(frames.clone(), false);
+            assert_eq!(read(&mut stream).unwrap(), data);
+            // nothing is replied, nor queued
+            assert_eq!(stream.as_ref().buf, frames);
+            assert_eq!(stream.next_control(), None);
+        }
+    }
+
+    #[test]
+    fn lenient_orphan_continuation() {
+        // an orphan continuation frame, then a message
+        let mut frames = Vec::new();
+        let mut payloads = Vec::new();
+        for (opcode, len) in [(OpCode::Continue, 300), (OpCode::Binary, 5)] {
+            let payload = make_data(len);
+            frames.extend_from_slice(&make_head_with_fin(Fin::Y, opcode, Mask::None, len));
+            frames.extend_from_slice(&payload);
+            payloads.push(payload);
+        }
+
+        for read in [read_all, read_all_messages] {
+            for rlimit in [7, 4096] {
+                for (strict, policy) in [
+                    (true, OrphanContinuation::TreatAsBinary),
+                    (true, OrphanContinuation::Ignore),
+                    (false, OrphanContinuation::Error),
+                ] {
+                    let mut stream = make_stream(frames.clone(), strict);
+                    stream.as_mut().rlimit = rlimit;
+                    stream.set_orphan_continuation_policy(policy);
+                    let e = read(&mut stream).unwrap_err();
+                    assert_eq!(
+                        e.source().unwrap().downcast_ref::<FrameError>(),
+                        Some(&FrameError::IllegalContinuation)
+                    );
+                }
+
+                let mut stream = make_stream(frames.clone(), false);
+                stream.as_mut().rlimit = rlimit;
+                assert_eq!(
+                    stream.orphan_continuation_policy(),
+                    OrphanContinuation::Error
+                );
+                stream.set_orphan_continuation_policy(OrphanContinuation::TreatAsBinary);
+                assert_eq!(read(&mut stream).unwrap(), payloads.concat());
+
+                let mut stream = make_stream(frames.clone(), false);
+                stream.as_mut().rlimit = rlimit;
+                stream.set_orphan_continuation_policy(OrphanContinuation::Ignore);
+                assert_eq!(read(&mut stream).unwrap(), payloads[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn ctrl_between_fragments() {
+        use crate::frame::CloseCode;
+
+        // a fragmented message, with a ping in the middle
+        fn make_message(ping_fin: Fin) -> (Vec<u8>, Vec<u8>) {
+            let mut buf = Vec::new();
+            let mut data = Vec::new();
+            for (fin, opcode, len) in [
+                (Fin::N, OpCode::Binary, 10),
+                (ping_fin, OpCode::Ping, 4),
+                (Fin::Y, OpCode::Continue, 5),
+            ] {
+                let payload = make_data(len);
+                buf.extend_from_slice(&make_head_with_fin(fin, opcode, Mask::None, len));
+                buf.extend_from_slice(&payload);
+                if opcode != OpCode::Ping {
+                    data.extend_from_slice(&payload);
+                }
+            }
+            (buf, data)
+        }
+
+        for read in [read_all, read_all_messages] {
+            for rlimit in 1..=32 {
+                let (frames, data) = make_message(Fin::Y);
+                let mut stream = make_stream(frames.clone(), true);
+                stream.as_mut().rlimit = rlimit;
+                assert_eq!(read(&mut stream).unwrap(), data);
+                assert_eq!(stream.ping_data(), &frames[14..18]);
+                assert!(!stream.is_read_fragmented());
+
+                let (frames, _) = make_message(Fin::N);
+                let mut stream = make_stream(frames, true);
+                stream.as_mut().rlimit = rlimit;
+                let e = read(&mut stream).unwrap_err();
+                let e = e.source().unwrap().downcast_ref::<FrameError>().unwrap();
+                assert_eq!(e, &FrameError::IllegalFin);
+                assert_eq!(e.close_code(), CloseCode::ProtocolError);
+                assert!(stream.ping_data().is_empty());
+            }
+        }
+    }
 }
@@ -1,11 +1,20 @@
 mod read;
 mod write;
 
-pub(super) use read::read_some;
-pub(super) use write::write_some;
+pub(super) use read::{read_some, read_payload_vectored};
+pub(super) use write::{write_some, write_frame, write_pong, write_ctrl};
+#[cfg(feature = "async")]
+pub(super) use write::write_frame_vectored;
+
+use std::io::{self, ErrorKind};
+
+use super::RoleHelper;
+use crate::frame::FrameHead;
+use crate::frame::mask::{apply_mask4, rotate_mask_key};
+use crate::error::FrameError;
 
 #[inline]
-fn min_len(buf_len: usize, length: u64) -> usize {
+pub(super) fn min_len(buf_len: usize, length: u64) -> usize {
     #[cfg(target_pointer_width = "64")]
     {
         std::cmp::min(buf_len, length as usize)
@@ -17,3 +26,155 @@ fn min_len(buf_len: usize, length: u64) -> usize {
         std::cmp::min(buf_len, next)
     }
 }
+
+/// Convert a payload length to `usize`, which saturates at `usize::MAX`
+/// rather than wraps on 32-bit platforms.
+#[inline]
+pub(super) fn saturate_len(length: u64) -> usize { min_len(usize::MAX, length) }
+
+/// Interpret an `Ok(0)` from the underlying IO source as a `WouldBlock`
+/// error rather than `EOF`, unless it is configured to be `EOF`,
+/// see [`Stream::set_zero_read_is_eof`](super::Stream::set_zero_read_is_eof).
+#[inline]
+pub(super) fn check_zero_read(
+    read_n: io::Result<usize>,
+    zero_read_is_eof: bool,
+) -> io::Result<usize> {
+    match read_n {
+        Ok(0) if !zero_read_is_eof => Err(ErrorKind::WouldBlock.into()),
+        x => x,
+    }
+}
+
+/// Unmask payload data, continue from the `offset`th byte of the payload.
+///
+/// An all-zero key is skipped, which is usually decoded as
+/// [`Mask::Skip`](crate::frame::Mask::Skip) in the first place.
+#[inline]
+pub(super) fn unmask(key: [u8; 4], offset: usize, buf: &mut [u8]) {
+    if u32::from_ne_bytes(key) == 0 {
+        return;
+    }
+    apply_mask4(rotate_mask_key(key, offset), buf);
+}
+
+/// Parse an inbound frame head.
+///
+/// A server expects masked frames, where the mask key is read
+/// unconditionally. The role is resolved at compile time.
+#[inline]
+pub(super) fn decode_head<Role: RoleHelper>(buf: &[u8]) -> Result<(FrameHead, usize), FrameError> {
+    if Role::READ_MASKED {
+        match FrameHead::decode_masked(buf) {
+            // unmasked frames are still accepted,
+            // e.g. a server reads what it wrote
+            Err(FrameError::IllegalMask) => decode_unmasked(buf),
+            x => x,
+        }
+    } else {
+        FrameHead::decode(buf)
+    }
+}
+
+#[cold]
+fn decode_unmasked(buf: &[u8]) -> Result<(FrameHead, usize), FrameError> { FrameHead::decode(buf) }
+
+/// Emit an event for each frame head,
+/// this is compiled out without `tracing` feature.
+#[inline]
+pub(super) fn trace_head(_direction: &'static str, _head: &FrameHead) {
+    #[cfg(feature = "tracing")]
+    tracing::trace!(
+        direction = _direction,
+        fin = ?_head.fin,
+        opcode = ?_head.opcode,
+        length = _head.length.to_num(),
+        masked = !matches!(_head.mask, crate::frame::Mask::None),
+        "frame head"
+    );
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test {
+    use super::super::Stream;
+    use crate::frame::{Fin, OpCode};
+    use crate::role::*;
+
+    use std::io::{Read, Write};
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    #[derive(Default)]
+    struct Collector {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct FieldVisitor(String);
+
+    impl Visit for FieldVisitor {
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() != "message" {
+                self.0 += &format!("{}={:?} ", field.name(), value);
+            }
+        }
+    }
+
+    impl Subscriber for Collector {
+        fn enabled(&self, _: &Metadata<'_>) -> bool { true }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id { Id::from_u64(1) }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut visitor = FieldVisitor(String::new());
+            event.record(&mut visitor);
+            self.events.lock().unwrap().push(visitor.0);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn trace_frame_head() {
+        let collector = Collector::default();
+        let events = collector.events.clone();
+
+        tracing::subscriber::with_default(collector, || {
+            let mut stream = Stream::new(Vec::new(), Client::new());
+            assert_eq!(stream.write(b"hello").unwrap(), 5);
+            assert_eq!(stream.write(b"world!").unwrap(), 6);
+
+            let mut stream = Stream::new(stream.as_ref().as_slice(), Server::new());
+            let mut buf = vec![0; 64];
+            let read_n = stream.read(&mut buf).unwrap();
+            assert_eq!(&buf[..read_n], b"helloworld!");
+        });
+
+        let expect = |direction: &str, len: u64| {
+            format!(
+                "direction=\"{}\" fin={:?} opcode={:?} length={} masked=true ",
+                direction,
+                Fin::Y,
+                OpCode::Binary,
+                len
+            )
+        };
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                expect("write", 5),
+                expect("write", 6),
+                expect("read", 5),
+                expect("read", 6)
+            ]
+        );
+    }
+}
@@ -1,14 +1,35 @@
-use std::io::Result;
+use std::io::{IoSliceMut, Result};
 use std::task::{Poll, ready};
 
-use super::min_len;
+use super::{min_len, trace_head, decode_head, unmask, check_zero_read};
 use super::super::{Stream, RoleHelper};
 use super::super::state::{ReadState, HeadStore};
+use super::super::close::{record_io_error, truncated_eof};
+use super::super::replay::Replay;
 
 use crate::frame::{FrameHead, Mask, OpCode};
-use crate::frame::mask::apply_mask4;
 use crate::error::FrameError;
 
+/// Read from the bytes read ahead(if any), otherwise the IO source.
+#[inline]
+fn read_io<F, IO>(
+    replay: &mut Replay,
+    io: &mut IO,
+    count: &mut u64,
+    read: &mut F,
+    buf: &mut [u8],
+) -> Poll<Result<usize>>
+where
+    F: FnMut(&mut IO, &mut [u8]) -> Poll<Result<usize>>,
+{
+    if replay.is_empty() {
+        *count += 1;
+        read(io, buf)
+    } else {
+        Poll::Ready(Ok(replay.read(buf)))
+    }
+}
+
 pub fn read_some<F, IO, Role, Guard>(
     mut stream: &mut Stream<IO, Role, Guard>,
     mut read: F,
@@ -20,6 +41,10 @@ where
 {
     debug_assert!(buf.len() >= 14);
 
+    if let Err(e) = stream.check_liveness() {
+        return Poll::Ready(Err(e));
+    }
+
     loop {
         match stream.read_state {
             // always returns 0
@@ -35,14 +60,29 @@ where
                     left.copy_from_slice(head_store.read());
                 }
 
-                let read_n = ready!(read(&mut stream.io, &mut buf[head_store_len..]))?;
+                let read_n = check_zero_read(
+                    ready!(read_io(
+                        &mut stream.replay,
+                        &mut stream.io,
+                        &mut stream.stats.read_syscalls,
+                        &mut read,
+                        &mut buf[head_store_len..]
+                    )),
+                    stream.zero_read_is_eof,
+                )
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
 
                 // EOF ?
                 if read_n == 0 {
                     stream.read_state = ReadState::Eof;
+                    // in the middle of a frame head
+                    if head_store_len != 0 {
+                        return Poll::Ready(Err(truncated_eof(&mut stream.close.reason)));
+                    }
                     return Poll::Ready(Ok(0));
                 }
 
+                stream.partial = None;
                 stream.read_state = ReadState::ProcessBuf {
                     beg: 0,
                     end: read_n + head_store_len,
@@ -50,18 +90,35 @@ where
                 }
             }
             // continue to read data from the same frame
-            ReadState::ReadData { next, mask } => {
-                let read_n = ready!(read(&mut stream.io, buf))?;
-                // EOF ?
+            ReadState::ReadData { next, mask, offset } => {
+                let read_n = check_zero_read(
+                    ready!(read_io(
+                        &mut stream.replay,
+                        &mut stream.io,
+                        &mut stream.stats.read_syscalls,
+                        &mut read,
+                        buf
+                    )),
+                    stream.zero_read_is_eof,
+                )
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+                // EOF in the middle of a frame
                 if read_n == 0 {
                     stream.read_state = ReadState::Eof;
-                    return Poll::Ready(Ok(0));
+                    return Poll::Ready(Err(truncated_eof(&mut stream.close.reason)));
                 }
                 let len = min_len(read_n, next);
+                // discarded payload is never returned
+                let processed = if stream.discard_payloads || stream.fragment.is_ignored {
+                    0
+                } else {
+                    len
+                };
                 // unmask if server receives data from client
                 // this operation can be skipped if mask key is 0
+                // continue from the last offset of mask key
                 if let Mask::Key(key) = mask {
-                    apply_mask4(key, &mut buf[..len])
+                    unmask(key, offset as usize, &mut buf[..processed])
                 };
                 // read complete ?
                 if next > read_n as u64 {
@@ -69,34 +126,47 @@ where
                     stream.read_state = ReadState::ReadData {
                         next: next - read_n as u64,
                         mask,
+                        offset: ((offset as usize + read_n) & 0x03) as u8,
                     };
-                    return Poll::Ready(Ok(read_n));
+                    return Poll::Ready(Ok(processed));
                 } else {
                     // continue to process
                     stream.read_state = ReadState::ProcessBuf {
                         beg: len,
                         end: read_n,
-                        processed: len,
+                        processed,
                     }
                 }
             }
             // continue to read data from a ctrl frame
             ReadState::ReadPing { next, mask } => {
+                let offset = stream.heartbeat.ping_store.wr_pos();
                 let (buf, _) = stream
                     .heartbeat
                     .ping_store
                     .write()
                     .split_at_mut(next as usize);
-                let read_n = ready!(read(&mut stream.io, buf))?;
-                // EOF ?
+                let read_n = check_zero_read(
+                    ready!(read_io(
+                        &mut stream.replay,
+                        &mut stream.io,
+                        &mut stream.stats.read_syscalls,
+                        &mut read,
+                        buf
+                    )),
+                    stream.zero_read_is_eof,
+                )
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+                // EOF in the middle of a frame
                 if read_n == 0 {
                     stream.read_state = ReadState::Eof;
-                    return Poll::Ready(Ok(0));
+                    return Poll::Ready(Err(truncated_eof(&mut stream.close.reason)));
                 }
                 // unmask if server receives data from client
                 // this operation can be skipped if mask key is 0
+                // continue from the last offset of mask key
                 if let Mask::Key(key) = mask {
-                    apply_mask4(key, buf);
+                    unmask(key, offset, &mut buf[..read_n]);
                 };
 
                 stream.heartbeat.ping_store.advance_wr_pos(read_n);
@@ -105,6 +175,7 @@ where
                 if next == read_n as u8 {
                     stream.heartbeat.is_complete = true;
                     stream.read_state = ReadState::new();
+                    stream.queue_control(OpCode::Ping)?;
                 } else {
                     stream.read_state = ReadState::ReadPing {
                         next: next - read_n as u8,
@@ -113,22 +184,57 @@ where
                 }
                 return Poll::Ready(Ok(0));
             }
+            // continue to read data from a close frame
+            ReadState::ReadClose { next, mask } => {
+                let offset = stream.close.recv_store.wr_pos();
+                let (buf, _) = stream.close.recv_store.write().split_at_mut(next as usize);
+                let read_n = check_zero_read(
+                    ready!(read_io(
+                        &mut stream.replay,
+                        &mut stream.io,
+                        &mut stream.stats.read_syscalls,
+                        &mut read,
+                        buf
+                    )),
+                    stream.zero_read_is_eof,
+                )
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+                // EOF in the middle of a frame
+                if read_n == 0 {
+                    stream.read_state = ReadState::Eof;
+                    return Poll::Ready(Err(truncated_eof(&mut stream.close.reason)));
+                }
+                if let Mask::Key(key) = mask {
+                    unmask(key, offset, &mut buf[..read_n]);
+                };
+
+                stream.close.recv_store.advance_wr_pos(read_n);
+
+                // read complete ?
+                if next == read_n as u8 && stream.close.is_pong {
+                    stream.read_state = ReadState::new();
+                    stream.queue_control(OpCode::Pong)?;
+                } else if next == read_n as u8 {
+                    stream.read_state = ReadState::Close;
+                    stream.check_close_payload()?;
+                    stream.close.is_received = true;
+                    stream.queue_control(OpCode::Close)?;
+                } else {
+                    stream.read_state = ReadState::ReadClose {
+                        next: next - read_n as u8,
+                        mask,
+                    };
+                }
+                return Poll::Ready(Ok(0));
+            }
             // handle the read data in user provided buffer
             ReadState::ProcessBuf {
                 mut beg,
                 end,
                 mut processed,
             } => {
-                // parse head, fin is ignored
-                let (
-                    FrameHead {
-                        opcode,
-                        mask,
-                        length,
-                        ..
-                    },
-                    parse_n,
-                ) = match FrameHead::decode(&buf[beg..end]) {
+                // parse head
+                let (head, parse_n) = match decode_head::<Role>(&buf[beg..end]) {
                     Ok(x) => x,
                     Err(ref e) if *e == FrameError::NotEnoughData => {
                         if beg == end {
@@ -141,26 +247,53 @@ where
                     }
                     Err(e) => return Poll::Ready(Err(e.into())),
                 };
+                trace_head("read", &head);
+                let FrameHead {
+                    fin,
+                    rsv,
+                    opcode,
+                    mask,
+                    length,
+                } = head;
+
+                // no extension is negotiated
+                if !rsv.is_empty() {
+                    return Poll::Ready(Err(FrameError::IllegalRsv.into()));
+                }
+
                 // point to payload
                 beg += parse_n;
 
                 // may read a frame without payload
                 let frame_len = length.to_num();
+
+                // check limits
+                stream.count_read_frame(parse_n, frame_len)?;
                 let buf_len = end - beg;
                 let data_len = min_len(buf_len, frame_len);
 
                 match opcode {
                     // text is not allowed
-                    // we never send a ping, so we ignore the pong
-                    OpCode::Text | OpCode::Pong => {
+                    // we never send a ping, so we ignore the pong, unless control
+                    // frames are queued for the application or pings are sent
+                    // with a liveness timeout
+                    OpCode::Text => return Poll::Ready(Err(FrameError::UnsupportedOpcode.into())),
+                    OpCode::Pong if !stream.accept_pong() => {
                         return Poll::Ready(Err(FrameError::UnsupportedOpcode.into()));
                     }
-                    // ignore fin flag
                     OpCode::Binary | OpCode::Continue => {
-                        if data_len != 0 {
+                        // payload of an ignored frame is discarded
+                        let is_discarded =
+                            stream.check_data_frame(fin, opcode)? || stream.discard_payloads;
+                        if opcode == OpCode::Binary {
+                            stream.fragment.opcode = opcode;
+                        }
+
+                        // discarded payload is never moved
+                        if data_len != 0 && !is_discarded {
                             // unmask payload data from client
                             if let Mask::Key(key) = mask {
-                                apply_mask4(key, &mut buf[beg..beg + data_len]);
+                                unmask(key, 0, &mut buf[beg..beg + data_len]);
                             }
                             // move forward
                             unsafe {
@@ -172,12 +305,15 @@ where
                             };
                         }
                         beg += data_len;
-                        processed += data_len;
+                        if !is_discarded {
+                            processed += data_len;
+                        }
                         // need to read more payload
                         if frame_len > buf_len as u64 {
                             stream.read_state = ReadState::ReadData {
                                 next: frame_len - data_len as u64,
                                 mask,
+                                offset: (data_len & 0x03) as u8,
                             };
                             return Poll::Ready(Ok(processed));
                         }
@@ -193,10 +329,12 @@ where
                         if frame_len > 125 {
                             return Poll::Ready(Err(FrameError::IllegalData.into()));
                         }
+                        // nor be fragmented
+                        stream.check_ctrl_fin(fin)?;
                         if data_len != 0 {
                             // unmask payload data from client
                             if let Mask::Key(key) = mask {
-                                apply_mask4(key, &mut buf[beg..beg + data_len]);
+                                unmask(key, 0, &mut buf[beg..beg + data_len]);
                             }
                             // save ping data
                             stream
@@ -227,9 +365,48 @@ where
                             end,
                             processed,
                         };
+                        stream.queue_control(OpCode::Ping)?;
                     }
-                    OpCode::Close => {
+                    OpCode::Close | OpCode::Pong => {
+                        // a close frame must not have extened data
+                        if frame_len > 125 {
+                            return Poll::Ready(Err(FrameError::IllegalData.into()));
+                        }
+                        // nor be fragmented
+                        stream.check_ctrl_fin(fin)?;
+                        // unmask payload data from client
+                        if let Mask::Key(key) = mask {
+                            unmask(key, 0, &mut buf[beg..beg + data_len]);
+                        }
+                        // save close payload
+                        stream
+                            .close
+                            .recv_store
+                            .replace_with_data(&buf[beg..beg + data_len]);
+                        stream.close.is_pong = opcode == OpCode::Pong;
+
+                        // need to read more payload
+                        if frame_len > buf_len as u64 {
+                            stream.read_state = ReadState::ReadClose {
+                                next: frame_len as u8 - data_len as u8,
+                                mask,
+                            };
+                            return Poll::Ready(Ok(processed));
+                        }
+                        // continue to process after a pong
+                        if opcode == OpCode::Pong {
+                            stream.read_state = ReadState::ProcessBuf {
+                                beg: beg + data_len,
+                                end,
+                                processed,
+                            };
+                            stream.queue_control(OpCode::Pong)?;
+                            continue;
+                        }
                         stream.read_state = ReadState::Close;
+                        stream.check_close_payload()?;
+                        stream.close.is_received = true;
+                        stream.queue_control(OpCode::Close)?;
                         return Poll::Ready(Ok(processed));
                     }
                 }
@@ -237,3 +414,77 @@ where
         }
     }
 }
+
+/// Max count of buffers to scatter payload into.
+const MAX_IOSLICES: usize = 16;
+
+pub fn read_payload_vectored<F, IO, Role, Guard>(
+    stream: &mut Stream<IO, Role, Guard>,
+    mut read: F,
+    bufs: &mut [IoSliceMut<'_>],
+) -> Poll<Result<usize>>
+where
+    F: FnMut(&mut IO, &mut [IoSliceMut<'_>]) -> Poll<Result<usize>>,
+{
+    let (next, mask, offset) = match stream.read_state {
+        ReadState::ReadData { next, mask, offset } => (next, mask, offset),
+        _ => unreachable!(),
+    };
+
+    // never read beyond the frame
+    let mut left = next;
+    let mut iter = bufs.iter_mut();
+    let mut slices: [IoSliceMut; MAX_IOSLICES] = std::array::from_fn(|_| match iter.next() {
+        Some(buf) => {
+            let len = min_len(buf.len(), left);
+            left -= len as u64;
+            IoSliceMut::new(&mut buf[..len])
+        }
+        None => IoSliceMut::new(&mut []),
+    });
+
+    let read_n = check_zero_read(
+        ready!(if stream.replay.is_empty() {
+            stream.stats.read_syscalls += 1;
+            read(&mut stream.io, &mut slices)
+        } else {
+            Poll::Ready(Ok(stream.replay.read_vectored(&mut slices)))
+        }),
+        stream.zero_read_is_eof,
+    )
+    .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+
+    // EOF in the middle of a frame
+    if read_n == 0 {
+        stream.read_state = ReadState::Eof;
+        return Poll::Ready(Err(truncated_eof(&mut stream.close.reason)));
+    }
+
+    // unmask if server receives data from client
+    // continue from the last offset of mask key
+    if let Mask::Key(key) = mask {
+        let mut offset = offset as usize;
+        let mut left = read_n;
+        for buf in slices.iter_mut() {
+            if left == 0 {
+                break;
+            }
+            let len = std::cmp::min(buf.len(), left);
+            unmask(key, offset, &mut buf[..len]);
+            offset += len;
+            left -= len;
+        }
+    }
+
+    // read complete ?
+    if next > read_n as u64 {
+        stream.read_state = ReadState::ReadData {
+            next: next - read_n as u64,
+            mask,
+            offset: ((offset as usize + read_n) & 0x03) as u8,
+        };
+    } else {
+        stream.read_state = ReadState::new();
+    }
+    Poll::Ready(Ok(read_n))
+}
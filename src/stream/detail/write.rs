@@ -1,24 +1,55 @@
-use std::io::Result;
+use std::io::{Result, ErrorKind};
 use std::io::IoSlice;
 use std::task::{Poll, ready};
 use std::marker::PhantomData;
 
-use super::min_len;
+use super::{min_len, trace_head};
 use super::super::{Stream, RoleHelper};
 use super::super::state::{WriteState, HeadStore};
+use super::super::close::record_io_error;
 
 use crate::frame::FrameHead;
+use crate::error::CtrlError;
 use crate::frame::{Fin, OpCode, PayloadLen};
 
+#[inline]
 pub fn write_some<F, IO, Role, Guard>(
+    stream: &mut Stream<IO, Role, Guard>,
+    write: F,
+    buf: &[u8],
+) -> Poll<Result<usize>>
+where
+    F: FnMut(&mut IO, &[IoSlice]) -> Poll<Result<usize>>,
+    Role: RoleHelper,
+{
+    write_frame(stream, write, OpCode::Binary, buf)
+}
+
+/// Same as [`write_some`], the opcode takes effect when a new frame is created.
+pub fn write_frame<F, IO, Role, Guard>(
     mut stream: &mut Stream<IO, Role, Guard>,
     mut write: F,
+    opcode: OpCode,
     buf: &[u8],
 ) -> Poll<Result<usize>>
 where
     F: FnMut(&mut IO, &[IoSlice]) -> Poll<Result<usize>>,
     Role: RoleHelper,
 {
+    if let Err(e) = stream.check_liveness() {
+        return Poll::Ready(Err(e));
+    }
+
+    // no more data frames after a close frame
+    if stream.close.is_queued {
+        return Poll::Ready(Err(CtrlError::WriteAfterClose.into()));
+    }
+
+    // emit a queued pong at a frame boundary
+    if matches!(stream.write_state, WriteState::WriteHead(head) if head.is_empty()) {
+        ready!(write_pong(stream, &mut write))?;
+    }
+
     match stream.write_state {
         // always returns 0
         WriteState::WriteZero => Poll::Ready(Ok(0)),
@@ -30,12 +61,22 @@ where
             if head_store.is_empty() {
                 // build frame head
                 // mask payload(this is unsafe) if unsafe_auto_mask_write is activated
-                WriteFrameHead::<Role>::write_data_frame(&mut head_store, &mut stream.role, buf);
+                WriteFrameHead::<Role>::write_data_frame(
+                    &mut head_store,
+                    &mut stream.role,
+                    opcode,
+                    buf,
+                );
+                stream.last_frame_len = head_store.rd_left() as u64 + frame_len as u64;
             }
             // frame head(maybe partial) + payload
             let iovec = [IoSlice::new(head_store.read()), IoSlice::new(buf)];
-            let write_n = ready!(write(&mut stream.io, &iovec))?;
+            stream.stats.write_syscalls += 1;
+            let write_n = ready!(write(&mut stream.io, &iovec))
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
             let head_len = head_store.rd_left() as usize;
+            // never beyond the frame
+            debug_assert!(write_n <= head_len + frame_len);
 
             // write zero ?
             if write_n == 0 {
@@ -65,7 +106,10 @@ where
         // continue to write to the same frame
         WriteState::WriteData(next) => {
             let len = min_len(buf.len(), next);
-            let write_n = ready!(write(&mut stream.io, &[IoSlice::new(&buf[..len])]))?;
+            stream.stats.write_syscalls += 1;
+            let write_n = ready!(write(&mut stream.io, &[IoSlice::new(&buf[..len])]))
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+            debug_assert!(write_n as u64 <= next);
             // write zero ?
             if write_n == 0 {
                 stream.write_state = WriteState::WriteZero;
@@ -82,24 +126,229 @@ where
     }
 }
 
+/// Max count of buffers written as the payload of one frame.
+#[cfg(feature = "async")]
+const MAX_WRITE_IOVEC: usize = 16;
+
+/// Same as [`write_frame`], where the payload is gathered from `bufs`.
+/// At most [`MAX_WRITE_IOVEC`] buffers are written, the rest are ignored.
+///
+/// The frame head and payload are written with a single vectored write.
+/// A new frame takes all buffers as its payload, and a partially written
+/// frame continues with the rest of its payload in `bufs`.
+#[cfg(feature = "async")]
+pub fn write_frame_vectored<F, IO, Role, Guard>(
+    stream: &mut Stream<IO, Role, Guard>,
+    mut write: F,
+    opcode: OpCode,
+    bufs: &[IoSlice],
+) -> Poll<Result<usize>>
+where
+    F: FnMut(&mut IO, &[IoSlice]) -> Poll<Result<usize>>,
+    Role: RoleHelper,
+{
+    if let Err(e) = stream.check_liveness() {
+        return Poll::Ready(Err(e));
+    }
+
+    // no more data frames after a close frame
+    if stream.close.is_queued {
+        return Poll::Ready(Err(CtrlError::WriteAfterClose.into()));
+    }
+
+    // emit a queued pong at a frame boundary
+    if matches!(stream.write_state, WriteState::WriteHead(head) if head.is_empty()) {
+        ready!(write_pong(stream, &mut write))?;
+    }
+
+    let bufs = &bufs[..std::cmp::min(bufs.len(), MAX_WRITE_IOVEC)];
+    let mut iovec = [IoSlice::new(&[]); MAX_WRITE_IOVEC + 1];
+
+    match stream.write_state {
+        // always returns 0
+        WriteState::WriteZero => Poll::Ready(Ok(0)),
+        // create a new frame
+        WriteState::WriteHead(mut head_store) => {
+            // data frame length depends on provided buffers length
+            let frame_len: usize = bufs.iter().map(|buf| buf.len()).sum();
+
+            if head_store.is_empty() {
+                // build frame head
+                // mask payload(this is unsafe) if unsafe_auto_mask_write is activated
+                WriteFrameHead::<Role>::write_data_frame_vectored(
+                    &mut head_store,
+                    &mut stream.role,
+                    opcode,
+                    bufs,
+                );
+                stream.last_frame_len = head_store.rd_left() as u64 + frame_len as u64;
+            }
+            // frame head(maybe partial) + payload
+            iovec[0] = IoSlice::new(head_store.read());
+            iovec[1..=bufs.len()].copy_from_slice(bufs);
+            stream.stats.write_syscalls += 1;
+            let write_n = ready!(write(&mut stream.io, &iovec[..=bufs.len()]))
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+            let head_len = head_store.rd_left();
+            // never beyond the frame
+            debug_assert!(write_n <= head_len + frame_len);
+
+            // write zero ?
+            if write_n == 0 {
+                stream.write_state = WriteState::WriteZero;
+                return Poll::Ready(Ok(0));
+            }
+
+            // frame head is not written completely
+            if write_n < head_len {
+                head_store.advance_rd_pos(write_n);
+                stream.write_state = WriteState::WriteHead(head_store);
+                return Poll::Ready(Ok(0));
+            }
+
+            // frame has been written completely
+            let write_n = write_n - head_len;
+
+            // all data written ?
+            if write_n == frame_len {
+                stream.write_state = WriteState::new();
+            } else {
+                stream.write_state = WriteState::WriteData((frame_len - write_n) as u64);
+            }
+
+            Poll::Ready(Ok(write_n))
+        }
+        // continue to write to the same frame
+        WriteState::WriteData(next) => {
+            // never write beyond the frame
+            let mut count = 0;
+            let mut left = next;
+            for buf in bufs {
+                if left == 0 {
+                    break;
+                }
+                let len = min_len(buf.len(), left);
+                iovec[count] = IoSlice::new(&buf[..len]);
+                count += 1;
+                left -= len as u64;
+            }
+            stream.stats.write_syscalls += 1;
+            let write_n = ready!(write(&mut stream.io, &iovec[..count]))
+                .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+            debug_assert!(write_n as u64 <= next);
+            // write zero ?
+            if write_n == 0 {
+                stream.write_state = WriteState::WriteZero;
+                return Poll::Ready(Ok(0));
+            }
+            // all data written ?
+            if next == write_n as u64 {
+                stream.write_state = WriteState::new()
+            } else {
+                stream.write_state = WriteState::WriteData(next - write_n as u64)
+            }
+            Poll::Ready(Ok(write_n))
+        }
+    }
+}
+
+/// Write the queued `Pong` frame, loop until
+/// it is completely written.
+///
+/// This must be called at a frame boundary.
+pub fn write_pong<F, IO, Role, Guard>(
+    stream: &mut Stream<IO, Role, Guard>,
+    mut write: F,
+) -> Poll<Result<()>>
+where
+    F: FnMut(&mut IO, &[IoSlice]) -> Poll<Result<usize>>,
+{
+    while stream.heartbeat.pong_store.rd_left() != 0 {
+        let iovec = [IoSlice::new(stream.heartbeat.pong_store.read())];
+        stream.stats.write_syscalls += 1;
+        let write_n = ready!(write(&mut stream.io, &iovec))
+            .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+        // write zero ?
+        if write_n == 0 {
+            return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+        }
+        stream.heartbeat.pong_store.advance_rd_pos(write_n);
+    }
+    Poll::Ready(Ok(()))
+}
+
+/// Write the queued `Pong` and `Close` frames, loop until
+/// they are completely written.
+pub fn write_ctrl<F, IO, Role, Guard>(
+    stream: &mut Stream<IO, Role, Guard>,
+    mut write: F,
+) -> Poll<Result<()>>
+where
+    F: FnMut(&mut IO, &[IoSlice]) -> Poll<Result<usize>>,
+{
+    ready!(write_pong(stream, &mut write))?;
+    while stream.close.close_store.rd_left() != 0 {
+        let iovec = [IoSlice::new(stream.close.close_store.read())];
+        stream.stats.write_syscalls += 1;
+        let write_n = ready!(write(&mut stream.io, &iovec))
+            .map_err(|e| record_io_error(&mut stream.close.reason, e))?;
+        // write zero ?
+        if write_n == 0 {
+            return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+        }
+        stream.close.close_store.advance_rd_pos(write_n);
+    }
+    Poll::Ready(Ok(()))
+}
+
 struct WriteFrameHead<Role: RoleHelper> {
     _marker: PhantomData<Role>,
 }
 
 trait WriteFrameHeadTrait<R> {
-    fn write_data_frame(_: &mut HeadStore, _: &mut R, _: &[u8]) {}
+    fn write_data_frame(_: &mut HeadStore, _: &mut R, _: OpCode, _: &[u8]) {}
+
+    #[cfg(feature = "async")]
+    fn write_data_frame_vectored(_: &mut HeadStore, _: &mut R, _: OpCode, _: &[IoSlice]) {}
 }
 
 // use default impl
 impl<Role: RoleHelper> WriteFrameHeadTrait<Role> for WriteFrameHead<Role> {
     #[inline]
-    default fn write_data_frame(store: &mut HeadStore, role: &mut Role, buf: &[u8]) {
+    default fn write_data_frame(
+        store: &mut HeadStore,
+        role: &mut Role,
+        opcode: OpCode,
+        buf: &[u8],
+    ) {
         let head = FrameHead::new(
             Fin::Y,
-            OpCode::Binary,
+            opcode,
             role.mask_key(),
             PayloadLen::from_num(buf.len() as u64),
         );
+        trace_head("write", &head);
+        // The buffer is large enough to accommodate any kind of frame head.
+        let n = unsafe { head.encode_unchecked(store.as_mut()) };
+        store.set_wr_pos(n);
+    }
+
+    #[cfg(feature = "async")]
+    #[inline]
+    default fn write_data_frame_vectored(
+        store: &mut HeadStore,
+        role: &mut Role,
+        opcode: OpCode,
+        bufs: &[IoSlice],
+    ) {
+        let len: usize = bufs.iter().map(|buf| buf.len()).sum();
+        let head = FrameHead::new(
+            Fin::Y,
+            opcode,
+            role.mask_key(),
+            PayloadLen::from_num(len as u64),
+        );
+        trace_head("write", &head);
         // The buffer is large enough to accommodate any kind of frame head.
         let n = unsafe { head.encode_unchecked(store.as_mut()) };
         store.set_wr_pos(n);
@@ -111,6 +360,7 @@ cfg_if::cfg_if! {
         use crate::role::AutoMaskClientRole;
         use crate::bleed::const_cast;
         use crate::frame::{Mask, new_mask_key, apply_mask4};
+        use crate::frame::mask::rotate_mask_key;
     }
 }
 
@@ -118,7 +368,7 @@ cfg_if::cfg_if! {
 #[cfg(feature = "unsafe_auto_mask_write")]
 impl<Role: AutoMaskClientRole> WriteFrameHeadTrait<Role> for WriteFrameHead<Role> {
     #[inline]
-    fn write_data_frame(store: &mut HeadStore, role: &mut Role, buf: &[u8]) {
+    fn write_data_frame(store: &mut HeadStore, role: &mut Role, opcode: OpCode, buf: &[u8]) {
         let key = if Role::UPDATE_MASK_KEY {
             let key = new_mask_key();
             role.set_mask_key(key);
@@ -136,10 +386,51 @@ impl<Role: AutoMaskClientRole> WriteFrameHeadTrait<Role> for WriteFrameHead<Role
         // below is the same of default impl
         let head = FrameHead::new(
             Fin::Y,
-            OpCode::Binary,
+            opcode,
             Mask::Key(key),
             PayloadLen::from_num(buf.len() as u64),
         );
+        trace_head("write", &head);
+        // The buffer is large enough to accommodate any kind of frame head.
+        let n = unsafe { head.encode_unchecked(store.as_mut()) };
+        store.set_wr_pos(n);
+    }
+
+    #[cfg(feature = "async")]
+    #[inline]
+    fn write_data_frame_vectored(
+        store: &mut HeadStore,
+        role: &mut Role,
+        opcode: OpCode,
+        bufs: &[IoSlice],
+    ) {
+        let key = if Role::UPDATE_MASK_KEY {
+            let key = new_mask_key();
+            role.set_mask_key(key);
+            key
+        } else {
+            role.mask_key().to_key()
+        };
+
+        // !! const_cast immutable references
+        // each buffer is masked from where the previous one ends
+        let mut len = 0;
+        for buf in bufs {
+            unsafe {
+                let buf = const_cast::<[u8]>(buf);
+                apply_mask4(rotate_mask_key(key, len), buf);
+            }
+            len += buf.len();
+        }
+
+        // below is the same of default impl
+        let head = FrameHead::new(
+            Fin::Y,
+            opcode,
+            Mask::Key(key),
+            PayloadLen::from_num(len as u64),
+        );
+        trace_head("write", &head);
         // The buffer is large enough to accommodate any kind of frame head.
         let n = unsafe { head.encode_unchecked(store.as_mut()) };
         store.set_wr_pos(n);
@@ -155,7 +446,7 @@ mod test {
 
     fn auto_mask<R: RoleHelper>(role: &mut R, buf: &[u8]) {
         let mut store = Store::new();
-        WriteFrameHead::<R>::write_data_frame(&mut store, role, buf)
+        WriteFrameHead::<R>::write_data_frame(&mut store, role, OpCode::Binary, buf)
     }
 
     #[test]
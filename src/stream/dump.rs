@@ -0,0 +1,166 @@
+//! Snapshot of stream states, for debugging.
+
+use super::Stream;
+use super::state::{ReadState, WriteState};
+use super::stats::StreamStats;
+
+/// Phase of reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPhase {
+    /// at a frame boundary
+    Boundary,
+    /// a frame head is partially read
+    Head,
+    /// payload of a data frame is partially read
+    Payload,
+    /// payload of a control frame is partially read
+    Control,
+    /// frames in the caller's buffer are being processed
+    Buffered,
+    /// `EOF` is reached
+    Eof,
+    /// a `Close` frame is received
+    Close,
+}
+
+/// Phase of writing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePhase {
+    /// at a frame boundary
+    Boundary,
+    /// a frame head is partially written
+    Head,
+    /// payload of a data frame is partially written
+    Payload,
+    /// a write returned `Ok(0)`
+    WriteZero,
+}
+
+/// Snapshot of stream states, see [`Stream::debug_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamStateDump {
+    /// phase of reading
+    pub read_phase: ReadPhase,
+    /// bytes of a partially read frame head
+    pub head_read: usize,
+    /// payload bytes left of the frame being read
+    pub payload_left: u64,
+    /// see [`Stream::current_mask_offset`]
+    pub mask_offset: Option<u8>,
+    /// a fragmented message is partially read
+    pub is_fragmented: bool,
+    /// phase of writing
+    pub write_phase: WritePhase,
+    /// bytes left of a partially written frame head
+    pub head_left: usize,
+    /// payload bytes left of the frame being written
+    pub write_payload_left: u64,
+    /// a `Ping` frame is received
+    pub is_pinged: bool,
+    /// bytes left of the queued `Close` frame, if any
+    pub close_left: Option<usize>,
+    /// count of control frames queued in manual mode
+    pub queued_control: usize,
+    /// see [`Stream::stats`]
+    pub stats: StreamStats,
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Take a snapshot of internal states, which is richer than
+    /// the `Debug` output, and used to troubleshoot a stuck stream.
+    /// Nothing is changed.
+    pub fn debug_state(&self) -> StreamStateDump {
+        let (read_phase, head_read, payload_left) = match &self.read_state {
+            ReadState::ReadHead(store) if store.is_empty() => (ReadPhase::Boundary, 0, 0),
+            ReadState::ReadHead(store) => (ReadPhase::Head, store.rd_left(), 0),
+            ReadState::ReadData { next, .. } => (ReadPhase::Payload, 0, *next),
+            ReadState::ReadPing { next, .. } | ReadState::ReadClose { next, .. } => {
+                (ReadPhase::Control, 0, *next as u64)
+            }
+            ReadState::ProcessBuf { .. } => (ReadPhase::Buffered, 0, 0),
+            ReadState::Eof => (ReadPhase::Eof, 0, 0),
+            ReadState::Close => (ReadPhase::Close, 0, 0),
+        };
+        let (write_phase, head_left, write_payload_left) = match &self.write_state {
+            WriteState::WriteHead(store) if store.rd_left() == 0 => (WritePhase::Boundary, 0, 0),
+            WriteState::WriteHead(store) => (WritePhase::Head, store.rd_left(), 0),
+            WriteState::WriteData(next) => (WritePhase::Payload, 0, *next),
+            WriteState::WriteZero => (WritePhase::WriteZero, 0, 0),
+        };
+
+        StreamStateDump {
+            read_phase,
+            head_read,
+            payload_left,
+            mask_offset: self.current_mask_offset(),
+            is_fragmented: self.fragment.is_fragmented,
+            write_phase,
+            head_left,
+            write_payload_left,
+            is_pinged: self.is_pinged(),
+            close_left: self
+                .close
+                .is_queued
+                .then(|| self.close.close_store.rd_left()),
+            queued_control: self.control.frames.len(),
+            stats: self.stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_frame, make_data};
+    use crate::frame::OpCode;
+    use crate::role::*;
+
+    #[test]
+    fn dump_mid_frame() {
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 100);
+        let io = LimitReadWriter {
+            buf: frame,
+            rlimit: 1,
+            wlimit: 1,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+        let dump = stream.debug_state();
+        assert_eq!(dump.read_phase, ReadPhase::Boundary);
+        assert_eq!(dump.write_phase, WritePhase::Boundary);
+
+        // partial head
+        let mut buf = vec![0; 100];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        let dump = stream.debug_state();
+        assert_eq!(dump.read_phase, ReadPhase::Head);
+        assert_eq!(dump.head_read, 1);
+
+        // partial payload
+        while !matches!(stream.debug_state().read_phase, ReadPhase::Payload) {
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        }
+        let mut n = 0;
+        for _ in 0..10 {
+            n += stream.read(&mut buf[n..]).unwrap();
+        }
+        assert_eq!(&buf[..n], &data[..n]);
+        let dump = stream.debug_state();
+        assert_eq!(dump.read_phase, ReadPhase::Payload);
+        assert_eq!(dump.head_read, 0);
+        assert_eq!(dump.payload_left, (100 - n) as u64);
+        assert_eq!(dump.mask_offset, stream.current_mask_offset());
+        assert_eq!(dump.stats.read_frames, 1);
+        // nothing is changed
+        assert_eq!(stream.debug_state(), dump);
+
+        // partial write
+        let data = make_data(10);
+        assert_eq!(stream.write(&data).unwrap(), 0);
+        let dump = stream.debug_state();
+        assert_eq!(dump.write_phase, WritePhase::Head);
+        assert_eq!(dump.head_left, 1);
+        assert_eq!(dump.close_left, None);
+    }
+}
@@ -0,0 +1,2246 @@
+use std::io::{Read, Write, IoSliceMut, Result, ErrorKind};
+use std::task::Poll;
+use std::time::Instant;
+
+use super::{Stream, RoleHelper};
+use super::state::{ReadState, HeadStore, PartialRead};
+use super::close::{record_io_error, truncated_eof};
+use super::stats::CountIO;
+use super::detail::{trace_head, decode_head, read_payload_vectored, saturate_len};
+
+use crate::bleed::Store;
+use crate::frame::{FrameHead, Fin, Mask, OpCode, CloseCode, PayloadLen};
+use crate::frame::mask::{apply_mask4, rotate_mask_key};
+use crate::error::{CtrlError, FrameError};
+
+/// Size of the buffer to read discarded payload into.
+const DISCARD_BUF_SIZE: usize = 512;
+
+/// Max length of a frame head.
+pub(super) const MAX_HEAD_LEN: usize = 14;
+
+/// Decision on a data frame made from its head, see [`Stream::read_with_filter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameAction {
+    /// read the payload as usual
+    Accept,
+    /// send a `Close` frame with the status code, then fail
+    Reject(CloseCode),
+    /// discard the payload, then move on to the next frame
+    Skip,
+}
+
+/// Length of a frame head, inferred from its first two bytes.
+#[inline]
+const fn head_len(buf: &[u8]) -> usize {
+    if buf.len() < 2 {
+        return 2;
+    }
+    let mask_len = if buf[1] & 0x80 != 0 { 4 } else { 0 };
+    let ext_len = match buf[1] & 0x7f {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    2 + ext_len + mask_len
+}
+
+/// Length of the payload of a data frame, inferred from a partial head.
+/// Returns `None` if the length is unknown yet, or it is a control frame.
+#[inline]
+fn data_payload_len(buf: &[u8]) -> Option<u64> {
+    // continue, text or binary
+    if buf.len() < 2 || buf[0] & 0x0f > 0x02 {
+        return None;
+    }
+    match buf[1] & 0x7f {
+        126 if buf.len() >= 4 => Some(u16::from_be_bytes([buf[2], buf[3]]) as u64),
+        127 if buf.len() >= 10 => Some(u64::from_be_bytes(buf[2..10].try_into().unwrap())),
+        126 | 127 => None,
+        n => Some(n as u64),
+    }
+}
+
+/// Read the payload of a control frame into the store,
+/// until `len` bytes are stored. Returns `false` if reaching `EOF`.
+///
+/// Once an error occurs, the bytes have been read are left in the store.
+fn read_ctrl_payload<IO: Read, const N: usize>(
+    io: &mut IO,
+    store: &mut Store<N>,
+    len: usize,
+    mask: Mask,
+) -> Result<bool> {
+    while store.wr_pos() < len {
+        let offset = store.wr_pos();
+        let buf = &mut store.write()[..len - offset];
+        let read_n = io.read(buf)?;
+        // EOF ?
+        if read_n == 0 {
+            return Ok(false);
+        }
+        // unmask if server receives data from client
+        if let Mask::Key(key) = mask {
+            apply_mask4(rotate_mask_key(key, offset), &mut buf[..read_n]);
+        }
+        store.advance_wr_pos(read_n);
+    }
+    Ok(true)
+}
+
+/// Unmask the first `n` bytes of a payload split into two regions,
+/// where the mask offset continues across the boundary.
+#[inline]
+fn unmask_ring(mask: Mask, first: &mut [u8], second: &mut [u8], n: usize) {
+    if let Mask::Key(key) = mask {
+        let first_n = std::cmp::min(n, first.len());
+        apply_mask4(key, &mut first[..first_n]);
+        apply_mask4(rotate_mask_key(key, first_n), &mut second[..n - first_n]);
+    }
+}
+
+/// Unmask payload data, continue from the `offset`th byte of the payload.
+#[inline]
+fn unmask_from(mask: Mask, offset: usize, buf: &mut [u8]) {
+    if let Mask::Key(key) = mask {
+        apply_mask4(rotate_mask_key(key, offset), buf);
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Returns the head of a frame whose payload is partially copied by an
+    /// interrupted read, and the count of copied bytes,
+    /// if nothing else is read since.
+    #[inline]
+    fn partial_frame(&self) -> Option<(FrameHead, usize)> {
+        match (self.partial, &self.read_state) {
+            (
+                Some(PartialRead {
+                    head: Some(head),
+                    copied,
+                    next,
+                }),
+                ReadState::ReadData { next: left, .. },
+            ) if next == *left => Some((head, copied)),
+            _ => None,
+        }
+    }
+}
+
+impl<IO: Read, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Read a frame head, never consuming any byte beyond the frame.
+    /// Returns `None` if reaching `EOF`.
+    ///
+    /// Once the length of a data frame is known, and the whole payload
+    /// fits in `prefetch`, the rest of the head and the payload are read
+    /// together with a vectored read, which saves a syscall.
+    /// Returns the head and the count of masked payload bytes in `prefetch`.
+    ///
+    /// Incomplete head is saved if the underlying IO source
+    /// returns an error, so that a later read could resume it.
+    fn read_frame_head(&mut self, prefetch: &mut [u8]) -> Result<Option<(FrameHead, usize)>> {
+        match self.fetch_frame_head(prefetch)? {
+            Some((head, store, prefetch_n)) => {
+                self.check_frame_head(&head, store.read().len())?;
+                Ok(Some((head, prefetch_n)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Check a decoded frame head, and count the frame.
+    fn check_frame_head(&mut self, head: &FrameHead, head_len: usize) -> Result<()> {
+        trace_head("read", head);
+        // no extension is negotiated
+        if !head.rsv.is_empty() {
+            return Err(FrameError::IllegalRsv.into());
+        }
+        self.count_read_frame(head_len, head.length.to_num())?;
+        Ok(())
+    }
+
+    /// Same as `Stream::read_frame_head`, but the head is not checked,
+    /// and the raw head bytes are also returned.
+    fn fetch_frame_head(
+        &mut self,
+        prefetch: &mut [u8],
+    ) -> Result<Option<(FrameHead, HeadStore, usize)>> {
+        let mut store = match self.read_state {
+            ReadState::ReadHead(store) => store,
+            _ => return Err(CtrlError::ReadFrameInRead.into()),
+        };
+
+        let mut prefetch_n = 0;
+        loop {
+            match decode_head::<Role>(store.read()) {
+                Ok((head, _)) => {
+                    self.read_state = ReadState::new();
+                    self.partial = None;
+                    return Ok(Some((head, store, prefetch_n)));
+                }
+                Err(FrameError::NotEnoughData) => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let need = head_len(store.read()) - store.rd_left();
+            let payload_len = match data_payload_len(store.read()) {
+                Some(n) if n <= prefetch.len() as u64 => n as usize,
+                _ => 0,
+            };
+            let read_n = if payload_len != 0 {
+                let mut bufs = [
+                    IoSliceMut::new(&mut store.write()[..need]),
+                    IoSliceMut::new(&mut prefetch[..payload_len]),
+                ];
+                self.count_read_io().read_vectored(&mut bufs)
+            } else {
+                self.count_read_io().read(&mut store.write()[..need])
+            };
+            let read_n = match read_n {
+                Ok(n) => n,
+                Err(e) => {
+                    self.read_state = ReadState::ReadHead(store);
+                    return Err(record_io_error(&mut self.close.reason, e));
+                }
+            };
+
+            // EOF ?
+            if read_n == 0 {
+                self.read_state = ReadState::Eof;
+                // in the middle of a frame head
+                if store.rd_left() != 0 {
+                    return Err(truncated_eof(&mut self.close.reason));
+                }
+                return Ok(None);
+            }
+            // the rest belongs to payload
+            store.advance_wr_pos(std::cmp::min(read_n, need));
+            prefetch_n = read_n.saturating_sub(need);
+        }
+    }
+
+    /// Consume the payload of a data frame without returning it.
+    ///
+    /// If the underlying IO source returns an error,
+    /// a later read will resume the incomplete frame.
+    fn discard_payload(&mut self, len: u64, mask: Mask) -> Result<()> {
+        let mut stack_buf = [0u8; DISCARD_BUF_SIZE];
+        let mut scratch = self.scratch.read.take();
+        let buf = match scratch.as_deref_mut() {
+            Some(buf) => buf,
+            None => &mut stack_buf,
+        };
+        let ret = self.discard_payload_into(len, mask, buf);
+        self.scratch.read = scratch;
+        ret
+    }
+
+    /// Same as `Stream::discard_payload`, where `buf` is not empty.
+    fn discard_payload_into(&mut self, len: u64, mask: Mask, buf: &mut [u8]) -> Result<()> {
+        let mut n = 0;
+        while n < len {
+            let to_read = std::cmp::min(len - n, buf.len() as u64) as usize;
+            match self.count_read_io().read(&mut buf[..to_read]) {
+                Ok(0) => {
+                    self.read_state = ReadState::Eof;
+                    return Err(truncated_eof(&mut self.close.reason));
+                }
+                Ok(x) => n += x as u64,
+                Err(e) => {
+                    self.read_state = ReadState::ReadData {
+                        next: len - n,
+                        mask,
+                        offset: (n & 0x03) as u8,
+                    };
+                    return Err(record_io_error(&mut self.close.reason, e));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Read the payload of a data frame into `buf`, which is exactly as
+    /// long as the payload, where the first `prefetch_n` bytes are read
+    /// with the head. The payload is unmasked once it is complete.
+    ///
+    /// If the underlying IO source returns an error,
+    /// a later read will resume the incomplete frame.
+    pub(super) fn read_frame_payload(
+        &mut self,
+        buf: &mut [u8],
+        prefetch_n: usize,
+        mask: Mask,
+    ) -> Result<()> {
+        let len = buf.len();
+        let mut n = prefetch_n;
+        while n < len {
+            let read_n = match self.count_read_io().read(&mut buf[n..]) {
+                Ok(0) => {
+                    self.read_state = ReadState::Eof;
+                    return Err(truncated_eof(&mut self.close.reason));
+                }
+                Ok(x) => x,
+                Err(e) => {
+                    if let Mask::Key(key) = mask {
+                        apply_mask4(key, &mut buf[..n]);
+                    }
+                    self.read_state = ReadState::ReadData {
+                        next: (len - n) as u64,
+                        mask,
+                        offset: (n & 0x03) as u8,
+                    };
+                    return Err(record_io_error(&mut self.close.reason, e));
+                }
+            };
+            n += read_n;
+        }
+
+        // unmask payload data from client
+        if let Mask::Key(key) = mask {
+            apply_mask4(key, buf);
+        }
+        Ok(())
+    }
+
+    /// Same as `Stream::read_frame_payload`, where the first `copied` bytes
+    /// are copied and unmasked by an interrupted read, and the next `n - copied`
+    /// bytes are read with the head. The rest is unmasked once it is complete.
+    ///
+    /// If the underlying IO source returns an error, the bytes read so far
+    /// are unmasked and kept in `buf`, so that a later call with the same
+    /// buffer resumes the frame, see `Stream::partial_frame`.
+    fn fill_frame_payload(
+        &mut self,
+        head: FrameHead,
+        buf: &mut [u8],
+        copied: usize,
+        mut n: usize,
+    ) -> Result<()> {
+        let mask = head.mask;
+        let len = buf.len();
+        while n < len {
+            n += match self.count_read_io().read(&mut buf[n..]) {
+                Ok(0) => {
+                    self.read_state = ReadState::Eof;
+                    return Err(truncated_eof(&mut self.close.reason));
+                }
+                Ok(x) => x,
+                Err(e) => {
+                    unmask_from(mask, copied, &mut buf[copied..n]);
+                    let next = (len - n) as u64;
+                    self.read_state = ReadState::ReadData {
+                        next,
+                        mask,
+                        offset: (n & 0x03) as u8,
+                    };
+                    self.partial = Some(PartialRead {
+                        head: Some(head),
+                        copied: n,
+                        next,
+                    });
+                    return Err(record_io_error(&mut self.close.reason, e));
+                }
+            };
+        }
+        unmask_from(mask, copied, &mut buf[copied..]);
+        self.read_state = ReadState::new();
+        self.partial = None;
+        Ok(())
+    }
+
+    /// Read the payload of a `Ping`, `Pong` or `Close` frame, where `len`
+    /// is the length of payload, including the bytes already stored.
+    ///
+    /// A ping is saved, a pong is only accepted in manual control mode,
+    /// and a close ends the stream. The frame is queued in manual control mode.
+    /// If the underlying IO source returns an error,
+    /// a later read will resume the incomplete frame.
+    fn read_ctrl_frame(&mut self, opcode: OpCode, len: usize, mask: Mask) -> Result<()> {
+        let store = match opcode {
+            OpCode::Ping => {
+                self.heartbeat.is_complete = false;
+                &mut self.heartbeat.ping_store
+            }
+            _ => &mut self.close.recv_store,
+        };
+
+        let mut io = CountIO {
+            io: &mut self.io,
+            count: &mut self.stats.read_syscalls,
+            zero_read_is_eof: self.zero_read_is_eof,
+            replay: Some(&mut self.replay),
+            cancel: self.cancel.as_deref(),
+        };
+        match read_ctrl_payload(&mut io, store, len, mask) {
+            Ok(true) if opcode == OpCode::Ping => self.heartbeat.is_complete = true,
+            Ok(true) if opcode == OpCode::Pong => {}
+            Ok(true) => {
+                self.read_state = ReadState::Close;
+                self.check_close_payload()?;
+                self.close.is_received = true;
+            }
+            Ok(false) => {
+                self.read_state = ReadState::Eof;
+                return Err(truncated_eof(&mut self.close.reason));
+            }
+            Err(e) => {
+                let next = (len - store.wr_pos()) as u8;
+                self.read_state = match opcode {
+                    OpCode::Ping => ReadState::ReadPing { next, mask },
+                    _ => ReadState::ReadClose { next, mask },
+                };
+                return Err(record_io_error(&mut self.close.reason, e));
+            }
+        }
+        self.queue_control(opcode)?;
+        Ok(())
+    }
+
+    /// Start to handle a new `Ping`, `Pong` or `Close` frame.
+    #[inline]
+    fn start_ctrl_frame(&mut self, fin: Fin, opcode: OpCode, len: u64, mask: Mask) -> Result<()> {
+        // a control frame must not have extened data
+        if len > 125 {
+            return Err(FrameError::IllegalData.into());
+        }
+        // nor be fragmented
+        self.check_ctrl_fin(fin)?;
+        match opcode {
+            OpCode::Ping => self.heartbeat.ping_store.reset(),
+            _ => {
+                self.close.recv_store.reset();
+                self.close.is_pong = opcode == OpCode::Pong;
+            }
+        }
+        self.read_ctrl_frame(opcode, len as usize, mask)
+    }
+
+    /// Read a frame head with small reads, or the rest of a control frame or
+    /// a discarded payload, so that no payload is returned. It serves
+    /// `Stream::read_vectored` with buffers too short to hold a frame head,
+    /// where the payload of a data frame is left in [`ReadState::ReadData`].
+    pub(super) fn read_head_only(&mut self) -> Result<()> {
+        match self.read_state {
+            ReadState::ReadHead(_) => {}
+            // only a discarded payload is left here
+            ReadState::ReadData { next, mask, .. } => {
+                self.discard_payload(next, mask)?;
+                self.read_state = ReadState::new();
+                return Ok(());
+            }
+            ReadState::ReadPing { next, mask } => {
+                let len = self.heartbeat.ping_store.wr_pos() + next as usize;
+                return self.read_ctrl_frame(OpCode::Ping, len, mask);
+            }
+            ReadState::ReadClose { next, mask } => {
+                let len = self.close.recv_store.wr_pos() + next as usize;
+                return self.read_ctrl_frame(self.read_close_opcode(), len, mask);
+            }
+            ReadState::Eof | ReadState::Close => return Ok(()),
+            ReadState::ProcessBuf { .. } => unreachable!(),
+        }
+
+        let (
+            FrameHead {
+                fin,
+                opcode,
+                mask,
+                length,
+                ..
+            },
+            _,
+        ) = match self.read_frame_head(&mut [])? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let frame_len = length.to_num();
+
+        match opcode {
+            // text is not allowed
+            // we never send a ping, so we ignore the pong
+            OpCode::Text => Err(FrameError::UnsupportedOpcode.into()),
+            OpCode::Pong if !self.accept_pong() => Err(FrameError::UnsupportedOpcode.into()),
+            OpCode::Binary | OpCode::Continue => {
+                let is_discarded = self.check_data_frame(fin, opcode)? || self.discard_payloads;
+                if opcode == OpCode::Binary {
+                    self.fragment.opcode = opcode;
+                }
+                if is_discarded {
+                    return self.discard_payload(frame_len, mask);
+                }
+                if frame_len != 0 {
+                    self.read_state = ReadState::ReadData {
+                        next: frame_len,
+                        mask,
+                        offset: 0,
+                    };
+                }
+                Ok(())
+            }
+            OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                self.start_ctrl_frame(fin, opcode, frame_len, mask)
+            }
+        }
+    }
+
+    /// Opcode of a partially read frame in [`ReadState::ReadClose`].
+    #[inline]
+    const fn read_close_opcode(&self) -> OpCode {
+        if self.close.is_pong {
+            OpCode::Pong
+        } else {
+            OpCode::Close
+        }
+    }
+
+    /// Read until a complete frame head is buffered, returns the decoded head,
+    /// or `None` if reaching `EOF`. The payload is left untouched.
+    ///
+    /// This is the building block of frame reads, for callers which handle
+    /// payload on their own. The head is read with small reads, where the
+    /// extended length and mask key (if any) are fetched once known, and never
+    /// consumes any byte beyond the head. Then the whole payload, including
+    /// any of a control frame, must be read from the underlying IO source by
+    /// the caller, and unmasked with `head.mask` (if any), before reading the
+    /// next frame. Bytes read with the handshake (see [`Stream::remainder_len`])
+    /// are returned first, which should be consumed before this is called.
+    ///
+    /// The head is checked and counted as usual, but neither fragments nor
+    /// control frames are handled. This must be called at a frame boundary,
+    /// otherwise a [`CtrlError::ReadFrameInRead`] error is returned.
+    /// If the underlying IO source returns an error, the incomplete head is
+    /// saved, which is resumed by a later call.
+    #[inline]
+    pub fn fill_head(&mut self) -> Result<Option<FrameHead>> {
+        Ok(self.read_frame_head(&mut [])?.map(|(head, _)| head))
+    }
+
+    /// Read the payload of exactly one data frame,
+    /// returns the length of the payload.
+    ///
+    /// Unlike `Stream::read`, this never reads beyond the frame,
+    /// and the frame head is fetched with several small reads. Once the
+    /// length is known, the rest of the head and the payload are fetched
+    /// with a single vectored read, if the payload fits in the buffer.
+    /// It is efficient when the underlying IO source is buffered
+    /// (e.g. [`std::io::BufReader`]), then the order and boundary of
+    /// frames are preserved no matter how much data were buffered.
+    ///
+    /// Control frames are handled in the same way as `Stream::read`,
+    /// a Ping is saved and a Close or `EOF` leads to `Ok(0)`.
+    /// Same as `Stream::read`, reaching `EOF` in the middle of a frame
+    /// leads to an `UnexpectedEof` error.
+    ///
+    /// If the provided buffer is not large enough to hold the payload,
+    /// a [`FrameError::NotEnoughCapacity`] error is returned
+    /// after the head is consumed, and the payload could still be
+    /// read with `Stream::read`. If payload is discarded(see
+    /// [`Stream::set_discard_payloads`]), the length of payload
+    /// is returned, where the buffer is left untouched.
+    ///
+    /// This must be called at a frame boundary,
+    /// otherwise a [`CtrlError::ReadFrameInRead`] error is returned.
+    /// If the underlying IO source returns an error during reading the
+    /// payload, the bytes read so far are kept in the buffer, and a later
+    /// call with the same buffer resumes the frame, then returns the whole
+    /// payload. The rest of the payload could also be read with `Stream::read`.
+    pub fn read_exact_frame(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if let Some((head, copied)) = self.partial_frame() {
+            let len = head.length.to_num() as usize;
+            if len > buf.len() {
+                return Err(FrameError::NotEnoughCapacity.into());
+            }
+            self.fill_frame_payload(head, &mut buf[..len], copied, copied)?;
+            return Ok(len);
+        }
+
+        loop {
+            if self.is_read_end() {
+                return Ok(0);
+            }
+
+            let (head, prefetch_n) =
+                match self.read_frame_head(if self.discard_payloads { &mut [] } else { buf })? {
+                    Some(x) => x,
+                    None => return Ok(0),
+                };
+            let FrameHead {
+                fin,
+                opcode,
+                mask,
+                length,
+                ..
+            } = head;
+            let frame_len = length.to_num();
+
+            match opcode {
+                // text is not allowed
+                // we never send a ping, so we ignore the pong
+                OpCode::Text => return Err(FrameError::UnsupportedOpcode.into()),
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Binary | OpCode::Continue => {
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len - prefetch_n as u64, mask)?;
+                        continue;
+                    }
+
+                    if self.discard_payloads {
+                        self.discard_payload(frame_len, mask)?;
+                        return Ok(saturate_len(frame_len));
+                    }
+
+                    if frame_len > buf.len() as u64 {
+                        self.read_state = ReadState::ReadData {
+                            next: frame_len,
+                            mask,
+                            offset: 0,
+                        };
+                        return Err(FrameError::NotEnoughCapacity.into());
+                    }
+
+                    let len = frame_len as usize;
+                    self.fill_frame_payload(head, &mut buf[..len], 0, prefetch_n)?;
+                    return Ok(len);
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+    }
+
+    /// Read exactly `buf.len()` payload bytes of the current data frame,
+    /// e.g. a fixed-size record inside a binary message.
+    ///
+    /// Unlike `Stream::read`, this never reads beyond the current frame.
+    /// At a frame boundary, the head of the next data frame is read first,
+    /// where control frames are handled like [`Stream::read_exact_frame`].
+    /// If the frame has fewer bytes left, a [`FrameError::NotEnoughPayload`]
+    /// error is returned before any payload is read, so that the rest could
+    /// still be read with `Stream::read`. An empty frame is consumed.
+    ///
+    /// Unmasking continues from the offset of a partially read frame, see
+    /// [`Stream::current_mask_offset`]. Once a Close is received or `EOF`
+    /// is reached, an `UnexpectedEof` error is returned.
+    ///
+    /// If the underlying IO source returns an error, the bytes already
+    /// copied into `buf` are kept, and a later call with the same buffer
+    /// resumes the read, if nothing else is read since.
+    pub fn read_payload_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut n = 0;
+        loop {
+            match self.read_state {
+                ReadState::ReadHead(_) => {}
+                ReadState::ReadData { next, .. } => match self.partial {
+                    Some(PartialRead {
+                        head: None,
+                        copied,
+                        next: left,
+                    }) if left == next
+                        && copied < buf.len()
+                        && next >= (buf.len() - copied) as u64 =>
+                    {
+                        n = copied;
+                        break;
+                    }
+                    _ if next < buf.len() as u64 => {
+                        return Err(FrameError::NotEnoughPayload(next).into());
+                    }
+                    _ => break,
+                },
+                ReadState::ReadPing { next, mask } => {
+                    let len = self.heartbeat.ping_store.wr_pos() + next as usize;
+                    self.read_ctrl_frame(OpCode::Ping, len, mask)?;
+                    continue;
+                }
+                ReadState::ReadClose { next, mask } => {
+                    let len = self.close.recv_store.wr_pos() + next as usize;
+                    self.read_ctrl_frame(self.read_close_opcode(), len, mask)?;
+                    continue;
+                }
+                ReadState::Eof | ReadState::Close => return Err(ErrorKind::UnexpectedEof.into()),
+                ReadState::ProcessBuf { .. } => unreachable!(),
+            }
+
+            let (
+                FrameHead {
+                    fin,
+                    opcode,
+                    mask,
+                    length,
+                    ..
+                },
+                _,
+            ) = match self.read_frame_head(&mut [])? {
+                Some(x) => x,
+                None => return Err(ErrorKind::UnexpectedEof.into()),
+            };
+            let frame_len = length.to_num();
+
+            match opcode {
+                // text is not allowed
+                // we never send a ping, so we ignore the pong
+                OpCode::Text => return Err(FrameError::UnsupportedOpcode.into()),
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Binary | OpCode::Continue => {
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len, mask)?;
+                        continue;
+                    }
+                    if frame_len == 0 {
+                        return match buf.len() {
+                            0 => Ok(()),
+                            _ => Err(FrameError::NotEnoughPayload(0).into()),
+                        };
+                    }
+                    self.read_state = ReadState::ReadData {
+                        next: frame_len,
+                        mask,
+                        offset: 0,
+                    };
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+
+        while n < buf.len() {
+            let mut bufs = [IoSliceMut::new(&mut buf[n..])];
+            let read = |io: &mut IO, bufs: &mut [IoSliceMut]| io.read(&mut bufs[0]).into();
+            n += match read_payload_vectored(self, read, &mut bufs) {
+                Poll::Ready(Ok(x)) => x,
+                Poll::Ready(Err(e)) => {
+                    if let ReadState::ReadData { next, .. } = self.read_state {
+                        self.partial = Some(PartialRead {
+                            head: None,
+                            copied: n,
+                            next,
+                        });
+                    }
+                    return Err(e);
+                }
+                Poll::Pending => unreachable!(),
+            };
+        }
+        self.partial = None;
+        Ok(())
+    }
+
+    /// Read exactly one data frame, where the raw head bytes are copied
+    /// to `head_buf` and the unmasked payload to `payload_buf`.
+    /// Returns the length of the head, and the length of the payload.
+    /// Unlike [`Stream::read_exact_frame`], a Text frame is also accepted,
+    /// whose opcode could be found in the head.
+    ///
+    /// If either buffer is not large enough, the needed lengths are returned,
+    /// where both buffers are left untouched and the frame is not consumed,
+    /// so that it could be read again with larger buffers. A head takes
+    /// at most 14 bytes, depending on the encoding of payload length
+    /// and the presence of a mask key.
+    ///
+    /// Otherwise this is the same as `Stream::read_exact_frame`, control frames
+    /// are handled internally, and a Close or `EOF` leads to `Ok((0, 0))`.
+    /// If the underlying IO source returns an error during reading the
+    /// payload, the rest of the payload could only be read with `Stream::read`.
+    pub fn read_split(
+        &mut self,
+        head_buf: &mut [u8],
+        payload_buf: &mut [u8],
+    ) -> Result<(usize, usize)> {
+        loop {
+            if self.is_read_end() {
+                return Ok((0, 0));
+            }
+
+            // never prefetch payload before the head is sure to fit
+            let prefetch: &mut [u8] = if self.discard_payloads || head_buf.len() < MAX_HEAD_LEN {
+                &mut []
+            } else {
+                payload_buf
+            };
+            let (head, store, prefetch_n) = match self.fetch_frame_head(prefetch)? {
+                Some(x) => x,
+                None => return Ok((0, 0)),
+            };
+            let FrameHead {
+                fin,
+                opcode,
+                mask,
+                length,
+                ..
+            } = head;
+            let frame_len = length.to_num();
+            let raw_head = store.read();
+
+            match opcode {
+                OpCode::Text | OpCode::Binary | OpCode::Continue => {
+                    let payload_cap = if self.discard_payloads {
+                        u64::MAX
+                    } else {
+                        payload_buf.len() as u64
+                    };
+                    if raw_head.len() > head_buf.len() || frame_len > payload_cap {
+                        // keep the head to decode it again
+                        self.read_state = ReadState::ReadHead(store);
+                        return Ok((raw_head.len(), saturate_len(frame_len)));
+                    }
+                    self.check_frame_head(&head, raw_head.len())?;
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len - prefetch_n as u64, mask)?;
+                        continue;
+                    }
+                    head_buf[..raw_head.len()].copy_from_slice(raw_head);
+
+                    if self.discard_payloads {
+                        self.discard_payload(frame_len, mask)?;
+                        return Ok((raw_head.len(), saturate_len(frame_len)));
+                    }
+
+                    let len = frame_len as usize;
+                    self.read_frame_payload(&mut payload_buf[..len], prefetch_n, mask)?;
+                    return Ok((raw_head.len(), len));
+                }
+                // we never send a ping, so we ignore the pong
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.check_frame_head(&head, raw_head.len())?;
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+    }
+
+    /// Read exactly one data frame, where the payload is read straight into
+    /// `dst`, e.g. a memory-mapped region of a file, returns the head.
+    ///
+    /// `dst` must be at least as long as the payload, where only the first
+    /// `head.length` bytes are written. Otherwise a [`FrameError::BufferTooSmall`]
+    /// error with the length of payload is returned, where the frame is not
+    /// consumed, so that it could be read again with a larger region.
+    /// Payload is read with as many reads as needed, then unmasked in place.
+    ///
+    /// Otherwise this is the same as [`Stream::read_split`], a Text frame is
+    /// also accepted, and control frames are handled internally. A Close or `EOF`
+    /// leads to a head of an empty `Close` frame, which could be checked via
+    /// [`Stream::is_read_close`] and [`Stream::is_read_eof`].
+    /// Same as [`Stream::read_exact_frame`], a read interrupted by an error
+    /// is resumed by a later call with the same region.
+    pub fn read_frame_into_slice(&mut self, dst: &mut [u8]) -> Result<FrameHead> {
+        const CLOSE_HEAD: FrameHead =
+            FrameHead::new(Fin::Y, OpCode::Close, Mask::None, PayloadLen::Standard(0));
+
+        if let Some((head, copied)) = self.partial_frame() {
+            let frame_len = head.length.to_num();
+            if frame_len > dst.len() as u64 {
+                return Err(FrameError::BufferTooSmall(frame_len).into());
+            }
+            self.fill_frame_payload(head, &mut dst[..frame_len as usize], copied, copied)?;
+            return Ok(head);
+        }
+
+        loop {
+            if self.is_read_end() {
+                return Ok(CLOSE_HEAD);
+            }
+
+            let prefetch: &mut [u8] = if self.discard_payloads { &mut [] } else { dst };
+            let (head, store, prefetch_n) = match self.fetch_frame_head(prefetch)? {
+                Some(x) => x,
+                None => return Ok(CLOSE_HEAD),
+            };
+            let FrameHead {
+                fin,
+                opcode,
+                mask,
+                length,
+                ..
+            } = head;
+            let frame_len = length.to_num();
+
+            match opcode {
+                OpCode::Text | OpCode::Binary | OpCode::Continue => {
+                    if !self.discard_payloads && frame_len > dst.len() as u64 {
+                        // keep the head to decode it again
+                        self.read_state = ReadState::ReadHead(store);
+                        return Err(FrameError::BufferTooSmall(frame_len).into());
+                    }
+                    self.check_frame_head(&head, store.read().len())?;
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len - prefetch_n as u64, mask)?;
+                        continue;
+                    }
+
+                    if self.discard_payloads {
+                        self.discard_payload(frame_len, mask)?;
+                        return Ok(head);
+                    }
+
+                    let len = frame_len as usize;
+                    self.fill_frame_payload(head, &mut dst[..len], 0, prefetch_n)?;
+                    return Ok(head);
+                }
+                // we never send a ping, so we ignore the pong
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.check_frame_head(&head, store.read().len())?;
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+    }
+
+    /// Read up to `n` data frames, returns their heads and unmasked payloads.
+    /// This is a convenience over calling [`Stream::read_exact_frame`] in a loop,
+    /// where each payload is read into a new buffer, and a Text frame is also
+    /// accepted, whose opcode could be found in the head.
+    ///
+    /// Control frames are handled internally. It stops early once a Close
+    /// frame is received, `EOF` is reached, or the deadline has passed,
+    /// then returns the frames read so far. The deadline is checked before
+    /// each frame, a blocking read is not interrupted, so a read timeout
+    /// should be set on the underlying IO source to stop a stalled read.
+    ///
+    /// A payload buffer is as large as declared in the head, to read frames from
+    /// an untrusted peer, see [`Stream::set_max_total_payload_bytes`].
+    /// If an error occurs, the frames read so far are dropped, and the rest
+    /// of an incomplete frame could be read with `Stream::read`.
+    #[inline]
+    pub fn read_frames(
+        &mut self,
+        n: usize,
+        deadline: Option<Instant>,
+    ) -> Result<Vec<(FrameHead, Vec<u8>)>> {
+        self.read_frames_with(n, deadline, |_| Vec::new())
+    }
+
+    /// Same as [`Stream::read_frames`], where each payload buffer is created
+    /// by `alloc` with the length of the payload, e.g. drawn from an arena.
+    ///
+    /// The buffer is cleared, then resized to hold the payload. It should have
+    /// enough capacity, otherwise it is reallocated by the global allocator.
+    /// An empty buffer of a discarded payload is not created by `alloc`.
+    pub fn read_frames_with<F>(
+        &mut self,
+        n: usize,
+        deadline: Option<Instant>,
+        mut alloc: F,
+    ) -> Result<Vec<(FrameHead, Vec<u8>)>>
+    where
+        F: FnMut(usize) -> Vec<u8>,
+    {
+        let mut frames = Vec::new();
+        while frames.len() < n {
+            if self.is_read_end() || deadline.is_some_and(|d| Instant::now() >= d) {
+                break;
+            }
+
+            let head = match self.read_frame_head(&mut [])? {
+                Some((head, _)) => head,
+                None => break,
+            };
+            let FrameHead {
+                fin,
+                opcode,
+                mask,
+                length,
+                ..
+            } = head;
+            let frame_len = length.to_num();
+
+            match opcode {
+                OpCode::Text | OpCode::Binary | OpCode::Continue => {
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len, mask)?;
+                        continue;
+                    }
+
+                    if self.discard_payloads {
+                        self.discard_payload(frame_len, mask)?;
+                        frames.push((head, Vec::new()));
+                        continue;
+                    }
+
+                    // never truncate a length beyond usize::MAX
+                    let reserved = usize::try_from(frame_len).ok().and_then(|len| {
+                        let mut payload = alloc(len);
+                        payload.clear();
+                        payload.try_reserve_exact(len).ok().map(|_| (payload, len))
+                    });
+                    let (mut payload, len) = match reserved {
+                        Some(x) => x,
+                        None => {
+                            self.read_state = ReadState::ReadData {
+                                next: frame_len,
+                                mask,
+                                offset: 0,
+                            };
+                            return Err(FrameError::NotEnoughCapacity.into());
+                        }
+                    };
+                    payload.resize(len, 0);
+                    self.read_frame_payload(&mut payload, 0, mask)?;
+                    frames.push((head, payload));
+                }
+                // we never send a ping, so we ignore the pong
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+        Ok(frames)
+    }
+
+    /// Read exactly one data frame into two regions, e.g. the free space
+    /// of a ring buffer which wraps around its end, returns the length of
+    /// the payload. The payload fills `first`, then continues in `second`.
+    ///
+    /// Payload is unmasked as a whole, where the mask offset continues
+    /// across the boundary of regions rather than restarting at `second`.
+    ///
+    /// Otherwise this is the same as [`Stream::read_exact_frame`], where
+    /// the capacity is the total length of both regions.
+    pub fn read_into_ring(&mut self, first: &mut [u8], second: &mut [u8]) -> Result<usize> {
+        loop {
+            if self.is_read_end() {
+                return Ok(0);
+            }
+
+            // prefetch only if the whole payload fits in the first region
+            let (
+                FrameHead {
+                    fin,
+                    opcode,
+                    mask,
+                    length,
+                    ..
+                },
+                prefetch_n,
+            ) = match self.read_frame_head(if self.discard_payloads {
+                &mut []
+            } else {
+                first
+            })? {
+                Some(x) => x,
+                None => return Ok(0),
+            };
+            let frame_len = length.to_num();
+
+            match opcode {
+                // text is not allowed
+                // we never send a ping, so we ignore the pong
+                OpCode::Text => return Err(FrameError::UnsupportedOpcode.into()),
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Binary | OpCode::Continue => {
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len - prefetch_n as u64, mask)?;
+                        continue;
+                    }
+
+                    if self.discard_payloads {
+                        self.discard_payload(frame_len, mask)?;
+                        return Ok(saturate_len(frame_len));
+                    }
+
+                    if frame_len > (first.len() + second.len()) as u64 {
+                        self.read_state = ReadState::ReadData {
+                            next: frame_len,
+                            mask,
+                            offset: 0,
+                        };
+                        return Err(FrameError::NotEnoughCapacity.into());
+                    }
+
+                    let len = frame_len as usize;
+                    let first_len = std::cmp::min(len, first.len());
+                    let first = &mut first[..first_len];
+                    let second = &mut second[..len - first_len];
+                    let mut n = prefetch_n;
+                    while n < len {
+                        let mut bufs = if n < first_len {
+                            [IoSliceMut::new(&mut first[n..]), IoSliceMut::new(second)]
+                        } else {
+                            [
+                                IoSliceMut::new(&mut second[n - first_len..]),
+                                IoSliceMut::new(&mut []),
+                            ]
+                        };
+                        let read_n = match self.count_read_io().read_vectored(&mut bufs) {
+                            Ok(0) => {
+                                self.read_state = ReadState::Eof;
+                                return Err(truncated_eof(&mut self.close.reason));
+                            }
+                            Ok(x) => x,
+                            Err(e) => {
+                                unmask_ring(mask, first, second, n);
+                                self.read_state = ReadState::ReadData {
+                                    next: (len - n) as u64,
+                                    mask,
+                                    offset: (n & 0x03) as u8,
+                                };
+                                return Err(record_io_error(&mut self.close.reason, e));
+                            }
+                        };
+                        n += read_n;
+                    }
+
+                    unmask_ring(mask, first, second, len);
+                    return Ok(len);
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+    }
+
+    /// Read payload of data frames only, returns the opcode of the message
+    /// and the count of read bytes. The opcode is either `Text` or `Binary`,
+    /// where the opcode of a continuation frame is inherited from its message.
+    ///
+    /// Control frames are handled internally in the same way as `Stream::read`,
+    /// a Ping is saved(see [`Stream::is_pinged`]) and a Pong is not supported.
+    /// Once a Close frame is received or `EOF` is reached, `Ok((OpCode::Close, 0))`
+    /// is returned, which could be checked via [`Stream::is_read_close`]
+    /// and [`Stream::is_read_eof`]. Reaching `EOF` in the middle of a frame
+    /// leads to an `UnexpectedEof` error.
+    ///
+    /// This never reads beyond a frame, a frame with a large payload is returned
+    /// with several reads. A data frame without payload leads to `Ok((opcode, 0))`.
+    /// Caller should provide a non-empty buffer. If payload is discarded(see
+    /// [`Stream::set_discard_payloads`]), the count of discarded bytes is
+    /// returned, where the buffer is left untouched.
+    ///
+    /// If the underlying IO source returns an error, the error is returned
+    /// as is, and states are preserved. A later read will resume the incomplete frame.
+    pub fn read_data(&mut self, buf: &mut [u8]) -> Result<(OpCode, usize)> {
+        debug_assert!(!buf.is_empty());
+
+        loop {
+            match self.read_state {
+                ReadState::ReadHead(_) => {}
+                ReadState::ReadData { .. } => {
+                    let opcode = self.fragment.opcode;
+                    // read discarded payload into a temporary buffer
+                    let mut discard_buf = [0u8; DISCARD_BUF_SIZE];
+                    let buf = if self.discard_payloads || self.fragment.is_ignored {
+                        &mut discard_buf[..]
+                    } else {
+                        &mut *buf
+                    };
+                    let mut bufs = [IoSliceMut::new(buf)];
+                    let read = |io: &mut IO, bufs: &mut [IoSliceMut]| io.read(&mut bufs[0]).into();
+                    let read_n = match read_payload_vectored(self, read, &mut bufs) {
+                        Poll::Ready(x) => x?,
+                        Poll::Pending => unreachable!(),
+                    };
+                    if self.is_read_eof() {
+                        return Ok((OpCode::Close, 0));
+                    }
+                    if self.fragment.is_ignored {
+                        continue;
+                    }
+                    return Ok((opcode, read_n));
+                }
+                ReadState::ReadPing { next, mask } => {
+                    let len = self.heartbeat.ping_store.wr_pos() + next as usize;
+                    self.read_ctrl_frame(OpCode::Ping, len, mask)?;
+                    continue;
+                }
+                ReadState::ReadClose { next, mask } => {
+                    let len = self.close.recv_store.wr_pos() + next as usize;
+                    self.read_ctrl_frame(self.read_close_opcode(), len, mask)?;
+                    continue;
+                }
+                ReadState::Eof | ReadState::Close => return Ok((OpCode::Close, 0)),
+                ReadState::ProcessBuf { .. } => unreachable!(),
+            }
+
+            let (
+                FrameHead {
+                    fin,
+                    opcode,
+                    mask,
+                    length,
+                    ..
+                },
+                prefetch_n,
+            ) = match self.read_frame_head(if self.discard_payloads { &mut [] } else { buf })? {
+                Some(x) => x,
+                None => return Ok((OpCode::Close, 0)),
+            };
+            let frame_len = length.to_num();
+
+            match opcode {
+                // we never send a ping, so we ignore the pong
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into())
+                }
+                OpCode::Text | OpCode::Binary | OpCode::Continue => {
+                    // drop the payload read with the head, then the rest
+                    if self.check_data_frame(fin, opcode)? {
+                        if frame_len > prefetch_n as u64 {
+                            self.read_state = ReadState::ReadData {
+                                next: frame_len - prefetch_n as u64,
+                                mask,
+                                offset: (prefetch_n & 0x03) as u8,
+                            };
+                        }
+                        continue;
+                    }
+                    if opcode != OpCode::Continue {
+                        self.fragment.opcode = opcode;
+                    }
+                    if frame_len == 0 {
+                        return Ok((self.fragment.opcode, 0));
+                    }
+                    // continue to read payload
+                    if prefetch_n == 0 {
+                        self.read_state = ReadState::ReadData {
+                            next: frame_len,
+                            mask,
+                            offset: 0,
+                        };
+                        continue;
+                    }
+                    // some payload has been read with the head
+                    if let Mask::Key(key) = mask {
+                        apply_mask4(key, &mut buf[..prefetch_n]);
+                    }
+                    if frame_len > prefetch_n as u64 {
+                        self.read_state = ReadState::ReadData {
+                            next: frame_len - prefetch_n as u64,
+                            mask,
+                            offset: (prefetch_n & 0x03) as u8,
+                        };
+                    }
+                    return Ok((self.fragment.opcode, prefetch_n));
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+    }
+
+    /// Read exactly one control frame, returns its opcode and payload.
+    /// The opcode is either `Ping` or `Close`, or `Pong` if it is accepted
+    /// (see [`Stream::set_manual_control`] and [`Stream::set_strict`]).
+    ///
+    /// This is only valid on a stream where data frames are not expected,
+    /// e.g. draining a stream during the close handshake. A ping is also
+    /// saved(see [`Stream::is_pinged`]) and a Pong is not supported,
+    /// like `Stream::read`. Once a Close frame is received,
+    /// its payload is returned each time this is called.
+    /// If reaching `EOF`, `Ok((OpCode::Close, &[]))` is returned,
+    /// which could be checked via [`Stream::is_read_eof`].
+    ///
+    /// Data frames are never dropped. If a data frame appears,
+    /// a [`CtrlError::DataFrameInControl`] error is returned after
+    /// the head is consumed, and the payload could still be read
+    /// with `Stream::read` or [`Stream::read_data`].
+    ///
+    /// This must be called at a frame boundary or during an incomplete
+    /// control frame, otherwise a [`CtrlError::ReadFrameInRead`] error
+    /// is returned. If the underlying IO source returns an error,
+    /// a later read will resume the incomplete frame.
+    pub fn read_control(&mut self) -> Result<(OpCode, &[u8])> {
+        let opcode = loop {
+            break match self.read_state {
+                ReadState::ReadHead(_) => {
+                    let FrameHead {
+                        fin,
+                        opcode,
+                        mask,
+                        length,
+                        ..
+                    } = match self.read_frame_head(&mut [])? {
+                        Some((head, _)) => head,
+                        None => return Ok((OpCode::Close, &[])),
+                    };
+                    let frame_len = length.to_num();
+
+                    match opcode {
+                        // we never send a ping, so we ignore the pong
+                        OpCode::Pong if !self.accept_pong() => {
+                            return Err(FrameError::UnsupportedOpcode.into())
+                        }
+                        OpCode::Text | OpCode::Binary | OpCode::Continue => {
+                            if self.check_data_frame(fin, opcode)? {
+                                self.discard_payload(frame_len, mask)?;
+                                continue;
+                            }
+                            if opcode != OpCode::Continue {
+                                self.fragment.opcode = opcode;
+                            }
+                            // leave the payload to other reads
+                            if frame_len != 0 {
+                                self.read_state = ReadState::ReadData {
+                                    next: frame_len,
+                                    mask,
+                                    offset: 0,
+                                };
+                            }
+                            return Err(CtrlError::DataFrameInControl.into());
+                        }
+                        OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                            self.start_ctrl_frame(fin, opcode, frame_len, mask)?;
+                            opcode
+                        }
+                    }
+                }
+                ReadState::ReadPing { next, mask } => {
+                    let len = self.heartbeat.ping_store.wr_pos() + next as usize;
+                    self.read_ctrl_frame(OpCode::Ping, len, mask)?;
+                    OpCode::Ping
+                }
+                ReadState::ReadClose { next, mask } => {
+                    let len = self.close.recv_store.wr_pos() + next as usize;
+                    let opcode = self.read_close_opcode();
+                    self.read_ctrl_frame(opcode, len, mask)?;
+                    opcode
+                }
+                ReadState::Close => OpCode::Close,
+                ReadState::Eof => return Ok((OpCode::Close, &[])),
+                ReadState::ReadData { .. } | ReadState::ProcessBuf { .. } => {
+                    return Err(CtrlError::ReadFrameInRead.into())
+                }
+            };
+        };
+
+        if self.is_read_eof() {
+            return Ok((OpCode::Close, &[]));
+        }
+        match opcode {
+            OpCode::Ping => Ok((opcode, self.heartbeat.ping_store.read())),
+            _ => Ok((opcode, self.close.recv_store.read())),
+        }
+    }
+}
+
+impl<IO: Read + Write, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Read exactly one data frame which is accepted by `filter`,
+    /// returns the opcode of the message and the length of the payload.
+    /// Same as [`Stream::read_data`], the opcode is either `Text` or `Binary`,
+    /// where the opcode of a continuation frame is inherited from its message.
+    ///
+    /// The filter is called on the head of each data frame before its
+    /// payload is read, where a continuation frame has its own opcode.
+    /// A skipped frame is discarded, then the next frame is read.
+    /// Skipping a fragment also discards the rest of its message,
+    /// without calling the filter on them.
+    /// A rejected frame leads to a `Close` frame with the provided status code,
+    /// then a [`FrameError::RejectedFrame`] error, where the
+    /// payload is left unread.
+    ///
+    /// Otherwise this is the same as [`Stream::read_exact_frame`],
+    /// control frames are handled internally, and a Close
+    /// or `EOF` leads to `Ok((OpCode::Close, 0))`.
+    pub fn read_with_filter<F>(&mut self, buf: &mut [u8], filter: F) -> Result<(OpCode, usize)>
+    where
+        F: Fn(&FrameHead) -> FrameAction,
+    {
+        // the frame is accepted before
+        if let Some((head, copied)) = self.partial_frame() {
+            let len = head.length.to_num() as usize;
+            if len > buf.len() {
+                return Err(FrameError::NotEnoughCapacity.into());
+            }
+            self.fill_frame_payload(head, &mut buf[..len], copied, copied)?;
+            return Ok((self.fragment.opcode, len));
+        }
+
+        loop {
+            if self.is_read_end() {
+                return Ok((OpCode::Close, 0));
+            }
+
+            // never prefetch payload before the frame is accepted
+            let head = match self.read_frame_head(&mut [])? {
+                Some((head, _)) => head,
+                None => return Ok((OpCode::Close, 0)),
+            };
+            let FrameHead {
+                fin,
+                opcode,
+                mask,
+                length,
+                ..
+            } = head;
+            let frame_len = length.to_num();
+
+            match opcode {
+                OpCode::Text | OpCode::Binary | OpCode::Continue => {
+                    let is_skipped = opcode == OpCode::Continue
+                        && self.fragment.is_fragmented
+                        && self.fragment.is_ignored;
+                    if self.check_data_frame(fin, opcode)? {
+                        self.discard_payload(frame_len, mask)?;
+                        continue;
+                    }
+                    // the rest of a skipped message
+                    if is_skipped {
+                        self.fragment.is_ignored = fin == Fin::N;
+                        self.discard_payload(frame_len, mask)?;
+                        continue;
+                    }
+                    if opcode != OpCode::Continue {
+                        self.fragment.opcode = opcode;
+                    }
+
+                    match filter(&head) {
+                        FrameAction::Accept => {}
+                        FrameAction::Reject(code) => {
+                            self.read_state = ReadState::ReadData {
+                                next: frame_len,
+                                mask,
+                                offset: 0,
+                            };
+                            self.send_close(code, &[])?;
+                            return Err(FrameError::RejectedFrame(code).into());
+                        }
+                        FrameAction::Skip => {
+                            self.fragment.is_ignored = fin == Fin::N;
+                            self.discard_payload(frame_len, mask)?;
+                            continue;
+                        }
+                    }
+
+                    if self.discard_payloads {
+                        self.discard_payload(frame_len, mask)?;
+                        return Ok((self.fragment.opcode, saturate_len(frame_len)));
+                    }
+
+                    if frame_len > buf.len() as u64 {
+                        self.read_state = ReadState::ReadData {
+                            next: frame_len,
+                            mask,
+                            offset: 0,
+                        };
+                        return Err(FrameError::NotEnoughCapacity.into());
+                    }
+
+                    let len = frame_len as usize;
+                    self.fill_frame_payload(head, &mut buf[..len], 0, 0)?;
+                    return Ok((self.fragment.opcode, len));
+                }
+                // we never send a ping, so we ignore the pong
+                OpCode::Pong if !self.accept_pong() => {
+                    return Err(FrameError::UnsupportedOpcode.into());
+                }
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.start_ctrl_frame(fin, opcode, frame_len, mask)?
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Read};
+    use std::error::Error;
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_frame, make_frame_with_mask};
+    use super::super::test::{make_data, make_head_with_fin};
+    use crate::frame::*;
+    use crate::role::*;
+
+    #[test]
+    fn read_frame_at_extended_flag() {
+        fn read<R: RoleHelper>(mask: Mask, len: usize, rlimit: usize) {
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, mask, len);
+            let (head, frame_head_len) = FrameHead::decode(&frame).unwrap();
+            assert_eq!(head.mask, mask);
+            assert_eq!(head.length.to_num(), len as u64);
+            assert_eq!(super::head_len(&frame), frame_head_len);
+            assert_eq!(data_payload_len(&frame[..frame_head_len]), Some(len as u64));
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut frame[frame_head_len..]);
+            }
+
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit,
+                wlimit: 0,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, R::new());
+            let mut buf = vec![0; 4096];
+            let mut data2 = Vec::new();
+            while data2.len() < len {
+                let n = stream.read(&mut buf).unwrap();
+                data2.extend_from_slice(&buf[..n]);
+            }
+            assert_eq!(data2, data);
+            assert_eq!(stream.stats().read_frames, 1);
+        }
+
+        // a length of 126 or 127 requires an extended length,
+        // whether the MASK bit is set or not
+        for len in [125, 126, 127, 128] {
+            for rlimit in [1, 3, 4096] {
+                read::<Client>(Mask::None, len, rlimit);
+                read::<Server>(Mask::Skip, len, rlimit);
+                read::<Server>(Mask::Key(new_mask_key()), len, rlimit);
+            }
+        }
+    }
+
+    #[test]
+    fn read_payload_exact_records() {
+        // two records in a masked frame, then a short frame
+        let key = new_mask_key();
+        let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 20);
+        apply_mask4(key, &mut frame[6..]);
+        let (frame2, data2) = make_frame_with_mask(OpCode::Binary, Mask::Skip, 5);
+        frame.extend(frame2);
+
+        let io = LimitReadWriter {
+            buf: frame,
+            rlimit: 3,
+            wlimit: 0,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+
+        let mut record = [0u8; 10];
+        stream.read_payload_exact(&mut record).unwrap();
+        assert_eq!(record, data[..10]);
+        assert_eq!(stream.next_payload_len(), Some(10));
+        assert_eq!(stream.current_mask_offset(), Some(2));
+        stream.read_payload_exact(&mut record).unwrap();
+        assert_eq!(record, data[10..]);
+        assert_eq!(stream.next_payload_len(), None);
+
+        // never cross into the next frame
+        let e = stream.read_payload_exact(&mut record).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::NotEnoughPayload(5))
+        );
+        let mut buf = [0u8; 32];
+        let mut rest = Vec::new();
+        while rest.len() < 5 {
+            let n = stream.read(&mut buf).unwrap();
+            rest.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(rest, data2);
+
+        let e = stream.read_payload_exact(&mut record).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_exact_across_would_block() {
+        // 3 bytes at a time, every third read is `WouldBlock`
+        struct BlockReader {
+            buf: Vec<u8>,
+            cursor: usize,
+            reads: usize,
+        }
+
+        impl Read for BlockReader {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+                self.reads = (self.reads + 1) % 3;
+                if self.reads == 0 {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                let n = [buf.len(), 3, self.buf.len() - self.cursor]
+                    .into_iter()
+                    .min()
+                    .unwrap();
+                buf[..n].copy_from_slice(&self.buf[self.cursor..self.cursor + n]);
+                self.cursor += n;
+                Ok(n)
+            }
+        }
+
+        fn make_stream() -> (Stream<BlockReader, Server>, Vec<u8>) {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 40);
+            apply_mask4(key, &mut frame[6..]);
+            let io = BlockReader {
+                buf: frame,
+                cursor: 0,
+                reads: 0,
+            };
+            (Stream::new(io, Server::new()), data)
+        }
+
+        fn retry<T>(mut f: impl FnMut() -> Result<T>) -> T {
+            loop {
+                match f() {
+                    Ok(x) => return x,
+                    Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+                }
+            }
+        }
+
+        // the same buffer is passed again
+        let (mut stream, data) = make_stream();
+        let mut buf = [0u8; 64];
+        assert_eq!(retry(|| stream.read_exact_frame(&mut buf)), 40);
+        assert_eq!(&buf[..40], &data);
+        assert_eq!(retry(|| stream.read_exact_frame(&mut buf)), 0);
+        assert!(stream.is_read_eof());
+
+        let (mut stream, data) = make_stream();
+        let head = retry(|| stream.read_frame_into_slice(&mut buf));
+        assert_eq!(head.length, PayloadLen::from_num(40));
+        assert_eq!(&buf[..40], &data);
+
+        let (mut stream, data) = make_stream();
+        let mut record = [0u8; 10];
+        for chunk in data.chunks(10) {
+            retry(|| stream.read_payload_exact(&mut record));
+            assert_eq!(record, chunk);
+        }
+        assert_eq!(stream.next_payload_len(), None);
+
+        // the rest is read with `Stream::read`
+        let (mut stream, data) = make_stream();
+        let left = loop {
+            let e = stream.read_exact_frame(&mut buf).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock);
+            if let Some(n) = stream.next_payload_len() {
+                break n as usize;
+            }
+        };
+        let mut data2 = buf[..data.len() - left].to_vec();
+        while data2.len() < data.len() {
+            if let Ok(n) = stream.read(&mut buf) {
+                data2.extend_from_slice(&buf[..n]);
+            }
+        }
+        assert_eq!(data2, data);
+        assert_eq!(retry(|| stream.read_exact_frame(&mut buf)), 0);
+    }
+
+    #[test]
+    fn fill_head_across_reads() {
+        // two bytes at a time, separated by `WouldBlock`
+        struct SlowReader {
+            buf: Vec<u8>,
+            cursor: usize,
+            is_blocked: bool,
+        }
+
+        impl Read for SlowReader {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+                self.is_blocked = !self.is_blocked;
+                if !self.is_blocked {
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                let n = [buf.len(), 2, self.buf.len() - self.cursor]
+                    .into_iter()
+                    .min()
+                    .unwrap();
+                buf[..n].copy_from_slice(&self.buf[self.cursor..self.cursor + n]);
+                self.cursor += n;
+                Ok(n)
+            }
+        }
+
+        // 2 bytes, 16-bit length, then the mask key
+        let key = new_mask_key();
+        let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 300);
+        apply_mask4(key, &mut frame[8..]);
+        let io = SlowReader {
+            buf: frame,
+            cursor: 0,
+            is_blocked: false,
+        };
+        let mut stream = Stream::new(io, Server::new());
+
+        let head = loop {
+            match stream.fill_head() {
+                Ok(head) => break head.unwrap(),
+                Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+            }
+        };
+        assert_eq!(head.fin, Fin::Y);
+        assert_eq!(head.opcode, OpCode::Binary);
+        assert_eq!(head.mask, Mask::Key(key));
+        assert_eq!(head.length, PayloadLen::from_num(300));
+        // the payload is untouched
+        assert_eq!(stream.as_ref().cursor, 8);
+        assert_eq!(stream.stats().read_frames, 1);
+
+        // payload is read by the caller
+        let io = stream.as_mut();
+        let mut payload = io.buf[io.cursor..].to_vec();
+        io.cursor = io.buf.len();
+        apply_mask4(key, &mut payload);
+        assert_eq!(payload, data);
+
+        let mut buf = vec![0; 16];
+        while stream.fill_head().is_err() {}
+        assert!(stream.is_read_eof());
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_exact_frame_from_stream() {
+        for i in 0..=0x2000 {
+            let (frame, data) = make_frame::<Client>(OpCode::Binary, i);
+            let frame_len = frame.len();
+            let frame2 = make_frame::<Client>(OpCode::Binary, i + 1).0;
+            let io = Cursor::new([frame, frame2].concat());
+
+            let mut stream = Stream::new(io, Server::new());
+            let mut buf = vec![0; 0x2001];
+
+            let n = stream.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(n, i);
+            assert_eq!(&buf[..n], &data);
+
+            // never read beyond the frame
+            assert_eq!(stream.as_ref().position() as usize, frame_len);
+
+            let n = stream.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(n, i + 1);
+            let n = stream.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(n, 0);
+            assert!(stream.is_read_eof());
+        }
+    }
+
+    #[test]
+    fn read_split_from_stream() {
+        for i in [0, 1, 125, 126, 0x1000, 0xffff, 0x10000] {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), i);
+            let head_len = frame.len() - i;
+            apply_mask4(key, &mut frame[head_len..]);
+            let (frame2, data2) = make_frame::<Client>(OpCode::Text, i + 1);
+            let head_len2 = frame2.len() - i - 1;
+            let io = Cursor::new([frame.clone(), frame2.clone()].concat());
+
+            let mut stream = Stream::new(io, Server::new());
+            let mut head_buf = [0; 14];
+            let mut payload_buf = vec![0; 0x10001];
+
+            // not enough capacity, nothing is consumed
+            assert_eq!(stream.next_payload_len(), None);
+            let (n1, n2) = stream
+                .read_split(&mut head_buf[..2], &mut payload_buf)
+                .unwrap();
+            assert_eq!((n1, n2), (head_len, i));
+            assert_eq!(stream.next_payload_len(), Some(i as u64));
+            if i != 0 {
+                let (n1, n2) = stream
+                    .read_split(&mut head_buf, &mut payload_buf[..i - 1])
+                    .unwrap();
+                assert_eq!((n1, n2), (head_len, i));
+                assert!(head_buf.iter().all(|x| *x == 0));
+            }
+
+            let (n1, n2) = stream.read_split(&mut head_buf, &mut payload_buf).unwrap();
+            assert_eq!(&head_buf[..n1], &frame[..head_len]);
+            assert_eq!(&payload_buf[..n2], &data);
+
+            let (n1, n2) = stream.read_split(&mut head_buf, &mut payload_buf).unwrap();
+            assert_eq!(&head_buf[..n1], &frame2[..head_len2]);
+            assert_eq!(&payload_buf[..n2], &data2);
+
+            assert_eq!(
+                stream.read_split(&mut head_buf, &mut payload_buf).unwrap(),
+                (0, 0)
+            );
+            assert!(stream.is_read_eof());
+            assert_eq!(stream.stats().read_frames, 2);
+        }
+    }
+
+    #[test]
+    fn read_frame_into_slice_from_stream() {
+        let len = 1 << 20;
+        let key = new_mask_key();
+        let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), len);
+        let head_len = frame.len() - len;
+        apply_mask4(key, &mut frame[head_len..]);
+        let io = LimitReadWriter {
+            buf: frame,
+            rlimit: 4093,
+            wlimit: 0,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+
+        // too small, nothing is consumed
+        let mut dst = vec![0; len - 1];
+        let e = stream.read_frame_into_slice(&mut dst).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::BufferTooSmall(len as u64))
+        );
+        assert!(dst.iter().all(|x| *x == 0));
+
+        // pre-sized, with room to spare
+        let mut dst = vec![0; len + 16];
+        let head = stream.read_frame_into_slice(&mut dst).unwrap();
+        assert_eq!((head.opcode, head.mask), (OpCode::Binary, Mask::Key(key)));
+        assert_eq!(head.length.to_num(), len as u64);
+        assert_eq!(&dst[..len], &data);
+        assert!(dst[len..].iter().all(|x| *x == 0));
+
+        let head = stream.read_frame_into_slice(&mut dst).unwrap();
+        assert_eq!(head.opcode, OpCode::Close);
+        assert!(stream.is_read_eof());
+    }
+
+    #[test]
+    fn read_exact_masked_frame_from_stream() {
+        for i in 0..=0x200 {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), i);
+            let head_len = frame.len() - i;
+            apply_mask4(key, &mut frame[head_len..]);
+
+            let mut stream = Stream::new(Cursor::new(frame), Server::new());
+            let mut buf = vec![0; 0x200];
+
+            let n = stream.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &data);
+        }
+    }
+
+    #[test]
+    fn read_exact_frame_not_enough_capacity() {
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 64);
+        let mut stream = Stream::new(Cursor::new(frame), Server::new());
+
+        let mut buf = vec![0; 32];
+        let e = stream.read_exact_frame(&mut buf).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::NotEnoughCapacity)
+        );
+        assert_eq!(stream.next_payload_len(), Some(64));
+
+        // continue with a normal read
+        let mut buf = vec![0; 128];
+        let n = stream.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], &data);
+    }
+
+    #[test]
+    fn read_data_from_stream() {
+        use super::super::test::LimitReadWriter;
+
+        fn read(rlimit: usize, buf_len: usize) {
+            let key = new_mask_key();
+            let mut frames = Vec::new();
+            let mut expected = Vec::new();
+            let mut ping = Vec::new();
+            for (i, opcode) in [
+                OpCode::Ping,
+                OpCode::Binary,
+                OpCode::Ping,
+                OpCode::Ping,
+                OpCode::Text,
+                OpCode::Ping,
+            ]
+            .into_iter()
+            .enumerate()
+            {
+                let len = if opcode == OpCode::Ping { i } else { 100 + i };
+                let (mut frame, data) = make_frame_with_mask(opcode, Mask::Key(key), len);
+                let head_len = frame.len() - len;
+                apply_mask4(key, &mut frame[head_len..]);
+                frames.append(&mut frame);
+                if opcode == OpCode::Ping {
+                    ping = data;
+                } else {
+                    expected.push((opcode, data));
+                }
+            }
+            frames.extend_from_slice(&make_frame::<Client>(OpCode::Close, 0).0);
+
+            let io = LimitReadWriter {
+                buf: frames,
+                rlimit,
+                wlimit: 0,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+            let mut buf = vec![0; buf_len];
+
+            for (opcode, data) in expected {
+                let mut data2 = Vec::new();
+                while data2.len() < data.len() {
+                    let (opcode2, n) = stream.read_data(&mut buf).unwrap();
+                    assert_eq!(opcode2, opcode);
+                    data2.extend_from_slice(&buf[..n]);
+                }
+                // payload of pings are never returned
+                assert_eq!(data2, data);
+            }
+            assert_eq!(stream.read_data(&mut buf).unwrap(), (OpCode::Close, 0));
+            assert!(stream.is_read_close());
+            assert_eq!(stream.ping_data(), &ping);
+        }
+
+        for rlimit in 1..=16 {
+            for buf_len in [1, 7, 64, 256] {
+                read(rlimit, buf_len);
+            }
+        }
+    }
+
+    #[test]
+    fn read_frame_syscalls() {
+        use std::io::IoSliceMut;
+
+        // count calls to the underlying IO source
+        struct CountIO {
+            io: Cursor<Vec<u8>>,
+            count: usize,
+        }
+
+        impl Read for CountIO {
+            fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+                self.count += 1;
+                self.io.read(buf)
+            }
+
+            fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+                self.count += 1;
+                self.io.read_vectored(bufs)
+            }
+        }
+
+        for len in [1, 64, 125, 126, 1024, 0xffff, 0x10000] {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), len);
+            let head_len = frame.len() - len;
+            apply_mask4(key, &mut frame[head_len..]);
+            let mut buf = vec![0; 0x10000 + 14];
+
+            // byte stream, one syscall per read
+            let io = CountIO {
+                io: Cursor::new(frame.clone()),
+                count: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+            let n = stream.read(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &data);
+            assert_eq!(stream.as_ref().count, 1);
+            assert_eq!(stream.stats().read_syscalls, 1);
+
+            // frame, the first two bytes, extended length,
+            // then the mask key and payload together
+            let io = CountIO {
+                io: Cursor::new(frame),
+                count: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+            let n = stream.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &data);
+            let expected = if len <= 125 { 2 } else { 3 };
+            assert_eq!(stream.as_ref().count, expected);
+            assert_eq!(stream.stats().read_syscalls, expected as u64);
+        }
+    }
+
+    #[test]
+    fn read_control_from_stream() {
+        use super::super::test::LimitReadWriter;
+
+        let key = new_mask_key();
+        let (mut ping, ping_data) = make_frame_with_mask(OpCode::Ping, Mask::Key(key), 100);
+        let head_len = ping.len() - 100;
+        apply_mask4(key, &mut ping[head_len..]);
+        let (binary, data) = make_frame::<Client>(OpCode::Binary, 32);
+        let (close, close_data) = make_frame::<Client>(OpCode::Close, 16);
+
+        for rlimit in 1..=16 {
+            let io = LimitReadWriter {
+                buf: [ping.clone(), binary.clone(), close.clone()].concat(),
+                rlimit,
+                wlimit: 0,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+
+            let (opcode, payload) = stream.read_control().unwrap();
+            assert_eq!(opcode, OpCode::Ping);
+            assert_eq!(payload, &ping_data);
+            assert!(stream.is_pinged());
+
+            // data frame is left to other reads
+            let e = stream.read_control().unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<CtrlError>(),
+                Some(&CtrlError::DataFrameInControl)
+            );
+            let e = stream.read_control().unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<CtrlError>(),
+                Some(&CtrlError::ReadFrameInRead)
+            );
+            let mut buf = vec![0; 32];
+            let mut n = 0;
+            while n < 32 {
+                n += stream.read_data(&mut buf[n..]).unwrap().1;
+            }
+            assert_eq!(buf, data);
+
+            let (opcode, payload) = stream.read_control().unwrap();
+            assert_eq!(opcode, OpCode::Close);
+            assert_eq!(payload, &close_data);
+            assert!(stream.is_read_close());
+            assert_eq!(
+                stream.read_control().unwrap(),
+                (OpCode::Close, &close_data[..])
+            );
+        }
+    }
+
+    #[test]
+    fn read_with_filter_from_stream() {
+        let (binary, data) = make_frame::<Client>(OpCode::Binary, 100);
+        let (text, _) = make_frame::<Client>(OpCode::Text, 200);
+        let (large, _) = make_frame::<Client>(OpCode::Binary, 1000);
+        let (binary2, data2) = make_frame::<Client>(OpCode::Binary, 10);
+        let fragment = |fin, opcode, len| {
+            let head = make_head_with_fin(fin, opcode, Client::new().mask_key(), len);
+            [head, make_data(len)].concat()
+        };
+        // the continuations of a skipped fragment are small enough to be accepted
+        let fragmented = [
+            fragment(Fin::N, OpCode::Binary, 1000),
+            fragment(Fin::N, OpCode::Continue, 5),
+            fragment(Fin::Y, OpCode::Continue, 5),
+        ]
+        .concat();
+
+        let filter = |head: &FrameHead| match head.opcode {
+            OpCode::Text => FrameAction::Reject(CloseCode::Unsupported),
+            _ if head.length.to_num() > 500 => FrameAction::Skip,
+            _ => FrameAction::Accept,
+        };
+
+        for rlimit in [1, 7, 4096] {
+            let io = LimitReadWriter {
+                buf: [
+                    binary.clone(),
+                    large.clone(),
+                    fragmented.clone(),
+                    binary2.clone(),
+                    text.clone(),
+                ]
+                .concat(),
+                rlimit,
+                wlimit: 4096,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+            let mut buf = vec![0; 4096];
+
+            // accept binary frames, and skip the large ones
+            let (opcode, n) = stream.read_with_filter(&mut buf, filter).unwrap();
+            assert_eq!(opcode, OpCode::Binary);
+            assert_eq!(&buf[..n], &data);
+            let (opcode, n) = stream.read_with_filter(&mut buf, filter).unwrap();
+            assert_eq!(opcode, OpCode::Binary);
+            assert_eq!(&buf[..n], &data2);
+
+            // reject the text frame, before its payload is read
+            let cursor = stream.as_ref().cursor;
+            let e = stream.read_with_filter(&mut buf, filter).unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<FrameError>(),
+                Some(&FrameError::RejectedFrame(CloseCode::Unsupported))
+            );
+            assert_eq!(stream.as_ref().cursor, cursor + 8);
+            assert!(stream.is_write_close());
+            assert_eq!(stream.stats().read_frames, 7);
+        }
+    }
+
+    #[test]
+    fn read_into_ring_from_stream() {
+        let key = new_mask_key();
+        let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 100);
+        let head_len = frame.len() - 100;
+        apply_mask4(key, &mut frame[head_len..]);
+        let (frame2, data2) = make_frame::<Client>(OpCode::Binary, 10);
+
+        for rlimit in [1, 7, 4096] {
+            // the wrap is not aligned to the mask key
+            for split in [0, 1, 3, 50, 99, 100, 200] {
+                let io = LimitReadWriter {
+                    buf: [frame.clone(), frame2.clone()].concat(),
+                    rlimit,
+                    wlimit: 0,
+                    cursor: 0,
+                };
+                let mut stream = Stream::new(io, Server::new());
+                let mut ring = [0; 200];
+                let (second, first) = ring.split_at_mut(200 - split);
+
+                let n = stream.read_into_ring(first, second).unwrap();
+                assert_eq!(n, 100);
+                let n1 = std::cmp::min(split, 100);
+                assert_eq!(&ring[200 - split..200 - split + n1], &data[..n1]);
+                assert_eq!(&ring[..100 - n1], &data[n1..]);
+
+                // the next frame is untouched
+                let (first, second) = ring.split_at_mut(4);
+                let n = stream.read_into_ring(first, second).unwrap();
+                assert_eq!(&ring[..n], &data2);
+                assert_eq!(stream.read_into_ring(&mut [], &mut []).unwrap(), 0);
+                assert!(stream.is_read_eof());
+            }
+        }
+
+        // not enough capacity, then continue with a normal read
+        let mut stream = Stream::new(Cursor::new(frame), Server::new());
+        let (mut first, mut second) = ([0; 50], [0; 49]);
+        let e = stream.read_into_ring(&mut first, &mut second).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::NotEnoughCapacity)
+        );
+        let mut buf = vec![0; 100];
+        assert_eq!(stream.read(&mut buf).unwrap(), 100);
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn read_huge_frame_from_stream() {
+        // beyond usize::MAX on 32-bit platforms,
+        // and beyond the address space on 64-bit platforms
+        let frame_len = 1_u64 << 62;
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Binary,
+            Mask::None,
+            PayloadLen::from_num(frame_len),
+        );
+        let mut frame = vec![0; 14];
+        let head_len = head.encode(&mut frame).unwrap();
+        frame.truncate(head_len);
+        frame.extend_from_slice(&[1; 100]);
+
+        let expect_len = std::cmp::min(frame_len, usize::MAX as u64) as usize;
+        let check_state = |stream: &Stream<Cursor<Vec<u8>>, Client>, next: u64| {
+            assert!(matches!(
+                stream.read_state,
+                ReadState::ReadData { next: x, .. } if x == next
+            ));
+        };
+
+        // streamed
+        let mut stream = Stream::new(Cursor::new(frame.clone()), Client::new());
+        let mut buf = vec![0; 128];
+        assert_eq!(stream.read(&mut buf).unwrap(), 100);
+        check_state(&stream, frame_len - 100);
+
+        // rejected, and could still be streamed
+        let mut stream = Stream::new(Cursor::new(frame.clone()), Client::new());
+        let e = stream.read_frames(1, None).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::NotEnoughCapacity)
+        );
+        check_state(&stream, frame_len);
+
+        // length is saturated
+        let mut stream = Stream::new(Cursor::new(frame.clone()), Client::new());
+        let mut head_buf = [0; 14];
+        assert_eq!(
+            stream.read_split(&mut head_buf, &mut buf).unwrap(),
+            (head_len, expect_len)
+        );
+        let mut stream = Stream::new(Cursor::new(frame), Client::new());
+        stream.set_discard_payloads(true);
+        assert!(stream.read_exact_frame(&mut buf).is_err());
+        assert_eq!(stream.stats().read_payload_bytes, frame_len);
+    }
+
+    #[test]
+    fn read_frames_from_stream() {
+        use std::time::Duration;
+
+        let mut buf = Vec::new();
+        let mut frames = Vec::new();
+        for (opcode, len) in [
+            (OpCode::Binary, 10),
+            (OpCode::Ping, 4),
+            (OpCode::Text, 200),
+            (OpCode::Binary, 0),
+            (OpCode::Binary, 30),
+            (OpCode::Binary, 40),
+        ] {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(opcode, Mask::Key(key), len);
+            let head_len = frame.len() - len;
+            apply_mask4(key, &mut frame[head_len..]);
+            buf.append(&mut frame);
+            if opcode != OpCode::Ping {
+                frames.push((opcode, data, buf.len()));
+            }
+        }
+
+        // only 3 of 5 frames are consumed
+        let mut stream = Stream::new(Cursor::new(buf.clone()), Server::new());
+        let frames2 = stream.read_frames(3, None).unwrap();
+        assert_eq!(frames2.len(), 3);
+        for ((head, payload), (opcode, data, _)) in frames2.iter().zip(frames.iter()) {
+            assert_eq!(head.opcode, *opcode);
+            assert_eq!(payload, data);
+        }
+        assert!(stream.is_pinged());
+        assert_eq!(stream.as_ref().position() as usize, frames[2].2);
+
+        // the rest, then stop at EOF
+        let frames2 = stream.read_frames(3, None).unwrap();
+        assert_eq!(frames2.len(), 2);
+        assert_eq!(frames2[1].1, frames[4].1);
+        assert!(stream.is_read_eof());
+
+        // the deadline has passed
+        let mut stream = Stream::new(Cursor::new(buf.clone()), Server::new());
+        let deadline = Instant::now() - Duration::from_millis(1);
+        assert!(stream.read_frames(3, Some(deadline)).unwrap().is_empty());
+        assert_eq!(stream.as_ref().position(), 0);
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(stream.read_frames(1, Some(deadline)).unwrap().len(), 1);
+
+        // payloads are created by the factory
+        let mut stream = Stream::new(Cursor::new(buf), Server::new());
+        let mut allocated = Vec::new();
+        let frames2 = stream
+            .read_frames_with(5, None, |len| {
+                let payload = Vec::with_capacity(len + 16);
+                allocated.push((payload.as_ptr(), len));
+                payload
+            })
+            .unwrap();
+        assert_eq!(frames2.len(), 5);
+        assert_eq!(allocated.len(), 5);
+        for ((_, payload), (ptr, len)) in frames2.iter().zip(allocated) {
+            assert_eq!(payload.as_ptr(), ptr);
+            assert_eq!(payload.len(), len);
+        }
+        for ((_, payload), (_, data, _)) in frames2.iter().zip(frames.iter()) {
+            assert_eq!(payload, data);
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use std::io::{Error, ErrorKind, Result};
+use std::time::{Duration, Instant};
+
+use super::Stream;
+use super::close::CloseReason;
+
+use crate::error::{self, CtrlError};
+
+/// Detect a half-open connection, where a ping is never replied.
+#[derive(Debug)]
+pub(super) struct Liveness {
+    pub timeout: Option<Duration>,
+    // when the first unanswered ping is sent
+    pub ping_at: Option<Instant>,
+}
+
+impl Liveness {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            timeout: None,
+            ping_at: None,
+        }
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Get the timeout to wait for a reply after sending a ping.
+    #[inline]
+    pub const fn liveness_timeout(&self) -> Option<Duration> { self.liveness.timeout }
+
+    /// Set the timeout to wait for a reply after sending a ping,
+    /// `None` to disable the detection, which is the default.
+    ///
+    /// Once a ping is sent with [`Stream::write_frame`], any incoming frame
+    /// within the timeout keeps the connection alive. Otherwise the peer is
+    /// considered dead: [`Stream::close_reason`] returns
+    /// [`CloseReason::Timeout`], and the next read or write fails with a
+    /// `NotConnected` error, whose source is [`CtrlError::PeerTimeout`].
+    ///
+    /// The check happens when the stream is read or written, a blocked
+    /// read is not woken by the timeout. With a blocking socket, also set
+    /// a read timeout on it; with tokio, wrap the read in
+    /// `tokio::time::timeout` or poll on a timer.
+    #[inline]
+    pub fn set_liveness_timeout(&mut self, timeout: Option<Duration>) {
+        self.liveness.timeout = timeout;
+    }
+
+    /// Record that a ping is sent, which starts the timeout.
+    ///
+    /// [`Stream::write_frame`] does this, it is required if a ping is
+    /// written by other means, e.g. with [`Stream::write_raw_frame`]
+    /// or directly to the IO source.
+    #[inline]
+    pub fn mark_ping_sent(&mut self) {
+        if self.liveness.ping_at.is_none() {
+            self.liveness.ping_at = Some(Instant::now());
+        }
+    }
+
+    /// Check if a ping is not replied in time,
+    /// see [`Stream::set_liveness_timeout`].
+    pub fn check_liveness(&mut self) -> Result<()> {
+        if self.close.reason == Some(CloseReason::Timeout) {
+            return Err(peer_timeout());
+        }
+        if let (Some(timeout), Some(ping_at)) = (self.liveness.timeout, self.liveness.ping_at) {
+            if ping_at.elapsed() >= timeout {
+                self.close.reason = Some(CloseReason::Timeout);
+                return Err(peer_timeout());
+            }
+        }
+        Ok(())
+    }
+
+    /// Any incoming frame is a reply.
+    #[inline]
+    pub(super) fn mark_read_activity(&mut self) { self.liveness.ping_at = None; }
+}
+
+#[inline]
+fn peer_timeout() -> Error {
+    Error::new(
+        ErrorKind::NotConnected,
+        error::Error::Ctrl(CtrlError::PeerTimeout),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+    use super::*;
+    use super::super::test::{make_frame, LimitReadWriter};
+    use crate::frame::{Fin, OpCode};
+    use crate::role::*;
+
+    #[test]
+    fn ping_replied_in_time() {
+        let (frame, _) = make_frame::<Client>(OpCode::Pong, 4);
+        let io = LimitReadWriter {
+            buf: frame,
+            rlimit: usize::MAX,
+            wlimit: usize::MAX,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+        stream.set_liveness_timeout(Some(Duration::from_millis(20)));
+        assert_eq!(stream.liveness_timeout(), Some(Duration::from_millis(20)));
+
+        stream.write_frame(Fin::Y, OpCode::Ping, b"ping").unwrap();
+        assert!(stream.liveness.ping_at.is_some());
+
+        let mut buf = vec![0; 64];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        assert!(stream.liveness.ping_at.is_none());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(stream.check_liveness().is_ok());
+        assert!(stream.close_reason().is_none());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn silent_peer() {
+        use std::error::Error as _;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // the peer never replies
+        let (io, _peer) = tokio::io::duplex(1024);
+        let mut stream = Stream::new(io, Client::new());
+        stream.set_liveness_timeout(Some(Duration::from_millis(200)));
+        stream.mark_ping_sent();
+
+        // a blocked read is not woken
+        let mut buf = vec![0; 64];
+        let timer = Duration::from_millis(10);
+        assert!(tokio::time::timeout(timer, stream.read(&mut buf))
+            .await
+            .is_err());
+        assert!(stream.close_reason().is_none());
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+
+        let e = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<CtrlError>(),
+            Some(&CtrlError::PeerTimeout)
+        );
+        assert_eq!(stream.close_reason(), Some(CloseReason::Timeout));
+
+        // reads and writes keep failing
+        let e = stream.write(b"hello").await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+        let e = stream.read(&mut buf).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::NotConnected);
+    }
+}
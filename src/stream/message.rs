@@ -0,0 +1,1318 @@
+//! Message oriented websocket stream.
+//!
+//! Unlike [`Stream`], where frame boundaries are hidden,
+//! [`MessageStream`] reassembles fragmented frames into a complete message,
+//! which is stored in an internal buffer. This requires heap allocation.
+//!
+//! Control frames are handled during a read, and never returned:
+//! the most recent ping is saved (see [`MessageStream::ping_data`]),
+//! a pong is ignored, and a close ends the stream.
+//!
+//! Messages could also be consumed as owned [`Message`]s
+//! with an iterator, see [`MessageStream::messages`].
+//! An async stream could surface control frames as well,
+//! see [`MessageStream::next_event`].
+//!
+//! The reassembly buffer could be drawn from a [`BufferPool`],
+//! to recycle allocations across messages and streams,
+//! see [`MessageStream::with_pool`].
+
+use std::io::{Read, Result, ErrorKind};
+use std::task::{Poll, ready};
+use std::sync::Arc;
+
+#[cfg(feature = "async")]
+use std::{pin::Pin, future::poll_fn};
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, ReadBuf};
+
+use super::Stream;
+use super::state::PingStore;
+use super::detail::min_len;
+
+use crate::frame::{FrameHead, Fin, Mask, OpCode, CloseCode};
+#[cfg(feature = "async")]
+use crate::frame::decode_close_payload;
+use crate::frame::mask::{apply_mask4, rotate_mask_key};
+use crate::error::{FrameError, MessageError};
+
+/// Size of the internal read buffer.
+const READ_BUF_SIZE: usize = 4096;
+
+/// Default max count of continuation frames in a message.
+pub const DEFAULT_MAX_FRAGMENTS: usize = 1024;
+
+/// Policy to handle illegal utf-8 text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Reject illegal text with an error, which is the default.
+    #[default]
+    Strict,
+    /// Replace illegal sequences with `U+FFFD`.
+    Lossy,
+}
+
+/// A complete message, yielded by [`Messages`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// utf-8 text, which has been validated or repaired
+    Text(String),
+    /// binary data
+    Binary(Vec<u8>),
+}
+
+/// Source of reassembly buffers, shared by message streams.
+pub trait BufferPool {
+    /// Get a buffer, which is cleared before use.
+    fn get(&self) -> Vec<u8>;
+
+    /// Return a buffer to the pool.
+    fn put(&self, buf: Vec<u8>);
+}
+
+/// A complete message or control frame, returned by [`MessageStream::next_event`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// a complete message
+    Message(Message),
+    /// payload of a `Ping` frame
+    Ping(Vec<u8>),
+    /// payload of a `Pong` frame
+    Pong(Vec<u8>),
+    /// status code and reason of a `Close` frame
+    Close(CloseCode, String),
+}
+
+/// Frame being read.
+#[derive(Debug, Clone, Copy)]
+struct FrameState {
+    fin: Fin,
+    opcode: OpCode,
+    mask: Mask,
+    next: u64,
+    offset: usize,
+}
+
+/// Message oriented websocket stream.
+///
+/// Text and binary messages are read via [`MessageStream::read_text`]
+/// and [`MessageStream::read_binary`].
+pub struct MessageStream<IO, Role> {
+    io: IO,
+    role: Role,
+    buf: Box<[u8]>,
+    beg: usize,
+    end: usize,
+    frame: Option<FrameState>,
+    message: Vec<u8>,
+    opcode: Option<OpCode>,
+    fragments: usize,
+    max_fragments: usize,
+    max_message_size: usize,
+    // the rest of an oversized message is dropped
+    is_discarded: bool,
+    utf8_valid: usize,
+    utf8_policy: Utf8Policy,
+    incremental_utf8: bool,
+    is_complete: bool,
+    ctrl_store: PingStore,
+    ping_store: PingStore,
+    is_eof: bool,
+    is_close: bool,
+    pool: Option<Arc<dyn BufferPool + Send + Sync>>,
+}
+
+impl<IO, Role> AsRef<IO> for MessageStream<IO, Role> {
+    #[inline]
+    fn as_ref(&self) -> &IO { &self.io }
+}
+
+impl<IO, Role> AsMut<IO> for MessageStream<IO, Role> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut IO { &mut self.io }
+}
+
+impl<IO, Role> std::fmt::Debug for MessageStream<IO, Role> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MessageStream")
+            .field("frame", &self.frame)
+            .field("opcode", &self.opcode)
+            .field("message_len", &self.message.len())
+            .field("is_complete", &self.is_complete)
+            .field("is_eof", &self.is_eof)
+            .field("is_close", &self.is_close)
+            .finish()
+    }
+}
+
+impl<IO, Role> MessageStream<IO, Role> {
+    /// Create message stream from IO source directly,
+    /// without a handshake.
+    #[inline]
+    pub fn new(io: IO, role: Role) -> Self {
+        Self {
+            io,
+            role,
+            buf: vec![0; READ_BUF_SIZE].into_boxed_slice(),
+            beg: 0,
+            end: 0,
+            frame: None,
+            message: Vec::new(),
+            opcode: None,
+            fragments: 0,
+            max_fragments: DEFAULT_MAX_FRAGMENTS,
+            max_message_size: 0,
+            is_discarded: false,
+            utf8_valid: 0,
+            utf8_policy: Utf8Policy::Strict,
+            incremental_utf8: true,
+            is_complete: false,
+            ctrl_store: PingStore::new(),
+            ping_store: PingStore::new(),
+            is_eof: false,
+            is_close: false,
+            pool: None,
+        }
+    }
+
+    /// Create message stream like [`MessageStream::new`], where
+    /// the reassembly buffer is drawn from the provided pool.
+    ///
+    /// A borrowed message (e.g. [`MessageStream::read_binary`]) is stored
+    /// in the same buffer. Once a message is moved out
+    /// (e.g. [`MessageStream::messages`]), a new buffer is drawn,
+    /// and the moved one could be returned with [`MessageStream::recycle`].
+    /// The buffer is returned to the pool once the stream is dropped.
+    #[inline]
+    pub fn with_pool(io: IO, role: Role, pool: Arc<dyn BufferPool + Send + Sync>) -> Self {
+        let mut stream = Self::new(io, role);
+        stream.pool = Some(pool);
+        stream.message = stream.new_message_buf();
+        stream
+    }
+
+    /// Return the buffer of a moved message to the pool, if any.
+    #[inline]
+    pub fn recycle(&self, message: Message) {
+        if let Some(pool) = &self.pool {
+            pool.put(match message {
+                Message::Text(text) => text.into_bytes(),
+                Message::Binary(data) => data,
+            });
+        }
+    }
+
+    /// Draw a reassembly buffer from the pool, if any.
+    fn new_message_buf(&self) -> Vec<u8> {
+        match &self.pool {
+            Some(pool) => {
+                let mut buf = pool.get();
+                buf.clear();
+                buf
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the role.
+    #[inline]
+    pub const fn role(&self) -> &Role { &self.role }
+
+    /// Get the policy to handle illegal utf-8 text.
+    #[inline]
+    pub const fn utf8_policy(&self) -> Utf8Policy { self.utf8_policy }
+
+    /// Set the policy to handle illegal utf-8 text.
+    /// The default is [`Utf8Policy::Strict`].
+    #[inline]
+    pub fn set_utf8_policy(&mut self, policy: Utf8Policy) { self.utf8_policy = policy; }
+
+    /// Check if utf-8 text is validated incrementally.
+    #[inline]
+    pub const fn is_incremental_utf8(&self) -> bool { self.incremental_utf8 }
+
+    /// Set whether to validate utf-8 text incrementally, which is the default.
+    ///
+    /// Incremental validation checks each frame once it arrives, so that
+    /// illegal text fails fast, before the whole message is buffered.
+    /// Otherwise the complete message is validated once,
+    /// which is slightly faster for small messages.
+    ///
+    /// This only affects [`Utf8Policy::Strict`].
+    #[inline]
+    pub fn set_incremental_utf8(&mut self, incremental: bool) {
+        self.incremental_utf8 = incremental;
+    }
+
+    /// Get the max count of continuation frames in a message.
+    #[inline]
+    pub const fn max_fragments(&self) -> usize { self.max_fragments }
+
+    /// Set the max count of continuation frames in a message.
+    /// Once exceeded, a read will fail with [`MessageError::TooManyFragments`].
+    ///
+    /// A value of 0 means unlimited.
+    /// The default is [`DEFAULT_MAX_FRAGMENTS`].
+    #[inline]
+    pub fn set_max_fragments(&mut self, n: usize) { self.max_fragments = n; }
+
+    /// Get the max size of a message in bytes.
+    #[inline]
+    pub const fn max_message_size(&self) -> usize { self.max_message_size }
+
+    /// Set the max size of a message in bytes.
+    ///
+    /// The size is checked as each chunk of payload arrives, so that the
+    /// reassembly buffer never grows beyond the limit. Once exceeded, a read
+    /// fails with [`MessageError::TooBig`] right away, even in the middle of
+    /// a frame. Caller should close the connection with
+    /// [`MessageError::close_code`]. Otherwise the buffered payload and the
+    /// rest of the message are discarded, and a later read continues
+    /// with the next message, as does [`MessageStream::messages`].
+    ///
+    /// A value of 0 means unlimited, which is the default.
+    #[inline]
+    pub fn set_max_message_size(&mut self, n: usize) { self.max_message_size = n; }
+
+    /// Check if a `Ping` frame is received.
+    #[inline]
+    pub const fn is_pinged(&self) -> bool { !self.ping_store.is_empty() }
+
+    /// Get the most recent ping.
+    #[inline]
+    pub const fn ping_data(&self) -> &[u8] { self.ping_store.read() }
+
+    /// Check if `EOF` is reached.
+    #[inline]
+    pub const fn is_read_eof(&self) -> bool { self.is_eof }
+
+    /// Check if a `Close` frame is received.
+    #[inline]
+    pub const fn is_read_close(&self) -> bool { self.is_close }
+
+    /// Check if a `Close` frame is received or `EOF` is reached.
+    #[inline]
+    pub const fn is_read_end(&self) -> bool { self.is_eof || self.is_close }
+}
+
+impl<IO, Role> Drop for MessageStream<IO, Role> {
+    fn drop(&mut self) {
+        if let Some(pool) = &self.pool {
+            pool.put(std::mem::take(&mut self.message));
+        }
+    }
+}
+
+/// Convert from a new established [`Stream`].
+///
+/// Read or write states of the stream are dropped,
+/// so this should be done right after the handshake.
+/// Bytes read with the handshake (see [`Stream::remainder_len`])
+/// are kept, which are read first.
+impl<IO, Role, Guard> From<Stream<IO, Role, Guard>> for MessageStream<IO, Role> {
+    fn from(stream: Stream<IO, Role, Guard>) -> Self {
+        let remainder = &stream.replay.buf[stream.replay.pos..];
+        let mut message_stream = Self::new(stream.io, stream.role);
+        if remainder.len() > message_stream.buf.len() {
+            message_stream.buf = vec![0; remainder.len()].into_boxed_slice();
+        }
+        message_stream.buf[..remainder.len()].copy_from_slice(remainder);
+        message_stream.end = remainder.len();
+        message_stream
+    }
+}
+
+impl<IO, Role> MessageStream<IO, Role> {
+    /// Read frames until a message of the `expect` type is complete,
+    /// or a message of any type if `expect` is `None`.
+    /// If `with_ctrl` is set, also return once a control frame is complete.
+    /// Returns the opcode of the message or the control frame.
+    ///
+    /// States are saved in the stream, so that an IO error
+    /// (e.g. `WouldBlock`) or `Pending` could be resumed later.
+    fn poll_read_message<F>(
+        &mut self,
+        expect: Option<OpCode>,
+        with_ctrl: bool,
+        mut read: F,
+    ) -> Poll<Result<OpCode>>
+    where
+        F: FnMut(&mut IO, &mut [u8]) -> Poll<Result<usize>>,
+    {
+        // the last message has been returned to caller
+        if self.is_complete {
+            self.reset_message();
+        }
+
+        loop {
+            if self.is_close {
+                return Poll::Ready(Err(MessageError::Closed.into()));
+            }
+            if self.is_eof {
+                return Poll::Ready(Err(ErrorKind::UnexpectedEof.into()));
+            }
+
+            let mut frame = match self.frame {
+                Some(frame) => frame,
+                None => match FrameHead::decode(&self.buf[self.beg..self.end]) {
+                    Ok((head, parse_n)) => {
+                        // head is not consumed on error
+                        self.check_head(&head, expect)?;
+                        self.beg += parse_n;
+                        FrameState {
+                            fin: head.fin,
+                            opcode: head.opcode,
+                            mask: head.mask,
+                            next: head.length.to_num(),
+                            offset: 0,
+                        }
+                    }
+                    Err(FrameError::NotEnoughData) => {
+                        ready!(self.poll_fill(&mut read))?;
+                        continue;
+                    }
+                    Err(e) => return Poll::Ready(Err(e.into())),
+                },
+            };
+
+            // handle payload in the buffer
+            let data_len = min_len(self.end - self.beg, frame.next);
+            let data = &mut self.buf[self.beg..self.beg + data_len];
+
+            // unmask, continue from the last offset
+            if let Mask::Key(key) = frame.mask {
+                apply_mask4(rotate_mask_key(key, frame.offset), data);
+            }
+
+            let mut error = None;
+            match frame.opcode {
+                OpCode::Ping | OpCode::Pong | OpCode::Close => {
+                    self.ctrl_store.write()[..data_len].copy_from_slice(data);
+                    self.ctrl_store.advance_wr_pos(data_len);
+                }
+                _ if self.is_discarded => {}
+                _ if self.max_message_size != 0
+                    && self.message.len() + data_len > self.max_message_size =>
+                {
+                    self.message.clear();
+                    self.is_discarded = true;
+                    error = Some(MessageError::TooBig);
+                }
+                _ => {
+                    self.message.extend_from_slice(data);
+                    if self.opcode == Some(OpCode::Text)
+                        && self.utf8_policy == Utf8Policy::Strict
+                        && self.incremental_utf8
+                        && self.check_utf8().is_err()
+                    {
+                        self.message.clear();
+                        self.is_discarded = true;
+                        error = Some(MessageError::IllegalUtf8);
+                    }
+                }
+            }
+
+            self.beg += data_len;
+            frame.next -= data_len as u64;
+            frame.offset += data_len;
+
+            // the rest of the frame is handled by a later read
+            if let Some(e) = error {
+                self.frame = Some(frame);
+                return Poll::Ready(Err(e.into()));
+            }
+
+            // need to read more payload
+            if frame.next != 0 {
+                self.frame = Some(frame);
+                ready!(self.poll_fill(&mut read))?;
+                continue;
+            }
+
+            self.frame = None;
+
+            match frame.opcode {
+                OpCode::Ping => self.ping_store = self.ctrl_store,
+                OpCode::Pong => {}
+                OpCode::Close => self.is_close = true,
+                // a failed message is dropped
+                _ if frame.fin == Fin::Y && self.is_discarded => {
+                    self.reset_message();
+                    continue;
+                }
+                _ if frame.fin == Fin::Y => {
+                    if self.opcode == Some(OpCode::Text) {
+                        if let Err(e) = self.finish_utf8() {
+                            self.reset_message();
+                            return Poll::Ready(Err(e));
+                        }
+                    }
+                    self.is_complete = true;
+                    return Poll::Ready(Ok(self.opcode.unwrap()));
+                }
+                _ => continue,
+            }
+            if with_ctrl {
+                return Poll::Ready(Ok(frame.opcode));
+            }
+        }
+    }
+
+    /// Drop the message being reassembled, if any.
+    fn reset_message(&mut self) {
+        self.message.clear();
+        self.opcode = None;
+        self.fragments = 0;
+        self.utf8_valid = 0;
+        self.is_complete = false;
+        self.is_discarded = false;
+    }
+
+    /// Check a new frame head against the message being reassembled.
+    fn check_head(&mut self, head: &FrameHead, expect: Option<OpCode>) -> Result<()> {
+        // no extension is negotiated
+        if !head.rsv.is_empty() {
+            return Err(FrameError::IllegalRsv.into());
+        }
+        match head.opcode {
+            OpCode::Text | OpCode::Binary => {
+                // a new message must not begin before
+                // the fragmented one is finished
+                if self.opcode.is_some() {
+                    return Err(FrameError::IllegalFragment.into());
+                }
+                match expect {
+                    Some(OpCode::Text) if head.opcode != OpCode::Text => {
+                        return Err(MessageError::NotText.into())
+                    }
+                    Some(OpCode::Binary) if head.opcode != OpCode::Binary => {
+                        return Err(MessageError::NotBinary.into())
+                    }
+                    _ => {}
+                }
+                self.opcode = Some(head.opcode);
+            }
+            OpCode::Continue => {
+                // a message must begin with a text or binary frame
+                if self.opcode.is_none() {
+                    return Err(FrameError::IllegalContinuation.into());
+                }
+                if self.max_fragments != 0 && self.fragments >= self.max_fragments {
+                    return Err(MessageError::TooManyFragments.into());
+                }
+                self.fragments += 1;
+            }
+            _ => {
+                if head.length.to_num() > 125 {
+                    return Err(FrameError::IllegalData.into());
+                }
+                // a control frame must not be fragmented
+                if head.fin == Fin::N {
+                    return Err(FrameError::IllegalFin.into());
+                }
+                self.ctrl_store.reset();
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate utf-8 text incrementally,
+    /// a multi-byte sequence may be split across frames.
+    fn check_utf8(&mut self) -> Result<()> {
+        match std::str::from_utf8(&self.message[self.utf8_valid..]) {
+            Ok(_) => self.utf8_valid = self.message.len(),
+            Err(e) if e.error_len().is_none() => self.utf8_valid += e.valid_up_to(),
+            Err(_) => return Err(MessageError::IllegalUtf8.into()),
+        }
+        Ok(())
+    }
+
+    /// Validate or repair the complete text.
+    fn finish_utf8(&mut self) -> Result<()> {
+        // validate the rest of text
+        if self.utf8_policy == Utf8Policy::Strict && !self.incremental_utf8 {
+            self.check_utf8()?;
+        }
+        match self.utf8_policy {
+            // a multi-byte sequence is left incomplete
+            Utf8Policy::Strict if self.utf8_valid != self.message.len() => {
+                return Err(MessageError::IllegalUtf8.into());
+            }
+            Utf8Policy::Strict => {}
+            Utf8Policy::Lossy => {
+                if std::str::from_utf8(&self.message).is_err() {
+                    let text = String::from_utf8_lossy(&self.message).into_owned();
+                    self.message = text.into_bytes();
+                }
+                self.utf8_valid = self.message.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the complete message out of the internal buffer.
+    fn take_message(&mut self) -> Message {
+        let buf = self.new_message_buf();
+        let message = std::mem::replace(&mut self.message, buf);
+        match self.opcode {
+            // validated during reassembly
+            Some(OpCode::Text) => Message::Text(unsafe { String::from_utf8_unchecked(message) }),
+            _ => Message::Binary(message),
+        }
+    }
+
+    /// Read more data into the internal buffer.
+    fn poll_fill<F>(&mut self, read: &mut F) -> Poll<Result<()>>
+    where
+        F: FnMut(&mut IO, &mut [u8]) -> Poll<Result<usize>>,
+    {
+        // move unhandled data to the front
+        if self.beg != 0 {
+            self.buf.copy_within(self.beg..self.end, 0);
+            self.end -= self.beg;
+            self.beg = 0;
+        }
+
+        let read_n = ready!(read(&mut self.io, &mut self.buf[self.end..]))?;
+
+        // EOF ?
+        if read_n == 0 {
+            self.is_eof = true;
+        }
+        self.end += read_n;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: Read, Role> MessageStream<IO, Role> {
+    /// Read a complete text message, returns a borrowed `&str`
+    /// of the internal buffer, which is valid until the next read.
+    ///
+    /// Text is validated incrementally as each frame arrives, illegal utf-8
+    /// leads to a [`MessageError::IllegalUtf8`] error, where the rest of the
+    /// message is discarded by later reads. With [`Utf8Policy::Lossy`],
+    /// illegal sequences are replaced with `U+FFFD` once the message is complete.
+    ///
+    /// If the next message is a binary message, a [`MessageError::NotText`]
+    /// error is returned, and the message is left untouched, so that
+    /// it could be read via [`MessageStream::read_binary`].
+    ///
+    /// Once a `Close` frame is received, a [`MessageError::Closed`] error
+    /// is returned. Reaching `EOF` leads to an `UnexpectedEof` error.
+    pub fn read_text(&mut self) -> Result<&str> {
+        match self.poll_read_message(Some(OpCode::Text), false, |io, buf| io.read(buf).into()) {
+            Poll::Ready(x) => x?,
+            Poll::Pending => unreachable!(),
+        };
+        // validated during reassembly
+        Ok(unsafe { std::str::from_utf8_unchecked(&self.message) })
+    }
+
+    /// Read a complete binary message, returns a borrowed `&[u8]`
+    /// of the internal buffer, which is valid until the next read.
+    ///
+    /// If the next message is a text message, a [`MessageError::NotBinary`]
+    /// error is returned, and the message is left untouched, so that
+    /// it could be read via [`MessageStream::read_text`].
+    ///
+    /// See also: [`MessageStream::read_text`].
+    pub fn read_binary(&mut self) -> Result<&[u8]> {
+        match self.poll_read_message(Some(OpCode::Binary), false, |io, buf| io.read(buf).into()) {
+            Poll::Ready(x) => x?,
+            Poll::Pending => unreachable!(),
+        };
+        Ok(&self.message)
+    }
+
+    /// Iterate over complete messages of either type.
+    ///
+    /// Each message is moved out of the internal buffer. Control frames
+    /// are handled as usual. The iterator ends once a `Close` frame is
+    /// received, or `EOF` is reached between messages. An error of a
+    /// discarded message (e.g. [`MessageError::TooBig`]) is yielded, then
+    /// the iterator continues with the next message. Other errors
+    /// are yielded once, then the iterator ends.
+    #[inline]
+    pub fn messages(&mut self) -> Messages<'_, IO, Role> {
+        Messages {
+            stream: self,
+            is_done: false,
+        }
+    }
+}
+
+/// Iterator over complete messages, see [`MessageStream::messages`].
+pub struct Messages<'a, IO, Role> {
+    stream: &'a mut MessageStream<IO, Role>,
+    is_done: bool,
+}
+
+impl<IO: Read, Role> Iterator for Messages<'_, IO, Role> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let stream = &mut *self.stream;
+        if self.is_done || stream.is_read_end() {
+            return None;
+        }
+        match stream.poll_read_message(None, false, |io, buf| io.read(buf).into()) {
+            Poll::Ready(Ok(_)) => {}
+            // the rest of the message is discarded by the next read
+            Poll::Ready(Err(e)) if stream.is_discarded => return Some(Err(e)),
+            Poll::Ready(Err(e)) => {
+                self.is_done = true;
+                // EOF is reached between messages
+                let is_clean = stream.is_close
+                    || (stream.is_eof
+                        && stream.frame.is_none()
+                        && stream.opcode.is_none()
+                        && stream.beg == stream.end);
+                return if is_clean { None } else { Some(Err(e)) };
+            }
+            Poll::Pending => unreachable!(),
+        };
+
+        Some(Ok(stream.take_message()))
+    }
+}
+
+#[cfg(feature = "async")]
+impl<IO: AsyncRead + Unpin, Role> MessageStream<IO, Role> {
+    /// Read until a message or a control frame is complete,
+    /// which gives a single await point for all kinds of frames.
+    ///
+    /// Fragmented messages are reassembled, a control frame between
+    /// fragments is returned once it arrives, and the message continues.
+    /// Control frames are also handled as usual, e.g. a ping is saved.
+    /// The reason of a close frame is checked against the
+    /// [`Utf8Policy`], like text messages.
+    ///
+    /// After an [`Event::Close`], a [`MessageError::Closed`] error
+    /// is returned. Reaching `EOF` leads to an `UnexpectedEof` error.
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe. Progress is saved in the stream, and an event
+    /// is only taken out once it is returned.
+    pub async fn next_event(&mut self) -> Result<Event> {
+        let opcode = poll_fn(|cx| {
+            self.poll_read_message(None, true, |io, buf| {
+                let mut buf = ReadBuf::new(buf);
+                Pin::new(io)
+                    .poll_read(cx, &mut buf)
+                    .map_ok(|_| buf.filled().len())
+            })
+        })
+        .await?;
+        self.take_event(opcode)
+    }
+
+    /// Convert a complete message or control frame to an event.
+    fn take_event(&mut self, opcode: OpCode) -> Result<Event> {
+        let payload = self.ctrl_store.read();
+        Ok(match opcode {
+            OpCode::Ping => Event::Ping(payload.to_vec()),
+            OpCode::Pong => Event::Pong(payload.to_vec()),
+            OpCode::Close => {
+                let (code, reason) = decode_close_payload(payload)?;
+                let reason = match (std::str::from_utf8(reason), self.utf8_policy) {
+                    (Ok(reason), _) => reason.to_owned(),
+                    (Err(_), Utf8Policy::Lossy) => String::from_utf8_lossy(reason).into_owned(),
+                    (Err(_), Utf8Policy::Strict) => return Err(MessageError::IllegalUtf8.into()),
+                };
+                Event::Close(code, reason)
+            }
+            _ => Event::Message(self.take_message()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_head, make_head_with_fin};
+    use crate::frame::CloseCode;
+    use crate::role::*;
+
+    fn make_fragment(fin: Fin, opcode: OpCode, mask: Mask, data: &[u8]) -> Vec<u8> {
+        let mut frame = make_head_with_fin(fin, opcode, mask, data.len());
+        let mut data = data.to_vec();
+        if let Mask::Key(key) = mask {
+            apply_mask4(key, &mut data);
+        }
+        frame.append(&mut data);
+        frame
+    }
+
+    fn make_stream(buf: Vec<u8>, rlimit: usize) -> MessageStream<LimitReadWriter, Client> {
+        let io = LimitReadWriter {
+            buf,
+            rlimit,
+            wlimit: 0,
+            cursor: 0,
+        };
+        MessageStream::new(io, Client::new())
+    }
+
+    fn get_error(e: std::io::Error) -> MessageError {
+        let e = e.into_inner().unwrap();
+        match *e.downcast::<crate::error::Error>().unwrap() {
+            crate::error::Error::Message(e) => e,
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn read_text_message() {
+        let text = "Hello, 世界! Привет, мир!";
+        let bytes = text.as_bytes();
+
+        for mask in [Mask::None, Mask::Key(rand::random())] {
+            // split inside multi-byte sequences
+            let mut buf = Vec::new();
+            buf.append(&mut make_fragment(Fin::N, OpCode::Text, mask, &bytes[..8]));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Ping, mask, b"ping"));
+            buf.append(&mut make_fragment(
+                Fin::N,
+                OpCode::Continue,
+                mask,
+                &bytes[8..20],
+            ));
+            buf.append(&mut make_fragment(
+                Fin::Y,
+                OpCode::Continue,
+                mask,
+                &bytes[20..],
+            ));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Text, mask, b"next"));
+            buf.append(&mut make_head(OpCode::Close, Mask::None, 0));
+
+            for rlimit in 1..64 {
+                let mut stream = make_stream(buf.clone(), rlimit);
+
+                assert_eq!(stream.read_text().unwrap(), text);
+                assert_eq!(stream.ping_data(), b"ping");
+                assert_eq!(stream.read_text().unwrap(), "next");
+
+                let e = stream.read_text().unwrap_err();
+                assert_eq!(get_error(e), MessageError::Closed);
+                assert!(stream.is_read_close());
+            }
+        }
+    }
+
+    #[test]
+    fn read_illegal_text_message() {
+        let text = "世界".as_bytes();
+
+        // illegal byte
+        let mut buf = make_fragment(Fin::N, OpCode::Text, Mask::None, &text[..2]);
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            b"\xff",
+        ));
+        let e = make_stream(buf, 4096).read_text().unwrap_err();
+        assert_eq!(get_error(e), MessageError::IllegalUtf8);
+
+        // incomplete sequence
+        let buf = make_fragment(Fin::Y, OpCode::Text, Mask::None, &text[..4]);
+        let e = make_stream(buf, 4096).read_text().unwrap_err();
+        assert_eq!(get_error(e), MessageError::IllegalUtf8);
+
+        // the rest of an illegal message is discarded
+        let mask = Mask::Key([1, 2, 3, 4]);
+        let mut buf = make_fragment(Fin::N, OpCode::Text, mask, b"\xff\xfe illegal");
+        buf.append(&mut make_fragment(Fin::N, OpCode::Continue, mask, b"rest"));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            mask,
+            &text[..4],
+        ));
+        buf.append(&mut make_fragment(Fin::Y, OpCode::Text, mask, text));
+        for rlimit in [1, 3, 4096] {
+            let mut stream = make_stream(buf.clone(), rlimit);
+            let e = stream.read_text().unwrap_err();
+            assert_eq!(get_error(e), MessageError::IllegalUtf8);
+            assert_eq!(stream.read_text().unwrap().as_bytes(), text);
+        }
+
+        // an incomplete sequence at the end
+        let mut buf = make_fragment(Fin::Y, OpCode::Text, mask, &text[..4]);
+        buf.append(&mut make_fragment(Fin::Y, OpCode::Text, mask, text));
+        let mut stream = make_stream(buf, 4096);
+        let e = stream.read_text().unwrap_err();
+        assert_eq!(get_error(e), MessageError::IllegalUtf8);
+        assert_eq!(stream.read_text().unwrap().as_bytes(), text);
+    }
+
+    #[test]
+    fn read_fragmented_control() {
+        let mut buf = make_fragment(Fin::N, OpCode::Text, Mask::None, b"hello");
+        buf.append(&mut make_fragment(
+            Fin::N,
+            OpCode::Ping,
+            Mask::None,
+            b"ping",
+        ));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            b"world",
+        ));
+
+        let e = make_stream(buf, 4096).read_text().unwrap_err();
+        let e = e.into_inner().unwrap();
+        let e = match *e.downcast::<crate::error::Error>().unwrap() {
+            crate::error::Error::Frame(e) => e,
+            e => panic!("unexpected error: {}", e),
+        };
+        assert_eq!(e, FrameError::IllegalFin);
+        assert_eq!(e.close_code(), CloseCode::ProtocolError);
+    }
+
+    #[test]
+    fn read_lossy_text_message() {
+        let text = "世界".as_bytes();
+
+        for policy in [Utf8Policy::Strict, Utf8Policy::Lossy] {
+            // illegal byte, with a split sequence
+            let mut buf = make_fragment(Fin::N, OpCode::Text, Mask::None, &text[..2]);
+            buf.append(&mut make_fragment(
+                Fin::N,
+                OpCode::Continue,
+                Mask::None,
+                &text[2..4],
+            ));
+            buf.append(&mut make_fragment(
+                Fin::Y,
+                OpCode::Continue,
+                Mask::None,
+                b"\xff!",
+            ));
+            buf.append(&mut make_fragment(
+                Fin::Y,
+                OpCode::Text,
+                Mask::None,
+                b"next",
+            ));
+
+            for rlimit in 1..16 {
+                let mut stream = make_stream(buf.clone(), rlimit);
+                stream.set_utf8_policy(policy);
+
+                match policy {
+                    Utf8Policy::Strict => {
+                        let e = stream.read_text().unwrap_err();
+                        assert_eq!(get_error(e), MessageError::IllegalUtf8);
+                    }
+                    Utf8Policy::Lossy => {
+                        assert_eq!(stream.read_text().unwrap(), "世\u{FFFD}\u{FFFD}!");
+                        assert_eq!(stream.read_text().unwrap(), "next");
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn read_text_whole_or_incremental() {
+        let text = "Hello, 世界!".as_bytes();
+
+        let mut valid = Vec::new();
+        for i in 0..text.len() {
+            // split the text at every position
+            valid.append(&mut make_fragment(
+                Fin::N,
+                OpCode::Text,
+                Mask::None,
+                &text[..i],
+            ));
+            valid.append(&mut make_fragment(
+                Fin::Y,
+                OpCode::Continue,
+                Mask::None,
+                &text[i..],
+            ));
+        }
+
+        let mut invalid = Vec::new();
+        invalid.append(&mut make_fragment(
+            Fin::N,
+            OpCode::Text,
+            Mask::None,
+            &text[..8],
+        ));
+        invalid.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            b"\xff",
+        ));
+        let mut incomplete = make_fragment(Fin::N, OpCode::Text, Mask::None, &text[..4]);
+        incomplete.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            &text[4..8],
+        ));
+
+        for incremental in [true, false] {
+            for rlimit in [1, 3, 4096] {
+                let mut stream = make_stream(valid.clone(), rlimit);
+                stream.set_incremental_utf8(incremental);
+                assert_eq!(stream.is_incremental_utf8(), incremental);
+                for _ in 0..text.len() {
+                    assert_eq!(stream.read_text().unwrap().as_bytes(), text);
+                }
+
+                for buf in [&invalid, &incomplete] {
+                    let mut stream = make_stream(buf.clone(), rlimit);
+                    stream.set_incremental_utf8(incremental);
+                    let e = stream.read_text().unwrap_err();
+                    assert_eq!(get_error(e), MessageError::IllegalUtf8);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn read_continuation_first() {
+        let buf = make_fragment(Fin::Y, OpCode::Continue, Mask::None, b"orphan");
+        let mut stream = make_stream(buf, 4096);
+
+        let e = stream.read_binary().unwrap_err();
+        let e = e.into_inner().unwrap();
+        let e = match *e.downcast::<crate::error::Error>().unwrap() {
+            crate::error::Error::Frame(e) => e,
+            e => panic!("unexpected error: {}", e),
+        };
+        assert_eq!(e, FrameError::IllegalContinuation);
+        assert_eq!(e.close_code(), CloseCode::ProtocolError);
+    }
+
+    #[test]
+    fn read_binary_as_text() {
+        let mut buf = make_fragment(Fin::Y, OpCode::Binary, Mask::None, b"binary");
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Text,
+            Mask::None,
+            b"text",
+        ));
+        let mut stream = make_stream(buf, 4096);
+
+        let e = stream.read_text().unwrap_err();
+        assert_eq!(get_error(e), MessageError::NotText);
+        assert_eq!(stream.read_binary().unwrap(), b"binary");
+
+        let e = stream.read_binary().unwrap_err();
+        assert_eq!(get_error(e), MessageError::NotBinary);
+        assert_eq!(stream.read_text().unwrap(), "text");
+    }
+
+    #[test]
+    fn read_too_many_fragments() {
+        fn make_message(fragments: usize) -> Vec<u8> {
+            let mut buf = make_fragment(Fin::N, OpCode::Binary, Mask::None, b"a");
+            for i in 1..=fragments {
+                let fin = if i == fragments { Fin::Y } else { Fin::N };
+                buf.append(&mut make_fragment(fin, OpCode::Continue, Mask::None, b"a"));
+            }
+            buf
+        }
+
+        // within the limit
+        let mut stream = make_stream(make_message(16), 4096);
+        stream.set_max_fragments(16);
+        assert_eq!(stream.read_binary().unwrap(), &[b'a'; 17]);
+
+        // exceed the limit
+        let mut stream = make_stream(make_message(17), 4096);
+        stream.set_max_fragments(16);
+        let e = get_error(stream.read_binary().unwrap_err());
+        assert_eq!(e, MessageError::TooManyFragments);
+        assert_eq!(e.close_code(), CloseCode::TooBig);
+
+        // unlimited
+        let mut stream = make_stream(make_message(DEFAULT_MAX_FRAGMENTS + 1), 4096);
+        stream.set_max_fragments(0);
+        assert_eq!(
+            stream.read_binary().unwrap().len(),
+            DEFAULT_MAX_FRAGMENTS + 2
+        );
+    }
+
+    #[test]
+    fn read_too_big_message() {
+        let mut buf = make_fragment(Fin::Y, OpCode::Binary, Mask::None, &[b'a'; 2048]);
+        buf.append(&mut make_fragment(
+            Fin::N,
+            OpCode::Text,
+            Mask::None,
+            &[b'b'; 1000],
+        ));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            &[b'b'; 1000],
+        ));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Binary,
+            Mask::None,
+            b"next",
+        ));
+
+        let mut stream = make_stream(buf, 100);
+        stream.set_max_message_size(1024);
+        assert_eq!(stream.max_message_size(), 1024);
+
+        // fail in the middle of a frame
+        let e = get_error(stream.read_binary().unwrap_err());
+        assert_eq!(e, MessageError::TooBig);
+        assert_eq!(e.close_code(), CloseCode::TooBig);
+        assert!(stream.as_ref().cursor > 1024);
+        assert!(stream.as_ref().cursor < 1024 + 100 + 4);
+
+        // fail in the middle of a fragmented message
+        let e = get_error(stream.read_text().unwrap_err());
+        assert_eq!(e, MessageError::TooBig);
+        assert!(stream.as_ref().cursor < 2048 + 2000);
+
+        // the rest is discarded
+        assert_eq!(stream.read_binary().unwrap(), b"next");
+    }
+
+    #[test]
+    fn iterate_after_too_big_message() {
+        let mut buf = make_fragment(Fin::N, OpCode::Binary, Mask::None, &[b'a'; 1000]);
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            &[b'a'; 1000],
+        ));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Text,
+            Mask::None,
+            b"next",
+        ));
+
+        let mut stream = make_stream(buf, 100);
+        stream.set_max_message_size(1024);
+        let mut messages = stream.messages();
+        let e = get_error(messages.next().unwrap().unwrap_err());
+        assert_eq!(e, MessageError::TooBig);
+        assert_eq!(
+            messages.next().unwrap().unwrap(),
+            Message::Text(String::from("next"))
+        );
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn iterate_messages() {
+        let mut buf = Vec::new();
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Text,
+            Mask::None,
+            b"hello",
+        ));
+        buf.append(&mut make_fragment(
+            Fin::N,
+            OpCode::Binary,
+            Mask::None,
+            b"wor",
+        ));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Ping,
+            Mask::None,
+            b"ping",
+        ));
+        buf.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Continue,
+            Mask::None,
+            b"ld",
+        ));
+        buf.append(&mut make_fragment(Fin::Y, OpCode::Text, Mask::None, b"!"));
+        let expected = [
+            Message::Text("hello".to_string()),
+            Message::Binary(b"world".to_vec()),
+            Message::Text("!".to_string()),
+        ];
+
+        // ends with a close frame
+        let mut closed = buf.clone();
+        closed.append(&mut make_head(OpCode::Close, Mask::None, 0));
+        closed.append(&mut make_fragment(
+            Fin::Y,
+            OpCode::Text,
+            Mask::None,
+            b"after",
+        ));
+        for rlimit in 1..32 {
+            let mut stream = make_stream(closed.clone(), rlimit);
+            let messages: Vec<_> = stream.messages().map(|x| x.unwrap()).collect();
+            assert_eq!(messages, expected);
+            assert_eq!(stream.ping_data(), b"ping");
+            assert!(stream.is_read_close());
+        }
+
+        // ends with EOF
+        let mut stream = make_stream(buf.clone(), 7);
+        let messages: Vec<_> = stream.messages().map(|x| x.unwrap()).collect();
+        assert_eq!(messages, expected);
+        assert!(stream.is_read_eof());
+
+        // EOF in the middle of a message
+        let mut truncated = buf;
+        truncated.append(&mut make_fragment(
+            Fin::N,
+            OpCode::Text,
+            Mask::None,
+            b"part",
+        ));
+        let mut stream = make_stream(truncated, 7);
+        let mut messages = stream.messages();
+        for message in expected {
+            assert_eq!(messages.next().unwrap().unwrap(), message);
+        }
+        let e = messages.next().unwrap().unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn message_with_pool() {
+        use std::sync::Mutex;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        #[derive(Default)]
+        struct CountPool {
+            bufs: Mutex<Vec<Vec<u8>>>,
+            gets: AtomicUsize,
+            puts: AtomicUsize,
+            allocs: AtomicUsize,
+        }
+
+        impl BufferPool for CountPool {
+            fn get(&self) -> Vec<u8> {
+                self.gets.fetch_add(1, Ordering::Relaxed);
+                self.bufs.lock().unwrap().pop().unwrap_or_else(|| {
+                    self.allocs.fetch_add(1, Ordering::Relaxed);
+                    Vec::with_capacity(64)
+                })
+            }
+
+            fn put(&self, buf: Vec<u8>) {
+                self.puts.fetch_add(1, Ordering::Relaxed);
+                self.bufs.lock().unwrap().push(buf);
+            }
+        }
+
+        let mut buf = Vec::new();
+        for _ in 0..4 {
+            buf.append(&mut make_fragment(
+                Fin::N,
+                OpCode::Binary,
+                Mask::None,
+                b"Hello, ",
+            ));
+            buf.append(&mut make_fragment(
+                Fin::Y,
+                OpCode::Continue,
+                Mask::None,
+                b"world",
+            ));
+        }
+        let pool = Arc::new(CountPool::default());
+        let count = |pool: &CountPool| {
+            (
+                pool.gets.load(Ordering::Relaxed),
+                pool.puts.load(Ordering::Relaxed),
+                pool.allocs.load(Ordering::Relaxed),
+            )
+        };
+
+        // borrowed messages share a buffer
+        let io = LimitReadWriter {
+            buf: buf.clone(),
+            rlimit: 7,
+            wlimit: 0,
+            cursor: 0,
+        };
+        let mut stream = MessageStream::with_pool(io, Client::new(), pool.clone());
+        for _ in 0..4 {
+            assert_eq!(stream.read_binary().unwrap(), b"Hello, world");
+        }
+        drop(stream);
+        assert_eq!(count(&pool), (1, 1, 1));
+
+        // moved messages are recycled across streams
+        for _ in 0..2 {
+            let io = LimitReadWriter {
+                buf: buf.clone(),
+                rlimit: 7,
+                wlimit: 0,
+                cursor: 0,
+            };
+            let mut stream = MessageStream::with_pool(io, Client::new(), pool.clone());
+            let messages: Vec<_> = stream.messages().map(|x| x.unwrap()).collect();
+            assert_eq!(messages.len(), 4);
+            for message in messages {
+                assert_eq!(message, Message::Binary(b"Hello, world".to_vec()));
+                stream.recycle(message);
+            }
+        }
+        let (gets, puts, allocs) = count(&pool);
+        assert_eq!(gets, puts);
+        assert_eq!(gets, 1 + 2 * 5);
+        assert_eq!(allocs, 5);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn next_event() {
+        let mut data = 1000_u16.to_be_bytes().to_vec();
+        data.extend_from_slice(b"bye");
+
+        for mask in [Mask::None, Mask::Key(rand::random())] {
+            let mut buf = Vec::new();
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Ping, mask, b"ping"));
+            buf.append(&mut make_fragment(Fin::N, OpCode::Text, mask, b"Hello, "));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Ping, mask, b"beat"));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Continue, mask, b"world"));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Pong, mask, b""));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Binary, mask, b"data"));
+            buf.append(&mut make_fragment(Fin::Y, OpCode::Close, mask, &data));
+
+            let mut stream = MessageStream::new(buf.as_slice(), Client::new());
+            let expected = [
+                Event::Ping(b"ping".to_vec()),
+                Event::Ping(b"beat".to_vec()),
+                Event::Message(Message::Text("Hello, world".to_string())),
+                Event::Pong(Vec::new()),
+                Event::Message(Message::Binary(b"data".to_vec())),
+                Event::Close(CloseCode::Normal, "bye".to_string()),
+            ];
+            for event in expected {
+                assert_eq!(stream.next_event().await.unwrap(), event);
+            }
+            assert_eq!(stream.ping_data(), b"beat");
+            assert!(stream.is_read_close());
+
+            let e = stream.next_event().await.unwrap_err();
+            assert_eq!(get_error(e), MessageError::Closed);
+        }
+    }
+}
@@ -24,6 +24,24 @@
 //!
 //! Stream itself does not buffer any payload data during
 //! a `Read` or `Write`, so there is no extra heap allocation.
+//! Frame heads and control frames are saved in small inline buffers.
+//!
+//! Some APIs allocate or buffer on purpose, which are opt-in:
+//!
+//! - Bytes read with the handshake are copied, then returned first,
+//!   see [`Stream::from_handshake_with_remainder`].
+//! - Scratch buffers are provided by the caller and owned by the stream,
+//!   see [`Stream::with_buffers`].
+//! - In manual control mode, each control frame is copied into a new `Vec`,
+//!   see [`Stream::set_manual_control`] and [`Stream::next_control`].
+//! - [`Stream::read_frames`] returns each payload in a new `Vec`,
+//!   unless it is created by the caller, see [`Stream::read_frames_with`].
+//! - Coalesced writes are buffered until they are emitted as one frame,
+//!   see [`Stream::set_coalesce_writes`].
+//! - [`MessageStream`] reassembles messages in its own buffer, and returns
+//!   each message or event (see [`MessageStream::next_event`]) in a new
+//!   `Vec`, unless it is drawn from a [`BufferPool`].
+//! - [`RpcStream`] buffers responses which arrive before they are awaited.
 //!
 //! # Masking payload
 //!
@@ -85,6 +103,22 @@ mod read;
 mod write;
 
 mod ctrl;
+mod close;
+mod pong;
+mod frame;
+mod stats;
+mod control;
+mod coalesce;
+mod scratch;
+mod replay;
+mod cancel;
+mod liveness;
+mod dump;
+mod observe;
+mod writer;
+mod builder;
+mod message;
+mod rpc;
 mod state;
 mod detail;
 mod special;
@@ -93,12 +127,37 @@ cfg_if::cfg_if! {
     if #[cfg(feature = "async")] {
         mod async_read;
         mod async_write;
+        mod pair;
+        pub use pair::DEFAULT_PAIR_BUF_SIZE;
     }
 }
 
+pub use close::{CloseReason, ConnectionState};
+pub use stats::StreamStats;
+pub use frame::FrameAction;
+pub use control::{ControlOverflow, DEFAULT_CONTROL_QUEUE_SIZE};
+pub use ctrl::OrphanContinuation;
+pub use coalesce::DEFAULT_COALESCE_LIMIT;
+pub use dump::{StreamStateDump, ReadPhase, WritePhase};
+pub use observe::{ObservedStream, FrameObserver, Direction};
+pub use writer::MessageWriter;
+pub use builder::StreamBuilder;
+pub use message::{
+    MessageStream, Message, Messages, Event, BufferPool, Utf8Policy, DEFAULT_MAX_FRAGMENTS,
+};
+pub use rpc::{RpcStream, DEFAULT_MAX_PENDING};
+
 use std::marker::PhantomData;
-use state::{ReadState, WriteState, HeartBeat};
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use state::{ReadState, WriteState, HeartBeat, CloseState, Fragment, Limit, PartialRead};
+use control::ControlQueue;
+use coalesce::Coalesce;
+use scratch::Scratch;
+use replay::Replay;
+use liveness::Liveness;
 use crate::role::RoleHelper;
+use crate::handshake::Extensions;
 
 /// Direct read or write.
 pub struct Direct {}
@@ -119,8 +178,27 @@ pub struct Stream<IO, Role, Guard = Direct> {
     io: IO,
     role: Role,
     read_state: ReadState,
+    partial: Option<PartialRead>,
     write_state: WriteState,
     heartbeat: HeartBeat,
+    close: CloseState,
+    fragment: Fragment,
+    stats: StreamStats,
+    limit: Limit,
+    extensions: Extensions,
+    discard_payloads: bool,
+    control: ControlQueue,
+    strict: bool,
+    orphan: OrphanContinuation,
+    coalesce: Coalesce,
+    zero_read_is_eof: bool,
+    last_frame_len: u64,
+    cancel: Option<Arc<AtomicBool>>,
+    liveness: Liveness,
+    flush_on_message_end: bool,
+    combine_head_payload: bool,
+    scratch: Scratch,
+    replay: Replay,
     __marker: PhantomData<Guard>,
 }
 
@@ -138,8 +216,27 @@ impl<IO, Role, Guard> std::fmt::Debug for Stream<IO, Role, Guard> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("Stream")
             .field("read_state", &self.read_state)
+            .field("partial", &self.partial)
             .field("write_state", &self.write_state)
             .field("heartbeat", &self.heartbeat)
+            .field("close", &self.close)
+            .field("fragment", &self.fragment)
+            .field("stats", &self.stats)
+            .field("limit", &self.limit)
+            .field("extensions", &self.extensions)
+            .field("discard_payloads", &self.discard_payloads)
+            .field("control", &self.control)
+            .field("strict", &self.strict)
+            .field("orphan", &self.orphan)
+            .field("coalesce", &self.coalesce)
+            .field("zero_read_is_eof", &self.zero_read_is_eof)
+            .field("last_frame_len", &self.last_frame_len)
+            .field("cancel", &self.cancel)
+            .field("liveness", &self.liveness)
+            .field("flush_on_message_end", &self.flush_on_message_end)
+            .field("combine_head_payload", &self.combine_head_payload)
+            .field("scratch", &self.scratch)
+            .field("replay", &self.replay)
             .finish()
     }
 }
@@ -153,8 +250,27 @@ impl<IO, Role> Stream<IO, Role> {
             io,
             role,
             read_state: ReadState::new(),
+            partial: None,
             write_state: WriteState::new(),
             heartbeat: HeartBeat::new(),
+            close: CloseState::new(),
+            fragment: Fragment::new(),
+            stats: StreamStats::new(),
+            limit: Limit::new(),
+            extensions: Extensions::new(),
+            discard_payloads: false,
+            control: ControlQueue::new(),
+            strict: true,
+            orphan: OrphanContinuation::Error,
+            coalesce: Coalesce::new(),
+            zero_read_is_eof: true,
+            last_frame_len: 0,
+            cancel: None,
+            liveness: Liveness::new(),
+            flush_on_message_end: false,
+            combine_head_payload: false,
+            scratch: Scratch::new(),
+            replay: Replay::new(),
             __marker: PhantomData,
         }
     }
@@ -166,17 +282,85 @@ impl<IO, Role> Stream<IO, Role> {
             io: self.io,
             role: self.role,
             read_state: self.read_state,
+            partial: self.partial,
             write_state: self.write_state,
             heartbeat: self.heartbeat,
+            close: self.close,
+            fragment: self.fragment,
+            stats: self.stats,
+            limit: self.limit,
+            extensions: self.extensions,
+            discard_payloads: self.discard_payloads,
+            control: self.control,
+            strict: self.strict,
+            orphan: self.orphan,
+            coalesce: self.coalesce,
+            zero_read_is_eof: self.zero_read_is_eof,
+            last_frame_len: self.last_frame_len,
+            cancel: self.cancel,
+            liveness: self.liveness,
+            flush_on_message_end: self.flush_on_message_end,
+            combine_head_payload: self.combine_head_payload,
+            scratch: self.scratch,
+            replay: self.replay,
             __marker: PhantomData,
         }
     }
 }
 
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Reset read, write and close states, so that a new session
+    /// could proceed on the same IO source, e.g. after a clean close
+    /// and a new handshake. The IO source is left untouched.
+    ///
+    /// Queued control frames, partially read or written frames, bytes
+    /// read with the handshake, coalesced writes, statistics and negotiated
+    /// extensions are dropped. Scratch buffers (see [`Stream::with_buffers`])
+    /// are kept, where stale bytes are zeroed.
+    /// Limits and other options are preserved.
+    pub fn reset(&mut self) {
+        self.read_state = ReadState::new();
+        self.partial = None;
+        self.write_state = WriteState::new();
+        self.heartbeat = HeartBeat::new();
+        self.liveness.ping_at = None;
+        self.close = CloseState::new();
+        self.fragment = Fragment::new();
+        self.stats = StreamStats::new();
+        self.extensions = Extensions::new();
+        self.control.frames.clear();
+        self.coalesce.buf.clear();
+        self.coalesce.written = 0;
+        self.last_frame_len = 0;
+        self.replay = Replay::new();
+        for buf in [&mut self.scratch.read, &mut self.scratch.write]
+            .into_iter()
+            .flatten()
+        {
+            buf.fill(0);
+        }
+    }
+
+    /// Replace the underlying IO source, returns the old one,
+    /// e.g. to upgrade the transport or resume on a new connection.
+    /// Unlike [`Stream::reset`], all states are preserved.
+    ///
+    /// This should be called at a frame boundary, where nothing is
+    /// partially read or written (see [`Stream::is_read_partial_head`] and
+    /// [`Stream::has_pending_write`]). Otherwise the rest of a frame
+    /// is expected from, or written to the new IO source.
+    #[inline]
+    pub fn replace_io(&mut self, io: IO) -> IO { std::mem::replace(&mut self.io, io) }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io::{Read, Write, Result};
+    use std::io::{Read, Write, Result, ErrorKind, IoSlice};
+    #[cfg(feature = "async")]
+    use std::pin::Pin;
+    #[cfg(feature = "async")]
+    use std::task::{Poll, Context};
     use crate::frame::*;
     use crate::role::*;
 
@@ -216,9 +400,107 @@ mod test {
         fn flush(&mut self) -> Result<()> { Ok(()) }
     }
 
+    /// Accept at most `wlimit` bytes each write, and at most `capacity`
+    /// bytes in total, then return `WouldBlock`. With `alternate`, every
+    /// other write returns `WouldBlock` instead, starting from the first one.
+    /// A vectored write only takes the first non-empty slice, unless
+    /// `vectored` is set. As an async writer, `WouldBlock` becomes `Pending`.
+    pub struct BlockWriter {
+        pub buf: Vec<u8>,
+        pub wlimit: usize,
+        pub capacity: usize,
+        pub alternate: bool,
+        pub vectored: bool,
+        pub is_blocked: bool,
+    }
+
+    impl BlockWriter {
+        pub fn new(wlimit: usize) -> Self {
+            Self {
+                buf: Vec::new(),
+                wlimit,
+                capacity: usize::MAX,
+                alternate: false,
+                vectored: false,
+                is_blocked: false,
+            }
+        }
+    }
+
+    impl Write for BlockWriter {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.write_vectored(&[IoSlice::new(buf)])
+        }
+
+        fn write_vectored(&mut self, mut bufs: &[IoSlice<'_>]) -> Result<usize> {
+            if self.alternate {
+                self.is_blocked = !self.is_blocked;
+                if self.is_blocked {
+                    return Err(ErrorKind::WouldBlock.into());
+                }
+            }
+            if self.buf.len() >= self.capacity {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            if !self.vectored {
+                let first = bufs.iter().position(|buf| !buf.is_empty()).unwrap_or(0);
+                bufs = &bufs[first..std::cmp::min(first + 1, bufs.len())];
+            }
+            let limit = std::cmp::min(self.wlimit, self.capacity - self.buf.len());
+            let mut n = 0;
+            for buf in bufs {
+                let len = std::cmp::min(buf.len(), limit - n);
+                self.buf.extend_from_slice(&buf[..len]);
+                n += len;
+            }
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    #[cfg(feature = "async")]
+    impl tokio::io::AsyncWrite for BlockWriter {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<Result<usize>> {
+            self.poll_write_vectored(cx, &[IoSlice::new(buf)])
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            bufs: &[IoSlice<'_>],
+        ) -> Poll<Result<usize>> {
+            match self.get_mut().write_vectored(bufs) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+                x => Poll::Ready(x),
+            }
+        }
+
+        fn is_write_vectored(&self) -> bool { self.vectored }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
     pub fn make_head(opcode: OpCode, mask: Mask, len: usize) -> Vec<u8> {
+        make_head_with_fin(Fin::Y, opcode, mask, len)
+    }
+
+    pub fn make_head_with_fin(fin: Fin, opcode: OpCode, mask: Mask, len: usize) -> Vec<u8> {
         let mut tmp = vec![0; 14];
-        let head = FrameHead::new(Fin::Y, opcode, mask, PayloadLen::from_num(len as u64));
+        let head = FrameHead::new(fin, opcode, mask, PayloadLen::from_num(len as u64));
 
         let head_len = head.encode(&mut tmp).unwrap();
         let mut head = Vec::new();
@@ -235,9 +517,12 @@ mod test {
         make_frame_with_mask(opcode, R::new().mask_key(), len)
     }
 
-    // data is unmasked
+    // data is unmasked, a close payload begins with a valid status code
     pub fn make_frame_with_mask(opcode: OpCode, mask: Mask, len: usize) -> (Vec<u8>, Vec<u8>) {
-        let data = make_data(len);
+        let mut data = make_data(len);
+        if opcode == OpCode::Close && len >= 2 {
+            data[..2].copy_from_slice(&1000_u16.to_be_bytes());
+        }
         let mut data2 = data.clone();
 
         let mut frame = make_head(opcode, mask, len);
@@ -310,4 +595,88 @@ mod test {
             }
         }
     }
+    #[test]
+    fn reset_stream() {
+        use crate::frame::CloseCode;
+
+        // a close frame, then a data frame of the next session
+        let (mut buf, _) = make_frame::<Client>(OpCode::Close, 0);
+        let (mut frame, data) = make_frame::<Client>(OpCode::Binary, 16);
+        buf.append(&mut frame);
+        let io = LimitReadWriter {
+            buf,
+            rlimit: 2,
+            wlimit: 1024,
+            cursor: 0,
+        };
+        let scratch = vec![0xff; 16].into_boxed_slice();
+        let mut stream = Stream::with_buffers(io, Server::new(), scratch.clone(), scratch);
+
+        let mut buf = vec![0; 64];
+        while !stream.is_read_end() {
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        }
+        stream.close(CloseCode::Normal, b"").unwrap();
+        assert!(stream.is_read_close());
+        assert!(stream.is_write_close());
+        assert!(stream.write(b"closed").is_err());
+        // left from the old session
+        stream.replay.buf = make_frame::<Client>(OpCode::Binary, 4).0;
+        stream.last_frame_len = 32;
+
+        stream.reset();
+        assert!(!stream.is_read_close());
+        assert!(!stream.is_write_close());
+        assert_eq!(stream.stats().read_frames, 0);
+        assert!(stream.replay.is_empty());
+        assert_eq!(stream.last_frame_len, 0);
+        assert_eq!(stream.scratch.read.as_deref(), Some(&[0; 16][..]));
+        assert_eq!(stream.scratch.write.as_deref(), Some(&[0; 16][..]));
+
+        // a fresh read
+        let mut data2 = Vec::new();
+        while data2.len() < data.len() {
+            let n = stream.read(&mut buf).unwrap();
+            data2.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(data2, data);
+
+        // a fresh write
+        let len = stream.as_ref().buf.len();
+        let mut n = 0;
+        while n < 5 {
+            n += stream.write(&b"hello"[n..]).unwrap();
+        }
+        assert_eq!(
+            &stream.as_ref().buf[len..],
+            &[0x82, 0x05, b'h', b'e', b'l', b'l', b'o']
+        );
+    }
+
+    #[test]
+    fn replace_io() {
+        use std::io::Cursor;
+
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 16);
+        let (frame2, data2) = make_frame::<Client>(OpCode::Binary, 32);
+        let frame_len = frame.len();
+        let mut stream = Stream::new(Cursor::new(frame), Server::new());
+        stream.set_strict(false);
+
+        let mut buf = vec![0; 64];
+        assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 16);
+        assert_eq!(&buf[..16], &data);
+
+        // continue with the next frame on a new IO source
+        let io = stream.replace_io(Cursor::new(frame2));
+        assert_eq!(io.position() as usize, frame_len);
+        assert!(!stream.is_strict());
+        assert_eq!(stream.stats().read_frames, 1);
+
+        assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 32);
+        assert_eq!(&buf[..32], &data2);
+        assert_eq!(stream.stats().read_frames, 2);
+        assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 0);
+        assert!(stream.is_read_eof());
+    }
 }
@@ -0,0 +1,170 @@
+//! Observe frames passing through a stream, for mirroring or debugging.
+
+use std::io::{Read, Write, Result};
+
+use super::{Stream, Direct};
+use super::frame::MAX_HEAD_LEN;
+
+use crate::frame::{FrameHead, Fin, OpCode, PayloadLen};
+use crate::role::RoleHelper;
+use crate::error::FrameError;
+
+/// Direction of an observed frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// the frame is read from the peer
+    Read,
+    /// the frame is written to the peer
+    Write,
+}
+
+/// Observer of complete frames, see [`ObservedStream`].
+///
+/// It only receives shared references, so that
+/// the frame or stream states could not be modified.
+pub trait FrameObserver {
+    /// Called on a complete frame, with its unmasked payload.
+    fn observe(&mut self, direction: Direction, head: &FrameHead, payload: &[u8]);
+}
+
+impl<F> FrameObserver for F
+where
+    F: FnMut(Direction, &FrameHead, &[u8]),
+{
+    #[inline]
+    fn observe(&mut self, direction: Direction, head: &FrameHead, payload: &[u8]) {
+        self(direction, head, payload)
+    }
+}
+
+/// A stream whose data frames are observed once they are
+/// completely read or written.
+///
+/// Frames are read or written as a whole, with [`ObservedStream::read_frame`]
+/// and [`ObservedStream::write_frame`]. Control frames are handled by the
+/// stream internally, which are not observed.
+pub struct ObservedStream<IO, Role, O, Guard = Direct> {
+    stream: Stream<IO, Role, Guard>,
+    observer: O,
+}
+
+impl<IO, Role, O, Guard> ObservedStream<IO, Role, O, Guard> {
+    /// Wrap a stream with an observer.
+    #[inline]
+    pub const fn new(stream: Stream<IO, Role, Guard>, observer: O) -> Self {
+        Self { stream, observer }
+    }
+
+    /// Get a reference to the inner stream.
+    #[inline]
+    pub const fn stream(&self) -> &Stream<IO, Role, Guard> { &self.stream }
+
+    /// Get a mutable reference to the inner stream.
+    ///
+    /// Frames read or written through it are not observed.
+    #[inline]
+    pub fn stream_mut(&mut self) -> &mut Stream<IO, Role, Guard> { &mut self.stream }
+
+    /// Get a reference to the observer.
+    #[inline]
+    pub const fn observer(&self) -> &O { &self.observer }
+
+    /// Unwrap, returns the inner stream and the observer.
+    #[inline]
+    pub fn into_inner(self) -> (Stream<IO, Role, Guard>, O) { (self.stream, self.observer) }
+}
+
+impl<IO: Read, Role: RoleHelper, O: FrameObserver, Guard> ObservedStream<IO, Role, O, Guard> {
+    /// Read exactly one data frame, then notify the observer.
+    /// Returns the opcode of the frame and the length of the payload.
+    ///
+    /// This is the same as [`Stream::read_split`], where a Close or `EOF`
+    /// leads to `Ok((OpCode::Close, 0))`. If the buffer is not large enough,
+    /// a [`FrameError::NotEnoughCapacity`] error is returned before the frame
+    /// is consumed, so that it could be read again with a larger buffer.
+    pub fn read_frame(&mut self, buf: &mut [u8]) -> Result<(OpCode, usize)> {
+        let mut head_buf = [0u8; MAX_HEAD_LEN];
+        let (head_len, payload_len) = self.stream.read_split(&mut head_buf, buf)?;
+        if head_len == 0 {
+            return Ok((OpCode::Close, 0));
+        }
+        if payload_len > buf.len() {
+            return Err(FrameError::NotEnoughCapacity.into());
+        }
+
+        let (head, _) = FrameHead::decode(&head_buf[..head_len])?;
+        let payload = if self.stream.is_discard_payloads() {
+            &[]
+        } else {
+            &buf[..payload_len]
+        };
+        self.observer.observe(Direction::Read, &head, payload);
+        Ok((head.opcode, payload_len))
+    }
+}
+
+impl<IO: Write, Role: RoleHelper, O: FrameObserver, Guard> ObservedStream<IO, Role, O, Guard> {
+    /// Write exactly one complete frame with [`Stream::write_frame`],
+    /// then notify the observer, returns the count of written payload bytes.
+    pub fn write_frame(&mut self, fin: Fin, opcode: OpCode, payload: &[u8]) -> Result<usize> {
+        let n = self.stream.write_frame(fin, opcode, payload)?;
+        let head = FrameHead::new(
+            fin,
+            opcode,
+            self.stream.role.mask_key(),
+            PayloadLen::from_num(payload.len() as u64),
+        );
+        self.observer.observe(Direction::Write, &head, payload);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_frame};
+    use crate::role::*;
+
+    #[test]
+    fn observe_frames() {
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 100);
+        let (frame2, _) = make_frame::<Client>(OpCode::Text, 200);
+        let io = LimitReadWriter {
+            buf: [frame, frame2].concat(),
+            rlimit: 7,
+            wlimit: 4096,
+            cursor: 0,
+        };
+
+        let mut frames = Vec::new();
+        let observer = |direction, head: &FrameHead, payload: &[u8]| {
+            frames.push((direction, head.opcode, payload.to_vec()))
+        };
+        let mut stream = ObservedStream::new(Stream::new(io, Server::new()), observer);
+
+        let written = b"hello".to_vec();
+        assert_eq!(
+            stream.write_frame(Fin::Y, OpCode::Text, &written).unwrap(),
+            5
+        );
+        let mut buf = vec![0; 128];
+        assert_eq!(stream.read_frame(&mut buf).unwrap(), (OpCode::Binary, 100));
+
+        // not observed until it is read
+        let e = stream.read_frame(&mut buf).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::NotEnoughCapacity)
+        );
+        drop(stream);
+
+        assert_eq!(
+            frames,
+            vec![
+                (Direction::Write, OpCode::Text, written),
+                (Direction::Read, OpCode::Binary, data),
+            ]
+        );
+    }
+}
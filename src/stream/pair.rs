@@ -0,0 +1,61 @@
+//! In-memory stream pair.
+
+use tokio::io::{DuplexStream, duplex};
+
+use super::Stream;
+use crate::role::{Client, Server};
+
+/// Default max buffer size of each direction of a [`Stream::pair`].
+pub const DEFAULT_PAIR_BUF_SIZE: usize = 64 * 1024;
+
+impl Stream<DuplexStream, Client> {
+    /// Create a connected client and server over an in-memory duplex,
+    /// without a handshake. This is useful for tests or local IPC.
+    ///
+    /// Data written to one end could be read from the other end,
+    /// a write is pending once [`DEFAULT_PAIR_BUF_SIZE`] bytes are buffered.
+    #[inline]
+    pub fn pair() -> (Stream<DuplexStream, Client>, Stream<DuplexStream, Server>) {
+        Self::pair_with_capacity(DEFAULT_PAIR_BUF_SIZE)
+    }
+
+    /// Create a connected client and server over an in-memory duplex,
+    /// with the max buffer size of each direction.
+    ///
+    /// See also: [`Stream::pair`].
+    #[inline]
+    pub fn pair_with_capacity(
+        max_buf_size: usize,
+    ) -> (Stream<DuplexStream, Client>, Stream<DuplexStream, Server>) {
+        let (client, server) = duplex(max_buf_size);
+        (Stream::new(client, Client), Stream::new(server, Server))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn pair_echo() {
+        let (mut client, mut server) = Stream::pair_with_capacity(64);
+        let mut buf = vec![0; 32];
+
+        // client -> server
+        client.write_all(b"hello").await.unwrap();
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+
+        // server -> client
+        server.write_all(b"world").await.unwrap();
+        let n = client.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"world");
+
+        // close
+        client.shutdown().await.unwrap();
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(n, 0);
+        assert!(server.is_read_close());
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::Result;
+
+use super::Stream;
+use super::detail::trace_head;
+
+use crate::frame::{FrameHead, Fin, Mask, OpCode, PayloadLen};
+use crate::frame::mask::apply_mask4;
+use crate::role::RoleHelper;
+use crate::error::{CtrlError, FrameError};
+
+impl<IO, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Build a `Pong` frame in place, which will be written later,
+    /// e.g. to reply the most recent ping(see [`Stream::ping_data`]).
+    ///
+    /// Unlike a `Close` frame, a pong could be queued during a write.
+    /// It is never inserted into a data frame: the pong is written
+    /// at the next frame boundary, before a new data frame or a
+    /// `Close` frame, or by [`Stream::poll_flush_control`].
+    ///
+    /// A pong which is not written yet is replaced, an attempt to queue
+    /// a pong while the previous one is partially written will fail with
+    /// [`CtrlError::PongInWrite`]. The payload must fit in a control frame,
+    /// otherwise a [`FrameError::IllegalData`] error is returned.
+    pub fn queue_pong(&mut self, payload: &[u8]) -> Result<()> {
+        if self.close.is_queued {
+            return Err(CtrlError::WriteAfterClose.into());
+        }
+        if payload.len() > 125 {
+            return Err(FrameError::IllegalData.into());
+        }
+
+        let store = &mut self.heartbeat.pong_store;
+        if store.rd_pos() != 0 && store.rd_left() != 0 {
+            return Err(CtrlError::PongInWrite.into());
+        }
+
+        let mask = self.role.mask_key();
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Pong,
+            mask,
+            PayloadLen::from_num(payload.len() as u64),
+        );
+
+        // The buffer is large enough to accommodate any kind of control frame.
+        let head_len = unsafe { head.encode_unchecked(store.as_mut()) };
+        let frame_len = head_len + payload.len();
+        let data = &mut store.as_mut()[head_len..frame_len];
+        data.copy_from_slice(payload);
+
+        trace_head("write", &head);
+
+        // payload is copied, so it is always masked
+        if let Mask::Key(key) = mask {
+            apply_mask4(key, data);
+        }
+
+        store.set_rd_pos(0);
+        store.set_wr_pos(frame_len);
+        Ok(())
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if a queued `Pong` frame is not completely written.
+    #[inline]
+    pub const fn is_pong_queued(&self) -> bool { self.heartbeat.pong_store.rd_left() != 0 }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Write, ErrorKind};
+    use super::*;
+    use super::super::test::{BlockWriter, make_head};
+    use crate::frame::CloseCode;
+    use crate::role::*;
+
+    #[test]
+    fn pong_before_next_frame() {
+        fn write<R: RoleHelper>() {
+            let mut stream = Stream::new(Vec::new(), R::new());
+            let mask = stream.mask_key();
+            stream.queue_pong(b"a").unwrap();
+            // replaced before written
+            stream.queue_pong(b"pong").unwrap();
+            assert!(stream.is_pong_queued());
+            assert!(stream.has_pending_write());
+            assert!(stream.queue_pong(&[0; 126]).is_err());
+
+            assert_eq!(stream.write(b"hello").unwrap(), 5);
+            assert!(!stream.is_pong_queued());
+
+            let mut frame = make_head(OpCode::Pong, mask, 4);
+            let mut data = b"pong".to_vec();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut data);
+            }
+            frame.append(&mut data);
+            frame.append(&mut make_head(OpCode::Binary, mask, 5));
+            frame.extend_from_slice(b"hello");
+            assert_eq!(stream.as_ref(), &frame);
+        }
+
+        write::<Client>();
+        write::<Server>();
+    }
+
+    #[test]
+    fn pong_in_write() {
+        use std::error::Error;
+
+        let io = BlockWriter {
+            capacity: 3,
+            ..BlockWriter::new(usize::MAX)
+        };
+        let mut stream = Stream::new(io, Server::new()).guard();
+        stream.queue_pong(b"pong").unwrap();
+
+        // the pong is partially written
+        let e = stream.write(b"hello").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::WouldBlock);
+        assert!(stream.is_pong_queued());
+        let e = stream.queue_pong(b"ping").unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<CtrlError>(),
+            Some(&CtrlError::PongInWrite)
+        );
+
+        // continue to write the pong, then the data frame
+        stream.as_mut().capacity = usize::MAX;
+        assert_eq!(stream.write(b"hello").unwrap(), 5);
+        let mut frame = make_head(OpCode::Pong, Mask::None, 4);
+        frame.extend_from_slice(b"pong");
+        frame.append(&mut make_head(OpCode::Binary, Mask::None, 5));
+        frame.extend_from_slice(b"hello");
+        assert_eq!(stream.as_ref().buf, frame);
+
+        // no more pongs after a close frame
+        stream.send_close(CloseCode::Normal, b"").unwrap();
+        assert!(stream.queue_pong(b"pong").is_err());
+    }
+}
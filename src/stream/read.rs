@@ -1,8 +1,44 @@
-use std::io::{Read, Result};
+use std::io::{Read, IoSliceMut, Result};
 use std::task::Poll;
 
 use super::{Stream, RoleHelper, Guarded};
-use super::detail::read_some;
+use super::state::ReadState;
+use super::frame::MAX_HEAD_LEN;
+use super::detail::{read_some, read_payload_vectored};
+use super::cancel::poll_unless_cancelled;
+
+#[inline]
+fn first_non_empty<'a>(bufs: &'a mut [IoSliceMut<'_>]) -> &'a mut [u8] {
+    bufs.iter_mut()
+        .find(|buf| !buf.is_empty())
+        .map_or(&mut [][..], |buf| &mut **buf)
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if the payload of a partially read frame
+    /// could be scattered into buffers.
+    #[inline]
+    fn is_reading_payload(&self) -> bool {
+        !self.discard_payloads
+            && !self.fragment.is_ignored
+            && matches!(self.read_state, ReadState::ReadData { .. })
+    }
+
+    /// Check if an `Ok(0)` from the underlying IO source means `EOF`,
+    /// which is the default.
+    #[inline]
+    pub const fn is_zero_read_eof(&self) -> bool { self.zero_read_is_eof }
+
+    /// Set whether an `Ok(0)` from the underlying IO source means `EOF`.
+    ///
+    /// Some IO sources return `Ok(0)` to mean "would block". Once disabled,
+    /// such a read fails with a `WouldBlock` error, where states are
+    /// preserved and a later read resumes the incomplete frame, the same as
+    /// other IO errors. The stream never reaches `EOF` then, a caller should
+    /// detect closure in another way, e.g. via [`Stream::is_read_close`].
+    #[inline]
+    pub fn set_zero_read_is_eof(&mut self, eof: bool) { self.zero_read_is_eof = eof; }
+}
 
 impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role> {
     /// Read some data from the underlying IO source,
@@ -17,8 +53,55 @@ impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role> {
     /// will return `Ok(0)`,
     /// which could be checked via [`Stream::is_read_end`],
     /// [`Stream::is_read_close`], [`Stream::is_read_eof`].
+    /// Reaching `EOF` in the middle of a frame leads to an `UnexpectedEof`
+    /// error instead, where [`Stream::close_reason`] is
+    /// [`CloseReason::Truncated`](super::CloseReason::Truncated).
+    ///
+    /// If the underlying IO source returns an error(e.g. `WouldBlock`,
+    /// or `TimedOut` with a read timeout set), the error is returned
+    /// as is, and states are preserved. A later read will resume
+    /// the incomplete frame.
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
-        match read_some(self, |io, buf| io.read(buf).into(), buf) {
+        let cancel = self.cancel.clone();
+        match read_some(
+            self,
+            |io, buf| poll_unless_cancelled(&cancel, || io.read(buf)),
+            buf,
+        ) {
+            Poll::Ready(x) => x,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    /// Scatter the payload of a partially read frame into the buffers
+    /// with a single syscall, payload is unmasked across buffer boundaries.
+    /// At most 16 buffers are filled, and it never reads beyond the frame.
+    ///
+    /// At a frame boundary, or if payload is discarded(see
+    /// [`Stream::set_discard_payloads`]), this is the same as `Stream::read`
+    /// with the first non-empty buffer. If that buffer is shorter than **14**,
+    /// the head is read with small reads into an internal buffer instead,
+    /// then the payload is scattered into the buffers.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        if !self.is_reading_payload() {
+            let buf = first_non_empty(bufs);
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            if buf.len() >= MAX_HEAD_LEN {
+                return self.read(buf);
+            }
+            // too short to hold a frame head
+            self.read_head_only()?;
+            if !self.is_reading_payload() {
+                return Ok(0);
+            }
+        }
+        let cancel = self.cancel.clone();
+        let read = |io: &mut IO, bufs: &mut [IoSliceMut<'_>]| {
+            poll_unless_cancelled(&cancel, || io.read_vectored(bufs))
+        };
+        match read_payload_vectored(self, read, bufs) {
             Poll::Ready(x) => x,
             Poll::Pending => unreachable!(),
         }
@@ -44,8 +127,13 @@ impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role, Guarded> {
     /// Wrap read in a loop.
     /// Continue to read if frame head is not complete.
     fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let cancel = self.cancel.clone();
         loop {
-            match read_some(self, |io, buf| io.read(buf).into(), buf) {
+            match read_some(
+                self,
+                |io, buf| poll_unless_cancelled(&cancel, || io.read(buf)),
+                buf,
+            ) {
                 Poll::Ready(Ok(0)) if self.is_read_partial_head() || !self.is_read_end() => {
                     continue
                 }
@@ -55,6 +143,35 @@ impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role, Guarded> {
         }
     }
 
+    /// Wrap read in a loop, then scatter the payload into the buffers.
+    /// See `Stream::read_vectored` in `Direct` mode.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        if !self.is_reading_payload() {
+            let buf = first_non_empty(bufs);
+            if buf.is_empty() {
+                return Ok(0);
+            }
+            if buf.len() >= MAX_HEAD_LEN {
+                return self.read(buf);
+            }
+            // too short to hold a frame head
+            while !self.is_reading_payload() {
+                if self.is_read_end() {
+                    return Ok(0);
+                }
+                self.read_head_only()?;
+            }
+        }
+        let cancel = self.cancel.clone();
+        let read = |io: &mut IO, bufs: &mut [IoSliceMut<'_>]| {
+            poll_unless_cancelled(&cancel, || io.read_vectored(bufs))
+        };
+        match read_payload_vectored(self, read, bufs) {
+            Poll::Ready(x) => x,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
     /// Override default implement, extend reserved buffer size,
     /// so that there is enough space to accommodate frame head.
     fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
@@ -79,7 +196,7 @@ impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role, Guarded> {
 
             match self.read_buf(&mut read_buf) {
                 Ok(()) => {}
-                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) if e.kind() == ErrorKind::Interrupted && !self.is_cancelled() => continue,
                 Err(e) => return Err(e),
             }
 
@@ -110,7 +227,11 @@ impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role, Guarded> {
                             buf.extend_from_slice(&probe[..n]);
                             break;
                         }
-                        Err(ref e) if e.kind() == ErrorKind::Interrupted => continue,
+                        Err(ref e)
+                            if e.kind() == ErrorKind::Interrupted && !self.is_cancelled() =>
+                        {
+                            continue
+                        }
                         Err(e) => return Err(e),
                     }
                 }
@@ -123,7 +244,8 @@ impl<IO: Read, Role: RoleHelper> Read for Stream<IO, Role, Guarded> {
 mod test {
     use std::io::Read;
     use super::*;
-    use super::super::test::{LimitReadWriter, make_frame};
+    use super::super::test::{LimitReadWriter, make_frame, make_data, make_head, make_head_with_fin};
+    use super::super::test::make_frame_with_mask;
     use crate::frame::*;
     use crate::role::*;
 
@@ -215,10 +337,247 @@ mod test {
         read::<Server>();
     }
 
+    #[test]
+    fn read_zero_from_stream() {
+        use std::io::ErrorKind;
+        use std::collections::VecDeque;
+
+        // an empty chunk is returned as Ok(0)
+        struct StallReader(VecDeque<Vec<u8>>);
+
+        impl Read for StallReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let mut chunk = self.0.pop_front().unwrap_or_default();
+                let n = std::cmp::min(buf.len(), chunk.len());
+                buf[..n].copy_from_slice(&chunk[..n]);
+                if n < chunk.len() {
+                    self.0.push_front(chunk.split_off(n));
+                }
+                Ok(n)
+            }
+        }
+
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 100);
+        let (frame2, data2) = make_frame::<Client>(OpCode::Binary, 50);
+        let make_stream = |at: &[usize]| {
+            let mut chunks = VecDeque::from([frame.clone(), Vec::new()]);
+            let mut beg = 0;
+            for &end in at.iter().chain([&frame2.len()]) {
+                chunks.push_back(frame2[beg..end].to_vec());
+                chunks.push_back(Vec::new());
+                beg = end;
+            }
+            Stream::new(StallReader(chunks), Server::new())
+        };
+        let mut buf = vec![0; 128];
+
+        // EOF, which is the default
+        let mut stream = make_stream(&[1, 20]);
+        assert!(stream.is_zero_read_eof());
+        assert_eq!(stream.read(&mut buf).unwrap(), 100);
+        assert_eq!(&buf[..100], &data);
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        assert!(stream.is_read_eof());
+
+        // would block, then resume
+        let mut stream = make_stream(&[1, 20]);
+        stream.set_zero_read_is_eof(false);
+        assert!(!stream.is_zero_read_eof());
+        assert_eq!(stream.read(&mut buf).unwrap(), 100);
+        let mut data3 = Vec::new();
+        while data3.len() < data2.len() {
+            match stream.read(&mut buf) {
+                Ok(n) => data3.extend_from_slice(&buf[..n]),
+                Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+            }
+        }
+        assert_eq!(data3, data2);
+        assert!(!stream.is_read_end());
+        assert_eq!(stream.close_reason(), None);
+
+        // same for a frame read, in the middle of a head
+        let mut stream = make_stream(&[1]).guard();
+        stream.set_zero_read_is_eof(false);
+        assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 100);
+        let n = loop {
+            match stream.read_exact_frame(&mut buf) {
+                Ok(n) => break n,
+                Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+            }
+        };
+        assert_eq!(&buf[..n], &data2);
+    }
+
+    #[test]
+    fn read_truncated_from_stream() {
+        use crate::stream::CloseReason;
+
+        fn check<R: RoleHelper>(stream: &mut Stream<LimitReadWriter, R>, e: std::io::Error) {
+            assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+            assert_eq!(stream.close_reason(), Some(CloseReason::Truncated));
+            assert!(stream.is_read_eof());
+            // no more errors
+            assert_eq!(stream.read(&mut [0; 32]).unwrap(), 0);
+        }
+
+        fn read<R: RoleHelper>(buf: &[u8], rlimit: usize) {
+            let make_stream = || {
+                let io = LimitReadWriter {
+                    buf: buf.to_vec(),
+                    rlimit,
+                    wlimit: 0,
+                    cursor: 0,
+                };
+                Stream::new(io, R::new())
+            };
+            let mut buf = vec![0; 256];
+
+            let mut stream = make_stream();
+            let e = loop {
+                match stream.read(&mut buf) {
+                    Ok(n) => assert!(n != 0 || !stream.is_read_end()),
+                    Err(e) => break e,
+                }
+            };
+            check(&mut stream, e);
+
+            let mut stream = make_stream();
+            let e = stream.read_exact_frame(&mut buf).unwrap_err();
+            check(&mut stream, e);
+
+            let mut stream = make_stream();
+            let e = loop {
+                if let Err(e) = stream.read_data(&mut buf) {
+                    break e;
+                }
+            };
+            check(&mut stream, e);
+        }
+
+        for rlimit in [1, 7, 64, 4096] {
+            // 50 of 100 bytes
+            let (frame, _) = make_frame::<Client>(OpCode::Binary, 100);
+            read::<Server>(&frame[..frame.len() - 50], rlimit);
+            let (frame, _) = make_frame::<Server>(OpCode::Binary, 100);
+            read::<Client>(&frame[..frame.len() - 50], rlimit);
+
+            // incomplete head
+            let (frame, _) = make_frame::<Client>(OpCode::Binary, 1000);
+            read::<Server>(&frame[..3], rlimit);
+
+            // incomplete ping
+            let (frame, _) = make_frame::<Client>(OpCode::Ping, 100);
+            read::<Server>(&frame[..frame.len() - 50], rlimit);
+        }
+    }
+
+    #[test]
+    fn read_zero_mask_key_from_stream() {
+        use super::super::detail::unmask;
+
+        for n in [0, 1, 7, 125, 0x1000, 0x10000] {
+            let (frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key([0; 4]), n);
+            let head_len = frame.len() - n;
+
+            // decoded as Mask::Skip
+            let (head, _) = FrameHead::decode(&frame).unwrap();
+            assert_eq!(head.mask, Mask::Skip);
+
+            // payload is left untouched, whether or not the key is skipped
+            let mut payload = frame[head_len..].to_vec();
+            unmask([0; 4], 3, &mut payload);
+            assert_eq!(payload, data);
+            apply_mask4([0; 4], &mut payload);
+            assert_eq!(payload, data);
+
+            for rlimit in [1, 7, 4096, 0x20000] {
+                let io = LimitReadWriter {
+                    buf: frame.clone(),
+                    rlimit,
+                    wlimit: 0,
+                    cursor: 0,
+                };
+                let mut stream = Stream::new(io, Server::new());
+                let mut buf = vec![0; n + 14];
+                let mut data2 = Vec::new();
+                while !stream.is_read_eof() {
+                    let read_n = stream.read(&mut buf).unwrap();
+                    data2.extend_from_slice(&buf[..read_n]);
+                }
+                assert_eq!(data2, data);
+            }
+        }
+    }
+
+    #[test]
+    fn read_split_mask_key_from_stream() {
+        // deliver a chunk of bytes per read
+        struct ChunkReader {
+            buf: Vec<u8>,
+            chunks: Vec<usize>,
+            cursor: usize,
+        }
+
+        impl Read for ChunkReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let left = self.buf.len() - self.cursor;
+                let chunk = if self.chunks.is_empty() {
+                    left
+                } else {
+                    self.chunks.remove(0)
+                };
+                let n = std::cmp::min(std::cmp::min(chunk, left), buf.len());
+                buf[..n].copy_from_slice(&self.buf[self.cursor..self.cursor + n]);
+                self.cursor += n;
+                Ok(n)
+            }
+        }
+
+        // 7-bit, 16-bit and 64-bit payload length
+        for len in [1, 125, 126, 1000, 0x10000] {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), len);
+            let head_len = frame.len() - len;
+            apply_mask4(key, &mut frame[head_len..]);
+
+            // head without mask key, then the key byte by byte
+            let chunks = vec![head_len - 4, 1, 1, 1, 1];
+
+            let io = ChunkReader {
+                buf: frame.clone(),
+                chunks: chunks.clone(),
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+            let mut buf = vec![0; len + 14];
+            let mut data2 = Vec::new();
+            let mut empty_reads = 0;
+            while data2.len() < len {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    empty_reads += 1;
+                }
+                data2.extend_from_slice(&buf[..n]);
+            }
+            // one read per chunk before payload
+            assert_eq!(empty_reads, 5);
+            assert_eq!(data2, data);
+
+            let io = ChunkReader {
+                buf: frame,
+                chunks,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, Server::new());
+            let n = stream.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &data);
+        }
+    }
+
     #[test]
     fn read_close_from_stream() {
         fn read<R1: RoleHelper, R2: RoleHelper>(limit: usize) {
-            let (frame, _) = make_frame::<R1>(OpCode::Close, 1);
+            let (frame, _) = make_frame::<R1>(OpCode::Close, 2);
             let io = LimitReadWriter {
                 buf: frame,
                 rlimit: limit,
@@ -276,6 +635,56 @@ mod test {
         }
     }
 
+    #[test]
+    fn read_invalid_close_from_stream() {
+        use std::error::Error;
+        use crate::error::FrameError;
+
+        fn read<R1: RoleHelper, R2: RoleHelper>(limit: usize) {
+            // 1005 must not appear on the wire
+            let (mut frame, _) = make_frame::<R1>(OpCode::Close, 2);
+            let offset = frame.len() - 2;
+            frame[offset..].copy_from_slice(&1005_u16.to_be_bytes());
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit: limit,
+                wlimit: 256,
+                cursor: 0,
+            };
+
+            let mut stream = Stream::new(io, R2::new());
+            let mut buf = vec![0; 32];
+            let e = loop {
+                match stream.read(&mut buf) {
+                    Ok(n) => assert_eq!(n, 0),
+                    Err(e) => break e,
+                }
+            };
+            let e = e.source().unwrap().downcast_ref::<FrameError>().unwrap();
+            assert_eq!(e, &FrameError::IllegalData);
+            assert!(stream.is_read_close());
+            assert_eq!(stream.peer_close(), None);
+
+            // the queued close wins over the provided code
+            let written = stream.as_ref().buf.len();
+            stream.send_close(CloseCode::Normal, b"").unwrap();
+            let frame = stream.as_ref().buf[written..].to_vec();
+            let mut peer = Stream::new(frame.as_slice(), R1::new());
+            while !peer.is_read_end() {
+                assert_eq!(peer.read(&mut buf).unwrap(), 0);
+            }
+            assert_eq!(
+                peer.peer_close(),
+                Some((CloseCode::ProtocolError, &b""[..]))
+            );
+        }
+
+        for i in 1..=8 {
+            read::<Client, Server>(i);
+            read::<Server, Client>(i);
+        }
+    }
+
     #[test]
     fn read_multi_frame_from_stream() {
         fn read<R1: RoleHelper, R2: RoleHelper>(n: usize, step: usize, limit: usize) {
@@ -291,7 +700,7 @@ mod test {
                 assert_eq!(len, (i + 1) * (i + 2) * step / 2);
             }
 
-            let (mut close, _) = make_frame::<R1>(OpCode::Close, 1);
+            let (mut close, _) = make_frame::<R1>(OpCode::Close, 2);
             frame.append(&mut close);
 
             let io = LimitReadWriter {
@@ -360,4 +769,421 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn read_fragment_from_stream() {
+        fn read<R1: RoleHelper, R2: RoleHelper>(n: usize, limit: usize) {
+            let mask = R1::new().mask_key();
+            let mut frame = Vec::new();
+            let mut data = Vec::new();
+
+            for (fin, opcode) in [
+                (Fin::N, OpCode::Binary),
+                (Fin::N, OpCode::Continue),
+                (Fin::Y, OpCode::Continue),
+            ] {
+                let mut d = make_data(n);
+                frame.append(&mut make_head_with_fin(fin, opcode, mask, n));
+                frame.extend_from_slice(&d);
+                data.append(&mut d);
+            }
+
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit: limit,
+                wlimit: 0,
+                cursor: 0,
+            };
+
+            let mut buf = Vec::new();
+            let mut stream = Stream::new(io, R2::new()).guard();
+
+            let read_n = stream.read_to_end(&mut buf).unwrap();
+
+            assert_eq!(read_n, data.len());
+            assert_eq!(&buf, &data);
+            assert!(!stream.is_read_fragmented());
+        }
+
+        for n in [0, 1, 10, 100, 1000] {
+            for limit in [1, 10, 100, 1000, usize::MAX] {
+                read::<Client, Server>(n, limit);
+                read::<Server, Client>(n, limit);
+            }
+        }
+    }
+
+    #[test]
+    fn read_illegal_fragment_from_stream() {
+        use std::error::Error;
+        use crate::error::FrameError;
+
+        fn read<R1: RoleHelper, R2: RoleHelper>(limit: usize) {
+            let mask = R1::new().mask_key();
+            let mut frame = make_head_with_fin(Fin::N, OpCode::Binary, mask, 4);
+            frame.extend_from_slice(&make_data(4));
+            // a new data frame, instead of a continuation frame
+            frame.append(&mut make_head_with_fin(Fin::Y, OpCode::Binary, mask, 4));
+            frame.extend_from_slice(&make_data(4));
+
+            let frame_len = frame.len();
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit: limit,
+                wlimit: usize::MAX,
+                cursor: 0,
+            };
+
+            let mut buf = Vec::new();
+            let mut stream = Stream::new(io, R2::new()).guard();
+
+            let e = stream.read_to_end(&mut buf).unwrap_err();
+            let e = e.source().unwrap();
+            let e: &FrameError = e.downcast_ref().unwrap();
+            assert_eq!(*e, FrameError::IllegalFragment);
+
+            // a protocol error close is queued, the provided code is ignored
+            let mask = stream.mask_key();
+            let mut close = make_head(OpCode::Close, mask, 2);
+            let mut code = 1002_u16.to_be_bytes().to_vec();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut code);
+            }
+            close.append(&mut code);
+            stream.send_close(CloseCode::Normal, b"").unwrap();
+            assert_eq!(&stream.as_ref().buf[frame_len..], &close);
+        }
+
+        for limit in 1..=32 {
+            read::<Client, Server>(limit);
+            read::<Server, Client>(limit);
+        }
+    }
+
+    #[test]
+    fn read_continuation_first_from_stream() {
+        use std::error::Error;
+        use crate::error::FrameError;
+
+        fn read<R1: RoleHelper, R2: RoleHelper>(limit: usize) {
+            let mask = R1::new().mask_key();
+            // a continuation frame on a fresh connection
+            let mut frame = make_head_with_fin(Fin::Y, OpCode::Continue, mask, 4);
+            frame.extend_from_slice(&make_data(4));
+
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit: limit,
+                wlimit: 0,
+                cursor: 0,
+            };
+
+            let mut buf = Vec::new();
+            let mut stream = Stream::new(io, R2::new()).guard();
+
+            let e = stream.read_to_end(&mut buf).unwrap_err();
+            let e = e.source().unwrap();
+            let e: &FrameError = e.downcast_ref().unwrap();
+            assert_eq!(*e, FrameError::IllegalContinuation);
+        }
+
+        for limit in 1..=32 {
+            read::<Client, Server>(limit);
+            read::<Server, Client>(limit);
+        }
+    }
+
+    #[test]
+    fn read_timeout_from_stream() {
+        use std::io::{Error, ErrorKind};
+
+        // return TimedOut after each successful read
+        struct TimeoutReader {
+            inner: LimitReadWriter,
+            timeout: bool,
+        }
+
+        impl Read for TimeoutReader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.timeout = !self.timeout;
+                if self.timeout {
+                    return Err(Error::new(ErrorKind::TimedOut, "timeout"));
+                }
+                self.inner.read(buf)
+            }
+        }
+
+        fn read<R1: RoleHelper, R2: RoleHelper>(n: usize, limit: usize) {
+            let (mut frame, data) = make_frame::<R1>(OpCode::Binary, n);
+            let (mut close, _) = make_frame::<R1>(OpCode::Close, 0);
+            frame.append(&mut close);
+
+            let io = TimeoutReader {
+                inner: LimitReadWriter {
+                    buf: frame,
+                    rlimit: limit,
+                    wlimit: 0,
+                    cursor: 0,
+                },
+                timeout: false,
+            };
+
+            let mut buf = vec![0; n + 14];
+            let mut data2 = Vec::new();
+            let mut stream = Stream::new(io, R2::new());
+
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) if stream.is_read_end() => break,
+                    Ok(read_n) => data2.extend_from_slice(&buf[..read_n]),
+                    Err(e) if e.kind() == ErrorKind::TimedOut => continue,
+                    Err(e) => panic!("{}", e),
+                }
+            }
+
+            assert!(stream.is_read_close());
+            assert_eq!(&data2, &data);
+        }
+
+        for n in [0, 1, 10, 100, 1000] {
+            for limit in 1..=32 {
+                read::<Client, Server>(n, limit);
+                read::<Server, Client>(n, limit);
+            }
+        }
+    }
+
+    #[test]
+    fn read_limit_from_stream() {
+        use std::error::Error;
+        use crate::error::FrameError;
+
+        fn read<R1: RoleHelper, R2: RoleHelper>(n: usize, max: u64, by_frames: bool) {
+            let mut frame = Vec::new();
+            let mut frame_len = 0;
+            for _ in 0..=max {
+                let (mut f, _) = make_frame::<R1>(OpCode::Binary, n);
+                frame_len = f.len();
+                frame.append(&mut f);
+            }
+
+            // read a frame each time
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit: frame_len,
+                wlimit: 0,
+                cursor: 0,
+            };
+
+            let mut buf = vec![0; n + 14];
+            let mut stream = Stream::new(io, R2::new());
+            if by_frames {
+                stream.set_max_total_frames(max);
+            } else {
+                stream.set_max_total_payload_bytes(max * n as u64);
+            }
+
+            for _ in 0..max {
+                let read_n = stream.read(&mut buf).unwrap();
+                assert_eq!(read_n, n);
+            }
+
+            let e = stream.read(&mut buf).unwrap_err();
+            let e = e.source().unwrap();
+            let e: &FrameError = e.downcast_ref().unwrap();
+            if by_frames {
+                assert_eq!(*e, FrameError::ExceedFrameLimit);
+            } else {
+                assert_eq!(*e, FrameError::ExceedPayloadLimit);
+            }
+            assert_eq!(stream.stats().read_frames, max + 1);
+
+            // the queued close wins over the provided code
+            let written = stream.as_ref().buf.len();
+            stream.as_mut().wlimit = 256;
+            stream.send_close(CloseCode::Normal, b"").unwrap();
+            let frame = stream.as_ref().buf[written..].to_vec();
+            let mut peer = Stream::new(frame.as_slice(), R1::new());
+            while !peer.is_read_end() {
+                assert_eq!(peer.read(&mut buf).unwrap(), 0);
+            }
+            assert_eq!(
+                peer.peer_close(),
+                Some((CloseCode::PolicyViolation, &b""[..]))
+            );
+        }
+
+        for n in [1, 10, 100, 1000] {
+            for max in 1..=8 {
+                read::<Client, Server>(n, max, true);
+                read::<Server, Client>(n, max, true);
+                read::<Client, Server>(n, max, false);
+                read::<Server, Client>(n, max, false);
+            }
+        }
+    }
+
+    #[test]
+    fn read_masked_from_limit_stream() {
+        fn read(n: usize, limit: usize) {
+            let key: [u8; 4] = rand::random();
+            let (ping, ping_data) = make_frame_with_mask(OpCode::Ping, Mask::Key(key), 125);
+            let (data_frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), n);
+
+            let mut frame = Vec::new();
+            for (mut f, len) in [(ping, 125), (data_frame, n)] {
+                // manually mask frame data
+                let offset = f.len() - len;
+                apply_mask4(key, &mut f[offset..]);
+                frame.append(&mut f);
+            }
+
+            let io = LimitReadWriter {
+                buf: frame,
+                rlimit: limit,
+                wlimit: 0,
+                cursor: 0,
+            };
+
+            let mut buf = vec![0; n + 14];
+            let mut data2 = Vec::new();
+            let mut stream = Stream::new(io, Server::new());
+
+            // ping is completely read
+            while !stream.is_ping_completed() {
+                let read_n = stream.read(&mut buf).unwrap();
+                assert_eq!(read_n, 0);
+                if let Some(offset) = stream.current_mask_offset() {
+                    assert_eq!(offset as usize, stream.ping_data().len() % 4);
+                }
+            }
+            assert_eq!(stream.ping_data(), &ping_data);
+
+            while data2.len() < n {
+                let read_n = stream.read(&mut buf).unwrap();
+                data2.extend_from_slice(&buf[..read_n]);
+                match stream.current_mask_offset() {
+                    Some(offset) => assert_eq!(offset as usize, data2.len() % 4),
+                    None => assert!(data2.len() == n || stream.is_read_partial_head()),
+                }
+            }
+            assert_eq!(&data2, &data);
+        }
+
+        for n in [1, 2, 3, 4, 5, 10, 100, 1000] {
+            for limit in 1..=32 {
+                read(n, limit);
+            }
+        }
+    }
+
+    #[test]
+    fn read_vectored_from_stream() {
+        use std::io::{Cursor, IoSliceMut};
+
+        fn read(n: usize, first: usize) {
+            let key: [u8; 4] = rand::random();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), n);
+            let offset = frame.len() - n;
+            apply_mask4(key, &mut frame[offset..]);
+            // followed by another frame
+            let (frame2, data2) = make_frame::<Client>(OpCode::Binary, 16);
+            frame.extend_from_slice(&frame2);
+
+            let mut stream = Stream::new(Cursor::new(frame), Server::new());
+
+            // read frame head and some payload
+            let mut buf = vec![0; offset + first];
+            let read_n = stream.read(&mut buf).unwrap();
+            assert_eq!(read_n, first);
+            assert_eq!(&buf[..first], &data[..first]);
+
+            // scatter the rest into three buffers
+            let mut buf1 = vec![0; 3];
+            let mut buf2 = vec![0; 7];
+            let mut buf3 = vec![0; n];
+            let mut bufs = [
+                IoSliceMut::new(&mut buf1),
+                IoSliceMut::new(&mut buf2),
+                IoSliceMut::new(&mut buf3),
+            ];
+            let read_n = stream.read_vectored(&mut bufs).unwrap();
+            assert_eq!(read_n, n - first);
+
+            let scattered = [buf1, buf2, buf3].concat();
+            assert_eq!(&scattered[..read_n], &data[first..]);
+
+            // never read beyond the frame
+            let mut buf = vec![0; 32];
+            let read_n = stream.read(&mut buf).unwrap();
+            assert_eq!(&buf[..read_n], &data2);
+        }
+
+        for n in 16..=64 {
+            for first in 8..=12 {
+                read(n, first);
+            }
+        }
+    }
+
+    #[test]
+    fn read_vectored_into_short_buffers() {
+        use std::io::{Cursor, IoSliceMut};
+
+        // a ping, a masked frame, then an empty frame before another one
+        fn make_frames() -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            let key: [u8; 4] = rand::random();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 20);
+            apply_mask4(key, &mut frame[6..]);
+            let (ping, _) = make_frame::<Client>(OpCode::Ping, 5);
+            let (empty, _) = make_frame::<Client>(OpCode::Binary, 0);
+            let (frame2, data2) = make_frame::<Client>(OpCode::Binary, 7);
+            ([ping, frame, empty, frame2].concat(), data, data2)
+        }
+
+        fn read_all<R: Read>(stream: &mut R, len: usize) -> Vec<u8> {
+            let mut bufs = [[0u8; 2]; 4];
+            let mut data = Vec::new();
+            while data.len() < len {
+                let [b1, b2, b3, b4] = &mut bufs;
+                let mut slices = [
+                    IoSliceMut::new(b1),
+                    IoSliceMut::new(b2),
+                    IoSliceMut::new(b3),
+                    IoSliceMut::new(b4),
+                ];
+                let n = stream.read_vectored(&mut slices).unwrap();
+                assert!(n <= 8);
+                data.extend_from_slice(&bufs.concat()[..n]);
+            }
+            data
+        }
+
+        // never read beyond the frame
+        let (frames, data, data2) = make_frames();
+        let mut stream = Stream::new(Cursor::new(frames.clone()), Server::new());
+        assert_eq!(read_all(&mut stream, 20), data);
+        assert!(stream.is_pinged());
+        assert_eq!(stream.next_payload_len(), None);
+        assert_eq!(read_all(&mut stream, 7), data2);
+        let mut buf = [0u8; 2];
+        assert_eq!(
+            stream
+                .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+                .unwrap(),
+            0
+        );
+        assert!(stream.is_read_eof());
+
+        let mut stream = Stream::new(Cursor::new(frames), Server::new()).guard();
+        assert_eq!(read_all(&mut stream, 27), [data, data2].concat());
+        let mut buf = [0u8; 2];
+        assert_eq!(
+            stream
+                .read_vectored(&mut [IoSliceMut::new(&mut buf)])
+                .unwrap(),
+            0
+        );
+        assert!(stream.is_read_eof());
+    }
 }
@@ -0,0 +1,67 @@
+use std::io::IoSliceMut;
+
+use super::Stream;
+
+/// Bytes read ahead of the stream, e.g. frames right after a handshake
+/// response, which are returned before reading from the IO source.
+#[derive(Debug)]
+pub(super) struct Replay {
+    pub buf: Vec<u8>,
+    pub pos: usize,
+}
+
+impl Replay {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            buf: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool { self.pos == self.buf.len() }
+
+    /// Copy the bytes into `buf`, returns the count of copied bytes.
+    /// The storage is released once all bytes are copied.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let n = std::cmp::min(buf.len(), self.buf.len() - self.pos);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        if self.is_empty() {
+            *self = Self::new();
+        }
+        n
+    }
+
+    /// Same as `Replay::read`, fill the buffers in order.
+    pub fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> usize {
+        bufs.iter_mut().map(|buf| self.read(buf)).sum()
+    }
+}
+
+impl<IO, Role> Stream<IO, Role> {
+    /// Create websocket stream from IO source which has completed a
+    /// handshake, where `remainder` was read with the handshake.
+    ///
+    /// A peer may send frames right after the handshake, which arrive
+    /// in the same read. They are returned before any data from the IO
+    /// source, so that nothing is lost. The bytes are copied, which
+    /// requires heap allocation unless `remainder` is empty.
+    ///
+    /// [`Endpoint::connect`](crate::endpoint::Endpoint::connect) does this,
+    /// otherwise it is the same as [`Stream::new`].
+    #[inline]
+    pub fn from_handshake_with_remainder(io: IO, role: Role, remainder: &[u8]) -> Self {
+        let mut stream = Self::new(io, role);
+        stream.replay.buf = remainder.to_vec();
+        stream
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Get the count of bytes read with the handshake which are not
+    /// yet consumed, see [`Stream::from_handshake_with_remainder`].
+    #[inline]
+    pub fn remainder_len(&self) -> usize { self.replay.buf.len() - self.replay.pos }
+}
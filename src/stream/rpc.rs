@@ -0,0 +1,381 @@
+//! Request/response correlation over a message stream.
+//!
+//! Each request carries an ID, which is echoed by its response.
+//! [`RpcStream`] sends requests, then reads messages until the response
+//! with the expected ID arrives. Responses to other requests are buffered,
+//! so that they could arrive in any order.
+
+use std::io::{Read, Write, Result, Error, ErrorKind};
+use std::collections::{HashMap, VecDeque};
+
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+use super::{MessageStream, Message};
+#[cfg(feature = "async")]
+use super::Event;
+
+use crate::frame::{FrameHead, Fin, Mask, OpCode, PayloadLen};
+use crate::frame::mask::apply_mask4;
+use crate::role::RoleHelper;
+use crate::error::MessageError;
+
+/// Default max count of buffered messages.
+pub const DEFAULT_MAX_PENDING: usize = 1024;
+
+/// Extract the correlation ID from the payload of a message.
+type IdOf = Box<dyn FnMut(&[u8]) -> Option<u64> + Send>;
+
+/// Correlate requests and responses, built on [`MessageStream`].
+///
+/// The correlation ID is extracted from the payload of either a request
+/// or a response, with the closure provided to [`RpcStream::new`].
+/// A message without an ID is buffered as an unsolicited message,
+/// see [`RpcStream::take_unsolicited`].
+///
+/// Control frames are handled by the message stream during a read,
+/// e.g. a ping is saved. Once a `Close` frame is received, an outstanding
+/// request fails with [`MessageError::Closed`], while buffered responses
+/// could still be received.
+pub struct RpcStream<IO, Role> {
+    stream: MessageStream<IO, Role>,
+    id_of: IdOf,
+    responses: HashMap<u64, Message>,
+    unsolicited: VecDeque<Message>,
+    max_pending: usize,
+}
+
+impl<IO, Role> AsRef<IO> for RpcStream<IO, Role> {
+    #[inline]
+    fn as_ref(&self) -> &IO { self.stream.as_ref() }
+}
+
+impl<IO, Role> AsMut<IO> for RpcStream<IO, Role> {
+    #[inline]
+    fn as_mut(&mut self) -> &mut IO { self.stream.as_mut() }
+}
+
+impl<IO, Role> std::fmt::Debug for RpcStream<IO, Role> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RpcStream")
+            .field("stream", &self.stream)
+            .field("responses", &self.responses.len())
+            .field("unsolicited", &self.unsolicited.len())
+            .field("max_pending", &self.max_pending)
+            .finish()
+    }
+}
+
+impl<IO, Role> RpcStream<IO, Role> {
+    /// Create from a message stream, where `id_of` extracts
+    /// the correlation ID from the payload of a message.
+    #[inline]
+    pub fn new<F>(stream: MessageStream<IO, Role>, id_of: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Option<u64> + Send + 'static,
+    {
+        Self {
+            stream,
+            id_of: Box::new(id_of),
+            responses: HashMap::new(),
+            unsolicited: VecDeque::new(),
+            max_pending: DEFAULT_MAX_PENDING,
+        }
+    }
+
+    /// Get the underlying message stream.
+    #[inline]
+    pub const fn message_stream(&self) -> &MessageStream<IO, Role> { &self.stream }
+
+    /// Get the underlying message stream, e.g. to check the most recent ping.
+    #[inline]
+    pub fn message_stream_mut(&mut self) -> &mut MessageStream<IO, Role> { &mut self.stream }
+
+    /// Return the underlying message stream, buffered messages are dropped.
+    #[inline]
+    pub fn into_inner(self) -> MessageStream<IO, Role> { self.stream }
+
+    /// Get the max count of buffered messages.
+    #[inline]
+    pub const fn max_pending(&self) -> usize { self.max_pending }
+
+    /// Set the max count of buffered messages, including responses and
+    /// unsolicited messages. Once exceeded, a read will fail with
+    /// [`MessageError::TooManyPending`].
+    ///
+    /// A value of 0 means unlimited.
+    /// The default is [`DEFAULT_MAX_PENDING`].
+    #[inline]
+    pub fn set_max_pending(&mut self, n: usize) { self.max_pending = n; }
+
+    /// Get the count of buffered messages.
+    #[inline]
+    pub fn pending_len(&self) -> usize { self.responses.len() + self.unsolicited.len() }
+
+    /// Take the oldest buffered message without an ID, if any.
+    #[inline]
+    pub fn take_unsolicited(&mut self) -> Option<Message> { self.unsolicited.pop_front() }
+
+    /// Extract the correlation ID of a request.
+    fn request_id(&mut self, request: &Message) -> Result<u64> {
+        (self.id_of)(payload(request))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no correlation id"))
+    }
+
+    /// Return the message if it is the response with `id`,
+    /// otherwise buffer it.
+    fn dispatch(&mut self, id: u64, message: Message) -> Result<Option<Message>> {
+        let message_id = (self.id_of)(payload(&message));
+        if message_id == Some(id) {
+            return Ok(Some(message));
+        }
+        if self.max_pending != 0 && self.pending_len() >= self.max_pending {
+            return Err(MessageError::TooManyPending.into());
+        }
+        match message_id {
+            Some(message_id) => {
+                self.responses.insert(message_id, message);
+            }
+            None => self.unsolicited.push_back(message),
+        }
+        Ok(None)
+    }
+}
+
+impl<IO, Role: RoleHelper> RpcStream<IO, Role> {
+    /// Encode a message as a single frame, masked if required by the role.
+    fn encode(&self, message: &Message) -> Vec<u8> {
+        let (opcode, payload) = match message {
+            Message::Text(text) => (OpCode::Text, text.as_bytes()),
+            Message::Binary(data) => (OpCode::Binary, data.as_slice()),
+        };
+        let mask = self.stream.role().mask_key();
+        let head = FrameHead::new(
+            Fin::Y,
+            opcode,
+            mask,
+            PayloadLen::from_num(payload.len() as u64),
+        );
+
+        let mut frame = vec![0; 14 + payload.len()];
+        // this is safe since a head takes at most 14 bytes
+        let head_len = unsafe { head.encode_unchecked(&mut frame) };
+        frame.truncate(head_len + payload.len());
+
+        let data = &mut frame[head_len..];
+        data.copy_from_slice(payload);
+        if let Mask::Key(key) = mask {
+            apply_mask4(key, data);
+        }
+        frame
+    }
+}
+
+impl<IO: Read + Write, Role: RoleHelper> RpcStream<IO, Role> {
+    /// Send a request or any other message as a single frame,
+    /// then flush the IO source.
+    pub fn send(&mut self, message: &Message) -> Result<()> {
+        let frame = self.encode(message);
+        let io = self.stream.as_mut();
+        io.write_all(&frame)?;
+        io.flush()
+    }
+
+    /// Read messages until the response with `id` arrives,
+    /// a buffered one is returned at once.
+    ///
+    /// If a `Close` frame is received, a [`MessageError::Closed`] error
+    /// is returned. Reaching `EOF` leads to an `UnexpectedEof` error.
+    pub fn recv(&mut self, id: u64) -> Result<Message> {
+        loop {
+            if let Some(message) = self.responses.remove(&id) {
+                return Ok(message);
+            }
+            let message = match self.stream.messages().next() {
+                Some(message) => message?,
+                None if self.stream.is_read_close() => return Err(MessageError::Closed.into()),
+                None => return Err(ErrorKind::UnexpectedEof.into()),
+            };
+            if let Some(message) = self.dispatch(id, message)? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Send a request, then wait for its response.
+    ///
+    /// The ID of the request is extracted like a response, an `InvalidInput`
+    /// error is returned if there is none.
+    pub fn call(&mut self, request: &Message) -> Result<Message> {
+        let id = self.request_id(request)?;
+        self.send(request)?;
+        self.recv(id)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<IO: AsyncRead + AsyncWrite + Unpin, Role: RoleHelper> RpcStream<IO, Role> {
+    /// Async version of [`RpcStream::send`].
+    pub async fn send_async(&mut self, message: &Message) -> Result<()> {
+        let frame = self.encode(message);
+        let io = self.stream.as_mut();
+        io.write_all(&frame).await?;
+        io.flush().await
+    }
+
+    /// Async version of [`RpcStream::recv`].
+    ///
+    /// # Cancel safety
+    ///
+    /// This is cancel safe, a message is buffered once it is read.
+    pub async fn recv_async(&mut self, id: u64) -> Result<Message> {
+        loop {
+            if let Some(message) = self.responses.remove(&id) {
+                return Ok(message);
+            }
+            let message = match self.stream.next_event().await? {
+                Event::Message(message) => message,
+                Event::Close(..) => return Err(MessageError::Closed.into()),
+                Event::Ping(_) | Event::Pong(_) => continue,
+            };
+            if let Some(message) = self.dispatch(id, message)? {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Async version of [`RpcStream::call`].
+    pub async fn call_async(&mut self, request: &Message) -> Result<Message> {
+        let id = self.request_id(request)?;
+        self.send_async(request).await?;
+        self.recv_async(id).await
+    }
+}
+
+#[inline]
+fn payload(message: &Message) -> &[u8] {
+    match message {
+        Message::Text(text) => text.as_bytes(),
+        Message::Binary(data) => data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use super::*;
+    use super::super::test::make_head;
+    use crate::role::*;
+
+    // 8-byte big endian id, then the body
+    fn id_of(payload: &[u8]) -> Option<u64> {
+        payload
+            .get(..8)
+            .map(|x| u64::from_be_bytes(x.try_into().unwrap()))
+    }
+
+    fn make_message(id: u64, body: &[u8]) -> Message {
+        let mut payload = id.to_be_bytes().to_vec();
+        payload.extend_from_slice(body);
+        Message::Binary(payload)
+    }
+
+    fn make_response(message: &Message) -> Vec<u8> {
+        let payload = payload(message);
+        let mut frame = make_head(OpCode::Binary, Mask::None, payload.len());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    struct Duplex {
+        rbuf: Cursor<Vec<u8>>,
+        wbuf: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> { self.rbuf.read(buf) }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.wbuf.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> Result<()> { Ok(()) }
+    }
+
+    #[test]
+    fn correlate_out_of_order() {
+        let req1 = make_message(1, b"first");
+        let req2 = make_message(2, b"second");
+        let resp1 = make_message(1, b"first done");
+        let resp2 = make_message(2, b"second done");
+        let notice = Message::Text(String::from("hi"));
+
+        // responses arrive in reverse order, with a ping
+        // and a message without an id in between
+        let mut rbuf = make_response(&resp2);
+        rbuf.extend(make_head(OpCode::Ping, Mask::None, 4));
+        rbuf.extend_from_slice(b"ping");
+        rbuf.extend(make_head(OpCode::Text, Mask::None, 2));
+        rbuf.extend_from_slice(b"hi");
+        rbuf.extend(make_response(&resp1));
+        rbuf.extend(make_head(OpCode::Close, Mask::None, 0));
+
+        let io = Duplex {
+            rbuf: Cursor::new(rbuf),
+            wbuf: Vec::new(),
+        };
+        let mut stream = RpcStream::new(MessageStream::new(io, Client::new()), id_of);
+
+        stream.send(&req1).unwrap();
+        stream.send(&req2).unwrap();
+
+        // both requests are sent as masked frames
+        let wbuf = &stream.as_ref().wbuf;
+        let (head, n) = FrameHead::decode(wbuf).unwrap();
+        assert_eq!(head.mask, Mask::Skip);
+        assert_eq!(&wbuf[n..n + 13], payload(&req1));
+        let (_, n2) = FrameHead::decode(&wbuf[n + 13..]).unwrap();
+        assert_eq!(&wbuf[n + 13 + n2..], payload(&req2));
+
+        assert_eq!(stream.recv(1).unwrap(), resp1);
+        assert_eq!(stream.pending_len(), 2);
+        assert_eq!(stream.message_stream().ping_data(), b"ping");
+        assert_eq!(stream.recv(2).unwrap(), resp2);
+        assert_eq!(stream.take_unsolicited(), Some(notice));
+        assert_eq!(stream.pending_len(), 0);
+
+        // closed during an outstanding request
+        let e = stream.call(&make_message(3, b"third")).unwrap_err();
+        let e = e.into_inner().unwrap();
+        assert!(matches!(
+            *e.downcast::<crate::error::Error>().unwrap(),
+            crate::error::Error::Message(MessageError::Closed)
+        ));
+
+        // a request must carry an id
+        let e = stream.call(&Message::Binary(Vec::new())).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn too_many_pending() {
+        let mut rbuf = Vec::new();
+        for id in 0..4 {
+            rbuf.extend(make_response(&make_message(id, b"")));
+        }
+        let io = Duplex {
+            rbuf: Cursor::new(rbuf),
+            wbuf: Vec::new(),
+        };
+        let mut stream = RpcStream::new(MessageStream::new(io, Client::new()), id_of);
+        stream.set_max_pending(2);
+        assert_eq!(stream.max_pending(), 2);
+
+        assert!(stream.recv(3).is_err());
+        assert_eq!(stream.pending_len(), 2);
+        assert_eq!(stream.recv(1).unwrap(), make_message(1, b""));
+    }
+}
@@ -0,0 +1,121 @@
+use super::Stream;
+
+/// Scratch buffers owned by a stream, see [`Stream::with_buffers`].
+#[derive(Debug)]
+pub(super) struct Scratch {
+    pub read: Option<Box<[u8]>>,
+    pub write: Option<Box<[u8]>>,
+}
+
+impl Scratch {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            read: None,
+            write: None,
+        }
+    }
+}
+
+impl<IO, Role> Stream<IO, Role> {
+    /// Create websocket stream from IO source directly, with scratch
+    /// buffers owned by the stream, so that their placement and
+    /// lifetime (e.g. in a pool) are controlled by the caller.
+    ///
+    /// Without them, a small buffer is prepared on the stack for each call.
+    /// The read buffer is used to consume discarded payload, see
+    /// [`Stream::set_discard_payloads`]. The write buffer is used to stage
+    /// payload while it is masked, see [`Stream::write_frame`]. An empty
+    /// buffer is ignored. Frame heads are always stored inline.
+    ///
+    /// Otherwise this is the same as [`Stream::new`],
+    /// buffers are returned by [`Stream::into_parts`].
+    #[inline]
+    pub fn with_buffers(
+        io: IO,
+        role: Role,
+        read_scratch: Box<[u8]>,
+        write_scratch: Box<[u8]>,
+    ) -> Self {
+        let mut stream = Self::new(io, role);
+        stream.scratch = Scratch {
+            read: Some(read_scratch).filter(|buf| !buf.is_empty()),
+            write: Some(write_scratch).filter(|buf| !buf.is_empty()),
+        };
+        stream
+    }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Destruct the stream, returns the IO source, the read and the write
+    /// scratch buffers (see [`Stream::with_buffers`]), which are empty
+    /// if not provided. Other states are dropped.
+    #[inline]
+    pub fn into_parts(self) -> (IO, Box<[u8]>, Box<[u8]>) {
+        (
+            self.io,
+            self.scratch.read.unwrap_or_default(),
+            self.scratch.write.unwrap_or_default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::super::test::make_frame_with_mask;
+    use crate::frame::{Fin, Mask, OpCode, new_mask_key};
+    use crate::role::*;
+
+    #[test]
+    fn stream_with_buffers() {
+        let key = new_mask_key();
+        let (_, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 1000);
+
+        // masked in a tiny buffer
+        let mut stream = Stream::new(Vec::new(), FixedMaskClient::new());
+        stream.set_mask_key(key).unwrap();
+        stream.write_frame(Fin::Y, OpCode::Binary, &data).unwrap();
+        let frame = stream.as_ref().clone();
+
+        let read_scratch = vec![0; 3].into_boxed_slice();
+        let write_scratch = vec![0; 7].into_boxed_slice();
+        let (read_ptr, write_ptr) = (read_scratch.as_ptr(), write_scratch.as_ptr());
+        let mut stream = Stream::with_buffers(
+            Vec::new(),
+            FixedMaskClient::new(),
+            read_scratch,
+            write_scratch,
+        );
+        stream.set_mask_key(key).unwrap();
+        stream.write_frame(Fin::Y, OpCode::Binary, &data).unwrap();
+        assert_eq!(stream.as_ref(), &frame);
+
+        let (io, read_scratch, write_scratch) = stream.into_parts();
+        assert_eq!(io, frame);
+        assert_eq!(read_scratch.as_ptr(), read_ptr);
+        assert_eq!(write_scratch.as_ptr(), write_ptr);
+
+        // discarded in a tiny buffer, then read as usual
+        let frames = [frame.clone(), frame].concat();
+        let mut stream = Stream::with_buffers(
+            frames.as_slice(),
+            Server::new(),
+            read_scratch,
+            write_scratch,
+        )
+        .guard();
+        stream.set_discard_payloads(true);
+        let mut buf = vec![0; 1024];
+        assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 1000);
+        stream.set_discard_payloads(false);
+        assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 1000);
+        assert_eq!(&buf[..1000], &data);
+        assert_eq!(stream.stats().read_payload_bytes, 2000);
+
+        // empty if not provided
+        let (_, read_scratch, write_scratch) =
+            Stream::new(Vec::<u8>::new(), Server::new()).into_parts();
+        assert!(read_scratch.is_empty() && write_scratch.is_empty());
+    }
+}
@@ -8,6 +8,8 @@ impl<Role: RoleHelper> Stream<TcpStream, Role> {
     /// Caution: **states are not shared among instances!**
     pub fn try_clone(&self) -> Result<Self> {
         let io = self.io.try_clone()?;
-        Ok(Self::new(io, self.role))
+        let mut stream = Self::new(io, self.role);
+        stream.extensions = self.extensions.clone();
+        Ok(stream)
     }
 }
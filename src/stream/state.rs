@@ -1,6 +1,7 @@
 use super::Stream;
+use super::close::CloseReason;
 
-use crate::frame::Mask;
+use crate::frame::{FrameHead, Mask, OpCode};
 use crate::bleed::Store;
 
 /// Store incomplete frame head.
@@ -9,10 +10,18 @@ pub(super) type HeadStore = Store<14>;
 /// Store the most recent ping.
 pub(super) type PingStore = Store<125>;
 
+/// Store the received close payload.
+pub(super) type ClosePayloadStore = Store<125>;
+
+/// Store a control frame to write, including frame head.
+pub(super) type CtrlStore = Store<131>;
+
 #[derive(Debug)]
 pub(super) struct HeartBeat {
     pub ping_store: PingStore,
     pub is_complete: bool,
+    // a pong to write at the next frame boundary
+    pub pong_store: CtrlStore,
 }
 
 impl HeartBeat {
@@ -21,6 +30,71 @@ impl HeartBeat {
         Self {
             ping_store: PingStore::new(),
             is_complete: false,
+            pong_store: CtrlStore::new(),
+        }
+    }
+}
+
+/// Track a fragmented message.
+#[derive(Debug)]
+pub(super) struct Fragment {
+    pub is_fragmented: bool,
+    pub opcode: OpCode,
+    // payload of an orphan continuation frame is dropped
+    pub is_ignored: bool,
+}
+
+impl Fragment {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            is_fragmented: false,
+            opcode: OpCode::Binary,
+            is_ignored: false,
+        }
+    }
+}
+
+/// Close handshake.
+#[derive(Debug)]
+pub(super) struct CloseState {
+    pub close_store: CtrlStore,
+    pub is_queued: bool,
+    pub recv_store: ClosePayloadStore,
+    pub is_received: bool,
+    // a pong is read into recv_store in manual control mode
+    pub is_pong: bool,
+    pub reason: Option<CloseReason>,
+}
+
+impl CloseState {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            close_store: CtrlStore::new(),
+            is_queued: false,
+            recv_store: ClosePayloadStore::new(),
+            is_received: false,
+            is_pong: false,
+            reason: None,
+        }
+    }
+}
+
+/// Limit frames and payload during the connection lifetime.
+/// A value of 0 means unlimited.
+#[derive(Debug)]
+pub(super) struct Limit {
+    pub max_frames: u64,
+    pub max_payload_bytes: u64,
+}
+
+impl Limit {
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            max_frames: 0,
+            max_payload_bytes: 0,
         }
     }
 }
@@ -32,11 +106,16 @@ pub(super) enum ReadState {
     ReadData {
         next: u64,
         mask: Mask,
+        offset: u8,
     },
     ReadPing {
         next: u8,
         mask: Mask,
     },
+    ReadClose {
+        next: u8,
+        mask: Mask,
+    },
     ProcessBuf {
         beg: usize,
         end: usize,
@@ -51,6 +130,18 @@ impl ReadState {
     pub const fn new() -> Self { ReadState::ReadHead(Store::new()) }
 }
 
+/// An interrupted read, where some payload is already copied to the
+/// caller's buffer. It is resumed only if nothing else is read since.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct PartialRead {
+    // head of the frame, if a whole frame is read
+    pub head: Option<FrameHead>,
+    // bytes copied to the caller's buffer
+    pub copied: usize,
+    // bytes left, the same as in `ReadState::ReadData`
+    pub next: u64,
+}
+
 /// Write state.
 #[allow(clippy::enum_variant_names)]
 #[derive(Debug)]
@@ -91,6 +182,62 @@ impl<IO, Role, Guard> Stream<IO, Role, Guard> {
     #[inline]
     pub const fn is_read_end(&self) -> bool { self.is_read_eof() || self.is_read_close() }
 
+    /// Check if a fragmented message is partially read.
+    #[inline]
+    pub const fn is_read_fragmented(&self) -> bool { self.fragment.is_fragmented }
+
+    /// Check if a `Close` frame is completely written.
+    #[inline]
+    pub const fn is_write_close(&self) -> bool {
+        self.close.is_queued && self.close.close_store.rd_left() == 0
+    }
+
+    /// Get the offset of mask key, where unmasking continues
+    /// for the next payload byte of a partially read frame.
+    ///
+    /// Returns `None` if there is no partially read frame, or the frame is not masked.
+    #[inline]
+    pub const fn current_mask_offset(&self) -> Option<u8> {
+        match &self.read_state {
+            ReadState::ReadData {
+                mask: Mask::Key(_) | Mask::Skip,
+                offset,
+                ..
+            } => Some(*offset),
+            ReadState::ReadPing {
+                mask: Mask::Key(_) | Mask::Skip,
+                ..
+            } => Some((self.heartbeat.ping_store.wr_pos() & 0x03) as u8),
+            ReadState::ReadClose {
+                mask: Mask::Key(_) | Mask::Skip,
+                ..
+            } => Some((self.close.recv_store.wr_pos() & 0x03) as u8),
+            _ => None,
+        }
+    }
+
+    /// Get the payload length of the next frame, so that
+    /// a buffer could be allocated with the exact size.
+    ///
+    /// If a complete frame head is buffered (e.g. left by
+    /// [`Stream::read_split`]), returns its declared payload length.
+    /// If a frame is partially read, returns the length of the rest payload.
+    /// Otherwise returns `None`.
+    #[inline]
+    pub fn next_payload_len(&self) -> Option<u64> {
+        match &self.read_state {
+            ReadState::ReadHead(store) => match FrameHead::decode(store.read()) {
+                Ok((head, _)) => Some(head.length.to_num()),
+                Err(_) => None,
+            },
+            ReadState::ReadData { next, .. } => Some(*next),
+            ReadState::ReadPing { next, .. } | ReadState::ReadClose { next, .. } => {
+                Some(*next as u64)
+            }
+            _ => None,
+        }
+    }
+
     /// Check if a `WriteZero` error occurred.
     #[inline]
     pub const fn is_write_zero(&self) -> bool { matches!(&self.write_state, WriteState::WriteZero) }
@@ -106,4 +253,23 @@ impl<IO, Role, Guard> Stream<IO, Role, Guard> {
     pub const fn is_write_partial_head(&self) -> bool {
         matches!(&self.write_state, WriteState::WriteHead(..))
     }
+
+    /// Check if there are unflushed bytes that must be written
+    /// before a new frame, including a partially written data frame,
+    /// a queued `Pong` or `Close` frame and coalesced writes.
+    ///
+    /// This is usually used to decide whether to wait for
+    /// writable readiness in an event loop.
+    #[inline]
+    pub const fn has_pending_write(&self) -> bool {
+        let is_partial_frame = match &self.write_state {
+            WriteState::WriteHead(head) => !head.is_empty(),
+            WriteState::WriteData(_) => true,
+            WriteState::WriteZero => false,
+        };
+        is_partial_frame
+            || self.heartbeat.pong_store.rd_left() != 0
+            || self.close.close_store.rd_left() != 0
+            || !self.coalesce.buf.is_empty()
+    }
 }
@@ -0,0 +1,327 @@
+use std::io::{self, Read, Write, IoSlice, IoSliceMut};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::Stream;
+use super::detail::{check_zero_read, saturate_len};
+use super::replay::Replay;
+use crate::frame::CloseCode;
+use crate::role::RoleHelper;
+use crate::error::FrameError;
+
+/// Statistics of a websocket stream, during the connection lifetime.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Count of received frames, including control frames.
+    pub read_frames: u64,
+    /// Count of received payload bytes, as declared in frame heads.
+    pub read_payload_bytes: u64,
+    /// Count of reads from the underlying IO source.
+    pub read_syscalls: u64,
+    /// Count of writes to the underlying IO source.
+    pub write_syscalls: u64,
+}
+
+impl StreamStats {
+    /// Constructor.
+    #[inline]
+    pub const fn new() -> Self {
+        Self {
+            read_frames: 0,
+            read_payload_bytes: 0,
+            read_syscalls: 0,
+            write_syscalls: 0,
+        }
+    }
+}
+
+/// Count calls to the underlying IO source, where a helper
+/// like [`Write::write_all`] may call it more than once.
+///
+/// An `Ok(0)` from a read into a non-empty buffer is `EOF`,
+/// unless `zero_read_is_eof` is false. Bytes read ahead(if any)
+/// are returned first, which are not counted. A read fails with
+/// `Interrupted` once `cancel` is set.
+pub(super) struct CountIO<'a, IO> {
+    pub io: &'a mut IO,
+    pub count: &'a mut u64,
+    pub zero_read_is_eof: bool,
+    pub replay: Option<&'a mut Replay>,
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+impl<IO> CountIO<'_, IO> {
+    #[inline]
+    fn check_cancel(&self) -> io::Result<()> {
+        match self.cancel {
+            Some(x) if x.load(Ordering::Acquire) => Err(io::ErrorKind::Interrupted.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl<IO: Read> Read for CountIO<'_, IO> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(replay) = self.replay.as_mut().filter(|x| !x.is_empty()) {
+            return Ok(replay.read(buf));
+        }
+        self.check_cancel()?;
+        *self.count += 1;
+        let read_n = self.io.read(buf);
+        check_zero_read(read_n, self.zero_read_is_eof || buf.is_empty())
+    }
+
+    #[inline]
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        if let Some(replay) = self.replay.as_mut().filter(|x| !x.is_empty()) {
+            return Ok(replay.read_vectored(bufs));
+        }
+        self.check_cancel()?;
+        *self.count += 1;
+        let read_n = self.io.read_vectored(bufs);
+        check_zero_read(
+            read_n,
+            self.zero_read_is_eof || bufs.iter().all(|buf| buf.is_empty()),
+        )
+    }
+}
+
+impl<IO: Write> Write for CountIO<'_, IO> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        *self.count += 1;
+        self.io.write(buf)
+    }
+
+    #[inline]
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        *self.count += 1;
+        self.io.write_vectored(bufs)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> { self.io.flush() }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Get statistics of this stream.
+    #[inline]
+    pub const fn stats(&self) -> &StreamStats { &self.stats }
+
+    /// Get the total length of the most recently read or written frame,
+    /// including the head and mask key, e.g. to account for the overhead
+    /// of frames. Returns 0 if there is not any.
+    ///
+    /// A frame is counted once its head is read, or created to write,
+    /// where the payload may be incomplete. A `Close` frame is counted
+    /// once it is queued. Bytes written with [`Stream::write_raw_frame`]
+    /// are not decoded, which are not counted.
+    #[inline]
+    pub fn last_frame_wire_len(&self) -> usize { saturate_len(self.last_frame_len) }
+
+    /// Check if payload of data frames is discarded.
+    #[inline]
+    pub const fn is_discard_payloads(&self) -> bool { self.discard_payloads }
+
+    /// Discard payload of data frames, which is useful for passive
+    /// traffic accounting, where only [`Stream::stats`] is concerned.
+    ///
+    /// Once enabled, payload is consumed from the underlying IO source
+    /// but never returned. A read returns `Ok(0)` until a `Close` frame
+    /// is received or `EOF` is reached, which could be checked via
+    /// [`Stream::is_read_end`]. For a guarded stream, a read returns
+    /// only when the stream ends. A frame read returns the length of
+    /// discarded payload, where the provided buffer is left untouched.
+    ///
+    /// Control frames are handled as usual.
+    #[inline]
+    pub fn set_discard_payloads(&mut self, discard: bool) { self.discard_payloads = discard; }
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Wrap the underlying IO source to count reads.
+    #[inline]
+    pub(super) fn count_read_io(&mut self) -> CountIO<'_, IO> {
+        CountIO {
+            io: &mut self.io,
+            count: &mut self.stats.read_syscalls,
+            zero_read_is_eof: self.zero_read_is_eof,
+            replay: Some(&mut self.replay),
+            cancel: self.cancel.as_deref(),
+        }
+    }
+
+    /// Wrap the underlying IO source to count writes.
+    #[inline]
+    pub(super) fn count_write_io(&mut self) -> CountIO<'_, IO> {
+        CountIO {
+            io: &mut self.io,
+            count: &mut self.stats.write_syscalls,
+            zero_read_is_eof: true,
+            replay: None,
+            cancel: None,
+        }
+    }
+}
+
+impl<IO, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Record an incoming frame, then check the limits.
+    ///
+    /// A `Close` frame with [`CloseCode::PolicyViolation`] is queued
+    /// once a limit is exceeded.
+    pub(super) fn count_read_frame(
+        &mut self,
+        head_len: usize,
+        frame_len: u64,
+    ) -> Result<(), FrameError> {
+        self.last_frame_len = (head_len as u64).saturating_add(frame_len);
+        self.mark_read_activity();
+        self.stats.read_frames += 1;
+        self.stats.read_payload_bytes += frame_len;
+        let limit = &self.limit;
+        if limit.max_frames != 0 && self.stats.read_frames > limit.max_frames {
+            return Err(
+                self.queue_violation(CloseCode::PolicyViolation, FrameError::ExceedFrameLimit)
+            );
+        }
+        if limit.max_payload_bytes != 0 && self.stats.read_payload_bytes > limit.max_payload_bytes {
+            return Err(
+                self.queue_violation(CloseCode::PolicyViolation, FrameError::ExceedPayloadLimit)
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use super::*;
+    use super::super::test::{LimitReadWriter, make_frame, make_frame_with_mask};
+    use crate::frame::{Fin, Mask, OpCode, apply_mask4, new_mask_key};
+    use crate::role::*;
+
+    fn make_stream(rlimit: usize) -> (Stream<LimitReadWriter, Server>, Vec<usize>) {
+        let lens = vec![0, 1, 100, 1000, 0x10000];
+        let mut buf = Vec::new();
+        for &len in lens.iter() {
+            buf.append(&mut make_frame::<Client>(OpCode::Binary, len).0);
+        }
+        buf.append(&mut make_frame::<Client>(OpCode::Ping, 8).0);
+        buf.append(&mut make_frame::<Client>(OpCode::Close, 2).0);
+        let io = LimitReadWriter {
+            buf,
+            rlimit,
+            wlimit: 0,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+        stream.set_discard_payloads(true);
+        assert!(stream.is_discard_payloads());
+        (stream, lens)
+    }
+
+    fn check_stats<Guard>(stream: &Stream<LimitReadWriter, Server, Guard>, lens: &[usize]) {
+        assert!(stream.is_read_close());
+        assert!(stream.is_pinged());
+        assert_eq!(stream.stats().read_frames, lens.len() as u64 + 2);
+        assert_eq!(
+            stream.stats().read_payload_bytes,
+            lens.iter().sum::<usize>() as u64 + 10
+        );
+    }
+
+    #[test]
+    fn discard_payloads() {
+        for rlimit in [1, 7, 4096, 0x20000] {
+            // stream read
+            let (mut stream, lens) = make_stream(rlimit);
+            let mut buf = vec![0; 4096];
+            while !stream.is_read_end() {
+                assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            }
+            check_stats(&stream, &lens);
+
+            // guarded stream read
+            let (stream, lens) = make_stream(rlimit);
+            let mut stream = stream.guard();
+            assert_eq!(stream.read(&mut buf).unwrap(), 0);
+            check_stats(&stream, &lens);
+
+            // frame read, buffer is untouched
+            let (mut stream, lens) = make_stream(rlimit);
+            let mut buf = vec![0xff; 16];
+            for &len in lens.iter() {
+                assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), len);
+            }
+            assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 0);
+            assert!(buf.iter().all(|&b| b == 0xff));
+            check_stats(&stream, &lens);
+
+            let (mut stream, lens) = make_stream(rlimit);
+            let mut n = 0;
+            while let (OpCode::Binary, x) = stream.read_data(&mut buf).unwrap() {
+                n += x;
+            }
+            assert_eq!(n, lens.iter().sum());
+            assert!(buf.iter().all(|&b| b == 0xff));
+            check_stats(&stream, &lens);
+        }
+    }
+
+    #[test]
+    fn count_syscalls() {
+        // a frame head of 2 bytes, and 100 bytes of payload,
+        // fetched 64 bytes at a time
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 100);
+        let io = LimitReadWriter {
+            buf: frame,
+            rlimit: 64,
+            wlimit: 0,
+            cursor: 0,
+        };
+        let mut stream = Stream::new(io, Server::new());
+        let mut buf = vec![0; 4096];
+        let mut data2 = Vec::new();
+        while data2.len() < data.len() {
+            let n = stream.read(&mut buf).unwrap();
+            data2.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(data2, data);
+        assert_eq!(stream.stats().read_syscalls, 2);
+        assert_eq!(stream.stats().write_syscalls, 0);
+
+        // one vectored write for the head and payload
+        let mut stream = Stream::new(Vec::new(), Server::new());
+        let data2 = data.clone();
+        assert_eq!(stream.write(&data2).unwrap(), 100);
+        assert_eq!(stream.stats().read_syscalls, 0);
+        assert_eq!(stream.stats().write_syscalls, 1);
+    }
+
+    #[test]
+    fn last_frame_wire_len() {
+        // payload + 2 (head) + 2 (16-bit len) + 4 (mask)
+        let key = new_mask_key();
+        let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), 200);
+        apply_mask4(key, &mut frame[8..]);
+        let mut stream = Stream::new(frame.as_slice(), Server::new());
+        assert_eq!(stream.last_frame_wire_len(), 0);
+
+        let mut buf = vec![0; 4096];
+        assert_eq!(stream.read(&mut buf).unwrap(), 200);
+        assert_eq!(&buf[..200], data.as_slice());
+        assert_eq!(stream.last_frame_wire_len(), 200 + 2 + 2 + 4);
+
+        // written frames
+        let mut stream = Stream::new(Vec::new(), FixedMaskClient::new());
+        stream.write_frame(Fin::Y, OpCode::Binary, &data).unwrap();
+        assert_eq!(stream.last_frame_wire_len(), 200 + 2 + 2 + 4);
+        assert_eq!(stream.as_ref().len(), 200 + 2 + 2 + 4);
+
+        let mut stream = Stream::new(Vec::new(), Server::new());
+        assert_eq!(stream.write(&data[..10]).unwrap(), 10);
+        assert_eq!(stream.last_frame_wire_len(), 10 + 2);
+        assert_eq!(stream.as_ref().len(), 10 + 2);
+    }
+}
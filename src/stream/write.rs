@@ -1,8 +1,27 @@
-use std::io::{Write, Result};
+use std::io::{Write, IoSlice, Result, ErrorKind};
 use std::task::Poll;
 
 use super::{Stream, RoleHelper, Guarded};
-use super::detail::write_some;
+use super::state::WriteState;
+use super::detail::{write_some, write_frame, write_pong, trace_head};
+use super::cancel::poll_unless_cancelled;
+use super::frame::MAX_HEAD_LEN;
+
+use crate::frame::{FrameHead, Fin, Mask, OpCode, PayloadLen};
+use crate::frame::mask::{apply_mask4, rotate_mask_key};
+use crate::role::{ClientRole, ServerRole};
+use crate::error::{CtrlError, FrameError};
+
+/// Size of the stack buffer to mask payload in.
+const MASK_BUF_SIZE: usize = 1024;
+
+/// Max length of payload which is written along with the head
+/// in a single write, e.g. a chat message.
+const SMALL_PAYLOAD_LEN: usize = 125;
+
+/// Size of the stack buffer to stage a frame head along with payload,
+/// see [`Stream::set_combine_head_payload`].
+const STAGE_BUF_SIZE: usize = MAX_HEAD_LEN + MASK_BUF_SIZE;
 
 impl<IO: Write, Role: RoleHelper> Write for Stream<IO, Role> {
     /// Write some data to the underlying IO source,
@@ -16,16 +35,34 @@ impl<IO: Write, Role: RoleHelper> Write for Stream<IO, Role> {
     /// according to the length of the provided buffer.
     ///
     /// A standard client should mask payload data before sending it.
+    ///
+    /// Data are buffered if writes are coalesced,
+    /// see [`Stream::set_coalesce_writes`].
+    ///
+    /// Once a `Close` frame is sent(see [`Stream::send_close`]),
+    /// a [`CtrlError::WriteAfterClose`] error is returned.
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
-        match write_some(self, |io, iovec| io.write_vectored(iovec).into(), buf) {
+        if self.coalesce.is_enabled {
+            return self.write_coalesced(buf);
+        }
+        let cancel = self.cancel.clone();
+        let combine = self.combine_head_payload;
+        let write = |io: &mut IO, iovec: &[IoSlice<'_>]| {
+            poll_unless_cancelled(&cancel, || write_iovec(io, iovec, combine))
+        };
+        match write_some(self, write, buf) {
             Poll::Ready(x) => x,
             Poll::Pending => unreachable!(),
         }
     }
 
-    /// The writer does not buffer any data, simply flush
-    /// the underlying IO source.
-    fn flush(&mut self) -> Result<()> { self.io.flush() }
+    /// The writer does not buffer any data unless writes are coalesced,
+    /// where buffered data are emitted as one frame first.
+    /// Then flush the underlying IO source.
+    fn flush(&mut self) -> Result<()> {
+        self.flush_coalesced()?;
+        self.io.flush()
+    }
 
     /// **This is NOT supported!**
     fn write_all(&mut self, _: &[u8]) -> Result<()> {
@@ -37,8 +74,16 @@ impl<IO: Write, Role: RoleHelper> Write for Stream<IO, Role, Guarded> {
     /// Wrap write in a loop.
     /// Continue to write if frame head is not completely written.
     fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        if self.coalesce.is_enabled {
+            return self.write_coalesced(buf);
+        }
+        let cancel = self.cancel.clone();
+        let combine = self.combine_head_payload;
         loop {
-            match write_some(self, |io, iovec| io.write_vectored(iovec).into(), buf) {
+            let write = |io: &mut IO, iovec: &[IoSlice<'_>]| {
+                poll_unless_cancelled(&cancel, || write_iovec(io, iovec, combine))
+            };
+            match write_some(self, write, buf) {
                 Poll::Ready(Ok(0)) if self.is_write_partial_head() || !self.is_write_zero() => {
                     continue
                 }
@@ -48,9 +93,307 @@ impl<IO: Write, Role: RoleHelper> Write for Stream<IO, Role, Guarded> {
         }
     }
 
-    /// The writer does not buffer any data, simply flush
-    /// the underlying IO source.
-    fn flush(&mut self) -> Result<()> { self.io.flush() }
+    /// See `Stream::flush` in `Direct` mode.
+    fn flush(&mut self) -> Result<()> {
+        self.flush_coalesced()?;
+        self.io.flush()
+    }
+}
+
+/// Write the slices at once, or stage a frame head along with
+/// the first chunk of payload if `combine` is set.
+fn write_iovec<IO: Write>(io: &mut IO, iovec: &[IoSlice<'_>], combine: bool) -> Result<usize> {
+    if !combine || iovec.len() < 2 {
+        return io.write_vectored(iovec);
+    }
+    let mut stage = [0_u8; STAGE_BUF_SIZE];
+    let mut n = 0;
+    for buf in iovec {
+        let len = std::cmp::min(buf.len(), STAGE_BUF_SIZE - n);
+        stage[n..n + len].copy_from_slice(&buf[..len]);
+        n += len;
+    }
+    io.write(&stage[..n])
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if a frame head is staged along with payload,
+    /// see [`Stream::set_combine_head_payload`].
+    #[inline]
+    pub const fn is_combine_head_payload(&self) -> bool { self.combine_head_payload }
+
+    /// Set whether to copy a frame head and the first chunk of payload
+    /// into a small stack buffer, so that a single `write` sends both.
+    /// This is disabled by default.
+    ///
+    /// A frame head and its payload are written with `write_vectored`,
+    /// which only writes the first slice (the head) if the IO source does
+    /// not support vectored writes. On a TCP socket with Nagle's algorithm
+    /// enabled, the small head write may delay the payload. Prefer
+    /// `set_nodelay`, or a vectored IO source; otherwise enable this.
+    ///
+    /// This only affects [`Write`] on a stream. Up to 1024 bytes of payload
+    /// are copied per write, the rest are written as usual.
+    #[inline]
+    pub fn set_combine_head_payload(&mut self, combine: bool) {
+        self.combine_head_payload = combine;
+    }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Write pre-encoded frames to the underlying IO source as is,
+    /// returns the count of written bytes.
+    /// This is usually used to forward frames without decoding them.
+    ///
+    /// Frame head is not generated, and payload data are not masked.
+    /// Caller is responsible for the correctness of these bytes.
+    /// A queued pong(see [`Stream::queue_pong`]) is written first.
+    ///
+    /// An attempt to write raw frames during a write will fail with
+    /// [`CtrlError::RawFrameInWrite`], or [`CtrlError::WriteAfterClose`]
+    /// once a `Close` frame is sent.
+    ///
+    /// If the underlying IO source returns an error(e.g. `WouldBlock`),
+    /// progress is saved in the stream. Then the next write **must** be
+    /// another `write_raw_frame` with the same bytes, which continues from
+    /// where it left off.
+    pub fn write_raw_frame(&mut self, bytes: &[u8]) -> Result<usize> {
+        let mut offset = match self.write_state {
+            // continue the rest of bytes
+            WriteState::WriteData(next) if next <= bytes.len() as u64 => {
+                bytes.len() - next as usize
+            }
+            _ => {
+                self.check_whole_frame()?;
+                self.write_queued_pong()?;
+                if bytes.is_empty() {
+                    return Ok(0);
+                }
+                // written as the payload of a partial frame
+                self.write_state = WriteState::WriteData(bytes.len() as u64);
+                0
+            }
+        };
+        while offset < bytes.len() {
+            let write = |io: &mut IO, iovec: &[IoSlice<'_>]| io.write_vectored(iovec).into();
+            match write_frame(self, write, OpCode::Binary, &bytes[offset..]) {
+                Poll::Ready(Ok(0)) => return Err(ErrorKind::WriteZero.into()),
+                Poll::Ready(Ok(n)) => offset += n,
+                Poll::Ready(Err(e)) => return Err(e),
+                Poll::Pending => unreachable!(),
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    /// Write a queued pong before a new frame.
+    fn write_queued_pong(&mut self) -> Result<()> {
+        match write_pong(self, |io, iovec| io.write_vectored(iovec).into()) {
+            Poll::Ready(x) => x,
+            Poll::Pending => unreachable!(),
+        }
+    }
+
+    /// Write a frame head and its pre-masked payload as is,
+    /// returns the count of written payload bytes.
+    ///
+    /// This is usually used to forward a masked payload, or to mask
+    /// payload data outside of the IO thread.
+    ///
+    /// The payload is not masked again. Caller is responsible for masking it
+    /// with the key in the head, otherwise the peer unmasks it to garbage.
+    /// The length in the head must equal the length of the payload,
+    /// otherwise a [`FrameError::IllegalData`] error is returned.
+    ///
+    /// Same as [`Stream::write_raw_frame`], an attempt to write during a write
+    /// will fail with [`CtrlError::RawFrameInWrite`], an attempt to write after
+    /// a close will fail with [`CtrlError::WriteAfterClose`], and an IO error
+    /// leaves the frame partially written.
+    pub fn write_premasked(&mut self, head: FrameHead, masked_payload: &[u8]) -> Result<usize> {
+        if head.length.to_num() != masked_payload.len() as u64 {
+            return Err(FrameError::IllegalData.into());
+        }
+        self.check_whole_frame()?;
+        self.write_queued_pong()?;
+        trace_head("write", &head);
+
+        let mut buf = [0_u8; MAX_HEAD_LEN];
+        let head_len = unsafe { head.encode_unchecked(&mut buf) };
+        self.last_frame_len = (head_len + masked_payload.len()) as u64;
+        self.count_write_io().write_all(&buf[..head_len])?;
+        self.count_write_io().write_all(masked_payload)?;
+        Ok(masked_payload.len())
+    }
+
+    /// Write a binary frame masked with the provided key regardless of role,
+    /// returns the count of written payload bytes.
+    ///
+    /// **This is a testing utility**, which is usually used to impersonate
+    /// a client, or build test vectors. A server should never mask its frames.
+    ///
+    /// Payload is masked in a small stack buffer, without heap allocation.
+    /// Same as [`Stream::write_raw_frame`], an attempt to write during a write
+    /// will fail with [`CtrlError::RawFrameInWrite`], an attempt to write after
+    /// a close will fail with [`CtrlError::WriteAfterClose`], and an IO error
+    /// leaves the frame partially written.
+    #[inline]
+    pub fn write_masked(&mut self, key: [u8; 4], payload: &[u8]) -> Result<usize> {
+        self.write_single_frame(Fin::Y, OpCode::Binary, Mask::Key(key), payload)
+    }
+
+    /// Write a complete frame, where payload is masked in a stack buffer.
+    fn write_single_frame(
+        &mut self,
+        fin: Fin,
+        opcode: OpCode,
+        mask: Mask,
+        payload: &[u8],
+    ) -> Result<usize> {
+        self.check_whole_frame()?;
+        self.check_liveness()?;
+        self.write_queued_pong()?;
+        if opcode == OpCode::Ping {
+            self.mark_ping_sent();
+        }
+
+        let head = FrameHead::new(
+            fin,
+            opcode,
+            mask,
+            PayloadLen::from_num(payload.len() as u64),
+        );
+        trace_head("write", &head);
+
+        // a small frame is built on stack and written at once
+        if payload.len() <= SMALL_PAYLOAD_LEN {
+            let mut frame_buf = [0_u8; MAX_HEAD_LEN + SMALL_PAYLOAD_LEN];
+            let head_len = unsafe { head.encode_unchecked(&mut frame_buf) };
+            let frame_len = head_len + payload.len();
+            self.last_frame_len = frame_len as u64;
+            let data = &mut frame_buf[head_len..frame_len];
+            data.copy_from_slice(payload);
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, data);
+            }
+            self.count_write_io().write_all(&frame_buf[..frame_len])?;
+            return Ok(payload.len());
+        }
+
+        let mut head_buf = [0_u8; MAX_HEAD_LEN];
+        let head_len = unsafe { head.encode_unchecked(&mut head_buf) };
+        self.last_frame_len = (head_len + payload.len()) as u64;
+        self.count_write_io().write_all(&head_buf[..head_len])?;
+
+        let key = match mask {
+            Mask::Key(key) => key,
+            Mask::Skip | Mask::None => {
+                self.count_write_io().write_all(payload)?;
+                return Ok(payload.len());
+            }
+        };
+
+        let mut stack_buf = [0_u8; MASK_BUF_SIZE];
+        let mut scratch = self.scratch.write.take();
+        let buf = match scratch.as_deref_mut() {
+            Some(buf) => buf,
+            None => &mut stack_buf,
+        };
+        let ret = self.write_masked_payload(key, payload, buf);
+        self.scratch.write = scratch;
+        ret
+    }
+
+    /// Mask payload in `buf` chunk by chunk, then write it,
+    /// where `buf` is not empty.
+    fn write_masked_payload(
+        &mut self,
+        key: [u8; 4],
+        payload: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize> {
+        let mut offset = 0;
+        for chunk in payload.chunks(buf.len()) {
+            let data = &mut buf[..chunk.len()];
+            data.copy_from_slice(chunk);
+            apply_mask4(rotate_mask_key(key, offset), data);
+            self.count_write_io().write_all(data)?;
+            offset += chunk.len();
+        }
+        Ok(payload.len())
+    }
+
+    /// Check if a whole frame could be written.
+    fn check_whole_frame(&self) -> Result<()> {
+        // no more frames after a close frame
+        if self.close.is_queued {
+            return Err(CtrlError::WriteAfterClose.into());
+        }
+        // must not be inserted into a data frame
+        match self.write_state {
+            WriteState::WriteHead(head) if head.is_empty() && self.coalesce.buf.is_empty() => {
+                Ok(())
+            }
+            _ => Err(CtrlError::RawFrameInWrite.into()),
+        }
+    }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Write exactly one complete frame with the provided fin and opcode,
+    /// returns the count of written payload bytes.
+    ///
+    /// Payload is masked with the role's mask key(if any) in a small
+    /// stack buffer, the caller's buffer is left untouched.
+    /// Nothing else is enforced, the caller is responsible for producing
+    /// a valid sequence of frames, e.g. a fragmented message that starts
+    /// with a `Text` or `Binary` frame and ends with a `Continue` frame
+    /// with fin set, or a control frame that fits in 125 bytes.
+    ///
+    /// Same as [`Stream::write_masked`], an attempt to write during a write
+    /// will fail with [`CtrlError::RawFrameInWrite`], an attempt to write after
+    /// a close will fail with [`CtrlError::WriteAfterClose`], and an IO error
+    /// leaves the frame partially written.
+    #[inline]
+    pub fn write_frame(&mut self, fin: Fin, opcode: OpCode, payload: &[u8]) -> Result<usize> {
+        let mask = self.role.mask_key();
+        self.write_single_frame(fin, opcode, mask, payload)
+    }
+}
+
+impl<IO: Write, Role: ClientRole, Guard> Stream<IO, Role, Guard> {
+    /// Write a complete data frame masked with the provided key,
+    /// returns the count of written payload bytes.
+    ///
+    /// The key is only used for this frame. The role's mask key is not
+    /// changed, a later write continues to use it(or a generated one),
+    /// see [`Stream::set_mask_key`] to change it.
+    ///
+    /// The opcode should be either `Text` or `Binary`, otherwise
+    /// a [`FrameError::IllegalOpCode`] error is returned. Other restrictions
+    /// are the same as [`Stream::write_masked`].
+    pub fn write_with_mask(
+        &mut self,
+        opcode: OpCode,
+        payload: &[u8],
+        key: [u8; 4],
+    ) -> Result<usize> {
+        if !matches!(opcode, OpCode::Text | OpCode::Binary) {
+            return Err(FrameError::IllegalOpCode.into());
+        }
+        self.write_single_frame(Fin::Y, opcode, Mask::Key(key), payload)
+    }
+}
+
+impl<IO: Write, Role: ServerRole, Guard> Stream<IO, Role, Guard> {
+    /// Write a frame built by [`encode_broadcast_frame`](crate::frame::encode_broadcast_frame),
+    /// returns the count of written bytes.
+    ///
+    /// Server does not mask payload data, so the same frame
+    /// could be written to many clients without re-encoding.
+    ///
+    /// See also: [`Stream::write_raw_frame`].
+    #[inline]
+    pub fn write_prebuilt(&mut self, frame: &[u8]) -> Result<usize> { self.write_raw_frame(frame) }
 }
 
 #[cfg(test)]
@@ -59,7 +402,7 @@ mod test {
     use super::super::test::*;
     use crate::frame::*;
     use crate::role::*;
-    use std::io::Write;
+    use std::io::{Read, Write};
 
     #[test]
     fn write_to_stream() {
@@ -121,6 +464,334 @@ mod test {
         }
     }
 
+    #[test]
+    fn pending_write_on_stream() {
+        use std::io::ErrorKind;
+
+        let (frame, data) = make_frame::<Client>(OpCode::Binary, 32);
+        // return WouldBlock every other write, starting from the second one
+        let io = BlockWriter {
+            alternate: true,
+            is_blocked: true,
+            ..BlockWriter::new(4)
+        };
+        let mut stream = Stream::new(io, Client::new());
+        assert!(!stream.has_pending_write());
+
+        // partial frame head and payload
+        let mut n = 0;
+        while n < data.len() {
+            match stream.write(&data[n..]) {
+                Ok(x) => n += x,
+                Err(e) => assert_eq!(e.kind(), ErrorKind::WouldBlock),
+            }
+            assert_eq!(stream.has_pending_write(), n < data.len());
+        }
+        assert_eq!(stream.as_ref().buf, frame);
+
+        // queued close frame
+        while let Err(e) = stream.send_close(CloseCode::Normal, b"") {
+            assert_eq!(e.kind(), ErrorKind::WouldBlock);
+            assert!(stream.has_pending_write());
+        }
+        assert!(!stream.has_pending_write());
+        assert!(stream.is_write_close());
+    }
+
+    #[test]
+    fn combine_head_payload() {
+        // count inner writes, without vectored writes
+        struct CountWriter {
+            buf: Vec<u8>,
+            writes: usize,
+        }
+
+        impl Write for CountWriter {
+            fn write(&mut self, buf: &[u8]) -> Result<usize> {
+                self.writes += 1;
+                self.buf.extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> Result<()> { Ok(()) }
+        }
+
+        let (frame, data) = make_frame::<Server>(OpCode::Binary, 100);
+        for (combine, writes) in [(false, 2), (true, 1)] {
+            let io = CountWriter {
+                buf: Vec::new(),
+                writes: 0,
+            };
+            let mut stream = Stream::new(io, Server::new()).guard();
+            stream.set_combine_head_payload(combine);
+            assert_eq!(stream.is_combine_head_payload(), combine);
+            assert_eq!(stream.write(&data).unwrap(), data.len());
+            assert_eq!(stream.as_ref().writes, writes);
+            assert_eq!(stream.as_ref().buf, frame);
+        }
+
+        // the rest of a large payload is written as usual
+        let (frame, data) = make_frame::<Server>(OpCode::Binary, 4096);
+        let io = CountWriter {
+            buf: Vec::new(),
+            writes: 0,
+        };
+        let mut stream = Stream::new(io, Server::new()).guard();
+        stream.set_combine_head_payload(true);
+        let mut n = 0;
+        while n < data.len() {
+            n += stream.write(&data[n..]).unwrap();
+        }
+        assert_eq!(stream.as_ref().buf, frame);
+    }
+
+    #[test]
+    fn write_vectored_odd_progress() {
+        fn write<R: RoleHelper>(limit: usize) {
+            let frames: Vec<_> = [1, 30, 300]
+                .into_iter()
+                .map(|n| make_frame::<R>(OpCode::Binary, n))
+                .collect();
+
+            // direct
+            let io = BlockWriter {
+                vectored: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, R::new());
+            for (_, data) in frames.iter() {
+                let mut n = 0;
+                while n < data.len() {
+                    n += stream.write(&data[n..]).unwrap();
+                }
+                assert!(!stream.has_pending_write());
+            }
+            let buf = stream.as_ref().buf.clone();
+
+            // guarded
+            let io = BlockWriter {
+                vectored: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, R::new()).guard();
+            for (_, data) in frames.iter() {
+                stream.write_all(data).unwrap();
+            }
+            assert_eq!(stream.as_ref().buf, buf);
+
+            // each frame is decoded by the peer
+            let expected: Vec<u8> = frames.iter().flat_map(|(frame, _)| frame.clone()).collect();
+            assert_eq!(buf, expected);
+            let mut stream = Stream::new(buf.as_slice(), Server::new());
+            let mut buf = vec![0; 300];
+            for (_, data) in frames.iter() {
+                let n = stream.read_exact_frame(&mut buf).unwrap();
+                assert_eq!(&buf[..n], data);
+            }
+            assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), 0);
+        }
+
+        for limit in [1, 2, 3, 7, 13, 64] {
+            write::<Client>(limit);
+            write::<Server>(limit);
+        }
+    }
+
+    #[test]
+    fn write_raw_frame_to_stream() {
+        fn write<R1: RoleHelper, R2: RoleHelper>(n: usize) {
+            let (frame, data) = make_frame::<R1>(OpCode::Binary, n);
+
+            let io: Vec<u8> = Vec::new();
+            let mut stream = Stream::new(io, R1::new());
+
+            // forward a frame, then write as usual
+            let write_n = stream.write_raw_frame(&frame).unwrap();
+            assert_eq!(write_n, frame.len());
+            let write_n = stream.write(&data).unwrap();
+            assert_eq!(write_n, n);
+
+            let mut stream = Stream::new(stream.as_ref().as_slice(), R2::new()).guard();
+            let mut buf = vec![0; 2 * n + 28];
+            let mut read_n = 0;
+            while read_n < 2 * n {
+                read_n += stream.read(&mut buf[read_n..]).unwrap();
+            }
+            assert_eq!(read_n, 2 * n);
+            assert_eq!(&buf[..n], &data);
+            assert_eq!(&buf[n..2 * n], &data);
+        }
+
+        for i in 1..=0x2000 {
+            write::<Client, Server>(i);
+            write::<Server, Client>(i);
+        }
+    }
+
+    #[test]
+    fn write_raw_frame_would_block() {
+        fn write(n: usize, limit: usize) {
+            let (frame, data) = make_frame::<Client>(OpCode::Binary, n);
+            let io = BlockWriter {
+                alternate: true,
+                ..BlockWriter::new(limit)
+            };
+            let mut stream = Stream::new(io, Server::new());
+            stream.queue_pong(b"pong").unwrap();
+
+            // retry with the same bytes
+            loop {
+                match stream.write_raw_frame(&frame) {
+                    Ok(write_n) => {
+                        assert_eq!(write_n, frame.len());
+                        break;
+                    }
+                    Err(e) => assert_eq!(e.kind(), std::io::ErrorKind::WouldBlock),
+                }
+            }
+            assert!(!stream.is_pong_queued());
+            assert!(!stream.has_pending_write());
+
+            // the pong goes first
+            let mut expect = make_head(OpCode::Pong, Mask::None, 4);
+            expect.extend_from_slice(b"pong");
+            expect.extend_from_slice(&frame);
+            assert_eq!(stream.as_ref().buf, expect);
+
+            let mut stream = Stream::new(&stream.as_ref().buf[6..], Server::new());
+            let mut buf = vec![0; n + 14];
+            assert_eq!(stream.read_exact_frame(&mut buf).unwrap(), n);
+            assert_eq!(&buf[..n], &data);
+        }
+
+        for n in [0, 1, 10, 200] {
+            for limit in [1, 3, 7, 4096] {
+                write(n, limit);
+            }
+        }
+    }
+
+    #[test]
+    fn write_masked_to_stream() {
+        let key = [0x37, 0xfa, 0x21, 0x3d];
+        let mut stream = Stream::new(Vec::new(), Server::new());
+        let write_n = stream.write_masked(key, b"Hello").unwrap();
+        assert_eq!(write_n, 5);
+
+        // RFC-6455 Section 5.7, a single-frame masked text message,
+        // with the opcode of binary
+        assert_eq!(
+            stream.as_ref().as_slice(),
+            &[0x82, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58]
+        );
+
+        // across chunks of the stack buffer
+        for n in [1023, 1024, 1025, 4096, 0x10000] {
+            let key = new_mask_key();
+            let (mut frame, data) = make_frame_with_mask(OpCode::Binary, Mask::Key(key), n);
+            let mut stream = Stream::new(Vec::new(), Server::new());
+            stream.write_masked(key, &data).unwrap();
+
+            let offset = frame.len() - n;
+            apply_mask4(key, &mut frame[offset..]);
+            assert_eq!(stream.as_ref(), &frame);
+        }
+    }
+
+    #[test]
+    fn write_premasked_to_stream() {
+        use std::error::Error;
+
+        let key = new_mask_key();
+        let (_, data) = make_frame::<Client>(OpCode::Binary, 1000);
+        let mut masked = data.clone();
+        apply_mask4(key, &mut masked);
+
+        let head = FrameHead::new(
+            Fin::Y,
+            OpCode::Binary,
+            Mask::Key(key),
+            PayloadLen::from_num(masked.len() as u64),
+        );
+        let mut stream = Stream::new(Vec::new(), Client::new());
+        assert_eq!(stream.write_premasked(head, &masked).unwrap(), 1000);
+
+        // length mismatch
+        let e = stream.write_premasked(head, &masked[1..]).unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::IllegalData)
+        );
+
+        // unmasked by the receiver
+        let mut stream = Stream::new(stream.as_ref().as_slice(), Server::new()).guard();
+        let mut data2 = Vec::new();
+        stream.read_to_end(&mut data2).unwrap();
+        assert_eq!(data2, data);
+    }
+
+    #[test]
+    fn write_with_mask_to_stream() {
+        use std::error::Error;
+
+        fn write<R: ClientRole>() {
+            let key = [0x37, 0xfa, 0x21, 0x3d];
+            let mut stream = Stream::new(Vec::new(), R::new());
+            let write_n = stream.write_with_mask(OpCode::Text, b"Hello", key).unwrap();
+            assert_eq!(write_n, 5);
+
+            // RFC-6455 Section 5.7, a single-frame masked text message
+            let frame = [
+                0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+            ];
+            assert_eq!(stream.as_ref().as_slice(), &frame);
+
+            // back to the role's key,
+            // payload may be masked in place
+            let data = *b"Hello";
+            loop {
+                if stream.write(&data).unwrap() != 0 {
+                    break;
+                }
+            }
+            let (head, _) = FrameHead::decode(&stream.as_ref()[frame.len()..]).unwrap();
+            assert_eq!(head.opcode, OpCode::Binary);
+            assert_ne!(head.mask, Mask::Key(key));
+
+            // not a data frame
+            let e = stream.write_with_mask(OpCode::Ping, b"", key).unwrap_err();
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<FrameError>(),
+                Some(&FrameError::IllegalOpCode)
+            );
+        }
+
+        write::<Client>();
+        write::<StandardClient>();
+        write::<FixedMaskClient>();
+    }
+
+    #[test]
+    fn write_prebuilt_to_stream() {
+        let data = make_data(1024);
+        let frame = encode_broadcast_frame(OpCode::Binary, &data);
+
+        let mut streams: Vec<_> = (0..2)
+            .map(|_| Stream::new(Vec::new(), Server::new()))
+            .collect();
+        for stream in streams.iter_mut() {
+            let write_n = stream.write_prebuilt(&frame).unwrap();
+            assert_eq!(write_n, frame.len());
+        }
+
+        for stream in streams.iter() {
+            let mut stream = Stream::new(stream.as_ref().as_slice(), Client::new()).guard();
+            let mut buf = vec![0; 2048];
+            let read_n = stream.read(&mut buf).unwrap();
+            assert_eq!(&buf[..read_n], &data);
+        }
+    }
+
     #[test]
     #[cfg(feature = "unsafe_auto_mask_write")]
     fn write_to_stream_auto_mask_fixed() {
@@ -259,4 +930,119 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn write_after_close() {
+        use std::error::Error;
+
+        fn check(e: std::io::Error) {
+            assert_eq!(
+                e.source().unwrap().downcast_ref::<CtrlError>(),
+                Some(&CtrlError::WriteAfterClose)
+            );
+        }
+
+        let frame = encode_broadcast_frame(OpCode::Binary, &make_data(16));
+        let mut stream = Stream::new(Vec::new(), Server::new());
+        stream.send_close(CloseCode::Normal, b"").unwrap();
+        let close_len = stream.as_ref().len();
+
+        let data = make_data(16);
+        check(stream.write(&data).unwrap_err());
+        check(stream.write_raw_frame(&frame).unwrap_err());
+        check(stream.write_prebuilt(&frame).unwrap_err());
+        check(stream.write_masked(new_mask_key(), &data).unwrap_err());
+        // nothing is emitted after the close frame
+        assert_eq!(stream.as_ref().len(), close_len);
+    }
+
+    #[test]
+    fn write_frame_fragmented() {
+        fn write<R: RoleHelper>() {
+            let data = make_data(300);
+            let data2 = data.clone();
+            let mut stream = Stream::new(Vec::new(), R::new());
+            let mask = stream.role.mask_key();
+            assert_eq!(
+                stream
+                    .write_frame(Fin::N, OpCode::Text, &data[..100])
+                    .unwrap(),
+                100
+            );
+            assert_eq!(
+                stream
+                    .write_frame(Fin::Y, OpCode::Continue, &data[100..])
+                    .unwrap(),
+                200
+            );
+            // the caller's buffer is untouched
+            assert_eq!(data, data2);
+
+            let frames = stream.as_ref();
+            let (head, n) = FrameHead::decode(frames).unwrap();
+            assert_eq!(
+                (head.fin, head.opcode, head.mask),
+                (Fin::N, OpCode::Text, mask)
+            );
+            assert_eq!(head.length.to_num(), 100);
+            let (head2, n2) = FrameHead::decode(&frames[n + 100..]).unwrap();
+            assert_eq!(
+                (head2.fin, head2.opcode, head2.mask),
+                (Fin::Y, OpCode::Continue, mask)
+            );
+            assert_eq!(head2.length.to_num(), 200);
+            assert_eq!(frames.len(), n + 100 + n2 + 200);
+
+            let mut payload = [&frames[n..n + 100], &frames[n + 100 + n2..]].concat();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut payload[..100]);
+                apply_mask4(key, &mut payload[100..]);
+            }
+            assert_eq!(payload, data);
+        }
+
+        write::<Client>();
+        write::<Server>();
+        write::<FixedMaskClient>();
+    }
+
+    #[test]
+    fn write_frame_small_and_large() {
+        fn write<R: RoleHelper>(n: usize, wlimit: usize) {
+            let data = make_data(n);
+            let io = LimitReadWriter {
+                buf: Vec::new(),
+                rlimit: 0,
+                wlimit,
+                cursor: 0,
+            };
+            let mut stream = Stream::new(io, R::new());
+            let mask = stream.mask_key();
+            assert_eq!(stream.write_frame(Fin::Y, OpCode::Text, &data).unwrap(), n);
+
+            // the same bytes, regardless of the length
+            let head = FrameHead::new(Fin::Y, OpCode::Text, mask, PayloadLen::from_num(n as u64));
+            let mut frame = vec![0; 14];
+            let head_len = head.encode(&mut frame).unwrap();
+            frame.truncate(head_len);
+            let mut payload = data.clone();
+            if let Mask::Key(key) = mask {
+                apply_mask4(key, &mut payload);
+            }
+            frame.extend_from_slice(&payload);
+            assert_eq!(stream.as_ref().buf, frame);
+
+            // a small frame is written at once
+            if wlimit >= frame.len() && n <= SMALL_PAYLOAD_LEN {
+                assert_eq!(stream.stats().write_syscalls, 1);
+            }
+        }
+
+        for n in 0..=300 {
+            for wlimit in [1, 7, 4096] {
+                write::<FixedMaskClient>(n, wlimit);
+                write::<Server>(n, wlimit);
+            }
+        }
+    }
 }
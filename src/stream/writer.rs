@@ -0,0 +1,235 @@
+//! Write a large message incrementally, as a sequence of fragments.
+
+use std::io::{Write, Result};
+
+use super::{Stream, RoleHelper};
+
+use crate::frame::{Fin, OpCode};
+use crate::error::FrameError;
+
+/// A writer of a single fragmented message, see [`Stream::message_writer`].
+///
+/// Each `write` emits one or more non-final frames, where the first one
+/// carries the opcode of the message, and later ones are `Continue` frames.
+/// The message ends with an empty final frame, once [`MessageWriter::finish`]
+/// is called, or the writer is dropped.
+///
+/// Once an error occurs, the message is incomplete, and nothing is written
+/// on drop. The stream should not be used to write data frames any more.
+///
+/// Same as [`Write`], nothing is flushed unless [`MessageWriter::flush`] is
+/// called, or the stream flushes at the end of a message, see
+/// [`Stream::set_flush_on_message_end`].
+pub struct MessageWriter<'a, IO: Write, Role: RoleHelper, Guard> {
+    stream: &'a mut Stream<IO, Role, Guard>,
+    opcode: OpCode,
+    max_frame_len: usize,
+    is_started: bool,
+    is_done: bool,
+}
+
+impl<IO, Role, Guard> Stream<IO, Role, Guard> {
+    /// Check if the underlying IO source is flushed once a message ends.
+    #[inline]
+    pub const fn is_flush_on_message_end(&self) -> bool { self.flush_on_message_end }
+
+    /// Flush the underlying IO source once a message written by
+    /// [`MessageWriter`] ends, so that the final frame is not left in
+    /// a buffer, e.g. [`std::io::BufWriter`]. The default is false,
+    /// where a caller should flush on its own.
+    ///
+    /// When the message ends on drop, an error of the flush is ignored.
+    #[inline]
+    pub fn set_flush_on_message_end(&mut self, flush: bool) { self.flush_on_message_end = flush; }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Stream<IO, Role, Guard> {
+    /// Start writing a message of `Text` or `Binary`,
+    /// returns a writer which fragments the message.
+    ///
+    /// Other opcodes are rejected with [`FrameError::IllegalOpCode`]
+    /// once the first frame is written. Same as [`Stream::write_frame`],
+    /// payload is masked with the role's mask key in a stack buffer.
+    #[inline]
+    pub fn message_writer(&mut self, opcode: OpCode) -> MessageWriter<'_, IO, Role, Guard> {
+        MessageWriter {
+            stream: self,
+            opcode,
+            max_frame_len: 0,
+            is_started: false,
+            is_done: false,
+        }
+    }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> MessageWriter<'_, IO, Role, Guard> {
+    /// Set the max length of payload in a frame, where a large write is split
+    /// into several frames. The default is 0, which means no limit, where
+    /// each write emits exactly one frame.
+    #[inline]
+    pub fn set_max_frame_len(&mut self, len: usize) { self.max_frame_len = len; }
+
+    /// Emit the final frame, which ends the message,
+    /// then flush the underlying IO source if configured.
+    #[inline]
+    pub fn finish(mut self) -> Result<()> { self.finish_frame() }
+
+    fn finish_frame(&mut self) -> Result<()> {
+        self.write_fragment(Fin::Y, &[])?;
+        self.is_done = true;
+        if self.stream.flush_on_message_end {
+            self.stream.io.flush()?;
+        }
+        Ok(())
+    }
+
+    fn write_fragment(&mut self, fin: Fin, payload: &[u8]) -> Result<()> {
+        let opcode = if self.is_started {
+            OpCode::Continue
+        } else if matches!(self.opcode, OpCode::Text | OpCode::Binary) {
+            self.opcode
+        } else {
+            self.is_done = true;
+            return Err(FrameError::IllegalOpCode.into());
+        };
+        if let Err(e) = self.stream.write_frame(fin, opcode, payload) {
+            self.is_done = true;
+            return Err(e);
+        }
+        self.is_started = true;
+        Ok(())
+    }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Write for MessageWriter<'_, IO, Role, Guard> {
+    /// Append data to the message as non-final frames,
+    /// returns the count of written bytes, which is always the length
+    /// of the provided buffer. An empty buffer emits nothing.
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let max_frame_len = match self.max_frame_len {
+            0 => usize::MAX,
+            n => n,
+        };
+        for chunk in buf.chunks(max_frame_len) {
+            self.write_fragment(Fin::N, chunk)?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Flush the underlying IO source, the message is not finished.
+    #[inline]
+    fn flush(&mut self) -> Result<()> { self.stream.io.flush() }
+}
+
+impl<IO: Write, Role: RoleHelper, Guard> Drop for MessageWriter<'_, IO, Role, Guard> {
+    /// Finish the message, where an error is ignored.
+    fn drop(&mut self) {
+        if !self.is_done {
+            let _ = self.finish_frame();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::error::Error;
+    use super::*;
+    use super::super::test::make_data;
+    use crate::frame::FrameHead;
+    use crate::role::*;
+
+    #[test]
+    fn message_writer() {
+        let data = make_data(5 * 1024);
+        let mut stream = Stream::new(Vec::new(), Server::new());
+
+        let mut writer = stream.message_writer(OpCode::Binary);
+        writer.set_max_frame_len(1024);
+        for chunk in data.chunks(1500) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+
+        // auto-finished on drop
+        let mut writer = stream.message_writer(OpCode::Text);
+        writer.write_all(b"hello").unwrap();
+        drop(writer);
+
+        let mut frames = Vec::new();
+        let mut buf = stream.as_ref().as_slice();
+        while !buf.is_empty() {
+            let (head, n) = FrameHead::decode(buf).unwrap();
+            let len = head.length.to_num() as usize;
+            frames.push((head.fin, head.opcode, buf[n..n + len].to_vec()));
+            buf = &buf[n + len..];
+        }
+
+        // 1500 = 1024 + 476, 5120 = 1500 * 3 + 620
+        let lens: Vec<usize> = frames.iter().map(|x| x.2.len()).collect();
+        assert_eq!(lens, [1024, 476, 1024, 476, 1024, 476, 620, 0, 5, 0]);
+        for (i, (fin, opcode, _)) in frames.iter().enumerate() {
+            let expect_fin = if i == 7 || i == 9 { Fin::Y } else { Fin::N };
+            let expect_opcode = match i {
+                0 => OpCode::Binary,
+                8 => OpCode::Text,
+                _ => OpCode::Continue,
+            };
+            assert_eq!((*fin, *opcode), (expect_fin, expect_opcode), "{}", i);
+        }
+        let data2: Vec<u8> = frames[..8].iter().flat_map(|x| x.2.clone()).collect();
+        assert_eq!(data2, data);
+
+        // reassembled by the reader
+        let mut stream = Stream::new(stream.as_ref().as_slice(), Client::new()).guard();
+        let mut buf = vec![0; 8 * 1024];
+        let mut data3 = Vec::new();
+        loop {
+            let (opcode, n) = stream.read_data(&mut buf).unwrap();
+            if opcode == OpCode::Text {
+                break;
+            }
+            data3.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(data3, data);
+
+        // not a data frame
+        let mut stream = Stream::new(Vec::new(), Server::new());
+        let mut writer = stream.message_writer(OpCode::Ping);
+        let e = writer.write(b"ping").unwrap_err();
+        assert_eq!(
+            e.source().unwrap().downcast_ref::<FrameError>(),
+            Some(&FrameError::IllegalOpCode)
+        );
+        drop(writer);
+        assert!(stream.as_ref().is_empty());
+    }
+
+    #[test]
+    fn flush_on_message_end() {
+        use std::io::BufWriter;
+
+        for flush in [false, true] {
+            let mut stream = Stream::new(BufWriter::new(Vec::new()), Server::new());
+            assert!(!stream.is_flush_on_message_end());
+            stream.set_flush_on_message_end(flush);
+
+            let mut writer = stream.message_writer(OpCode::Text);
+            writer.write_all(b"hello").unwrap();
+            writer.finish().unwrap();
+
+            // 7 bytes of the first frame, 2 bytes of the final one
+            let sink = stream.as_ref().get_ref();
+            if flush {
+                assert_eq!(sink.as_slice(), b"\x01\x05hello\x80\x00");
+            } else {
+                assert!(sink.is_empty());
+            }
+
+            // also on drop
+            let mut writer = stream.message_writer(OpCode::Binary);
+            writer.write_all(b"world").unwrap();
+            drop(writer);
+            assert_eq!(stream.as_ref().get_ref().len(), if flush { 18 } else { 0 });
+        }
+    }
+}
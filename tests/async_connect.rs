@@ -0,0 +1,48 @@
+use tokio::net::TcpListener;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use lightws::endpoint::Endpoint;
+use lightws::role::Server;
+
+use log::debug;
+
+const PATH: &str = "/ws?id=1";
+
+#[tokio::test]
+async fn async_connect() {
+    env_logger::init();
+
+    let lis = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let host = lis.local_addr().unwrap().to_string();
+    let url = format!("ws://{}{}", host, PATH);
+
+    let t1 = tokio::spawn(async move {
+        let mut buf = vec![0u8; 1024];
+        let (tcp, _) = lis.accept().await.unwrap();
+        debug!("server: tcp accepted!");
+        let mut ws = Endpoint::<_, Server>::accept_async(tcp, &mut buf, &host, PATH)
+            .await
+            .unwrap()
+            .guard();
+        debug!("server: websocket accepted!");
+        let n = ws.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    });
+
+    let t2 = tokio::spawn(async move {
+        let mut ws = lightws::connect_async(&url).await.unwrap().guard();
+        debug!("client: websocket connected!");
+        let data = *b"hello";
+        ws.write_all(&data).await.unwrap();
+    });
+
+    let (r1, r2) = tokio::join!(t1, t2);
+    r1.unwrap();
+    r2.unwrap();
+
+    // tls is not built in
+    let e = lightws::connect_async("wss://127.0.0.1/ws")
+        .await
+        .unwrap_err();
+    assert!(e.to_string().contains("wss"));
+}
@@ -0,0 +1,80 @@
+use std::io::{Read, Write, BufReader, BufWriter};
+
+use lightws::frame::{OpCode, encode_broadcast_frame};
+use lightws::role::{Client, Server, RoleHelper};
+use lightws::stream::Stream;
+
+const FRAMES: usize = 1024;
+const PING_DATA: &[u8] = b"PING PING PING!";
+
+fn make_data(i: usize) -> Vec<u8> { (0..i % 200 + 1).map(|x| (x + i) as u8).collect() }
+
+// write many small frames through a buffered writer,
+// a ping frame is inserted every 16 frames
+fn write_frames() -> Vec<u8> {
+    let io = BufWriter::with_capacity(61, Vec::new());
+    let mut ws = Stream::new(io, Client::new()).guard();
+
+    for i in 0..FRAMES {
+        let data = make_data(i);
+        let n = ws.write(&data).unwrap();
+        assert_eq!(n, data.len());
+        if i % 16 == 0 {
+            let ping = encode_broadcast_frame(OpCode::Ping, PING_DATA);
+            ws.write_raw_frame(&ping).unwrap();
+        }
+    }
+    ws.flush().unwrap();
+
+    let io: &BufWriter<Vec<u8>> = ws.as_ref();
+    io.get_ref().clone()
+}
+
+#[test]
+fn buffered_read_exact_frame() {
+    let frames = write_frames();
+
+    for cap in [1, 2, 7, 64, 4096] {
+        let io = BufReader::with_capacity(cap, frames.as_slice());
+        let mut ws = Stream::new(io, Server::new());
+        let mut buf = vec![0u8; 256];
+
+        for i in 0..FRAMES {
+            let n = ws.read_exact_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..n], &make_data(i));
+            // ping frame is handled before the next data frame
+            if i % 16 == 1 {
+                assert!(ws.is_pinged());
+                assert_eq!(ws.ping_data(), PING_DATA);
+            }
+        }
+
+        let n = ws.read_exact_frame(&mut buf).unwrap();
+        assert_eq!(n, 0);
+        assert!(ws.is_read_eof());
+    }
+}
+
+#[test]
+fn buffered_read() {
+    let frames = write_frames();
+    let expected: Vec<u8> = (0..FRAMES).flat_map(make_data).collect();
+
+    for cap in [1, 2, 7, 64, 4096] {
+        let io = BufReader::with_capacity(cap, frames.as_slice());
+        let mut ws = Stream::new(io, Server::new()).guard();
+        let mut buf = vec![0u8; 64];
+        let mut data = Vec::new();
+
+        loop {
+            let n = ws.read(&mut buf).unwrap();
+            if n == 0 && ws.is_read_end() {
+                break;
+            }
+            data.extend_from_slice(&buf[..n]);
+        }
+
+        assert!(ws.is_read_eof());
+        assert_eq!(data, expected);
+    }
+}
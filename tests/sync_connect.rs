@@ -0,0 +1,45 @@
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+use lightws::endpoint::Endpoint;
+use lightws::role::Server;
+
+use log::debug;
+
+const PATH: &str = "/ws?id=1";
+
+#[test]
+fn sync_connect() {
+    env_logger::init();
+
+    let lis = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = lis.local_addr().unwrap().to_string();
+    let url = format!("ws://{}{}", host, PATH);
+
+    let t1 = thread::spawn(move || {
+        let mut buf = vec![0u8; 1024];
+        let (tcp, _) = lis.accept().unwrap();
+        debug!("server: tcp accepted!");
+        let mut ws = Endpoint::<_, Server>::accept(tcp, &mut buf, &host, PATH)
+            .unwrap()
+            .guard();
+        debug!("server: websocket accepted!");
+        let n = ws.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"hello");
+    });
+
+    let t2 = thread::spawn(move || {
+        let mut ws = lightws::connect(&url).unwrap().guard();
+        debug!("client: websocket connected!");
+        let data = *b"hello";
+        ws.write_all(&data).unwrap();
+    });
+
+    t1.join().unwrap();
+    t2.join().unwrap();
+
+    // tls is not built in
+    let e = lightws::connect("wss://127.0.0.1/ws").unwrap_err();
+    assert!(e.to_string().contains("wss"));
+}